@@ -0,0 +1,324 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    env, fs,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Size of the disk images the block device tests run against.
+const TEST_DISK_SECTORS: u64 = 2048;
+
+/// The images the QEMU arguments in `Cargo.toml` attach, which name them by
+/// path. This is why they go to `target/disk` rather than `OUT_DIR`.
+const DISK_IMAGES: [&str; 7] = [
+    "virtio.img",
+    "ata.img",
+    "fat12.img",
+    "fat16.img",
+    "fat32.img",
+    "ext2-1k.img",
+    "ext2-4k.img",
+];
+
+/// A program building test images, with the package providing it.
+struct Tool {
+    name: &'static str,
+    package: &'static str,
+}
+
+const MKFS_FAT: Tool = Tool {
+    name: "mkfs.fat",
+    package: "dosfstools",
+};
+const MKE2FS: Tool = Tool {
+    name: "mke2fs",
+    package: "e2fsprogs",
+};
+const DEBUGFS: Tool = Tool {
+    name: "debugfs",
+    package: "e2fsprogs",
+};
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=initrd");
+
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let disk_dir = manifest_dir.join("target/disk");
+    // Run again to rebuild images that were deleted.
+    for image in DISK_IMAGES {
+        println!("cargo:rerun-if-changed={}", disk_dir.join(image).display());
+    }
+    write_test_disks(&disk_dir);
+    write_fat_images(&disk_dir);
+    write_ext2_images(&disk_dir, &out_dir.join("ext2"));
+    fs::write(
+        out_dir.join("initrd.tar"),
+        ustar(&manifest_dir.join("initrd")),
+    )
+    .expect("failed to write initrd");
+}
+
+fn write_test_disks(dir: &Path) {
+    // Sector `n` starts with `n` as a little-endian u64 followed by a byte
+    // pattern, so tests can tell sectors apart.
+    let mut image = Vec::with_capacity(TEST_DISK_SECTORS as usize * 512);
+    for sector in 0..TEST_DISK_SECTORS {
+        image.extend_from_slice(&sector.to_le_bytes());
+        image.extend((8..512).map(|i| (i as u64 ^ sector) as u8));
+    }
+
+    fs::create_dir_all(dir).expect("failed to create disk image directory");
+    // Each device gets its own image, as QEMU locks them. Tests write to
+    // them, so they are put back unless unchanged.
+    for name in ["virtio.img", "ata.img"] {
+        let path = dir.join(name);
+        if fs::read(&path).ok().as_deref() != Some(&image[..]) {
+            fs::write(path, &image).expect("failed to write test disk image");
+        }
+    }
+}
+
+/// Formats an empty image of each FAT type with `mkfs.fat`, for the FAT
+/// tests. QEMU attaches them with `snapshot=on`, so they stay empty.
+fn write_fat_images(dir: &Path) {
+    // Sizes in KiB and sectors per cluster that give each image the cluster
+    // count of its type.
+    for (fat_type, size, cluster_sectors) in [(12, 2048, 4), (16, 16384, 4), (32, 40960, 1)] {
+        let image = dir.join(format!("fat{}.img", fat_type));
+        let commands = |path: &Path| {
+            let args = vec![
+                "-F".into(),
+                fat_type.to_string(),
+                "-s".into(),
+                cluster_sectors.to_string(),
+                "-n".into(),
+                format!("FAT{}", fat_type),
+                "-i".into(),
+                "12345678".into(),
+                "-C".into(),
+                path.display().to_string(),
+                size.to_string(),
+            ];
+            vec![(&MKFS_FAT, args)]
+        };
+        build_image(&image, size, commands, 0);
+    }
+}
+
+/// Formats an ext2 image with 1 KiB and one with 4 KiB blocks with
+/// `mke2fs`, both holding the same files, for the ext2 tests.
+///
+/// The symlinks are made by `debugfs` in the image, as not every host can
+/// make them.
+fn write_ext2_images(dir: &Path, contents: &Path) {
+    // `big.bin` needs double indirect blocks with 1 KiB blocks, and `slow` is
+    // too long to be stored in its inode.
+    let _ = fs::remove_dir_all(contents);
+    fs::create_dir_all(contents.join("docs/nested")).expect("failed to create ext2 contents");
+    fs::write(contents.join("hello.txt"), "Hello from the host!\n").unwrap();
+    fs::hard_link(contents.join("hello.txt"), contents.join("docs/hello.txt")).unwrap();
+    fs::write(contents.join("docs/nested/deep.txt"), "deep").unwrap();
+    let big: Vec<u8> = (0..300_000u32).map(|i| (i % 253) as u8).collect();
+    fs::write(contents.join("big.bin"), big).unwrap();
+    let symlinks = [
+        ("fast", String::from("hello.txt")),
+        ("slow", format!("{}hello.txt", "./".repeat(40))),
+    ];
+
+    let inputs = hash_tree(contents);
+    for (block_size, size) in [(1024, 8192), (4096, 16384)] {
+        let image = dir.join(format!("ext2-{}k.img", block_size / 1024));
+        let commands = |path: &Path| {
+            let args = vec![
+                "-q".into(),
+                "-F".into(),
+                "-t".into(),
+                "ext2".into(),
+                "-b".into(),
+                block_size.to_string(),
+                "-L".into(),
+                "rustos".into(),
+                "-d".into(),
+                contents.display().to_string(),
+                path.display().to_string(),
+                format!("{}k", size),
+            ];
+            let mut commands = vec![(&MKE2FS, args)];
+            for (name, target) in &symlinks {
+                let args = vec![
+                    "-w".into(),
+                    "-R".into(),
+                    format!("symlink {} {}", name, target),
+                    path.display().to_string(),
+                ];
+                commands.push((&DEBUGFS, args));
+            }
+            commands
+        };
+        build_image(&image, size, commands, inputs);
+    }
+}
+
+/// Builds `image` of `size` KiB with the commands `commands` returns for
+/// the path to build it at, unless the stamp file next to it shows it was
+/// built by the same commands from the same `inputs` already.
+///
+/// If a tool is missing or fails, this warns and leaves a blank image, so
+/// that QEMU still starts and only the tests of that filesystem fail.
+fn build_image<'a>(
+    image: &Path,
+    size: u64,
+    commands: impl Fn(&Path) -> Vec<(&'a Tool, Vec<String>)>,
+    inputs: u64,
+) {
+    let stamp_path = image.with_extension("stamp");
+    let mut stamp = format!("{:016x}\n", inputs);
+    for (tool, args) in commands(image) {
+        stamp += &format!("{} {}\n", tool.name, args.join(" "));
+    }
+    let built = fs::read_to_string(&stamp_path).ok();
+    if image.exists() && built.as_ref() == Some(&stamp) {
+        return;
+    }
+
+    // Built next to the image, so that a failure leaves the old one alone.
+    let path = image.with_extension("new");
+    let _ = fs::remove_file(&path);
+    let result = commands(&path)
+        .iter()
+        .try_for_each(|(tool, args)| run(tool, args));
+    match result {
+        Ok(()) => {
+            fs::rename(&path, image).expect("failed to move disk image");
+            fs::write(stamp_path, stamp).expect("failed to write image stamp");
+        }
+        Err(err) => {
+            let _ = fs::remove_file(&path);
+            println!(
+                "cargo:warning={}; the tests using {} will fail",
+                err,
+                image.file_name().unwrap().to_string_lossy()
+            );
+            // A blank image has no stamp, so it is only written once.
+            if !image.exists() || built.is_some() {
+                let _ = fs::remove_file(&stamp_path);
+                fs::File::create(image)
+                    .and_then(|file| file.set_len(size * 1024))
+                    .expect("failed to write blank disk image");
+            }
+        }
+    }
+}
+
+/// Runs `tool` with `args`, looking for it in the `sbin` directories too.
+fn run(tool: &Tool, args: &[String]) -> Result<(), String> {
+    let output = ["", "/sbin/", "/usr/sbin/"].iter().find_map(|dir| {
+        Command::new(format!("{}{}", dir, tool.name))
+            .args(args)
+            .output()
+            .ok()
+    });
+    match output {
+        Some(output) if output.status.success() => Ok(()),
+        Some(output) => Err(format!(
+            "{} failed: {}",
+            tool.name,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )),
+        None => Err(format!("{} not found, install {}", tool.name, tool.package)),
+    }
+}
+
+/// Returns a hash of the names, link targets and contents of the files under
+/// `root`.
+fn hash_tree(root: &Path) -> u64 {
+    fn add(hasher: &mut DefaultHasher, dir: &Path) {
+        let mut entries: Vec<_> = fs::read_dir(dir)
+            .expect("failed to read image contents")
+            .map(|entry| entry.expect("failed to read image contents").path())
+            .collect();
+        entries.sort();
+        for path in entries {
+            path.file_name().hash(hasher);
+            let metadata = fs::symlink_metadata(&path).expect("failed to stat image contents");
+            if metadata.file_type().is_symlink() {
+                fs::read_link(&path).unwrap().hash(hasher);
+            } else if metadata.is_dir() {
+                add(hasher, &path);
+            } else {
+                fs::read(&path).unwrap().hash(hasher);
+            }
+        }
+    }
+
+    let mut hasher = DefaultHasher::new();
+    add(&mut hasher, root);
+    hasher.finish()
+}
+
+/// Returns a ustar archive of the contents of `root`.
+fn ustar(root: &Path) -> Vec<u8> {
+    let mut archive = Vec::new();
+    if root.exists() {
+        add_directory(&mut archive, root, "");
+    }
+    archive.resize(archive.len() + 1024, 0);
+    archive
+}
+
+fn add_directory(archive: &mut Vec<u8>, dir: &Path, prefix: &str) {
+    let mut entries: Vec<_> = fs::read_dir(dir)
+        .expect("failed to read initrd directory")
+        .map(|entry| entry.expect("failed to read initrd directory").path())
+        .collect();
+    entries.sort();
+
+    for path in entries {
+        let name = format!("{}{}", prefix, path.file_name().unwrap().to_str().unwrap());
+        let metadata = fs::symlink_metadata(&path).expect("failed to stat initrd file");
+        if metadata.file_type().is_symlink() {
+            let target = fs::read_link(&path).unwrap();
+            add_entry(archive, &name, b'2', 0o777, &[], target.to_str().unwrap());
+        } else if metadata.is_dir() {
+            add_entry(archive, &format!("{}/", name), b'5', 0o755, &[], "");
+            add_directory(archive, &path, &format!("{}/", name));
+        } else {
+            let data = fs::read(&path).expect("failed to read initrd file");
+            add_entry(archive, &name, b'0', 0o644, &data, "");
+        }
+    }
+}
+
+fn add_entry(archive: &mut Vec<u8>, name: &str, kind: u8, mode: u32, data: &[u8], link: &str) {
+    assert!(name.len() <= 100, "initrd path too long: {}", name);
+    assert!(
+        link.len() <= 100,
+        "initrd symlink target too long: {}",
+        link
+    );
+
+    let mut header = [0u8; 512];
+    let mut field = |offset: usize, value: &[u8]| {
+        header[offset..offset + value.len()].copy_from_slice(value);
+    };
+    field(0, name.as_bytes());
+    field(100, format!("{:07o}\0", mode).as_bytes());
+    field(108, b"0000000\0");
+    field(116, b"0000000\0");
+    field(124, format!("{:011o}\0", data.len()).as_bytes());
+    field(136, b"00000000000\0");
+    field(148, b"        ");
+    field(156, &[kind]);
+    field(157, link.as_bytes());
+    field(257, b"ustar\0");
+    field(263, b"00");
+    let checksum: u32 = header.iter().map(|&b| u32::from(b)).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+
+    archive.extend_from_slice(&header);
+    archive.extend_from_slice(data);
+    archive.resize(archive.len() + (512 - archive.len() % 512) % 512, 0);
+}
@@ -0,0 +1,98 @@
+//! Just enough AML to read the sleep type packages (`\_Sx`) out of the DSDT
+//! and SSDTs without an interpreter.
+
+const NAME_OP: u8 = 0x08;
+const ROOT_CHAR: u8 = b'\\';
+const PACKAGE_OP: u8 = 0x12;
+const ZERO_OP: u8 = 0x00;
+const ONE_OP: u8 = 0x01;
+const BYTE_PREFIX: u8 = 0x0a;
+const WORD_PREFIX: u8 = 0x0b;
+const DWORD_PREFIX: u8 = 0x0c;
+
+/// Sleep type values to write to the PM1a and PM1b control registers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SleepType {
+    pub a: u8,
+    pub b: u8,
+}
+
+/// Looks for a `Name(\_Sx, Package() { a, b, ... })` definition of the sleep
+/// state `state` in the AML byte code `aml`.
+///
+/// Definitions built by methods or conditionally are not found.
+pub fn sleep_type(aml: &[u8], state: u8) -> Option<SleepType> {
+    let name = [b'_', b'S', b'0' + state, b'_'];
+    aml.windows(name.len())
+        .enumerate()
+        .filter(|&(_, window)| window == name)
+        .find_map(|(at, _)| {
+            if matches!(aml[..at], [.., NAME_OP] | [.., NAME_OP, ROOT_CHAR]) {
+                parse_package(aml.get(at + name.len()..)?)
+            } else {
+                None
+            }
+        })
+}
+
+fn parse_package(aml: &[u8]) -> Option<SleepType> {
+    let (&op, rest) = aml.split_first()?;
+    if op != PACKAGE_OP {
+        return None;
+    }
+    // PkgLength: the top two bits of the lead byte give the number of bytes
+    // that follow it.
+    let pkg_length_bytes = 1 + usize::from(rest.first()? >> 6);
+    // Skip NumElements as well.
+    let elements = rest.get(pkg_length_bytes + 1..)?;
+
+    let (a, elements) = parse_integer(elements)?;
+    let (b, _) = parse_integer(elements)?;
+    Some(SleepType { a, b })
+}
+
+/// Parses an integer constant, returning its low byte and the remaining input.
+fn parse_integer(aml: &[u8]) -> Option<(u8, &[u8])> {
+    let (&op, rest) = aml.split_first()?;
+    let width = match op {
+        ZERO_OP => return Some((0, rest)),
+        ONE_OP => return Some((1, rest)),
+        BYTE_PREFIX => 1,
+        WORD_PREFIX => 2,
+        DWORD_PREFIX => 4,
+        _ => return None,
+    };
+    let value = *rest.first()?;
+    Some((value, rest.get(width..)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn finds_sleep_types() {
+        // Name (\_S5, Package (0x04) { 0x05, Zero, Zero, Zero })
+        let s5 = [
+            0x08, b'\\', b'_', b'S', b'5', b'_', 0x12, 0x0a, 0x04, 0x0a, 0x05, 0x00, 0x00, 0x00,
+        ];
+        // Name (_S3, Package (0x02) { One, 0x0b 0x0102 })
+        let s3 = [
+            0x10, 0x08, b'_', b'S', b'3', b'_', 0x12, 0x07, 0x02, 0x01, 0x0b, 0x02, 0x01,
+        ];
+        let mut aml = [0u8; 32];
+        aml[..s3.len()].copy_from_slice(&s3);
+        aml[s3.len()..s3.len() + s5.len()].copy_from_slice(&s5);
+
+        assert_eq!(sleep_type(&aml, 5), Some(SleepType { a: 5, b: 0 }));
+        assert_eq!(sleep_type(&aml, 3), Some(SleepType { a: 1, b: 2 }));
+        assert_eq!(sleep_type(&aml, 4), None);
+    }
+
+    #[test_case]
+    fn ignores_references() {
+        // Store (\_S5_, Local0) is not a definition.
+        let aml = [0x70, b'\\', b'_', b'S', b'5', b'_', 0x60];
+        assert_eq!(sleep_type(&aml, 5), None);
+    }
+}
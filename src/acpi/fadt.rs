@@ -0,0 +1,128 @@
+use core::fmt;
+use x86_64::PhysAddr;
+
+use super::{Bytes, GenericAddress};
+
+/// The Fixed ACPI Description Table.
+///
+/// Register blocks are taken from the 64-bit extended fields when present and
+/// fall back to the legacy I/O port fields otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fadt {
+    pub dsdt: PhysAddr,
+    /// ISA interrupt used for the system control interrupt.
+    pub sci_interrupt: u16,
+    /// Port used to hand ACPI control over from SMM, 0 if the system is
+    /// hardware reduced or always in ACPI mode.
+    pub smi_command: u32,
+    pub acpi_enable: u8,
+    pub acpi_disable: u8,
+    pub pm1a_event: Option<GenericAddress>,
+    pub pm1b_event: Option<GenericAddress>,
+    pub pm1a_control: Option<GenericAddress>,
+    pub pm1b_control: Option<GenericAddress>,
+    pub pm_timer: Option<GenericAddress>,
+    /// CMOS RAM index of the century register, 0 if not supported.
+    pub century: u8,
+    pub iapc_boot_arch: u16,
+    pub flags: u32,
+    /// Register that resets the system when `reset_value` is written to it.
+    pub reset_register: Option<GenericAddress>,
+    pub reset_value: u8,
+}
+
+/// `flags` bit indicating that `reset_register` is supported.
+pub const RESET_REG_SUP: u32 = 1 << 10;
+/// `flags` bit indicating a hardware reduced ACPI system.
+pub const HW_REDUCED_ACPI: u32 = 1 << 20;
+
+impl Fadt {
+    pub(super) fn parse(table: &[u8]) -> Self {
+        let bytes = Bytes(table);
+        let block = |x_offset, port_offset, len_offset| {
+            GenericAddress::parse(bytes, x_offset)
+                .or_else(|| GenericAddress::io(bytes.u32(port_offset), bytes.u8(len_offset)))
+        };
+
+        let dsdt = match bytes.u64(140) {
+            0 => bytes.u32(40).into(),
+            x_dsdt => x_dsdt,
+        };
+        let flags = bytes.u32(112);
+        Fadt {
+            dsdt: PhysAddr::new(dsdt),
+            sci_interrupt: bytes.u16(46),
+            smi_command: bytes.u32(48),
+            acpi_enable: bytes.u8(52),
+            acpi_disable: bytes.u8(53),
+            pm1a_event: block(148, 56, 88),
+            pm1b_event: block(160, 60, 88),
+            pm1a_control: block(172, 64, 89),
+            pm1b_control: block(184, 68, 89),
+            pm_timer: block(208, 76, 91),
+            century: bytes.u8(108),
+            iapc_boot_arch: bytes.u16(109),
+            flags,
+            reset_register: if flags & RESET_REG_SUP != 0 {
+                GenericAddress::parse(bytes, 116)
+            } else {
+                None
+            },
+            reset_value: bytes.u8(128),
+        }
+    }
+
+    pub(super) fn summary(&self, out: &mut dyn fmt::Write) -> fmt::Result {
+        writeln!(
+            out,
+            "FADT: DSDT at {:#x}, SCI IRQ {}, flags {:#x}",
+            self.dsdt.as_u64(),
+            self.sci_interrupt,
+            self.flags
+        )?;
+        if let Some(pm1a) = self.pm1a_control {
+            writeln!(out, "  PM1a control: {}", pm1a)?;
+        }
+        if let Some(pm1b) = self.pm1b_control {
+            writeln!(out, "  PM1b control: {}", pm1b)?;
+        }
+        if let Some(timer) = self.pm_timer {
+            writeln!(out, "  PM timer: {}", timer)?;
+        }
+        if let Some(reset) = self.reset_register {
+            writeln!(out, "  reset: {} <- {:#x}", reset, self.reset_value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acpi::{tests::table, AddressSpace, SDT_HEADER_SIZE};
+    use alloc::vec;
+
+    #[test_case]
+    fn legacy_ports_without_extended_fields() {
+        // An ACPI 1.0 FADT ends right after the flags.
+        let mut body = vec![0; 116 - SDT_HEADER_SIZE];
+        let mut put = |offset: usize, value: &[u8]| {
+            let offset = offset - SDT_HEADER_SIZE;
+            body[offset..offset + value.len()].copy_from_slice(value);
+        };
+        put(40, &0x7fe_0040u32.to_le_bytes());
+        put(46, &9u16.to_le_bytes());
+        put(64, &0x604u32.to_le_bytes());
+        put(89, &[2]);
+
+        let fadt = Fadt::parse(&table(b"FACP", 1, &body));
+        assert_eq!(fadt.dsdt, PhysAddr::new(0x7fe_0040));
+        assert_eq!(fadt.sci_interrupt, 9);
+        let pm1a = fadt.pm1a_control.unwrap();
+        assert_eq!(pm1a.space, AddressSpace::SystemIo);
+        assert_eq!(pm1a.address, 0x604);
+        assert_eq!(pm1a.bit_width, 16);
+        assert_eq!(fadt.pm1b_control, None);
+        assert_eq!(fadt.reset_register, None);
+    }
+}
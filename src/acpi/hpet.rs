@@ -0,0 +1,48 @@
+use core::fmt;
+use x86_64::PhysAddr;
+
+use super::{Bytes, GenericAddress};
+
+/// The High Precision Event Timer description table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hpet {
+    pub hardware_revision: u8,
+    pub comparators: u8,
+    pub counter_64bit: bool,
+    pub legacy_replacement: bool,
+    pub pci_vendor_id: u16,
+    pub base_address: PhysAddr,
+    pub number: u8,
+    /// Minimum clock tick in periodic mode.
+    pub minimum_tick: u16,
+}
+
+impl Hpet {
+    pub(super) fn parse(table: &[u8]) -> Self {
+        let bytes = Bytes(table);
+        let id = bytes.u32(36);
+        let base = GenericAddress::parse(bytes, 40).map_or(0, |base| base.address);
+        Hpet {
+            hardware_revision: id as u8,
+            comparators: (id >> 8 & 0x1f) as u8 + 1,
+            counter_64bit: id & 1 << 13 != 0,
+            legacy_replacement: id & 1 << 15 != 0,
+            pci_vendor_id: (id >> 16) as u16,
+            base_address: PhysAddr::new(base),
+            number: bytes.u8(52),
+            minimum_tick: bytes.u16(53),
+        }
+    }
+
+    pub(super) fn summary(&self, out: &mut dyn fmt::Write) -> fmt::Result {
+        writeln!(
+            out,
+            "HPET {}: at {:#x}, {} comparators, {}-bit counter, vendor {:#06x}",
+            self.number,
+            self.base_address.as_u64(),
+            self.comparators,
+            if self.counter_64bit { 64 } else { 32 },
+            self.pci_vendor_id
+        )
+    }
+}
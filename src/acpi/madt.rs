@@ -0,0 +1,247 @@
+use alloc::vec::Vec;
+use core::fmt;
+use x86_64::PhysAddr;
+
+use super::Bytes;
+
+/// The Multiple APIC Description Table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Madt {
+    pub local_apic_address: PhysAddr,
+    /// Whether the system also has dual 8259 PICs that must be masked
+    /// before using the APICs.
+    pub pcat_compat: bool,
+    pub processors: Vec<Processor>,
+    pub io_apics: Vec<IoApic>,
+    pub overrides: Vec<InterruptSourceOverride>,
+    pub nmis: Vec<LocalApicNmi>,
+}
+
+/// A processor with a local APIC (or x2APIC).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Processor {
+    pub uid: u32,
+    pub apic_id: u32,
+    pub enabled: bool,
+    /// Whether a disabled processor can be brought online at runtime.
+    pub online_capable: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoApic {
+    pub id: u8,
+    pub address: PhysAddr,
+    /// First global system interrupt handled by this I/O APIC.
+    pub gsi_base: u32,
+}
+
+/// Mapping of an ISA interrupt to a global system interrupt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptSourceOverride {
+    pub source: u8,
+    pub gsi: u32,
+    pub polarity: Polarity,
+    pub trigger: TriggerMode,
+}
+
+/// The local APIC input connected to NMI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalApicNmi {
+    /// Processor UID, or `None` for all processors.
+    pub processor: Option<u32>,
+    pub lint: u8,
+    pub polarity: Polarity,
+    pub trigger: TriggerMode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Polarity {
+    /// Conforms to the bus specification (active high for ISA).
+    Conforming,
+    ActiveHigh,
+    ActiveLow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerMode {
+    /// Conforms to the bus specification (edge for ISA).
+    Conforming,
+    Edge,
+    Level,
+}
+
+impl Polarity {
+    fn from_flags(flags: u16) -> Self {
+        match flags & 0b11 {
+            0b01 => Polarity::ActiveHigh,
+            0b11 => Polarity::ActiveLow,
+            _ => Polarity::Conforming,
+        }
+    }
+}
+
+impl TriggerMode {
+    fn from_flags(flags: u16) -> Self {
+        match flags >> 2 & 0b11 {
+            0b01 => TriggerMode::Edge,
+            0b11 => TriggerMode::Level,
+            _ => TriggerMode::Conforming,
+        }
+    }
+}
+
+impl Madt {
+    pub(super) fn parse(table: &[u8]) -> Self {
+        let bytes = Bytes(table);
+        let mut madt = Madt {
+            local_apic_address: PhysAddr::new(bytes.u32(36).into()),
+            pcat_compat: bytes.u32(40) & 1 != 0,
+            processors: Vec::new(),
+            io_apics: Vec::new(),
+            overrides: Vec::new(),
+            nmis: Vec::new(),
+        };
+
+        let mut offset = 44;
+        while offset + 2 <= table.len() {
+            let len = usize::from(bytes.u8(offset + 1));
+            if len < 2 || offset + len > table.len() {
+                break;
+            }
+            let entry = Bytes(&table[offset..offset + len]);
+            madt.parse_entry(bytes.u8(offset), entry);
+            offset += len;
+        }
+        madt
+    }
+
+    fn parse_entry(&mut self, kind: u8, entry: Bytes) {
+        match kind {
+            0 => self.processors.push(Processor {
+                uid: entry.u8(2).into(),
+                apic_id: entry.u8(3).into(),
+                enabled: entry.u32(4) & 1 != 0,
+                online_capable: entry.u32(4) & 2 != 0,
+            }),
+            1 => self.io_apics.push(IoApic {
+                id: entry.u8(2),
+                address: PhysAddr::new(entry.u32(4).into()),
+                gsi_base: entry.u32(8),
+            }),
+            2 => self.overrides.push(InterruptSourceOverride {
+                source: entry.u8(3),
+                gsi: entry.u32(4),
+                polarity: Polarity::from_flags(entry.u16(8)),
+                trigger: TriggerMode::from_flags(entry.u16(8)),
+            }),
+            4 => self.nmis.push(LocalApicNmi {
+                processor: match entry.u8(2) {
+                    0xff => None,
+                    uid => Some(uid.into()),
+                },
+                lint: entry.u8(5),
+                polarity: Polarity::from_flags(entry.u16(3)),
+                trigger: TriggerMode::from_flags(entry.u16(3)),
+            }),
+            5 => self.local_apic_address = PhysAddr::new(entry.u64(4)),
+            9 => self.processors.push(Processor {
+                uid: entry.u32(12),
+                apic_id: entry.u32(4),
+                enabled: entry.u32(8) & 1 != 0,
+                online_capable: entry.u32(8) & 2 != 0,
+            }),
+            0xa => self.nmis.push(LocalApicNmi {
+                processor: match entry.u32(4) {
+                    u32::MAX => None,
+                    uid => Some(uid),
+                },
+                lint: entry.u8(8),
+                polarity: Polarity::from_flags(entry.u16(2)),
+                trigger: TriggerMode::from_flags(entry.u16(2)),
+            }),
+            _ => {}
+        }
+    }
+
+    /// Returns the global system interrupt that ISA `irq` is connected to,
+    /// taking interrupt source overrides into account.
+    pub fn isa_gsi(&self, irq: u8) -> u32 {
+        self.overrides
+            .iter()
+            .find(|o| o.source == irq)
+            .map_or(irq.into(), |o| o.gsi)
+    }
+
+    pub(super) fn summary(&self, out: &mut dyn fmt::Write) -> fmt::Result {
+        let enabled = self.processors.iter().filter(|p| p.enabled).count();
+        writeln!(
+            out,
+            "MADT: local APIC at {:#x}, {} processors ({} enabled)",
+            self.local_apic_address.as_u64(),
+            self.processors.len(),
+            enabled
+        )?;
+        for cpu in &self.processors {
+            writeln!(
+                out,
+                "  CPU {}: APIC ID {}{}",
+                cpu.uid,
+                cpu.apic_id,
+                if cpu.enabled { "" } else { " (disabled)" }
+            )?;
+        }
+        for io_apic in &self.io_apics {
+            writeln!(
+                out,
+                "  I/O APIC {} at {:#x}, GSI base {}",
+                io_apic.id,
+                io_apic.address.as_u64(),
+                io_apic.gsi_base
+            )?;
+        }
+        for o in &self.overrides {
+            writeln!(
+                out,
+                "  IRQ {} -> GSI {} ({:?}, {:?})",
+                o.source, o.gsi, o.polarity, o.trigger
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acpi::tests::table;
+
+    #[test_case]
+    fn parse_entries() {
+        let mut body = Vec::new();
+        body.extend_from_slice(&0xfee0_0000u32.to_le_bytes());
+        body.extend_from_slice(&1u32.to_le_bytes());
+        // Processor local APIC, UID 0, APIC ID 0, enabled.
+        body.extend_from_slice(&[0, 8, 0, 0, 1, 0, 0, 0]);
+        // Processor local APIC, UID 1, APIC ID 2, online capable.
+        body.extend_from_slice(&[0, 8, 1, 2, 2, 0, 0, 0]);
+        // I/O APIC 0 at 0xfec00000, GSI base 0.
+        body.extend_from_slice(&[1, 12, 0, 0, 0, 0, 0xc0, 0xfe, 0, 0, 0, 0]);
+        // IRQ 0 -> GSI 2, conforming.
+        body.extend_from_slice(&[2, 10, 0, 0, 2, 0, 0, 0, 0, 0]);
+        // IRQ 9 -> GSI 9, active high, level triggered.
+        body.extend_from_slice(&[2, 10, 0, 9, 9, 0, 0, 0, 0x0d, 0]);
+
+        let madt = Madt::parse(&table(b"APIC", 3, &body));
+        assert_eq!(madt.local_apic_address, PhysAddr::new(0xfee0_0000));
+        assert!(madt.pcat_compat);
+        assert_eq!(madt.processors.len(), 2);
+        assert!(madt.processors[0].enabled);
+        assert_eq!(madt.processors[1].apic_id, 2);
+        assert!(!madt.processors[1].enabled && madt.processors[1].online_capable);
+        assert_eq!(madt.io_apics[0].address, PhysAddr::new(0xfec0_0000));
+        assert_eq!(madt.isa_gsi(0), 2);
+        assert_eq!(madt.isa_gsi(1), 1);
+        assert_eq!(madt.overrides[1].polarity, Polarity::ActiveHigh);
+        assert_eq!(madt.overrides[1].trigger, TriggerMode::Level);
+    }
+}
@@ -0,0 +1,67 @@
+use alloc::vec::Vec;
+use core::fmt;
+use x86_64::PhysAddr;
+
+use super::Bytes;
+
+/// The PCI Express memory mapped configuration space table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mcfg {
+    pub entries: Vec<McfgEntry>,
+}
+
+/// An enhanced configuration access region covering a range of buses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct McfgEntry {
+    pub base_address: PhysAddr,
+    pub segment: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+impl McfgEntry {
+    /// Returns the physical address of the configuration space of a function.
+    pub fn function_address(&self, bus: u8, device: u8, function: u8) -> Option<PhysAddr> {
+        if !(self.start_bus..=self.end_bus).contains(&bus) {
+            return None;
+        }
+        let offset = u64::from(bus - self.start_bus) << 20
+            | u64::from(device) << 15
+            | u64::from(function) << 12;
+        Some(self.base_address + offset)
+    }
+}
+
+impl Mcfg {
+    pub(super) fn parse(table: &[u8]) -> Self {
+        let entries = table
+            .get(44..)
+            .unwrap_or_default()
+            .chunks_exact(16)
+            .map(|entry| {
+                let entry = Bytes(entry);
+                McfgEntry {
+                    base_address: PhysAddr::new(entry.u64(0)),
+                    segment: entry.u16(8),
+                    start_bus: entry.u8(10),
+                    end_bus: entry.u8(11),
+                }
+            })
+            .collect();
+        Mcfg { entries }
+    }
+
+    pub(super) fn summary(&self, out: &mut dyn fmt::Write) -> fmt::Result {
+        for entry in &self.entries {
+            writeln!(
+                out,
+                "MCFG: segment {} buses {}-{} at {:#x}",
+                entry.segment,
+                entry.start_bus,
+                entry.end_bus,
+                entry.base_address.as_u64()
+            )?;
+        }
+        Ok(())
+    }
+}
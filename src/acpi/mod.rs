@@ -0,0 +1,436 @@
+pub mod aml;
+pub mod fadt;
+pub mod hpet;
+pub mod madt;
+pub mod mcfg;
+
+use alloc::vec::Vec;
+use core::{fmt, slice, str};
+use spin::Once;
+use x86_64::{instructions::port::Port, PhysAddr};
+
+use crate::memory::phys_to_virt;
+
+use self::{aml::SleepType, fadt::Fadt, hpet::Hpet, madt::Madt, mcfg::Mcfg};
+
+static ACPI: Once<Acpi> = Once::new();
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+const SDT_HEADER_SIZE: usize = 36;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcpiError {
+    /// No valid RSDP was found in the BIOS areas.
+    RsdpNotFound,
+    /// The table with the given signature failed checksum validation.
+    InvalidChecksum([u8; 4]),
+    /// The table at the given address has an unexpected signature.
+    InvalidSignature(PhysAddr),
+    /// A register lives in an address space we can't access.
+    UnsupportedAddressSpace(AddressSpace),
+}
+
+/// Header shared by every system description table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+}
+
+/// The platform's ACPI tables, as found at boot.
+#[derive(Debug)]
+pub struct Acpi {
+    pub revision: u8,
+    pub oem_id: [u8; 6],
+    /// Physical address and header of every table listed in the RSDT/XSDT.
+    pub tables: Vec<(PhysAddr, SdtHeader)>,
+    pub madt: Option<Madt>,
+    pub fadt: Option<Fadt>,
+    pub hpet: Option<Hpet>,
+    pub mcfg: Option<Mcfg>,
+}
+
+impl Acpi {
+    /// Returns the physical address of the first table with `signature`.
+    pub fn find_table(&self, signature: &[u8; 4]) -> Option<PhysAddr> {
+        self.tables
+            .iter()
+            .find(|(_, header)| &header.signature == signature)
+            .map(|&(addr, _)| addr)
+    }
+
+    /// Returns the sleep type values for sleep state `state` (e.g. 5 for soft
+    /// off), looking through the DSDT and then the SSDTs.
+    pub fn sleep_type(&self, state: u8) -> Option<SleepType> {
+        let dsdt = self.fadt.map(|fadt| fadt.dsdt);
+        let ssdts = self
+            .tables
+            .iter()
+            .filter(|(_, header)| &header.signature == b"SSDT")
+            .map(|&(addr, _)| addr);
+
+        dsdt.into_iter()
+            .chain(ssdts)
+            .filter_map(|addr| table_bytes(addr).ok())
+            .find_map(|table| aml::sleep_type(&table[SDT_HEADER_SIZE..], state))
+    }
+
+    /// Writes a summary of the tables to `out`.
+    pub fn summary(&self, out: &mut dyn fmt::Write) -> fmt::Result {
+        writeln!(
+            out,
+            "ACPI revision {} (OEM {:?})",
+            self.revision,
+            as_str(&self.oem_id)
+        )?;
+        for (addr, header) in &self.tables {
+            writeln!(
+                out,
+                "  {} at {:#x}, {} bytes, revision {} ({:?})",
+                as_str(&header.signature),
+                addr.as_u64(),
+                header.length,
+                header.revision,
+                as_str(&header.oem_table_id)
+            )?;
+        }
+
+        if let Some(madt) = &self.madt {
+            madt.summary(out)?;
+        }
+        if let Some(fadt) = &self.fadt {
+            fadt.summary(out)?;
+        }
+        if let Some(hpet) = &self.hpet {
+            hpet.summary(out)?;
+        }
+        if let Some(mcfg) = &self.mcfg {
+            mcfg.summary(out)?;
+        }
+        Ok(())
+    }
+}
+
+/// Finds and parses the ACPI tables.
+///
+/// Relies on the complete physical memory being mapped, so it must be called
+/// after [`crate::memory::init`]. Calling it again returns the tables found
+/// the first time.
+pub fn init() -> Result<&'static Acpi, AcpiError> {
+    if let Some(acpi) = ACPI.get() {
+        return Ok(acpi);
+    }
+
+    let acpi = parse()?;
+    Ok(ACPI.call_once(|| acpi))
+}
+
+/// Returns the ACPI tables, if [`init`] succeeded.
+pub fn get() -> Option<&'static Acpi> {
+    ACPI.get()
+}
+
+fn parse() -> Result<Acpi, AcpiError> {
+    let rsdp_addr = find_rsdp().ok_or(AcpiError::RsdpNotFound)?;
+    let rsdp = Bytes(unsafe { phys_bytes(rsdp_addr, 36) });
+    let revision = rsdp.u8(15);
+    let mut oem_id = [0; 6];
+    oem_id.copy_from_slice(rsdp.slice(9, 6));
+
+    // ACPI 2.0+ provides a 64-bit XSDT which takes precedence over the RSDT.
+    let (root, entry_size) = match (revision, rsdp.u64(24)) {
+        (revision, xsdt) if revision >= 2 && xsdt != 0 => (PhysAddr::new(xsdt), 8),
+        _ => (PhysAddr::new(rsdp.u32(16).into()), 4),
+    };
+    let root_table = Bytes(table_bytes(root)?);
+    let root_signature = if entry_size == 8 { b"XSDT" } else { b"RSDT" };
+    if &root_table.header().signature != root_signature {
+        return Err(AcpiError::InvalidSignature(root));
+    }
+
+    let mut tables = Vec::new();
+    for offset in (SDT_HEADER_SIZE..root_table.0.len()).step_by(entry_size) {
+        let addr = match entry_size {
+            8 => root_table.u64(offset),
+            _ => root_table.u32(offset).into(),
+        };
+        let addr = PhysAddr::new(addr);
+        let table = Bytes(table_bytes(addr)?);
+        tables.push((addr, table.header()));
+    }
+
+    let mut acpi = Acpi {
+        revision,
+        oem_id,
+        tables,
+        madt: None,
+        fadt: None,
+        hpet: None,
+        mcfg: None,
+    };
+    acpi.madt = acpi
+        .find_table(b"APIC")
+        .map(|addr| table_bytes(addr).map(Madt::parse))
+        .transpose()?;
+    acpi.fadt = acpi
+        .find_table(b"FACP")
+        .map(|addr| table_bytes(addr).map(Fadt::parse))
+        .transpose()?;
+    acpi.hpet = acpi
+        .find_table(b"HPET")
+        .map(|addr| table_bytes(addr).map(Hpet::parse))
+        .transpose()?;
+    acpi.mcfg = acpi
+        .find_table(b"MCFG")
+        .map(|addr| table_bytes(addr).map(Mcfg::parse))
+        .transpose()?;
+    Ok(acpi)
+}
+
+/// Searches the first KiB of the EBDA and the BIOS read-only area between
+/// 0xE0000 and 0xFFFFF for a valid RSDP.
+fn find_rsdp() -> Option<PhysAddr> {
+    let ebda = u64::from(unsafe { *phys_to_virt(PhysAddr::new(0x40e)).as_ptr::<u16>() }) << 4;
+    let areas = [(ebda, 1024), (0xe0000, 0x20000)];
+
+    areas
+        .into_iter()
+        .filter(|&(start, _)| start != 0)
+        .flat_map(|(start, len)| (start..start + len).step_by(16))
+        .map(PhysAddr::new)
+        .find(|&addr| {
+            let candidate = unsafe { phys_bytes(addr, 36) };
+            if &candidate[..8] != RSDP_SIGNATURE || !checksum_ok(&candidate[..20]) {
+                return false;
+            }
+            // The extended checksum covers the whole ACPI 2.0+ structure.
+            let length = Bytes(candidate).u32(20) as usize;
+            candidate[15] < 2 || (length >= 36 && checksum_ok(unsafe { phys_bytes(addr, length) }))
+        })
+}
+
+/// Returns the bytes of the table at `addr` after validating its checksum.
+fn table_bytes(addr: PhysAddr) -> Result<&'static [u8], AcpiError> {
+    let header = Bytes(unsafe { phys_bytes(addr, SDT_HEADER_SIZE) }).header();
+    let bytes = unsafe { phys_bytes(addr, header.length as usize) };
+    if checksum_ok(bytes) {
+        Ok(bytes)
+    } else {
+        Err(AcpiError::InvalidChecksum(header.signature))
+    }
+}
+
+/// # Safety
+///
+/// The range must be mapped physical memory that is not mutated for the
+/// remaining lifetime of the kernel.
+unsafe fn phys_bytes(addr: PhysAddr, len: usize) -> &'static [u8] {
+    slice::from_raw_parts(phys_to_virt(addr).as_ptr(), len)
+}
+
+fn checksum_ok(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0
+}
+
+fn as_str(bytes: &[u8]) -> &str {
+    str::from_utf8(bytes).unwrap_or("?").trim_end()
+}
+
+/// Little-endian accessor for table contents. Reads past the end of the table
+/// return zero, which matches how ACPI treats fields missing from older table
+/// revisions.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Bytes<'a>(pub &'a [u8]);
+
+impl<'a> Bytes<'a> {
+    pub fn u8(&self, offset: usize) -> u8 {
+        self.0.get(offset).copied().unwrap_or(0)
+    }
+
+    pub fn u16(&self, offset: usize) -> u16 {
+        u16::from_le_bytes([self.u8(offset), self.u8(offset + 1)])
+    }
+
+    pub fn u32(&self, offset: usize) -> u32 {
+        u32::from(self.u16(offset)) | u32::from(self.u16(offset + 2)) << 16
+    }
+
+    pub fn u64(&self, offset: usize) -> u64 {
+        u64::from(self.u32(offset)) | u64::from(self.u32(offset + 4)) << 32
+    }
+
+    pub fn slice(&self, offset: usize, len: usize) -> &'a [u8] {
+        let start = offset.min(self.0.len());
+        let end = (offset + len).min(self.0.len());
+        &self.0[start..end]
+    }
+
+    pub fn header(&self) -> SdtHeader {
+        let mut header = SdtHeader {
+            signature: [0; 4],
+            length: self.u32(4),
+            revision: self.u8(8),
+            oem_id: [0; 6],
+            oem_table_id: [0; 8],
+        };
+        header.signature.copy_from_slice(self.slice(0, 4));
+        header.oem_id.copy_from_slice(self.slice(10, 6));
+        header.oem_table_id.copy_from_slice(self.slice(16, 8));
+        header
+    }
+}
+
+/// Location of a register as described by the ACPI generic address structure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GenericAddress {
+    pub space: AddressSpace,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressSpace {
+    SystemMemory,
+    SystemIo,
+    PciConfig,
+    Other(u8),
+}
+
+impl GenericAddress {
+    pub(crate) fn parse(bytes: Bytes, offset: usize) -> Option<Self> {
+        let address = bytes.u64(offset + 4);
+        if address == 0 {
+            return None;
+        }
+        Some(Self {
+            space: match bytes.u8(offset) {
+                0 => AddressSpace::SystemMemory,
+                1 => AddressSpace::SystemIo,
+                2 => AddressSpace::PciConfig,
+                other => AddressSpace::Other(other),
+            },
+            bit_width: bytes.u8(offset + 1),
+            bit_offset: bytes.u8(offset + 2),
+            access_size: bytes.u8(offset + 3),
+            address,
+        })
+    }
+
+    /// Describes a legacy I/O port block of `len` bytes.
+    pub(crate) fn io(port: u32, len: u8) -> Option<Self> {
+        (port != 0).then(|| Self {
+            space: AddressSpace::SystemIo,
+            bit_width: len.saturating_mul(8),
+            bit_offset: 0,
+            access_size: 0,
+            address: port.into(),
+        })
+    }
+
+    /// Reads the register.
+    ///
+    /// Only system I/O space is supported for now.
+    ///
+    /// # Safety
+    ///
+    /// Reading a register may have side effects on the hardware.
+    pub unsafe fn read(&self) -> Result<u64, AcpiError> {
+        let port = self.port()?;
+        Ok(match self.access_width() {
+            1 => Port::<u8>::new(port).read().into(),
+            2 => Port::<u16>::new(port).read().into(),
+            _ => Port::<u32>::new(port).read().into(),
+        })
+    }
+
+    /// Writes `value` to the register.
+    ///
+    /// Only system I/O space is supported for now.
+    ///
+    /// # Safety
+    ///
+    /// Writing a register may have arbitrary effects on the hardware.
+    pub unsafe fn write(&self, value: u64) -> Result<(), AcpiError> {
+        let port = self.port()?;
+        match self.access_width() {
+            1 => Port::<u8>::new(port).write(value as u8),
+            2 => Port::<u16>::new(port).write(value as u16),
+            _ => Port::<u32>::new(port).write(value as u32),
+        }
+        Ok(())
+    }
+
+    fn port(&self) -> Result<u16, AcpiError> {
+        match self.space {
+            AddressSpace::SystemIo => Ok(self.address as u16),
+            space => Err(AcpiError::UnsupportedAddressSpace(space)),
+        }
+    }
+
+    /// Returns the access width in bytes.
+    fn access_width(&self) -> u8 {
+        match (self.access_size, self.bit_width) {
+            (1, _) | (0, 0..=8) => 1,
+            (2, _) | (0, 9..=16) => 2,
+            _ => 4,
+        }
+    }
+}
+
+impl fmt::Display for GenericAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.space {
+            AddressSpace::SystemMemory => write!(f, "mem {:#x}", self.address),
+            AddressSpace::SystemIo => write!(f, "port {:#x}", self.address),
+            AddressSpace::PciConfig => write!(f, "pci {:#x}", self.address),
+            AddressSpace::Other(space) => write!(f, "space {} {:#x}", space, self.address),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    /// Builds a table with a valid header and checksum around `body`.
+    pub fn table(signature: &[u8; 4], revision: u8, body: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(signature);
+        bytes.extend_from_slice(&((SDT_HEADER_SIZE + body.len()) as u32).to_le_bytes());
+        bytes.push(revision);
+        bytes.push(0);
+        bytes.extend_from_slice(b"RUSTOS");
+        bytes.extend_from_slice(b"TESTTABL");
+        bytes.extend_from_slice(&[0; 12]);
+        bytes.extend_from_slice(body);
+        let sum = bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+        bytes[9] = 0u8.wrapping_sub(sum);
+        bytes
+    }
+
+    #[test_case]
+    fn header_and_checksum() {
+        let bytes = table(b"TEST", 3, &[1, 2, 3]);
+        assert!(checksum_ok(&bytes));
+
+        let header = Bytes(&bytes).header();
+        assert_eq!(&header.signature, b"TEST");
+        assert_eq!(header.length as usize, SDT_HEADER_SIZE + 3);
+        assert_eq!(header.revision, 3);
+        assert_eq!(&header.oem_id, b"RUSTOS");
+    }
+
+    #[test_case]
+    fn reads_past_end_are_zero() {
+        let bytes = Bytes(&[0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(bytes.u32(0), 0x1234_5678);
+        assert_eq!(bytes.u32(2), 0x1234);
+        assert_eq!(bytes.u64(8), 0);
+    }
+}
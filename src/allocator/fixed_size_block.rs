@@ -49,6 +49,22 @@ impl FixedSizeBlockAllocator {
             .init(heap_start as *mut u8, heap_size);
     }
 
+    /// Returns the size of the heap and how many bytes of it are allocated.
+    ///
+    /// Blocks kept on the lists for reuse count as free.
+    pub fn stats(&self) -> (usize, usize) {
+        let mut cached = 0;
+        for (head, &size) in self.list_heads.iter().zip(BLOCK_SIZES) {
+            let mut node = head.as_deref();
+            while let Some(current) = node {
+                cached += size;
+                node = current.next.as_deref();
+            }
+        }
+        let size = self.fallback_allocator.size();
+        (size, self.fallback_allocator.used().saturating_sub(cached))
+    }
+
     fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
         match self.fallback_allocator.allocate_first_fit(layout) {
             Ok(ptr) => ptr.as_ptr(),
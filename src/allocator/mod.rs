@@ -12,6 +12,8 @@ use x86_64::{
     VirtAddr,
 };
 
+use crate::memory;
+
 // use self::bump::BumpAllocator;
 // use self::linked_list::LinkedListAllocator;
 use self::fixed_size_block::FixedSizeBlockAllocator;
@@ -27,7 +29,7 @@ fn alloc_error_handler(layout: Layout) -> ! {
 }
 
 pub const HEAP_START: usize = 0x4444_4444_0000;
-pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
+pub const HEAP_SIZE: usize = 1024 * 1024; // 1 MiB
 
 pub struct Locked<T> {
     inner: Mutex<T>,
@@ -69,14 +71,12 @@ pub fn init_heap(
         Page::range_inclusive(heap_start_page, heap_end_page)
     };
 
-    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
     for page in page_range {
         let frame = frame_allocator
             .allocate_frame()
             .ok_or(MapToError::FrameAllocationFailed)?;
-        unsafe {
-            mapper.map_to(page, frame, flags, frame_allocator)?.flush();
-        }
+        unsafe { memory::map_page(mapper, page, frame, flags, frame_allocator)? };
     }
 
     unsafe {
@@ -86,6 +86,11 @@ pub fn init_heap(
     Ok(())
 }
 
+/// Returns the size of the heap and how many bytes of it are allocated.
+pub fn stats() -> (usize, usize) {
+    ALLOCATOR.lock().stats()
+}
+
 /// Align address downwards.
 ///
 /// Returns the greatest x with alignment `align` so that `x <= addr`.
@@ -0,0 +1,97 @@
+//! The local APIC, which receives message signaled interrupts.
+//!
+//! Legacy interrupts keep going through the 8259 PICs: the local APIC is left
+//! in virtual wire mode, with the PICs connected to LINT0.
+
+use spin::Once;
+use x86_64::{
+    registers::model_specific::Msr,
+    structures::paging::{mapper::MapToError, Size4KiB},
+    PhysAddr, VirtAddr,
+};
+
+use crate::{
+    acpi,
+    cpu::{self, Feature},
+    memory::mmio,
+};
+
+/// Vector of the spurious interrupts the local APIC raises.
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+
+const IA32_APIC_BASE: u32 = 0x1b;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+
+const REG_ID: usize = 0x20;
+const REG_EOI: usize = 0xb0;
+const REG_SPURIOUS: usize = 0xf0;
+const SPURIOUS_APIC_ENABLE: u32 = 1 << 8;
+
+static LOCAL_APIC: Once<VirtAddr> = Once::new();
+
+#[derive(Debug)]
+pub enum ApicError {
+    Unsupported,
+    Map(MapToError<Size4KiB>),
+}
+
+/// Maps and software-enables the local APIC of the current CPU.
+///
+/// The address is taken from the MADT if available, so [`acpi::init`] should
+/// be called first.
+pub fn init() -> Result<(), ApicError> {
+    if LOCAL_APIC.is_completed() {
+        return Ok(());
+    }
+    if !cpu::has(Feature::Apic) {
+        return Err(ApicError::Unsupported);
+    }
+
+    let mut base_msr = Msr::new(IA32_APIC_BASE);
+    let base = unsafe { base_msr.read() };
+    if base & APIC_BASE_ENABLE == 0 {
+        unsafe { base_msr.write(base | APIC_BASE_ENABLE) };
+    }
+    let phys = match acpi::get().and_then(|acpi| acpi.madt.as_ref()) {
+        Some(madt) => madt.local_apic_address,
+        None => PhysAddr::new(base & 0x000f_ffff_ffff_f000),
+    };
+
+    let virt = mmio::map(phys, 4096).map_err(ApicError::Map)?;
+    LOCAL_APIC.call_once(|| virt);
+    unsafe {
+        write(
+            REG_SPURIOUS,
+            SPURIOUS_APIC_ENABLE | u32::from(SPURIOUS_VECTOR),
+        )
+    };
+    Ok(())
+}
+
+/// Returns whether [`init`] succeeded.
+pub fn is_enabled() -> bool {
+    LOCAL_APIC.is_completed()
+}
+
+/// Returns the APIC ID of the current CPU.
+pub fn id() -> u8 {
+    (unsafe { read(REG_ID) } >> 24) as u8
+}
+
+/// Signals the end of the interrupt currently being handled.
+pub fn end_of_interrupt() {
+    unsafe { write(REG_EOI, 0) };
+}
+
+fn register(offset: usize) -> *mut u32 {
+    let base = LOCAL_APIC.get().expect("local APIC uninitialized");
+    (*base + offset).as_mut_ptr()
+}
+
+unsafe fn read(offset: usize) -> u32 {
+    register(offset).read_volatile()
+}
+
+unsafe fn write(offset: usize, value: u32) {
+    register(offset).write_volatile(value)
+}
@@ -0,0 +1,506 @@
+//! Legacy ATA disks on the standard IDE channels, using PIO transfers with
+//! interrupts signaling when the drive is ready.
+
+use alloc::{boxed::Box, string::String, sync::Arc, vec::Vec};
+use core::{
+    fmt, hint,
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
+    task::{Poll, Waker},
+};
+use futures_util::{
+    future::{poll_fn, BoxFuture},
+    task::AtomicWaker,
+};
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+use crate::{
+    block::{self, BlockDevice, BlockError, SECTOR_SIZE},
+    interrupts, serial_println,
+};
+
+// Command block register offsets.
+const REG_DATA: u16 = 0;
+const REG_ERROR: u16 = 1;
+const REG_SECTOR_COUNT: u16 = 2;
+const REG_LBA_LOW: u16 = 3;
+const REG_LBA_MID: u16 = 4;
+const REG_LBA_HIGH: u16 = 5;
+const REG_DRIVE: u16 = 6;
+const REG_STATUS: u16 = 7;
+const REG_COMMAND: u16 = 7;
+
+const STATUS_ERR: u8 = 1 << 0;
+const STATUS_DRQ: u8 = 1 << 3;
+const STATUS_DF: u8 = 1 << 5;
+const STATUS_BSY: u8 = 1 << 7;
+
+const CONTROL_NIEN: u8 = 1 << 1;
+const CONTROL_SRST: u8 = 1 << 2;
+
+const CMD_READ_SECTORS: u8 = 0x20;
+const CMD_READ_SECTORS_EXT: u8 = 0x24;
+const CMD_WRITE_SECTORS: u8 = 0x30;
+const CMD_WRITE_SECTORS_EXT: u8 = 0x34;
+const CMD_FLUSH_CACHE: u8 = 0xe7;
+const CMD_FLUSH_CACHE_EXT: u8 = 0xea;
+const CMD_IDENTIFY: u8 = 0xec;
+
+/// Highest sector + 1 reachable with 28-bit addressing.
+const LBA28_LIMIT: u64 = 1 << 28;
+
+/// Status polls before a drive is considered absent.
+const POLLS: usize = 100_000;
+
+static CHANNELS: [Channel; 2] = [
+    Channel::new(0x1f0, 0x3f6, 14),
+    Channel::new(0x170, 0x376, 15),
+];
+
+/// An IDE channel, with up to two drives sharing its registers and IRQ.
+struct Channel {
+    base: u16,
+    control: u16,
+    irq: u8,
+    /// Whether a command is in progress.
+    busy: AtomicBool,
+    /// Tasks waiting for the channel to be free.
+    waiters: Mutex<Vec<Waker>>,
+    /// Status read by the interrupt handler, which acknowledges the interrupt.
+    irq_status: AtomicU8,
+    irq_pending: AtomicBool,
+    irq_waker: AtomicWaker,
+}
+
+impl Channel {
+    const fn new(base: u16, control: u16, irq: u8) -> Self {
+        Self {
+            base,
+            control,
+            irq,
+            busy: AtomicBool::new(false),
+            waiters: Mutex::new(Vec::new()),
+            irq_status: AtomicU8::new(0),
+            irq_pending: AtomicBool::new(false),
+            irq_waker: AtomicWaker::new(),
+        }
+    }
+
+    fn read(&self, register: u16) -> u8 {
+        unsafe { Port::new(self.base + register).read() }
+    }
+
+    fn write(&self, register: u16, value: u8) {
+        unsafe { Port::new(self.base + register).write(value) }
+    }
+
+    fn alternate_status(&self) -> u8 {
+        unsafe { Port::new(self.control).read() }
+    }
+
+    fn set_control(&self, value: u8) {
+        unsafe { Port::new(self.control).write(value) }
+    }
+
+    /// Waits the 400ns a drive needs to update its status, by reading the
+    /// alternate status register, which takes about 100ns per read.
+    fn delay(&self) {
+        for _ in 0..4 {
+            self.alternate_status();
+        }
+    }
+
+    fn select(&self, slave: bool, bits: u8) {
+        self.write(REG_DRIVE, bits | if slave { 0x10 } else { 0 });
+        self.delay();
+        self.wait_not_busy();
+    }
+
+    /// Spins until the drive is no longer busy, returning the final status
+    /// or `None` if it stays busy.
+    fn wait_not_busy(&self) -> Option<u8> {
+        for _ in 0..POLLS {
+            let status = self.alternate_status();
+            if status & STATUS_BSY == 0 {
+                return Some(status);
+            }
+            hint::spin_loop();
+        }
+        None
+    }
+
+    /// Spins until the drive requests data or reports an error.
+    fn wait_data(&self) -> Result<(), BlockError> {
+        for _ in 0..POLLS {
+            let status = self.alternate_status();
+            if status & STATUS_BSY == 0 {
+                return check(status);
+            }
+            hint::spin_loop();
+        }
+        Err(BlockError::Io)
+    }
+
+    /// Waits for the next interrupt and returns the status it was raised with.
+    async fn interrupt(&self) -> u8 {
+        poll_fn(|cx| {
+            if self.irq_pending.swap(false, Ordering::Acquire) {
+                return Poll::Ready(self.irq_status.load(Ordering::Relaxed));
+            }
+            self.irq_waker.register(cx.waker());
+            if self.irq_pending.swap(false, Ordering::Acquire) {
+                Poll::Ready(self.irq_status.load(Ordering::Relaxed))
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Waits until no other command uses the channel.
+    async fn lock(&self) -> ChannelGuard<'_> {
+        poll_fn(|cx| {
+            if self.try_lock() {
+                return Poll::Ready(());
+            }
+            self.waiters.lock().push(cx.waker().clone());
+            if self.try_lock() {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await;
+        self.irq_pending.store(false, Ordering::Relaxed);
+        ChannelGuard {
+            channel: self,
+            done: false,
+        }
+    }
+
+    fn try_lock(&self) -> bool {
+        self.busy
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    /// Resets both drives of the channel.
+    fn reset(&self) {
+        self.set_control(CONTROL_SRST);
+        self.delay();
+        self.set_control(0);
+        self.delay();
+        self.wait_not_busy();
+    }
+
+    /// Sends IDENTIFY to a drive and returns the data, or `None` if there is
+    /// no ATA drive.
+    fn identify(&self, slave: bool) -> Option<[u16; 256]> {
+        self.select(slave, 0xa0);
+        self.write(REG_SECTOR_COUNT, 0);
+        self.write(REG_LBA_LOW, 0);
+        self.write(REG_LBA_MID, 0);
+        self.write(REG_LBA_HIGH, 0);
+        self.write(REG_COMMAND, CMD_IDENTIFY);
+        self.delay();
+
+        // A status of zero means there is no drive, all ones a floating bus.
+        if matches!(self.alternate_status(), 0 | 0xff) {
+            return None;
+        }
+        self.wait_not_busy()?;
+        // ATAPI and SATA devices abort IDENTIFY and set a signature instead.
+        if self.read(REG_LBA_MID) != 0 || self.read(REG_LBA_HIGH) != 0 {
+            return None;
+        }
+        self.wait_data().ok()?;
+
+        let mut data = [0; 256];
+        let mut port = Port::<u16>::new(self.base + REG_DATA);
+        for word in &mut data {
+            *word = unsafe { port.read() };
+        }
+        // Acknowledge the completion.
+        self.read(REG_STATUS);
+        Some(data)
+    }
+
+    fn read_sector(&self, buf: &mut [u8]) {
+        let mut port = Port::<u16>::new(self.base + REG_DATA);
+        for chunk in buf.chunks_exact_mut(2) {
+            chunk.copy_from_slice(&unsafe { port.read() }.to_le_bytes());
+        }
+    }
+
+    fn write_sector(&self, buf: &[u8]) {
+        let mut port = Port::<u16>::new(self.base + REG_DATA);
+        for chunk in buf.chunks_exact(2) {
+            unsafe { port.write(u16::from_le_bytes([chunk[0], chunk[1]])) };
+        }
+    }
+}
+
+/// Exclusive use of a channel. Dropping it in the middle of a command, when
+/// the request is cancelled, resets the drives so the next command starts
+/// cleanly.
+struct ChannelGuard<'a> {
+    channel: &'a Channel,
+    done: bool,
+}
+
+impl Drop for ChannelGuard<'_> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.channel.reset();
+        }
+        self.channel.busy.store(false, Ordering::Release);
+        let waiters = core::mem::take(&mut *self.channel.waiters.lock());
+        waiters.into_iter().for_each(Waker::wake);
+    }
+}
+
+/// Turns the status after a transfer step into a result.
+fn check(status: u8) -> Result<(), BlockError> {
+    if status & (STATUS_ERR | STATUS_DF) != 0 || status & STATUS_DRQ == 0 {
+        Err(BlockError::Io)
+    } else {
+        Ok(())
+    }
+}
+
+/// Called by the interrupt handlers of IRQ 14 and 15.
+///
+/// NOTE: Must not block or allocate.
+pub(crate) fn handle_interrupt(channel: usize) {
+    let channel = &CHANNELS[channel];
+    // Reading the status register acknowledges the interrupt.
+    channel
+        .irq_status
+        .store(channel.read(REG_STATUS), Ordering::Relaxed);
+    channel.irq_pending.store(true, Ordering::Release);
+    channel.irq_waker.wake();
+}
+
+/// An ATA disk.
+pub struct AtaDrive {
+    name: String,
+    channel: &'static Channel,
+    slave: bool,
+    model: String,
+    sectors: u64,
+    lba48: bool,
+}
+
+impl AtaDrive {
+    fn new(name: String, channel: &'static Channel, slave: bool, identify: &[u16; 256]) -> Self {
+        let lba48 = identify[83] & (1 << 10) != 0;
+        let sectors = if lba48 {
+            identify[100..104]
+                .iter()
+                .rev()
+                .fold(0, |sectors, &word| sectors << 16 | u64::from(word))
+        } else {
+            u64::from(identify[60]) | u64::from(identify[61]) << 16
+        };
+
+        // The model is space padded, with the bytes of each word swapped.
+        let model = identify[27..47]
+            .iter()
+            .flat_map(|word| word.to_be_bytes())
+            .map(char::from)
+            .collect::<String>()
+            .trim_end()
+            .into();
+
+        Self {
+            name,
+            channel,
+            slave,
+            model,
+            sectors,
+            lba48,
+        }
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// Selects the drive and issues `command` for `count` sectors at
+    /// `sector`, using 48-bit addressing if `ext`.
+    fn issue(&self, command: u8, sector: u64, count: u32, ext: bool) {
+        let channel = self.channel;
+        if ext {
+            channel.select(self.slave, 0x40);
+            channel.write(REG_SECTOR_COUNT, (count >> 8) as u8);
+            channel.write(REG_LBA_LOW, (sector >> 24) as u8);
+            channel.write(REG_LBA_MID, (sector >> 32) as u8);
+            channel.write(REG_LBA_HIGH, (sector >> 40) as u8);
+        } else {
+            channel.select(self.slave, 0xe0 | ((sector >> 24) as u8 & 0x0f));
+        }
+        channel.write(REG_SECTOR_COUNT, count as u8);
+        channel.write(REG_LBA_LOW, sector as u8);
+        channel.write(REG_LBA_MID, (sector >> 8) as u8);
+        channel.write(REG_LBA_HIGH, (sector >> 16) as u8);
+        channel.write(REG_COMMAND, command);
+    }
+
+    /// Returns how many sectors at `sector` the next command transfers, and
+    /// whether it needs 48-bit addressing.
+    fn next_transfer(&self, sector: u64, remaining: usize) -> (usize, bool) {
+        if self.lba48 && (remaining > 256 || sector + remaining as u64 > LBA28_LIMIT) {
+            (remaining.min(65536), true)
+        } else {
+            (remaining.min(256), false)
+        }
+    }
+
+    async fn read_sectors(&self, mut sector: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        let mut guard = self.channel.lock().await;
+        let mut sectors = buf.chunks_exact_mut(SECTOR_SIZE);
+        let mut remaining = sectors.len();
+
+        while remaining > 0 {
+            let (count, ext) = self.next_transfer(sector, remaining);
+            let command = if ext {
+                CMD_READ_SECTORS_EXT
+            } else {
+                CMD_READ_SECTORS
+            };
+            // A count of zero stands for the maximum.
+            self.issue(command, sector, count as u32, ext);
+            for buf in sectors.by_ref().take(count) {
+                check(self.channel.interrupt().await)?;
+                self.channel.read_sector(buf);
+            }
+            sector += count as u64;
+            remaining -= count;
+        }
+
+        guard.done = true;
+        Ok(())
+    }
+
+    async fn write_sectors(&self, mut sector: u64, buf: &[u8]) -> Result<(), BlockError> {
+        let mut guard = self.channel.lock().await;
+        let mut sectors = buf.chunks_exact(SECTOR_SIZE);
+        let mut remaining = sectors.len();
+
+        while remaining > 0 {
+            let (count, ext) = self.next_transfer(sector, remaining);
+            let command = if ext {
+                CMD_WRITE_SECTORS_EXT
+            } else {
+                CMD_WRITE_SECTORS
+            };
+            self.issue(command, sector, count as u32, ext);
+            // The drive only interrupts after each sector, so wait for the
+            // first request for data.
+            self.channel.wait_data()?;
+            for (i, buf) in sectors.by_ref().take(count).enumerate() {
+                self.channel.write_sector(buf);
+                let status = self.channel.interrupt().await;
+                if i + 1 < count {
+                    check(status)?;
+                } else if status & (STATUS_ERR | STATUS_DF) != 0 {
+                    return Err(BlockError::Io);
+                }
+            }
+            sector += count as u64;
+            remaining -= count;
+        }
+
+        guard.done = true;
+        Ok(())
+    }
+
+    async fn flush_cache(&self) -> Result<(), BlockError> {
+        let mut guard = self.channel.lock().await;
+        let command = if self.lba48 {
+            CMD_FLUSH_CACHE_EXT
+        } else {
+            CMD_FLUSH_CACHE
+        };
+        self.channel.select(self.slave, 0xa0);
+        self.channel.write(REG_COMMAND, command);
+        let status = self.channel.interrupt().await;
+        guard.done = true;
+
+        if status & STATUS_ERR != 0 {
+            // Drives without a write cache may abort the command.
+            let aborted = self.channel.read(REG_ERROR) & 0x04 != 0;
+            return if aborted { Ok(()) } else { Err(BlockError::Io) };
+        }
+        Ok(())
+    }
+}
+
+impl BlockDevice for AtaDrive {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read<'a>(&'a self, sector: u64, buf: &'a mut [u8]) -> BoxFuture<'a, Result<(), BlockError>> {
+        Box::pin(async move {
+            block::check_request(self, sector, buf.len())?;
+            self.read_sectors(sector, buf).await
+        })
+    }
+
+    fn write<'a>(&'a self, sector: u64, buf: &'a [u8]) -> BoxFuture<'a, Result<(), BlockError>> {
+        Box::pin(async move {
+            block::check_request(self, sector, buf.len())?;
+            self.write_sectors(sector, buf).await
+        })
+    }
+
+    fn flush(&self) -> BoxFuture<'_, Result<(), BlockError>> {
+        Box::pin(self.flush_cache())
+    }
+}
+
+impl fmt::Display for AtaDrive {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "ATA disk \"{}\", {} sectors ({} MiB), {}",
+            self.model,
+            self.sectors,
+            self.sectors * SECTOR_SIZE as u64 / (1024 * 1024),
+            if self.lba48 { "LBA48" } else { "LBA28" }
+        )
+    }
+}
+
+/// Detects the drives on both channels, registering them as `hda` to `hdd`,
+/// and enables their interrupts.
+pub fn init() {
+    for (index, channel) in CHANNELS.iter().enumerate() {
+        // A floating bus means there is no controller behind the ports.
+        if channel.alternate_status() == 0xff {
+            continue;
+        }
+        channel.set_control(CONTROL_NIEN);
+        let drives: Vec<_> = [false, true]
+            .into_iter()
+            .filter_map(|slave| Some((slave, channel.identify(slave)?)))
+            .collect();
+        channel.set_control(0);
+        if drives.is_empty() {
+            continue;
+        }
+
+        interrupts::enable_irq(channel.irq);
+        for (slave, identify) in drives {
+            let letter = (b'a' + 2 * index as u8 + u8::from(slave)) as char;
+            let drive = AtaDrive::new(alloc::format!("hd{}", letter), channel, slave, &identify);
+            serial_println!("{}: {}", drive.name, drive);
+            block::register(Arc::new(drive));
+        }
+    }
+}
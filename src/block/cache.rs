@@ -0,0 +1,312 @@
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec::Vec};
+use core::cmp;
+use spin::Mutex;
+
+use super::{BlockDevice, BlockError, SECTOR_SIZE};
+
+/// A write-back cache of the fixed-size blocks of a device.
+///
+/// Blocks are evicted in least recently used order once `capacity` blocks
+/// are cached, writing them back if modified. Other modified blocks only
+/// reach the device on [`sync`](Self::sync), so they are lost if the cache
+/// is dropped without it.
+pub struct BlockCache {
+    device: Arc<dyn BlockDevice>,
+    block_size: usize,
+    capacity: usize,
+    state: Mutex<State>,
+}
+
+struct State {
+    entries: BTreeMap<u64, Entry>,
+    /// Advanced on every access, to order entries by last use.
+    clock: u64,
+}
+
+struct Entry {
+    data: Box<[u8]>,
+    dirty: bool,
+    last_used: u64,
+}
+
+impl State {
+    fn touch(&mut self, block: u64) -> Option<&mut Entry> {
+        self.clock += 1;
+        let clock = self.clock;
+        let entry = self.entries.get_mut(&block)?;
+        entry.last_used = clock;
+        Some(entry)
+    }
+}
+
+impl BlockCache {
+    /// Creates a cache of up to `capacity` blocks of `block_size` bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `block_size` is not a non-zero multiple of [`SECTOR_SIZE`]
+    /// or `capacity` is zero.
+    pub fn new(device: Arc<dyn BlockDevice>, block_size: usize, capacity: usize) -> Self {
+        assert!(
+            block_size > 0 && block_size % SECTOR_SIZE == 0,
+            "invalid block size {}",
+            block_size
+        );
+        assert!(capacity > 0, "empty block cache");
+
+        Self {
+            device,
+            block_size,
+            capacity,
+            state: Mutex::new(State {
+                entries: BTreeMap::new(),
+                clock: 0,
+            }),
+        }
+    }
+
+    pub fn device(&self) -> &Arc<dyn BlockDevice> {
+        &self.device
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Returns the number of whole blocks on the device.
+    pub fn block_count(&self) -> u64 {
+        self.device.sector_count() / self.sectors_per_block()
+    }
+
+    fn sectors_per_block(&self) -> u64 {
+        (self.block_size / SECTOR_SIZE) as u64
+    }
+
+    /// Reads `buf.len()` bytes starting `offset` bytes into the device.
+    pub async fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        let mut done = 0;
+        while done < buf.len() {
+            let (block, start, len) = self.split(offset + done as u64, buf.len() - done);
+            let dest = &mut buf[done..done + len];
+            loop {
+                if let Some(entry) = self.state.lock().touch(block) {
+                    dest.copy_from_slice(&entry.data[start..start + len]);
+                    break;
+                }
+                self.load(block).await?;
+            }
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Writes `buf` starting `offset` bytes into the device.
+    pub async fn write_at(&self, offset: u64, buf: &[u8]) -> Result<(), BlockError> {
+        if self.device.is_read_only() {
+            return Err(BlockError::ReadOnly);
+        }
+
+        let mut done = 0;
+        while done < buf.len() {
+            let (block, start, len) = self.split(offset + done as u64, buf.len() - done);
+            let src = &buf[done..done + len];
+            if len == self.block_size && !self.state.lock().entries.contains_key(&block) {
+                // The whole block is replaced, so there is no need to read it.
+                if block >= self.block_count() {
+                    return Err(BlockError::OutOfRange);
+                }
+                self.insert(block, src.into(), true).await?;
+            } else {
+                loop {
+                    if let Some(entry) = self.state.lock().touch(block) {
+                        entry.data[start..start + len].copy_from_slice(src);
+                        entry.dirty = true;
+                        break;
+                    }
+                    self.load(block).await?;
+                }
+            }
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Reads block number `block` into `buf`, which must be one block long.
+    pub async fn read_block(&self, block: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        if buf.len() != self.block_size {
+            return Err(BlockError::InvalidBuffer);
+        }
+        self.read_at(block * self.block_size as u64, buf).await
+    }
+
+    /// Replaces block number `block` with `buf`, which must be one block long.
+    pub async fn write_block(&self, block: u64, buf: &[u8]) -> Result<(), BlockError> {
+        if buf.len() != self.block_size {
+            return Err(BlockError::InvalidBuffer);
+        }
+        self.write_at(block * self.block_size as u64, buf).await
+    }
+
+    /// Writes back all modified blocks and flushes the device.
+    pub async fn sync(&self) -> Result<(), BlockError> {
+        let dirty: Vec<u64> = self
+            .state
+            .lock()
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.dirty)
+            .map(|(&block, _)| block)
+            .collect();
+        for block in dirty {
+            self.write_back(block).await?;
+        }
+        self.device.flush().await
+    }
+
+    /// Returns the number of cached blocks and how many of them are modified.
+    pub fn usage(&self) -> (usize, usize) {
+        let state = self.state.lock();
+        let dirty = state.entries.values().filter(|entry| entry.dirty).count();
+        (state.entries.len(), dirty)
+    }
+
+    /// Splits a request into the block it starts in, the offset into that
+    /// block and how many bytes of the block it covers.
+    fn split(&self, offset: u64, len: usize) -> (u64, usize, usize) {
+        let block = offset / self.block_size as u64;
+        let start = (offset % self.block_size as u64) as usize;
+        (block, start, cmp::min(len, self.block_size - start))
+    }
+
+    /// Reads `block` from the device into the cache.
+    async fn load(&self, block: u64) -> Result<(), BlockError> {
+        let mut data = alloc::vec![0; self.block_size].into_boxed_slice();
+        self.device
+            .read(block * self.sectors_per_block(), &mut data)
+            .await?;
+        self.insert(block, data, false).await
+    }
+
+    /// Adds `block` with `data`, evicting other blocks first if needed.
+    ///
+    /// If the block got cached in the meantime, the cached data is kept
+    /// unless `dirty`.
+    async fn insert(&self, block: u64, data: Box<[u8]>, dirty: bool) -> Result<(), BlockError> {
+        self.make_room().await?;
+
+        let mut state = self.state.lock();
+        state.clock += 1;
+        let last_used = state.clock;
+        match state.entries.get_mut(&block) {
+            Some(entry) if !dirty => entry.last_used = last_used,
+            _ => {
+                state.entries.insert(
+                    block,
+                    Entry {
+                        data,
+                        dirty,
+                        last_used,
+                    },
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Evicts blocks until there is room for another one.
+    async fn make_room(&self) -> Result<(), BlockError> {
+        loop {
+            let victim = {
+                let mut state = self.state.lock();
+                if state.entries.len() < self.capacity {
+                    return Ok(());
+                }
+                let (&block, entry) = state
+                    .entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.last_used)
+                    .expect("cache is full");
+                if !entry.dirty {
+                    state.entries.remove(&block);
+                    continue;
+                }
+                block
+            };
+
+            // The block stays cached while it is written, so it can't be
+            // read back from the device before it gets there.
+            self.write_back(victim).await?;
+            let mut state = self.state.lock();
+            if matches!(state.entries.get(&victim), Some(entry) if !entry.dirty) {
+                state.entries.remove(&victim);
+            }
+        }
+    }
+
+    /// Writes `block` to the device if it is modified.
+    async fn write_back(&self, block: u64) -> Result<(), BlockError> {
+        let data = {
+            let mut state = self.state.lock();
+            match state.entries.get_mut(&block) {
+                Some(entry) if entry.dirty => {
+                    entry.dirty = false;
+                    entry.data.clone()
+                }
+                _ => return Ok(()),
+            }
+        };
+
+        let result = self
+            .device
+            .write(block * self.sectors_per_block(), &data)
+            .await;
+        if result.is_err() {
+            if let Some(entry) = self.state.lock().entries.get_mut(&block) {
+                entry.dirty = true;
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{block::tests::RamDisk, task};
+
+    #[test_case]
+    fn unaligned_access() {
+        let disk = Arc::new(RamDisk::new(16));
+        let cache = BlockCache::new(disk.clone(), 1024, 4);
+
+        task::block_on(cache.write_at(1000, &[7; 100])).unwrap();
+        let mut buf = [0; 102];
+        task::block_on(cache.read_at(999, &mut buf)).unwrap();
+        assert_eq!(buf[0], 0);
+        assert!(buf[1..101].iter().all(|&b| b == 7));
+        assert_eq!(buf[101], 0);
+
+        // Nothing is written before the sync.
+        assert_eq!(disk.read_byte(1000), 0);
+        task::block_on(cache.sync()).unwrap();
+        assert_eq!(disk.read_byte(1000), 7);
+        assert_eq!(cache.usage(), (2, 0));
+    }
+
+    #[test_case]
+    fn evicts_least_recently_used() {
+        let disk = Arc::new(RamDisk::new(16));
+        let cache = BlockCache::new(disk.clone(), SECTOR_SIZE, 2);
+        let mut buf = [0; SECTOR_SIZE];
+
+        task::block_on(cache.write_block(0, &[1; SECTOR_SIZE])).unwrap();
+        task::block_on(cache.read_block(1, &mut buf)).unwrap();
+        task::block_on(cache.read_block(0, &mut buf)).unwrap();
+        // Block 1 is the least recently used, so block 0 stays dirty.
+        task::block_on(cache.read_block(2, &mut buf)).unwrap();
+        assert_eq!(disk.read_byte(0), 0);
+        task::block_on(cache.read_block(3, &mut buf)).unwrap();
+        assert_eq!(disk.read_byte(0), 1);
+        assert_eq!(cache.usage(), (2, 0));
+    }
+}
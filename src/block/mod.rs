@@ -0,0 +1,180 @@
+pub mod cache;
+pub mod partition;
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+use futures_util::future::BoxFuture;
+use spin::Mutex;
+
+/// Size of a sector, the unit block devices are addressed in.
+pub const SECTOR_SIZE: usize = 512;
+
+static DEVICES: Mutex<Vec<Arc<dyn BlockDevice>>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockError {
+    /// The request extends past the end of the device.
+    OutOfRange,
+    /// The buffer is not a whole number of sectors.
+    InvalidBuffer,
+    /// The device is read-only.
+    ReadOnly,
+    /// The device reported an error.
+    Io,
+    /// The device doesn't support the request.
+    Unsupported,
+}
+
+/// A device storing data in fixed-size sectors.
+pub trait BlockDevice: Send + Sync {
+    /// Returns the name the device is registered under, like `vda`.
+    fn name(&self) -> &str;
+
+    /// Returns the size of the device in sectors.
+    fn sector_count(&self) -> u64;
+
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    /// Returns the device this one is a part of, for partitions.
+    fn parent(&self) -> Option<Arc<dyn BlockDevice>> {
+        None
+    }
+
+    /// Reads `buf.len() / SECTOR_SIZE` sectors starting at `sector`.
+    fn read<'a>(&'a self, sector: u64, buf: &'a mut [u8]) -> BoxFuture<'a, Result<(), BlockError>>;
+
+    /// Writes `buf.len() / SECTOR_SIZE` sectors starting at `sector`.
+    fn write<'a>(&'a self, sector: u64, buf: &'a [u8]) -> BoxFuture<'a, Result<(), BlockError>>;
+
+    /// Waits until all completed writes are on stable storage.
+    fn flush(&self) -> BoxFuture<'_, Result<(), BlockError>>;
+}
+
+/// Checks that a request for `len` bytes at `sector` fits `device`.
+pub fn check_request(device: &dyn BlockDevice, sector: u64, len: usize) -> Result<(), BlockError> {
+    if len % SECTOR_SIZE != 0 {
+        return Err(BlockError::InvalidBuffer);
+    }
+    let sectors = (len / SECTOR_SIZE) as u64;
+    match sector.checked_add(sectors) {
+        Some(end) if end <= device.sector_count() => Ok(()),
+        _ => Err(BlockError::OutOfRange),
+    }
+}
+
+/// Makes `device` available through [`devices`] and [`find`].
+pub fn register(device: Arc<dyn BlockDevice>) {
+    DEVICES.lock().push(device);
+}
+
+/// Returns all registered block devices.
+pub fn devices() -> Vec<Arc<dyn BlockDevice>> {
+    DEVICES.lock().clone()
+}
+
+/// Returns the registered device called `name`.
+pub fn find(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    DEVICES
+        .lock()
+        .iter()
+        .find(|device| device.name() == name)
+        .cloned()
+}
+
+/// Returns the first unused name made of `prefix` and a letter, like `vdb`.
+pub fn next_name(prefix: &str) -> String {
+    let devices = DEVICES.lock();
+    let mut name = String::from(prefix);
+    for letter in 'a'..='z' {
+        name.truncate(prefix.len());
+        name.push(letter);
+        if !devices.iter().any(|device| device.name() == name) {
+            break;
+        }
+    }
+    name
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use alloc::{boxed::Box, vec};
+
+    /// A block device in memory.
+    pub struct RamDisk {
+        data: Mutex<Vec<u8>>,
+    }
+
+    impl RamDisk {
+        pub fn new(sectors: usize) -> Self {
+            Self::with_data(vec![0; sectors * SECTOR_SIZE])
+        }
+
+        pub fn with_data(data: Vec<u8>) -> Self {
+            assert_eq!(data.len() % SECTOR_SIZE, 0);
+            Self {
+                data: Mutex::new(data),
+            }
+        }
+
+        pub fn read_byte(&self, offset: usize) -> u8 {
+            self.data.lock()[offset]
+        }
+    }
+
+    impl BlockDevice for RamDisk {
+        fn name(&self) -> &str {
+            "ram"
+        }
+
+        fn sector_count(&self) -> u64 {
+            (self.data.lock().len() / SECTOR_SIZE) as u64
+        }
+
+        fn read<'a>(
+            &'a self,
+            sector: u64,
+            buf: &'a mut [u8],
+        ) -> BoxFuture<'a, Result<(), BlockError>> {
+            Box::pin(async move {
+                check_request(self, sector, buf.len())?;
+                let start = sector as usize * SECTOR_SIZE;
+                buf.copy_from_slice(&self.data.lock()[start..start + buf.len()]);
+                Ok(())
+            })
+        }
+
+        fn write<'a>(
+            &'a self,
+            sector: u64,
+            buf: &'a [u8],
+        ) -> BoxFuture<'a, Result<(), BlockError>> {
+            Box::pin(async move {
+                check_request(self, sector, buf.len())?;
+                let start = sector as usize * SECTOR_SIZE;
+                self.data.lock()[start..start + buf.len()].copy_from_slice(buf);
+                Ok(())
+            })
+        }
+
+        fn flush(&self) -> BoxFuture<'_, Result<(), BlockError>> {
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[test_case]
+    fn rejects_out_of_range() {
+        let disk = RamDisk::new(4);
+        assert_eq!(check_request(&disk, 3, SECTOR_SIZE), Ok(()));
+        assert_eq!(
+            check_request(&disk, 3, 2 * SECTOR_SIZE),
+            Err(BlockError::OutOfRange)
+        );
+        assert_eq!(
+            check_request(&disk, u64::MAX, SECTOR_SIZE),
+            Err(BlockError::OutOfRange)
+        );
+        assert_eq!(check_request(&disk, 0, 1), Err(BlockError::InvalidBuffer));
+    }
+}
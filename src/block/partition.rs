@@ -0,0 +1,577 @@
+//! MBR and GPT partition tables. Each partition found is registered as a
+//! block device of its own, named after the disk, like `vda1`.
+
+use alloc::{boxed::Box, format, string::String, sync::Arc, vec, vec::Vec};
+use core::fmt;
+use futures_util::future::BoxFuture;
+
+use super::{BlockDevice, BlockError, SECTOR_SIZE};
+use crate::serial_println;
+
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+const MBR_ENTRIES: usize = 446;
+const MBR_PROTECTIVE: u8 = 0xee;
+const MBR_EXTENDED: [u8; 3] = [0x05, 0x0f, 0x85];
+
+/// Extended boot records followed before the chain is considered looping.
+const MAX_LOGICAL: usize = 128;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+/// Largest partition entry array accepted.
+const GPT_MAX_ENTRIES_SIZE: usize = 64 * 1024;
+
+/// A GUID, stored as on disk.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Guid([u8; 16]);
+
+impl Guid {
+    pub const UNUSED: Guid = Guid([0; 16]);
+    pub const EFI_SYSTEM: Guid = Guid::new(
+        0xc12a7328,
+        0xf81f,
+        0x11d2,
+        [0xba, 0x4b, 0x00, 0xa0, 0xc9, 0x3e, 0xc9, 0x3b],
+    );
+    pub const BASIC_DATA: Guid = Guid::new(
+        0xebd0a0a2,
+        0xb9e5,
+        0x4433,
+        [0x87, 0xc0, 0x68, 0xb6, 0xb7, 0x26, 0x99, 0xc7],
+    );
+    pub const LINUX_FILESYSTEM: Guid = Guid::new(
+        0x0fc63daf,
+        0x8483,
+        0x4772,
+        [0x8e, 0x79, 0x3d, 0x69, 0xd8, 0x47, 0x7d, 0xe4],
+    );
+
+    /// Creates a GUID from the fields of its text form.
+    pub const fn new(a: u32, b: u16, c: u16, d: [u8; 8]) -> Self {
+        let a = a.to_le_bytes();
+        let b = b.to_le_bytes();
+        let c = c.to_le_bytes();
+        Guid([
+            a[0], a[1], a[2], a[3], b[0], b[1], c[0], c[1], d[0], d[1], d[2], d[3], d[4], d[5],
+            d[6], d[7],
+        ])
+    }
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let g = &self.0;
+        write!(
+            f,
+            "{:08X}-{:04X}-{:04X}-",
+            u32::from_le_bytes([g[0], g[1], g[2], g[3]]),
+            u16::from_le_bytes([g[4], g[5]]),
+            u16::from_le_bytes([g[6], g[7]])
+        )?;
+        g[8..10].iter().try_for_each(|b| write!(f, "{:02X}", b))?;
+        f.write_str("-")?;
+        g[10..].iter().try_for_each(|b| write!(f, "{:02X}", b))
+    }
+}
+
+impl fmt::Debug for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PartitionKind {
+    /// An MBR partition, with its system ID.
+    Mbr(u8),
+    /// A GPT partition, with its type and name.
+    Gpt { type_guid: Guid, label: String },
+}
+
+impl fmt::Display for PartitionKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PartitionKind::Mbr(id) => write!(f, "MBR type {:#04x}", id),
+            PartitionKind::Gpt { type_guid, label } => write!(f, "GPT {} {:?}", type_guid, label),
+        }
+    }
+}
+
+/// An entry of a partition table, in sectors of the disk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionEntry {
+    /// Number of the partition, from 1. Logical MBR partitions start at 5.
+    pub number: usize,
+    pub start: u64,
+    pub sectors: u64,
+    pub kind: PartitionKind,
+}
+
+/// A partition of a disk, accessed as a block device.
+pub struct Partition {
+    name: String,
+    disk: Arc<dyn BlockDevice>,
+    entry: PartitionEntry,
+}
+
+impl Partition {
+    pub fn entry(&self) -> &PartitionEntry {
+        &self.entry
+    }
+}
+
+impl BlockDevice for Partition {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.entry.sectors
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.disk.is_read_only()
+    }
+
+    fn parent(&self) -> Option<Arc<dyn BlockDevice>> {
+        Some(self.disk.clone())
+    }
+
+    fn read<'a>(&'a self, sector: u64, buf: &'a mut [u8]) -> BoxFuture<'a, Result<(), BlockError>> {
+        Box::pin(async move {
+            super::check_request(self, sector, buf.len())?;
+            self.disk.read(self.entry.start + sector, buf).await
+        })
+    }
+
+    fn write<'a>(&'a self, sector: u64, buf: &'a [u8]) -> BoxFuture<'a, Result<(), BlockError>> {
+        Box::pin(async move {
+            super::check_request(self, sector, buf.len())?;
+            self.disk.write(self.entry.start + sector, buf).await
+        })
+    }
+
+    fn flush(&self) -> BoxFuture<'_, Result<(), BlockError>> {
+        self.disk.flush()
+    }
+}
+
+impl fmt::Display for Partition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} sectors at {} of {} ({})",
+            self.entry.sectors,
+            self.entry.start,
+            self.disk.name(),
+            self.entry.kind
+        )
+    }
+}
+
+/// Reads the partition table of `disk`. A disk without one has no entries.
+///
+/// GPT is used if the MBR is protective, falling back to the backup GPT
+/// header if the primary one is damaged.
+pub async fn read_table(disk: &dyn BlockDevice) -> Result<Vec<PartitionEntry>, BlockError> {
+    let mut sector = vec![0; SECTOR_SIZE];
+    disk.read(0, &mut sector).await?;
+    let mbr = match parse_mbr(&sector) {
+        Some(mbr) => mbr,
+        None => return Ok(Vec::new()),
+    };
+
+    if mbr.iter().any(|entry| entry.kind == MBR_PROTECTIVE) {
+        return read_gpt(disk).await;
+    }
+
+    let mut entries = Vec::new();
+    for (i, entry) in mbr.iter().enumerate() {
+        if entry.kind == 0 || entry.sectors == 0 {
+            continue;
+        }
+        if MBR_EXTENDED.contains(&entry.kind) {
+            read_logical(disk, u64::from(entry.start), &mut entries).await?;
+        } else {
+            entries.push(entry.to_partition(i + 1, 0));
+        }
+    }
+    Ok(entries)
+}
+
+/// Follows the chain of extended boot records of the extended partition at
+/// `extended`, adding the logical partitions.
+async fn read_logical(
+    disk: &dyn BlockDevice,
+    extended: u64,
+    entries: &mut Vec<PartitionEntry>,
+) -> Result<(), BlockError> {
+    let mut sector = vec![0; SECTOR_SIZE];
+    let mut ebr = extended;
+    for number in 5..5 + MAX_LOGICAL {
+        disk.read(ebr, &mut sector).await?;
+        let [logical, next, ..] = match parse_mbr(&sector) {
+            Some(records) => records,
+            None => break,
+        };
+        if logical.kind != 0 && logical.sectors != 0 {
+            entries.push(logical.to_partition(number, ebr));
+        }
+        if next.start == 0 || !MBR_EXTENDED.contains(&next.kind) {
+            break;
+        }
+        ebr = extended + u64::from(next.start);
+    }
+    Ok(())
+}
+
+async fn read_gpt(disk: &dyn BlockDevice) -> Result<Vec<PartitionEntry>, BlockError> {
+    let mut sector = vec![0; SECTOR_SIZE];
+    disk.read(1, &mut sector).await?;
+    let header = match GptHeader::parse(&sector) {
+        Some(header) => header,
+        None => {
+            // The backup header is in the last sector.
+            let last = disk.sector_count().checked_sub(1);
+            let last = last.ok_or(BlockError::OutOfRange)?;
+            disk.read(last, &mut sector).await?;
+            match GptHeader::parse(&sector) {
+                Some(header) => header,
+                None => return Ok(Vec::new()),
+            }
+        }
+    };
+
+    let size = header.entry_count as usize * header.entry_size as usize;
+    let mut array = vec![0; x86_64::align_up(size as u64, SECTOR_SIZE as u64) as usize];
+    disk.read(header.entries_lba, &mut array).await?;
+    if crc32(&array[..size]) != header.entries_crc {
+        return Ok(Vec::new());
+    }
+    Ok(parse_gpt_entries(
+        &array[..size],
+        header.entry_size as usize,
+    ))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct MbrEntry {
+    kind: u8,
+    start: u32,
+    sectors: u32,
+}
+
+impl MbrEntry {
+    /// Converts the entry, whose start is relative to `base`.
+    fn to_partition(self, number: usize, base: u64) -> PartitionEntry {
+        PartitionEntry {
+            number,
+            start: base + u64::from(self.start),
+            sectors: u64::from(self.sectors),
+            kind: PartitionKind::Mbr(self.kind),
+        }
+    }
+}
+
+/// Returns the four entries of an MBR or extended boot record, or `None` if
+/// the boot signature is missing.
+fn parse_mbr(sector: &[u8]) -> Option<[MbrEntry; 4]> {
+    if sector[510..512] != MBR_SIGNATURE {
+        return None;
+    }
+    let mut entries = [MbrEntry {
+        kind: 0,
+        start: 0,
+        sectors: 0,
+    }; 4];
+    for (i, entry) in entries.iter_mut().enumerate() {
+        let raw = &sector[MBR_ENTRIES + 16 * i..][..16];
+        *entry = MbrEntry {
+            kind: raw[4],
+            start: u32_at(raw, 8),
+            sectors: u32_at(raw, 12),
+        };
+    }
+    Some(entries)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct GptHeader {
+    entries_lba: u64,
+    entry_count: u32,
+    entry_size: u32,
+    entries_crc: u32,
+}
+
+impl GptHeader {
+    /// Parses and validates a GPT header.
+    fn parse(sector: &[u8]) -> Option<Self> {
+        if &sector[..8] != GPT_SIGNATURE {
+            return None;
+        }
+        let header_size = u32_at(sector, 12) as usize;
+        if !(92..=SECTOR_SIZE).contains(&header_size) {
+            return None;
+        }
+        let mut header = sector[..header_size].to_vec();
+        header[16..20].fill(0);
+        if crc32(&header) != u32_at(sector, 16) {
+            return None;
+        }
+
+        let header = Self {
+            entries_lba: u64_at(sector, 72),
+            entry_count: u32_at(sector, 80),
+            entry_size: u32_at(sector, 84),
+            entries_crc: u32_at(sector, 88),
+        };
+        let size = header.entry_count as usize * header.entry_size as usize;
+        let valid =
+            header.entry_size >= 128 && header.entry_size % 8 == 0 && size <= GPT_MAX_ENTRIES_SIZE;
+        valid.then_some(header)
+    }
+}
+
+fn parse_gpt_entries(array: &[u8], entry_size: usize) -> Vec<PartitionEntry> {
+    array
+        .chunks_exact(entry_size)
+        .enumerate()
+        .filter_map(|(i, raw)| {
+            let mut type_guid = [0; 16];
+            type_guid.copy_from_slice(&raw[..16]);
+            let type_guid = Guid(type_guid);
+            if type_guid == Guid::UNUSED {
+                return None;
+            }
+            let first = u64_at(raw, 32);
+            let last = u64_at(raw, 40);
+            let name: Vec<u16> = raw[56..128]
+                .chunks_exact(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                .take_while(|&c| c != 0)
+                .collect();
+            Some(PartitionEntry {
+                number: i + 1,
+                start: first,
+                sectors: (last + 1).saturating_sub(first),
+                kind: PartitionKind::Gpt {
+                    type_guid,
+                    label: char::decode_utf16(name)
+                        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                        .collect(),
+                },
+            })
+        })
+        .collect()
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    let mut raw = [0; 4];
+    raw.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(raw)
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    let mut raw = [0; 8];
+    raw.copy_from_slice(&bytes[offset..offset + 8]);
+    u64::from_le_bytes(raw)
+}
+
+/// The CRC-32 used by GPT (IEEE 802.3, reflected).
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0, |crc, &byte| {
+        (0..8).fold(crc ^ u32::from(byte), |crc, _| {
+            (crc >> 1) ^ (0xedb8_8320 & 0u32.wrapping_sub(crc & 1))
+        })
+    })
+}
+
+/// Returns the name of partition `number` of the disk called `disk`.
+fn partition_name(disk: &str, number: usize) -> String {
+    if disk.ends_with(|c: char| c.is_ascii_digit()) {
+        format!("{}p{}", disk, number)
+    } else {
+        format!("{}{}", disk, number)
+    }
+}
+
+/// Reads the partition table of `disk` and returns its partitions.
+///
+/// Entries that don't fit on the disk are skipped.
+pub async fn scan(disk: Arc<dyn BlockDevice>) -> Result<Vec<Partition>, BlockError> {
+    let entries = read_table(&*disk).await?;
+    let sector_count = disk.sector_count();
+    Ok(entries
+        .into_iter()
+        .filter(|entry| {
+            let fits = matches!(
+                entry.start.checked_add(entry.sectors),
+                Some(end) if entry.sectors > 0 && end <= sector_count
+            );
+            if !fits {
+                serial_println!(
+                    "{}: partition {} extends past the end of the disk",
+                    disk.name(),
+                    entry.number
+                );
+            }
+            fits
+        })
+        .map(|entry| Partition {
+            name: partition_name(disk.name(), entry.number),
+            disk: disk.clone(),
+            entry,
+        })
+        .collect())
+}
+
+/// Scans every registered disk whose partitions are not registered yet and
+/// registers the partitions found.
+pub async fn probe_all() {
+    let devices = super::devices();
+    let disks = devices.iter().filter(|device| {
+        device.parent().is_none()
+            && !devices.iter().any(
+                |other| matches!(other.parent(), Some(parent) if parent.name() == device.name()),
+            )
+    });
+
+    for disk in disks {
+        match scan(disk.clone()).await {
+            Ok(partitions) => {
+                for partition in partitions {
+                    serial_println!("{}: {}", partition.name, partition);
+                    super::register(Arc::new(partition));
+                }
+            }
+            Err(err) => {
+                serial_println!("{}: failed to read partition table: {:?}", disk.name(), err)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{block::tests::RamDisk, task};
+
+    fn set_mbr_entry(sector: &mut [u8], index: usize, kind: u8, start: u32, sectors: u32) {
+        let raw = &mut sector[MBR_ENTRIES + 16 * index..][..16];
+        raw[4] = kind;
+        raw[8..12].copy_from_slice(&start.to_le_bytes());
+        raw[12..16].copy_from_slice(&sectors.to_le_bytes());
+        sector[510..512].copy_from_slice(&MBR_SIGNATURE);
+    }
+
+    /// Builds a GPT disk of `sectors` sectors with `partitions` given as type,
+    /// first and last sector and name.
+    pub fn gpt_disk(sectors: usize, partitions: &[(Guid, u64, u64, &str)]) -> Vec<u8> {
+        let mut disk = vec![0; sectors * SECTOR_SIZE];
+        set_mbr_entry(&mut disk, 0, MBR_PROTECTIVE, 1, sectors as u32 - 1);
+
+        let mut array = vec![0; 128 * 128];
+        for (entry, &(type_guid, first, last, name)) in array.chunks_mut(128).zip(partitions) {
+            entry[..16].copy_from_slice(&type_guid.0);
+            entry[32..40].copy_from_slice(&first.to_le_bytes());
+            entry[40..48].copy_from_slice(&last.to_le_bytes());
+            for (i, c) in name.encode_utf16().enumerate() {
+                entry[56 + 2 * i..][..2].copy_from_slice(&c.to_le_bytes());
+            }
+        }
+        disk[2 * SECTOR_SIZE..][..array.len()].copy_from_slice(&array);
+
+        let header = &mut disk[SECTOR_SIZE..2 * SECTOR_SIZE];
+        header[..8].copy_from_slice(GPT_SIGNATURE);
+        header[12..16].copy_from_slice(&92u32.to_le_bytes());
+        header[72..80].copy_from_slice(&2u64.to_le_bytes());
+        header[80..84].copy_from_slice(&128u32.to_le_bytes());
+        header[84..88].copy_from_slice(&128u32.to_le_bytes());
+        header[88..92].copy_from_slice(&crc32(&array).to_le_bytes());
+        let crc = crc32(&header[..92]);
+        header[16..20].copy_from_slice(&crc.to_le_bytes());
+        disk
+    }
+
+    #[test_case]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test_case]
+    fn guid_text_form() {
+        assert_eq!(
+            format!("{}", Guid::EFI_SYSTEM),
+            "C12A7328-F81F-11D2-BA4B-00A0C93EC93B"
+        );
+    }
+
+    #[test_case]
+    fn mbr_with_logical_partitions() {
+        let mut data = vec![0; 64 * SECTOR_SIZE];
+        set_mbr_entry(&mut data, 0, 0x83, 1, 9);
+        set_mbr_entry(&mut data, 1, 0x05, 10, 54);
+        // First EBR, at the start of the extended partition.
+        let ebr = &mut data[10 * SECTOR_SIZE..11 * SECTOR_SIZE];
+        set_mbr_entry(ebr, 0, 0x0b, 1, 19);
+        set_mbr_entry(ebr, 1, 0x05, 20, 34);
+        // Second EBR, relative to the extended partition.
+        let ebr = &mut data[30 * SECTOR_SIZE..31 * SECTOR_SIZE];
+        set_mbr_entry(ebr, 0, 0x83, 1, 33);
+
+        let disk = RamDisk::with_data(data);
+        let entries = task::block_on(read_table(&disk)).unwrap();
+        let found: Vec<_> = entries
+            .iter()
+            .map(|e| (e.number, e.start, e.sectors))
+            .collect();
+        assert_eq!(found, [(1, 1, 9), (5, 11, 19), (6, 31, 33)]);
+        assert_eq!(entries[1].kind, PartitionKind::Mbr(0x0b));
+    }
+
+    #[test_case]
+    fn gpt_partitions() {
+        let data = gpt_disk(
+            64,
+            &[
+                (Guid::EFI_SYSTEM, 34, 47, "boot"),
+                (Guid::LINUX_FILESYSTEM, 48, 62, "root"),
+            ],
+        );
+        let disk: Arc<dyn BlockDevice> = Arc::new(RamDisk::with_data(data));
+        let partitions = task::block_on(scan(disk)).unwrap();
+
+        assert_eq!(partitions.len(), 2);
+        assert_eq!(partitions[0].name(), "ram1");
+        assert_eq!(partitions[1].sector_count(), 15);
+        assert_eq!(
+            partitions[1].entry().kind,
+            PartitionKind::Gpt {
+                type_guid: Guid::LINUX_FILESYSTEM,
+                label: "root".into()
+            }
+        );
+    }
+
+    #[test_case]
+    fn partition_bounds() {
+        let data = gpt_disk(64, &[(Guid::BASIC_DATA, 40, 41, "data")]);
+        let disk: Arc<dyn BlockDevice> = Arc::new(RamDisk::with_data(data));
+        let partition = task::block_on(scan(disk)).unwrap().remove(0);
+
+        let marker = [0x5a; SECTOR_SIZE];
+        task::block_on(partition.write(1, &marker)).unwrap();
+        let mut buf = [0; 2 * SECTOR_SIZE];
+        assert_eq!(
+            task::block_on(partition.read(1, &mut buf)),
+            Err(BlockError::OutOfRange)
+        );
+        task::block_on(
+            partition
+                .parent()
+                .unwrap()
+                .read(41, &mut buf[..SECTOR_SIZE]),
+        )
+        .unwrap();
+        assert_eq!(buf[..SECTOR_SIZE], marker);
+    }
+}
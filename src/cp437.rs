@@ -0,0 +1,86 @@
+//! Code page 437, the character set of the font of the VGA text mode.
+
+/// What the glyphs 0x80 to 0xff show.
+const HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', //
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', //
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', //
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', //
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧', //
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀', //
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', //
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// What the glyphs 0x01 to 0x1f show, which text selects by character only,
+/// as these bytes are control characters in it.
+const LOW: [char; 31] = [
+    '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼', //
+    '►', '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',
+];
+
+/// What glyph 0x7f shows.
+const HOUSE: char = '⌂';
+
+/// Characters that look like a glyph made for another one.
+const ALIASES: [(char, u8); 6] = [
+    ('β', 0xe1),
+    ('μ', 0xe6),
+    ('∈', 0xee),
+    ('∅', 0xed),
+    ('Ø', 0xed),
+    ('∑', 0xe4),
+];
+
+/// The glyph shown for characters the font doesn't have.
+pub const REPLACEMENT: u8 = 0xfe;
+
+/// Returns the glyph that shows `c`, if there is one.
+pub fn encode(c: char) -> Option<u8> {
+    if (' '..='~').contains(&c) {
+        return Some(c as u8);
+    }
+    if c == HOUSE {
+        return Some(0x7f);
+    }
+    let position = |table: &[char]| table.iter().position(|&other| other == c);
+    if let Some(i) = position(&HIGH) {
+        return Some(0x80 + i as u8);
+    }
+    if let Some(i) = position(&LOW) {
+        return Some(0x01 + i as u8);
+    }
+    ALIASES
+        .iter()
+        .find(|&&(alias, _)| alias == c)
+        .map(|&(_, glyph)| glyph)
+}
+
+/// Returns the character glyph `glyph` shows, with glyph 0 as a space.
+pub fn decode(glyph: u8) -> char {
+    match glyph {
+        0x00 => ' ',
+        0x01..=0x1f => LOW[usize::from(glyph - 0x01)],
+        0x7f => HOUSE,
+        0x80..=0xff => HIGH[usize::from(glyph - 0x80)],
+        _ => char::from(glyph),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn round_trip() {
+        for glyph in 1..=u8::MAX {
+            assert_eq!(encode(decode(glyph)), Some(glyph));
+        }
+        assert_eq!(encode('╔'), Some(0xc9));
+        assert_eq!(encode('ñ'), Some(0xa4));
+        assert_eq!(encode('Ω'), Some(0xea));
+        assert_eq!(encode('μ'), encode('µ'));
+        assert_eq!(encode('€'), None);
+        assert_eq!(encode('\n'), None);
+    }
+}
@@ -0,0 +1,135 @@
+use alloc::string::String;
+use core::arch::x86_64::{__cpuid, __cpuid_count};
+
+/// CPU features queried through `CPUID`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    /// On-chip local APIC.
+    Apic,
+    /// Supervisor mode execution prevention.
+    Smep,
+    /// Supervisor mode access prevention.
+    Smap,
+    /// User mode instruction prevention.
+    Umip,
+    /// The `RDRAND` instruction.
+    RdRand,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Register {
+    Ebx,
+    Ecx,
+    Edx,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 5] = [
+        Feature::Apic,
+        Feature::Smep,
+        Feature::Smap,
+        Feature::Umip,
+        Feature::RdRand,
+    ];
+
+    /// Returns the name Linux uses for the feature.
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::Apic => "apic",
+            Feature::Smep => "smep",
+            Feature::Smap => "smap",
+            Feature::Umip => "umip",
+            Feature::RdRand => "rdrand",
+        }
+    }
+
+    /// Returns the leaf, subleaf, output register and bit reporting the feature.
+    fn location(self) -> (u32, u32, Register, u32) {
+        match self {
+            Feature::Apic => (1, 0, Register::Edx, 9),
+            Feature::Smep => (7, 0, Register::Ebx, 7),
+            Feature::Smap => (7, 0, Register::Ebx, 20),
+            Feature::Umip => (7, 0, Register::Ecx, 2),
+            Feature::RdRand => (1, 0, Register::Ecx, 30),
+        }
+    }
+}
+
+/// Returns whether the CPU supports `feature`.
+pub fn has(feature: Feature) -> bool {
+    let (leaf, subleaf, register, bit) = feature.location();
+    if leaf > max_leaf(leaf & 0x8000_0000) {
+        return false;
+    }
+
+    let result = unsafe { __cpuid_count(leaf, subleaf) };
+    let value = match register {
+        Register::Ebx => result.ebx,
+        Register::Ecx => result.ecx,
+        Register::Edx => result.edx,
+    };
+    value & (1 << bit) != 0
+}
+
+/// Identification of the CPU.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Info {
+    /// Vendor string, like `GenuineIntel`.
+    pub vendor: String,
+    /// Model name, empty if the CPU doesn't report one.
+    pub brand: String,
+    pub family: u32,
+    pub model: u32,
+    pub stepping: u32,
+}
+
+/// Returns the identification of the CPU.
+pub fn info() -> Info {
+    let leaf0 = unsafe { __cpuid(0) };
+    let vendor = [leaf0.ebx, leaf0.edx, leaf0.ecx]
+        .iter()
+        .flat_map(|register| register.to_le_bytes())
+        .map(char::from)
+        .collect();
+
+    let mut brand = String::new();
+    if max_leaf(0x8000_0000) >= 0x8000_0004 {
+        for leaf in 0x8000_0002..=0x8000_0004 {
+            let result = unsafe { __cpuid(leaf) };
+            let bytes = [result.eax, result.ebx, result.ecx, result.edx];
+            brand.extend(
+                bytes
+                    .iter()
+                    .flat_map(|register| register.to_le_bytes())
+                    .take_while(|&byte| byte != 0)
+                    .map(char::from),
+            );
+        }
+    }
+
+    // The extended family and model only apply to some base families.
+    let signature = unsafe { __cpuid(1) }.eax;
+    let base_family = signature >> 8 & 0xf;
+    let mut family = base_family;
+    let mut model = signature >> 4 & 0xf;
+    if base_family == 0xf {
+        family += signature >> 20 & 0xff;
+    }
+    if base_family == 0x6 || base_family == 0xf {
+        model |= (signature >> 16 & 0xf) << 4;
+    }
+
+    Info {
+        vendor,
+        brand: String::from(brand.trim()),
+        family,
+        model,
+        stepping: signature & 0xf,
+    }
+}
+
+/// Returns the highest supported leaf in the standard (`base == 0`) or
+/// extended (`base == 0x8000_0000`) range.
+fn max_leaf(base: u32) -> u32 {
+    unsafe { __cpuid(base).eax }
+}
@@ -0,0 +1,567 @@
+//! The device filesystem, mounted on `/dev`: the character devices registered
+//! with [`register`] and every block device.
+//!
+//! Requests for [`File::ioctl`] use the numbers Linux does, with values
+//! passed in the argument and returned rather than through pointers.
+
+use alloc::{
+    boxed::Box,
+    string::{String, ToString},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use core::{
+    cmp,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use futures_util::future::BoxFuture;
+use spin::{Mutex, Once, RwLock};
+use x86_64::instructions::random::RdRand;
+
+use crate::{
+    block::{self, BlockDevice, SECTOR_SIZE},
+    kmsg,
+    tty::{self, LocalFlags, Termios, Tty},
+    vfs::{
+        DirEntry, File, FileSystem, FileType, FsError, FsResult, Inode, Metadata, OpenFlags,
+        SeekFrom,
+    },
+};
+
+/// Returns the size of a block device in bytes.
+pub const BLKGETSIZE64: u32 = 0x8008_1272;
+/// Returns the sector size of a block device.
+pub const BLKSSZGET: u32 = 0x1268;
+/// Returns whether a block device is read-only.
+pub const BLKROGET: u32 = 0x125e;
+/// Writes the cached data of a block device to stable storage.
+pub const BLKFLSBUF: u32 = 0x1261;
+/// Returns the local mode flags of a terminal, its `c_lflag`.
+pub const TCGETS: u32 = 0x5401;
+/// Sets the local mode flags of a terminal.
+pub const TCSETS: u32 = 0x5402;
+/// Returns the foreground process group of a terminal.
+pub const TIOCGPGRP: u32 = 0x540f;
+/// Sets the foreground process group of a terminal.
+pub const TIOCSPGRP: u32 = 0x5410;
+/// Returns the size of a terminal as `rows << 16 | columns`.
+pub const TIOCGWINSZ: u32 = 0x5413;
+
+const ROOT_INO: u64 = 1;
+/// Inode number of the first block device, after those of the character
+/// devices.
+const BLOCK_INO: u64 = 1 << 32;
+/// Largest read or write of a block device done at once.
+const MAX_BLOCK_IO: usize = 64 * 1024;
+
+/// The registered character devices with their names.
+static CHAR_DEVICES: RwLock<Vec<(String, DevInode)>> = RwLock::new(Vec::new());
+
+/// A device read and written as a stream of bytes.
+pub trait CharDevice: Send + Sync {
+    /// Reads from the device, waiting until there is something to read.
+    ///
+    /// `offset` is the position of the open file, which devices that are
+    /// read like a file move past what they return and others ignore.
+    fn read<'a>(&'a self, offset: &'a mut u64, buf: &'a mut [u8])
+        -> BoxFuture<'a, FsResult<usize>>;
+
+    fn write<'a>(&'a self, buf: &'a [u8]) -> BoxFuture<'a, FsResult<usize>>;
+
+    /// Runs the device-specific request `cmd` with argument `arg`.
+    fn ioctl(&self, _cmd: u32, _arg: u64) -> FsResult<u64> {
+        Err(FsError::Unsupported)
+    }
+}
+
+/// Makes `device` available as `/dev/<name>`.
+pub fn register(name: &str, device: Arc<dyn CharDevice>) -> FsResult<()> {
+    static NEXT_INO: AtomicU64 = AtomicU64::new(ROOT_INO + 1);
+
+    let mut devices = CHAR_DEVICES.write();
+    if devices.iter().any(|(other, _)| other == name) || block::find(name).is_some() {
+        return Err(FsError::AlreadyExists);
+    }
+    let inode = DevInode {
+        ino: NEXT_INO.fetch_add(1, Ordering::Relaxed),
+        device: Device::Char(device),
+    };
+    devices.push((name.to_string(), inode));
+    Ok(())
+}
+
+/// Registers the character devices of the kernel itself.
+fn register_builtin() {
+    static REGISTERED: Once = Once::new();
+
+    REGISTERED.call_once(|| {
+        let devices: [(&str, Arc<dyn CharDevice>); 5] = [
+            ("console", Arc::new(TtyDevice(tty::console()))),
+            ("null", Arc::new(Null)),
+            ("zero", Arc::new(Zero)),
+            ("random", Arc::new(Random::new())),
+            ("kmsg", Arc::new(Kmsg)),
+        ];
+        for (name, device) in devices {
+            register(name, device).expect("built-in device registered twice");
+        }
+        for tty in tty::serials() {
+            register(tty.name(), Arc::new(TtyDevice(tty)))
+                .expect("built-in device registered twice");
+        }
+    });
+}
+
+pub struct DevFs {
+    root: Arc<DevRoot>,
+}
+
+impl DevFs {
+    pub fn new() -> Arc<Self> {
+        register_builtin();
+        Arc::new(Self {
+            root: Arc::new(DevRoot),
+        })
+    }
+}
+
+impl FileSystem for DevFs {
+    fn name(&self) -> &str {
+        "devfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+/// The directory of all devices.
+struct DevRoot;
+
+impl DevRoot {
+    /// Returns all devices with their names.
+    fn devices(&self) -> Vec<(String, DevInode)> {
+        let mut devices = CHAR_DEVICES.read().clone();
+        for (i, device) in block::devices().into_iter().enumerate() {
+            let inode = DevInode {
+                ino: BLOCK_INO + i as u64,
+                device: Device::Block(device.clone()),
+            };
+            devices.push((device.name().to_string(), inode));
+        }
+        devices
+    }
+}
+
+impl Inode for DevRoot {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            ino: ROOT_INO,
+            file_type: FileType::Directory,
+            size: 0,
+            mode: 0o755,
+            nlink: 2,
+        }
+    }
+
+    fn lookup<'a>(&'a self, name: &'a str) -> BoxFuture<'a, FsResult<Arc<dyn Inode>>> {
+        Box::pin(async move {
+            let (_, inode) = self
+                .devices()
+                .into_iter()
+                .find(|(device, _)| device == name)
+                .ok_or(FsError::NotFound)?;
+            Ok(Arc::new(inode) as Arc<dyn Inode>)
+        })
+    }
+
+    fn create<'a>(
+        &'a self,
+        _name: &'a str,
+        _file_type: FileType,
+        _mode: u16,
+    ) -> BoxFuture<'a, FsResult<Arc<dyn Inode>>> {
+        Box::pin(async { Err(FsError::ReadOnly) })
+    }
+
+    fn symlink<'a>(
+        &'a self,
+        _name: &'a str,
+        _target: &'a str,
+    ) -> BoxFuture<'a, FsResult<Arc<dyn Inode>>> {
+        Box::pin(async { Err(FsError::ReadOnly) })
+    }
+
+    fn unlink<'a>(&'a self, _name: &'a str) -> BoxFuture<'a, FsResult<()>> {
+        Box::pin(async { Err(FsError::ReadOnly) })
+    }
+
+    fn rmdir<'a>(&'a self, _name: &'a str) -> BoxFuture<'a, FsResult<()>> {
+        Box::pin(async { Err(FsError::ReadOnly) })
+    }
+
+    fn readdir(&self) -> BoxFuture<'_, FsResult<Vec<DirEntry>>> {
+        Box::pin(async move {
+            Ok(self
+                .devices()
+                .into_iter()
+                .map(|(name, inode)| DirEntry {
+                    name,
+                    ino: inode.ino,
+                    file_type: inode.metadata().file_type,
+                })
+                .collect())
+        })
+    }
+}
+
+#[derive(Clone)]
+enum Device {
+    Char(Arc<dyn CharDevice>),
+    Block(Arc<dyn BlockDevice>),
+}
+
+#[derive(Clone)]
+struct DevInode {
+    ino: u64,
+    device: Device,
+}
+
+impl Inode for DevInode {
+    fn metadata(&self) -> Metadata {
+        let (file_type, size, mode) = match &self.device {
+            Device::Char(_) => (FileType::CharDevice, 0, 0o666),
+            Device::Block(device) => {
+                let size = device.sector_count() * SECTOR_SIZE as u64;
+                (FileType::BlockDevice, size, 0o660)
+            }
+        };
+        Metadata {
+            ino: self.ino,
+            file_type,
+            size,
+            mode,
+            nlink: 1,
+        }
+    }
+
+    fn open(&self, flags: OpenFlags) -> Option<FsResult<Arc<dyn File>>> {
+        let file = DeviceFile {
+            inode: self.clone(),
+            flags,
+            offset: AtomicU64::new(0),
+        };
+        Some(Ok(Arc::new(file)))
+    }
+}
+
+/// An open device, with the offset of the next access.
+struct DeviceFile {
+    inode: DevInode,
+    flags: OpenFlags,
+    offset: AtomicU64,
+}
+
+impl DeviceFile {
+    fn check_access(&self, access: OpenFlags) -> FsResult<()> {
+        match self.flags.contains(access) {
+            true => Ok(()),
+            false => Err(FsError::BadFileDescriptor),
+        }
+    }
+}
+
+impl File for DeviceFile {
+    fn read<'a>(&'a self, buf: &'a mut [u8]) -> BoxFuture<'a, FsResult<usize>> {
+        Box::pin(async move {
+            self.check_access(OpenFlags::READ)?;
+            let mut offset = self.offset.load(Ordering::Relaxed);
+            let read = match &self.inode.device {
+                Device::Char(device) => device.read(&mut offset, buf).await?,
+                Device::Block(device) => {
+                    let read = read_block(&**device, offset, buf).await?;
+                    offset += read as u64;
+                    read
+                }
+            };
+            self.offset.store(offset, Ordering::Relaxed);
+            Ok(read)
+        })
+    }
+
+    fn write<'a>(&'a self, buf: &'a [u8]) -> BoxFuture<'a, FsResult<usize>> {
+        Box::pin(async move {
+            self.check_access(OpenFlags::WRITE)?;
+            match &self.inode.device {
+                Device::Char(device) => device.write(buf).await,
+                Device::Block(device) => {
+                    let offset = self.offset.load(Ordering::Relaxed);
+                    let written = write_block(&**device, offset, buf).await?;
+                    self.offset.fetch_add(written as u64, Ordering::Relaxed);
+                    Ok(written)
+                }
+            }
+        })
+    }
+
+    fn seek(&self, pos: SeekFrom) -> FsResult<u64> {
+        let device = match &self.inode.device {
+            Device::Block(device) => device,
+            Device::Char(_) => return Err(FsError::NotSeekable),
+        };
+        let size = device.sector_count() * SECTOR_SIZE as u64;
+        let offset = pos.resolve(self.offset.load(Ordering::Relaxed), size)?;
+        self.offset.store(offset, Ordering::Relaxed);
+        Ok(offset)
+    }
+
+    fn ioctl(&self, cmd: u32, arg: u64) -> BoxFuture<'_, FsResult<u64>> {
+        Box::pin(async move {
+            let device = match &self.inode.device {
+                Device::Char(device) => return device.ioctl(cmd, arg),
+                Device::Block(device) => device,
+            };
+            match cmd {
+                BLKGETSIZE64 => Ok(device.sector_count() * SECTOR_SIZE as u64),
+                BLKSSZGET => Ok(SECTOR_SIZE as u64),
+                BLKROGET => Ok(u64::from(device.is_read_only())),
+                BLKFLSBUF => Ok(device.flush().await.map(|()| 0)?),
+                _ => Err(FsError::Unsupported),
+            }
+        })
+    }
+
+    fn metadata(&self) -> FsResult<Metadata> {
+        Ok(self.inode.metadata())
+    }
+}
+
+/// Returns the sectors of `device` a request for `len` bytes at `offset`
+/// touches, as the first one and how many there are, limited to the device.
+fn sector_span(device: &dyn BlockDevice, offset: u64, len: usize) -> (u64, usize) {
+    let size = device.sector_count() * SECTOR_SIZE as u64;
+    let len = cmp::min(
+        cmp::min(len, MAX_BLOCK_IO) as u64,
+        size.saturating_sub(offset),
+    );
+    let first = offset / SECTOR_SIZE as u64;
+    let end = x86_64::align_up(offset + len, SECTOR_SIZE as u64) / SECTOR_SIZE as u64;
+    (first, (end - first) as usize)
+}
+
+/// Reads `device` at byte `offset`, returning how many bytes were read.
+async fn read_block(device: &dyn BlockDevice, offset: u64, buf: &mut [u8]) -> FsResult<usize> {
+    let (first, count) = sector_span(device, offset, buf.len());
+    let mut sectors = vec![0; count * SECTOR_SIZE];
+    device.read(first, &mut sectors).await?;
+    let start = (offset % SECTOR_SIZE as u64) as usize;
+    let len = cmp::min(buf.len(), sectors.len().saturating_sub(start));
+    buf[..len].copy_from_slice(&sectors[start..start + len]);
+    Ok(len)
+}
+
+/// Writes `device` at byte `offset`, returning how many bytes were written.
+///
+/// Sectors only partly written are read first.
+async fn write_block(device: &dyn BlockDevice, offset: u64, buf: &[u8]) -> FsResult<usize> {
+    let (first, count) = sector_span(device, offset, buf.len());
+    if count == 0 && !buf.is_empty() {
+        return Err(FsError::NoSpace);
+    }
+    let mut sectors = vec![0; count * SECTOR_SIZE];
+    let start = (offset % SECTOR_SIZE as u64) as usize;
+    let len = cmp::min(buf.len(), sectors.len().saturating_sub(start));
+    if start != 0 || len % SECTOR_SIZE != 0 {
+        device.read(first, &mut sectors).await?;
+    }
+    sectors[start..start + len].copy_from_slice(&buf[..len]);
+    device.write(first, &sectors).await?;
+    Ok(len)
+}
+
+/// A terminal, which reads nothing once end of file is typed.
+struct TtyDevice(&'static Tty);
+
+impl CharDevice for TtyDevice {
+    fn read<'a>(
+        &'a self,
+        _offset: &'a mut u64,
+        buf: &'a mut [u8],
+    ) -> BoxFuture<'a, FsResult<usize>> {
+        Box::pin(async move { Ok(self.0.read(buf).await) })
+    }
+
+    fn write<'a>(&'a self, buf: &'a [u8]) -> BoxFuture<'a, FsResult<usize>> {
+        Box::pin(async move {
+            self.0.write(buf).await;
+            Ok(buf.len())
+        })
+    }
+
+    fn ioctl(&self, cmd: u32, arg: u64) -> FsResult<u64> {
+        match cmd {
+            TCGETS => Ok(u64::from(self.0.termios().flags.bits())),
+            TCSETS => {
+                let bits = u32::try_from(arg).map_err(|_| FsError::InvalidArgument)?;
+                let flags = LocalFlags::from_bits(bits).ok_or(FsError::InvalidArgument)?;
+                self.0.set_termios(Termios {
+                    flags,
+                    ..self.0.termios()
+                });
+                Ok(0)
+            }
+            TIOCGPGRP => Ok(self.0.foreground() as u64),
+            TIOCSPGRP => {
+                self.0.set_foreground(arg as usize);
+                Ok(0)
+            }
+            TIOCGWINSZ => {
+                let (rows, columns) = self.0.size().ok_or(FsError::Unsupported)?;
+                Ok((rows as u64) << 16 | columns as u64)
+            }
+            _ => Err(FsError::Unsupported),
+        }
+    }
+}
+
+/// Reads nothing and discards writes.
+struct Null;
+
+impl CharDevice for Null {
+    fn read<'a>(
+        &'a self,
+        _offset: &'a mut u64,
+        _buf: &'a mut [u8],
+    ) -> BoxFuture<'a, FsResult<usize>> {
+        Box::pin(async { Ok(0) })
+    }
+
+    fn write<'a>(&'a self, buf: &'a [u8]) -> BoxFuture<'a, FsResult<usize>> {
+        Box::pin(async move { Ok(buf.len()) })
+    }
+}
+
+/// Reads zeroes and discards writes.
+struct Zero;
+
+impl CharDevice for Zero {
+    fn read<'a>(
+        &'a self,
+        _offset: &'a mut u64,
+        buf: &'a mut [u8],
+    ) -> BoxFuture<'a, FsResult<usize>> {
+        Box::pin(async move {
+            buf.fill(0);
+            Ok(buf.len())
+        })
+    }
+
+    fn write<'a>(&'a self, buf: &'a [u8]) -> BoxFuture<'a, FsResult<usize>> {
+        Box::pin(async move { Ok(buf.len()) })
+    }
+}
+
+/// Reads random bytes from `RDRAND`, or from a generator seeded with the time
+/// stamp counter on CPUs without it.
+///
+/// Writes are discarded.
+struct Random {
+    rdrand: Option<RdRand>,
+    /// State of the xorshift generator used without `RDRAND`.
+    state: Mutex<u64>,
+}
+
+impl Random {
+    fn new() -> Self {
+        let seed = unsafe { core::arch::x86_64::_rdtsc() };
+        Self {
+            rdrand: RdRand::new(),
+            // The state must not be zero.
+            state: Mutex::new(seed | 1),
+        }
+    }
+
+    fn next(&self) -> u64 {
+        if let Some(value) = self.rdrand.and_then(RdRand::get_u64) {
+            return value;
+        }
+        let mut state = self.state.lock();
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+}
+
+impl CharDevice for Random {
+    fn read<'a>(
+        &'a self,
+        _offset: &'a mut u64,
+        buf: &'a mut [u8],
+    ) -> BoxFuture<'a, FsResult<usize>> {
+        Box::pin(async move {
+            for chunk in buf.chunks_mut(8) {
+                chunk.copy_from_slice(&self.next().to_le_bytes()[..chunk.len()]);
+            }
+            Ok(buf.len())
+        })
+    }
+
+    fn write<'a>(&'a self, buf: &'a [u8]) -> BoxFuture<'a, FsResult<usize>> {
+        Box::pin(async move { Ok(buf.len()) })
+    }
+}
+
+/// The kernel log, which is read from where the open file is without
+/// waiting for more output.
+struct Kmsg;
+
+impl CharDevice for Kmsg {
+    fn read<'a>(
+        &'a self,
+        offset: &'a mut u64,
+        buf: &'a mut [u8],
+    ) -> BoxFuture<'a, FsResult<usize>> {
+        Box::pin(async move {
+            let (start, len) = kmsg::read(*offset, buf);
+            *offset = start + len as u64;
+            Ok(len)
+        })
+    }
+
+    fn write<'a>(&'a self, buf: &'a [u8]) -> BoxFuture<'a, FsResult<usize>> {
+        Box::pin(async move {
+            kmsg::write(buf);
+            Ok(buf.len())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{block::tests::RamDisk, task};
+
+    #[test_case]
+    fn partial_sectors() {
+        let disk = RamDisk::new(4);
+        let data: Vec<u8> = (0..700).map(|i| i as u8).collect();
+        assert_eq!(task::block_on(write_block(&disk, 300, &data)), Ok(700));
+        assert_eq!(disk.read_byte(299), 0);
+        assert_eq!(disk.read_byte(300), 0);
+        assert_eq!(disk.read_byte(301), 1);
+        assert_eq!(disk.read_byte(1000), 0);
+
+        let mut buf = [0; 800];
+        assert_eq!(task::block_on(read_block(&disk, 300, &mut buf)), Ok(800));
+        assert_eq!(buf[..700], data[..]);
+        // Accesses stop at the end of the device.
+        assert_eq!(task::block_on(read_block(&disk, 2000, &mut buf)), Ok(48));
+        assert_eq!(task::block_on(read_block(&disk, 2048, &mut buf)), Ok(0));
+        assert_eq!(
+            task::block_on(write_block(&disk, 2048, &data)),
+            Err(FsError::NoSpace)
+        );
+    }
+}
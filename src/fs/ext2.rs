@@ -0,0 +1,1367 @@
+//! The second extended filesystem, ext2.
+//!
+//! The device is split into block groups, each with a bitmap of its free
+//! blocks, one of its free inodes and a table of its inodes. Files reach
+//! their data through the direct and indirect block pointers of their inode.
+//!
+//! Deleted files free their blocks right away, even if they are still open.
+
+use alloc::{
+    boxed::Box,
+    collections::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use core::cmp;
+use futures_util::future::BoxFuture;
+use spin::Mutex;
+
+use crate::{
+    block::{cache::BlockCache, BlockDevice, SECTOR_SIZE},
+    task::mutex::Mutex as AsyncMutex,
+    vfs::{DirEntry, FileSystem, FileType, FsError, FsResult, Inode, Metadata},
+};
+
+/// Bytes of the device kept in the block cache.
+const CACHE_SIZE: usize = 64 * 1024;
+
+const SUPERBLOCK_OFFSET: u64 = 1024;
+const MAGIC: u16 = 0xef53;
+const ROOT_INO: u32 = 2;
+
+const INCOMPAT_FILETYPE: u32 = 0x0002;
+const RO_COMPAT_SPARSE_SUPER: u32 = 0x0001;
+const RO_COMPAT_LARGE_FILE: u32 = 0x0002;
+
+/// Size of the part of an inode the driver uses, which is all of it in the
+/// original format.
+const INODE_SIZE: usize = 128;
+const GROUP_SIZE: u64 = 32;
+const DIRECT_BLOCKS: usize = 12;
+/// Inode flag of directories with a hash index, which is not kept up to
+/// date.
+const INDEX_FL: u32 = 0x1000;
+/// Size of the block pointers of an inode, which hold the target of short
+/// symlinks instead.
+const FAST_SYMLINK_SIZE: usize = 60;
+const MAX_NAME: usize = 255;
+
+const S_IFMT: u16 = 0xf000;
+const S_IFIFO: u16 = 0x1000;
+const S_IFCHR: u16 = 0x2000;
+const S_IFDIR: u16 = 0x4000;
+const S_IFBLK: u16 = 0x6000;
+const S_IFREG: u16 = 0x8000;
+const S_IFLNK: u16 = 0xa000;
+
+/// The parameters of the filesystem from its superblock.
+struct Superblock {
+    block_size: u64,
+    blocks_count: u32,
+    first_data_block: u32,
+    blocks_per_group: u32,
+    inodes_per_group: u32,
+    inode_size: u64,
+    group_count: u32,
+    /// When the filesystem was last written, in seconds since 1970.
+    write_time: u32,
+    /// Whether directory entries store the file type.
+    filetype: bool,
+    /// Whether files can be larger than 4 GiB.
+    large_file: bool,
+    /// Whether the driver may write, as it knows all features that matter
+    /// for writing.
+    writable: bool,
+}
+
+impl Superblock {
+    fn parse(raw: &[u8]) -> FsResult<Self> {
+        if u16_at(raw, 56) != MAGIC {
+            return Err(FsError::Corrupt);
+        }
+        let log_block_size = u32_at(raw, 24);
+        let blocks_count = u32_at(raw, 4);
+        let first_data_block = u32_at(raw, 20);
+        let blocks_per_group = u32_at(raw, 32);
+        let inodes_per_group = u32_at(raw, 40);
+        if log_block_size > 6 || blocks_per_group == 0 || inodes_per_group == 0 {
+            return Err(FsError::Corrupt);
+        }
+
+        // Revision 0 has none of the fields from `s_first_ino` on.
+        let (inode_size, incompat, ro_compat) = match u32_at(raw, 76) {
+            0 => (INODE_SIZE as u64, 0, 0),
+            _ => (
+                u64::from(u16_at(raw, 88)),
+                u32_at(raw, 96),
+                u32_at(raw, 100),
+            ),
+        };
+        if inode_size < INODE_SIZE as u64 || !inode_size.is_power_of_two() {
+            return Err(FsError::Corrupt);
+        }
+        if incompat & !INCOMPAT_FILETYPE != 0 {
+            return Err(FsError::Unsupported);
+        }
+
+        let data_blocks = blocks_count
+            .checked_sub(first_data_block)
+            .ok_or(FsError::Corrupt)?;
+        let group_count =
+            data_blocks / blocks_per_group + u32::from(data_blocks % blocks_per_group != 0);
+        // Inode numbers must fit in 32 bits with one to spare for
+        // `deletion_time`.
+        match inodes_per_group.checked_mul(group_count) {
+            Some(inodes) if inodes == u32_at(raw, 0) && inodes != u32::MAX => {}
+            _ => return Err(FsError::Corrupt),
+        }
+        Ok(Superblock {
+            block_size: 1024 << log_block_size,
+            blocks_count,
+            first_data_block,
+            blocks_per_group,
+            inodes_per_group,
+            inode_size,
+            group_count,
+            write_time: u32_at(raw, 48),
+            filetype: incompat & INCOMPAT_FILETYPE != 0,
+            large_file: ro_compat & RO_COMPAT_LARGE_FILE != 0,
+            writable: ro_compat & !(RO_COMPAT_SPARSE_SUPER | RO_COMPAT_LARGE_FILE) == 0,
+        })
+    }
+
+    /// Returns the time to mark deleted inodes with.
+    ///
+    /// There is no clock, so it is the last time another system wrote the
+    /// filesystem. It must not be an inode number, which would make it look
+    /// like an orphan list.
+    fn deletion_time(&self) -> u32 {
+        cmp::max(
+            self.write_time,
+            self.inodes_per_group * self.group_count + 1,
+        )
+    }
+
+    /// Returns the number of block pointers in a block.
+    fn pointers_per_block(&self) -> u64 {
+        self.block_size / 4
+    }
+
+    /// Returns the number of blocks in group `group`, which is less than
+    /// the others for the last one.
+    fn blocks_in_group(&self, group: u32) -> u32 {
+        let start = group * self.blocks_per_group;
+        cmp::min(
+            self.blocks_per_group,
+            self.blocks_count - self.first_data_block - start,
+        )
+    }
+}
+
+/// A block group descriptor.
+#[derive(Debug, Clone, Copy)]
+struct Group {
+    block_bitmap: u32,
+    inode_bitmap: u32,
+    inode_table: u32,
+    free_blocks: u16,
+    free_inodes: u16,
+    used_dirs: u16,
+}
+
+impl Group {
+    fn parse(raw: &[u8]) -> Self {
+        Group {
+            block_bitmap: u32_at(raw, 0),
+            inode_bitmap: u32_at(raw, 4),
+            inode_table: u32_at(raw, 8),
+            free_blocks: u16_at(raw, 12),
+            free_inodes: u16_at(raw, 14),
+            used_dirs: u16_at(raw, 16),
+        }
+    }
+}
+
+/// The allocation state of the filesystem.
+struct State {
+    groups: Vec<Group>,
+}
+
+/// The first 128 bytes of an on-disk inode.
+#[derive(Clone, Copy)]
+struct RawInode([u8; INODE_SIZE]);
+
+impl RawInode {
+    fn new(mode: u16, links: u16) -> Self {
+        let mut raw = RawInode([0; INODE_SIZE]);
+        raw.0[0..2].copy_from_slice(&mode.to_le_bytes());
+        raw.set_links(links);
+        raw
+    }
+
+    fn mode(&self) -> u16 {
+        u16_at(&self.0, 0)
+    }
+
+    fn file_type(&self) -> FileType {
+        match self.mode() & S_IFMT {
+            S_IFDIR => FileType::Directory,
+            S_IFLNK => FileType::Symlink,
+            S_IFCHR => FileType::CharDevice,
+            S_IFBLK => FileType::BlockDevice,
+            S_IFREG => FileType::Regular,
+            // Sockets can only be used like FIFOs.
+            _ => FileType::Fifo,
+        }
+    }
+
+    fn size(&self) -> u64 {
+        let high = match self.file_type() {
+            FileType::Regular => u64::from(u32_at(&self.0, 108)),
+            _ => 0,
+        };
+        high << 32 | u64::from(u32_at(&self.0, 4))
+    }
+
+    fn set_size(&mut self, size: u64) {
+        self.0[4..8].copy_from_slice(&(size as u32).to_le_bytes());
+        if self.file_type() == FileType::Regular {
+            self.0[108..112].copy_from_slice(&((size >> 32) as u32).to_le_bytes());
+        }
+    }
+
+    fn links(&self) -> u16 {
+        u16_at(&self.0, 26)
+    }
+
+    fn set_links(&mut self, links: u16) {
+        self.0[26..28].copy_from_slice(&links.to_le_bytes());
+    }
+
+    /// Returns the number of 512-byte sectors allocated to the inode.
+    fn sectors(&self) -> u32 {
+        u32_at(&self.0, 28)
+    }
+
+    fn set_sectors(&mut self, sectors: u32) {
+        self.0[28..32].copy_from_slice(&sectors.to_le_bytes());
+    }
+
+    fn flags(&self) -> u32 {
+        u32_at(&self.0, 32)
+    }
+
+    fn set_flags(&mut self, flags: u32) {
+        self.0[32..36].copy_from_slice(&flags.to_le_bytes());
+    }
+
+    /// Returns block pointer `index`, the direct ones followed by the
+    /// single, double and triple indirect one.
+    fn block(&self, index: usize) -> u32 {
+        u32_at(&self.0, 40 + index * 4)
+    }
+
+    fn set_block(&mut self, index: usize, block: u32) {
+        self.0[40 + index * 4..44 + index * 4].copy_from_slice(&block.to_le_bytes());
+    }
+
+    fn file_acl(&self) -> u32 {
+        u32_at(&self.0, 104)
+    }
+
+    /// Returns the block pointer area, which holds the target of a fast
+    /// symlink.
+    fn inline_data(&mut self) -> &mut [u8] {
+        &mut self.0[40..40 + FAST_SYMLINK_SIZE]
+    }
+
+    /// Returns whether the inode is a symlink with the target in the inode.
+    fn is_fast_symlink(&self, block_size: u64) -> bool {
+        let acl_sectors = match self.file_acl() {
+            0 => 0,
+            _ => (block_size / SECTOR_SIZE as u64) as u32,
+        };
+        self.file_type() == FileType::Symlink && self.sectors() == acl_sectors
+    }
+}
+
+pub struct Ext2Fs {
+    this: Weak<Ext2Fs>,
+    cache: BlockCache,
+    superblock: Superblock,
+    /// The group descriptors, held by every operation from its first read
+    /// to its last write, as allocating updates a bitmap, the free counts of
+    /// the group and the inode table together.
+    state: AsyncMutex<State>,
+    /// The root inode as last written, to open it again without a read.
+    root: Mutex<RawInode>,
+    /// The open inodes by number, so that each has one copy of its entry in
+    /// the inode table.
+    inodes: Mutex<BTreeMap<u32, Weak<Ext2Inode>>>,
+}
+
+impl Ext2Fs {
+    /// Opens the ext2 filesystem on `device`, failing with
+    /// [`FsError::Corrupt`] if there is none and [`FsError::Unsupported`] if
+    /// it uses features the driver doesn't know.
+    ///
+    /// The filesystem is read-only if it has unknown features that only
+    /// matter for writing.
+    pub async fn new(device: Arc<dyn BlockDevice>) -> FsResult<Arc<Self>> {
+        let mut raw = [0; 1024];
+        device
+            .read(SUPERBLOCK_OFFSET / SECTOR_SIZE as u64, &mut raw)
+            .await?;
+        let mut superblock = Superblock::parse(&raw)?;
+        if u64::from(superblock.blocks_count) * superblock.block_size
+            > device.sector_count() * SECTOR_SIZE as u64
+        {
+            return Err(FsError::Corrupt);
+        }
+        superblock.writable &= !device.is_read_only();
+
+        let block_size = superblock.block_size as usize;
+        let cache = BlockCache::new(device, block_size, cmp::max(CACHE_SIZE / block_size, 4));
+        // The descriptor table follows the superblock.
+        let table = u64::from(superblock.first_data_block + 1) * superblock.block_size;
+        let mut raw = vec![0; superblock.group_count as usize * GROUP_SIZE as usize];
+        cache.read_at(table, &mut raw).await?;
+        let groups = raw
+            .chunks_exact(GROUP_SIZE as usize)
+            .map(Group::parse)
+            .collect();
+        let state = State { groups };
+
+        let root_offset = inode_offset(&superblock, &state, ROOT_INO)?;
+        let mut root = RawInode([0; INODE_SIZE]);
+        cache.read_at(root_offset, &mut root.0).await?;
+        if root.file_type() != FileType::Directory {
+            return Err(FsError::Corrupt);
+        }
+
+        Ok(Arc::new_cyclic(|this| Self {
+            this: this.clone(),
+            cache,
+            superblock,
+            state: AsyncMutex::new(state),
+            root: Mutex::new(root),
+            inodes: Mutex::new(BTreeMap::new()),
+        }))
+    }
+
+    pub fn block_size(&self) -> u64 {
+        self.superblock.block_size
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        !self.superblock.writable
+    }
+
+    /// Returns the number of free blocks and inodes.
+    pub async fn free_counts(&self) -> (u32, u32) {
+        let state = self.state.lock().await;
+        state.groups.iter().fold((0, 0), |(blocks, inodes), group| {
+            (
+                blocks + u32::from(group.free_blocks),
+                inodes + u32::from(group.free_inodes),
+            )
+        })
+    }
+
+    fn check_writable(&self) -> FsResult<()> {
+        match self.superblock.writable {
+            true => Ok(()),
+            false => Err(FsError::ReadOnly),
+        }
+    }
+
+    fn block_offset(&self, block: u32) -> u64 {
+        u64::from(block) * self.superblock.block_size
+    }
+
+    fn inode_offset(&self, state: &State, ino: u32) -> FsResult<u64> {
+        inode_offset(&self.superblock, state, ino)
+    }
+
+    /// Returns the inode numbered `ino`, reading it if it is not in use.
+    async fn load(&self, state: &State, ino: u32) -> FsResult<Arc<Ext2Inode>> {
+        if let Some(inode) = self.inodes.lock().get(&ino).and_then(Weak::upgrade) {
+            return Ok(inode);
+        }
+        let mut raw = RawInode([0; INODE_SIZE]);
+        self.cache
+            .read_at(self.inode_offset(state, ino)?, &mut raw.0)
+            .await?;
+        Ok(self.inode(ino, raw))
+    }
+
+    fn inode(&self, ino: u32, raw: RawInode) -> Arc<Ext2Inode> {
+        let mut inodes = self.inodes.lock();
+        if let Some(inode) = inodes.get(&ino).and_then(Weak::upgrade) {
+            return inode;
+        }
+        inodes.retain(|_, inode| inode.strong_count() > 0);
+        let inode = Arc::new(Ext2Inode {
+            fs: self.this.upgrade().expect("filesystem dropped"),
+            ino,
+            node: Mutex::new(Node {
+                raw,
+                removed: false,
+            }),
+        });
+        inodes.insert(ino, Arc::downgrade(&inode));
+        inode
+    }
+
+    async fn write_inode(&self, state: &State, ino: u32, raw: &RawInode) -> FsResult<()> {
+        let offset = self.inode_offset(state, ino)?;
+        self.cache.write_at(offset, &raw.0).await?;
+        if ino == ROOT_INO {
+            *self.root.lock() = *raw;
+        }
+        Ok(())
+    }
+
+    async fn write_group(&self, state: &State, group: u32) -> FsResult<()> {
+        let g = &state.groups[group as usize];
+        let mut raw = [0; 18];
+        raw[0..4].copy_from_slice(&g.block_bitmap.to_le_bytes());
+        raw[4..8].copy_from_slice(&g.inode_bitmap.to_le_bytes());
+        raw[8..12].copy_from_slice(&g.inode_table.to_le_bytes());
+        raw[12..14].copy_from_slice(&g.free_blocks.to_le_bytes());
+        raw[14..16].copy_from_slice(&g.free_inodes.to_le_bytes());
+        raw[16..18].copy_from_slice(&g.used_dirs.to_le_bytes());
+        let table = self.block_offset(self.superblock.first_data_block + 1);
+        let offset = table + u64::from(group) * GROUP_SIZE;
+        Ok(self.cache.write_at(offset, &raw).await?)
+    }
+
+    /// Finds a clear bit among the first `count` of a bitmap block and sets
+    /// it, returning its index.
+    async fn take_bit(&self, bitmap: u32, count: u32) -> FsResult<Option<u32>> {
+        let mut bits = vec![0; self.superblock.block_size as usize];
+        self.cache
+            .read_at(self.block_offset(bitmap), &mut bits)
+            .await?;
+        let bit = (0..count).find(|&bit| bits[bit as usize / 8] & (1 << (bit % 8)) == 0);
+        if let Some(bit) = bit {
+            let byte = bits[bit as usize / 8] | 1 << (bit % 8);
+            let offset = self.block_offset(bitmap) + u64::from(bit / 8);
+            self.cache.write_at(offset, &[byte]).await?;
+        }
+        Ok(bit)
+    }
+
+    async fn clear_bit(&self, bitmap: u32, bit: u32) -> FsResult<()> {
+        let offset = self.block_offset(bitmap) + u64::from(bit / 8);
+        let mut byte = [0];
+        self.cache.read_at(offset, &mut byte).await?;
+        if byte[0] & (1 << (bit % 8)) == 0 {
+            return Err(FsError::Corrupt);
+        }
+        byte[0] &= !(1 << (bit % 8));
+        Ok(self.cache.write_at(offset, &byte).await?)
+    }
+
+    /// Allocates a zeroed block, preferably in group `goal`.
+    async fn allocate_block(&self, state: &mut State, goal: u32) -> FsResult<u32> {
+        let sb = &self.superblock;
+        for i in 0..sb.group_count {
+            let group = (goal + i) % sb.group_count;
+            let g = state.groups[group as usize];
+            if g.free_blocks == 0 {
+                continue;
+            }
+            let bit = match self
+                .take_bit(g.block_bitmap, sb.blocks_in_group(group))
+                .await?
+            {
+                Some(bit) => bit,
+                None => continue,
+            };
+
+            state.groups[group as usize].free_blocks -= 1;
+            self.write_group(state, group).await?;
+            let block = sb.first_data_block + group * sb.blocks_per_group + bit;
+            let zeroes = vec![0; sb.block_size as usize];
+            self.cache
+                .write_at(self.block_offset(block), &zeroes)
+                .await?;
+            return Ok(block);
+        }
+        Err(FsError::NoSpace)
+    }
+
+    async fn free_block(&self, state: &mut State, block: u32) -> FsResult<()> {
+        let sb = &self.superblock;
+        if block < sb.first_data_block || block >= sb.blocks_count {
+            return Err(FsError::Corrupt);
+        }
+        let group = (block - sb.first_data_block) / sb.blocks_per_group;
+        let bit = (block - sb.first_data_block) % sb.blocks_per_group;
+        self.clear_bit(state.groups[group as usize].block_bitmap, bit)
+            .await?;
+        state.groups[group as usize].free_blocks += 1;
+        self.write_group(state, group).await
+    }
+
+    /// Allocates an inode, preferably in group `goal`, and zeroes it.
+    async fn allocate_inode(&self, state: &mut State, goal: u32, directory: bool) -> FsResult<u32> {
+        let sb = &self.superblock;
+        for i in 0..sb.group_count {
+            let group = (goal + i) % sb.group_count;
+            let g = state.groups[group as usize];
+            if g.free_inodes == 0 {
+                continue;
+            }
+            let bit = match self.take_bit(g.inode_bitmap, sb.inodes_per_group).await? {
+                Some(bit) => bit,
+                None => continue,
+            };
+
+            let g = &mut state.groups[group as usize];
+            g.free_inodes -= 1;
+            if directory {
+                g.used_dirs += 1;
+            }
+            self.write_group(state, group).await?;
+            let ino = group * sb.inodes_per_group + bit + 1;
+            let zeroes = vec![0; sb.inode_size as usize];
+            let offset = self.inode_offset(state, ino)?;
+            self.cache.write_at(offset, &zeroes).await?;
+            return Ok(ino);
+        }
+        Err(FsError::NoSpace)
+    }
+
+    /// Frees the blocks and the number of inode `inode`, which must have no
+    /// links left.
+    async fn release(&self, state: &mut State, inode: &Ext2Inode) -> FsResult<()> {
+        let mut raw = inode.raw();
+        if !raw.is_fast_symlink(self.superblock.block_size) {
+            self.truncate_blocks(state, &mut raw, 0).await?;
+        }
+        raw.set_links(0);
+        let dtime = self.superblock.deletion_time();
+        raw.0[20..24].copy_from_slice(&dtime.to_le_bytes());
+        self.write_inode(state, inode.ino, &raw).await?;
+
+        let sb = &self.superblock;
+        let group = (inode.ino - 1) / sb.inodes_per_group;
+        let bit = (inode.ino - 1) % sb.inodes_per_group;
+        self.clear_bit(state.groups[group as usize].inode_bitmap, bit)
+            .await?;
+        let g = &mut state.groups[group as usize];
+        g.free_inodes += 1;
+        if raw.file_type() == FileType::Directory {
+            g.used_dirs -= 1;
+        }
+        self.write_group(state, group).await?;
+
+        self.inodes.lock().remove(&inode.ino);
+        *inode.node.lock() = Node { raw, removed: true };
+        Ok(())
+    }
+
+    /// Returns the block holding block `index` of the data of an inode, or
+    /// zero for a hole.
+    ///
+    /// With `allocate`, holes are filled with new blocks in group `goal`,
+    /// which updates `raw`.
+    async fn data_block(
+        &self,
+        mut allocate: Option<(&mut State, u32)>,
+        raw: &mut RawInode,
+        index: u64,
+    ) -> FsResult<u32> {
+        let per_block = self.superblock.pointers_per_block();
+        let (slot, path) = block_path(index, per_block).ok_or(FsError::NoSpace)?;
+        let sectors_per_block = (self.superblock.block_size / SECTOR_SIZE as u64) as u32;
+
+        let mut block = raw.block(slot);
+        if block == 0 {
+            let (state, goal) = match &mut allocate {
+                Some(allocate) => allocate,
+                None => return Ok(0),
+            };
+            block = self.allocate_block(state, *goal).await?;
+            raw.set_block(slot, block);
+            raw.set_sectors(raw.sectors() + sectors_per_block);
+        }
+        for pointer in path {
+            let offset = self.block_offset(block) + pointer * 4;
+            let mut next = [0; 4];
+            self.cache.read_at(offset, &mut next).await?;
+            block = u32::from_le_bytes(next);
+            if block == 0 {
+                let (state, goal) = match &mut allocate {
+                    Some(allocate) => allocate,
+                    None => return Ok(0),
+                };
+                block = self.allocate_block(state, *goal).await?;
+                self.cache.write_at(offset, &block.to_le_bytes()).await?;
+                raw.set_sectors(raw.sectors() + sectors_per_block);
+            }
+        }
+        Ok(block)
+    }
+
+    /// Frees the data blocks of an inode from block `keep` on, with the
+    /// indirect blocks that no longer point anywhere.
+    async fn truncate_blocks(
+        &self,
+        state: &mut State,
+        raw: &mut RawInode,
+        keep: u64,
+    ) -> FsResult<()> {
+        for slot in cmp::min(keep, DIRECT_BLOCKS as u64) as usize..DIRECT_BLOCKS {
+            let block = raw.block(slot);
+            if block != 0 {
+                self.free_data_block(state, raw, block).await?;
+                raw.set_block(slot, 0);
+            }
+        }
+
+        let per_block = self.superblock.pointers_per_block();
+        let mut start = DIRECT_BLOCKS as u64;
+        let mut span = per_block;
+        for level in 1..=3 {
+            let slot = DIRECT_BLOCKS + level - 1;
+            let block = raw.block(slot);
+            if block != 0 && keep < start + span {
+                let from = keep.saturating_sub(start);
+                if self.free_tree(state, raw, block, level, from).await? {
+                    self.free_data_block(state, raw, block).await?;
+                    raw.set_block(slot, 0);
+                }
+            }
+            start += span;
+            span *= per_block;
+        }
+        Ok(())
+    }
+
+    /// Frees the blocks an indirect block of `level` points to from index
+    /// `from` on, returning whether it points nowhere anymore.
+    fn free_tree<'a>(
+        &'a self,
+        state: &'a mut State,
+        raw: &'a mut RawInode,
+        block: u32,
+        level: usize,
+        from: u64,
+    ) -> BoxFuture<'a, FsResult<bool>> {
+        Box::pin(async move {
+            let mut pointers = vec![0; self.superblock.block_size as usize];
+            self.cache
+                .read_at(self.block_offset(block), &mut pointers)
+                .await?;
+            let span = self.superblock.pointers_per_block().pow(level as u32 - 1);
+            for (i, pointer) in pointers.chunks_exact_mut(4).enumerate() {
+                let child = u32_at(pointer, 0);
+                let child_start = i as u64 * span;
+                if child == 0 || child_start + span <= from {
+                    continue;
+                }
+                let empty = level == 1
+                    || self
+                        .free_tree(
+                            state,
+                            raw,
+                            child,
+                            level - 1,
+                            from.saturating_sub(child_start),
+                        )
+                        .await?;
+                if empty {
+                    self.free_data_block(state, raw, child).await?;
+                    pointer.fill(0);
+                }
+            }
+            if from > 0 {
+                self.cache
+                    .write_at(self.block_offset(block), &pointers)
+                    .await?;
+            }
+            Ok(pointers.iter().all(|&b| b == 0))
+        })
+    }
+
+    async fn free_data_block(
+        &self,
+        state: &mut State,
+        raw: &mut RawInode,
+        block: u32,
+    ) -> FsResult<()> {
+        self.free_block(state, block).await?;
+        let sectors_per_block = (self.superblock.block_size / SECTOR_SIZE as u64) as u32;
+        raw.set_sectors(raw.sectors().saturating_sub(sectors_per_block));
+        Ok(())
+    }
+
+    /// Reads the data of an inode into `buf`, with zeroes for holes.
+    async fn read_data(&self, raw: &mut RawInode, offset: u64, buf: &mut [u8]) -> FsResult<()> {
+        let block_size = self.superblock.block_size;
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let start = (pos % block_size) as usize;
+            let len = cmp::min(buf.len() - done, block_size as usize - start);
+            let dest = &mut buf[done..done + len];
+            match self.data_block(None, raw, pos / block_size).await? {
+                0 => dest.fill(0),
+                block => {
+                    let offset = self.block_offset(block) + start as u64;
+                    self.cache.read_at(offset, dest).await?;
+                }
+            }
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Writes `buf` into the data of inode `ino`, allocating blocks as
+    /// needed.
+    async fn write_data(
+        &self,
+        state: &mut State,
+        ino: u32,
+        raw: &mut RawInode,
+        offset: u64,
+        buf: &[u8],
+    ) -> FsResult<()> {
+        let block_size = self.superblock.block_size;
+        let goal = (ino - 1) / self.superblock.inodes_per_group;
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let start = (pos % block_size) as usize;
+            let len = cmp::min(buf.len() - done, block_size as usize - start);
+            let block = self
+                .data_block(Some((&mut *state, goal)), raw, pos / block_size)
+                .await?;
+            let offset = self.block_offset(block) + start as u64;
+            self.cache.write_at(offset, &buf[done..done + len]).await?;
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Returns the entries of a directory, with the offset of each.
+    async fn entries(&self, raw: &mut RawInode) -> FsResult<Vec<(u64, Entry)>> {
+        let block_size = self.superblock.block_size;
+        let mut entries = Vec::new();
+        let mut block = vec![0; block_size as usize];
+        for index in 0..raw.size() / block_size {
+            self.read_data(raw, index * block_size, &mut block).await?;
+            for (offset, entry) in self.parse_block(&block)? {
+                if entry.ino != 0 {
+                    entries.push((index * block_size + offset as u64, entry));
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Returns the entries of a directory block, including unused ones,
+    /// with their offsets.
+    fn parse_block(&self, block: &[u8]) -> FsResult<Vec<(usize, Entry)>> {
+        let mut entries = Vec::new();
+        let mut offset = 0;
+        while offset < block.len() {
+            let rec_len = usize::from(u16_at(block, offset + 4));
+            let name_len = match self.superblock.filetype {
+                true => usize::from(block[offset + 6]),
+                false => usize::from(u16_at(block, offset + 6)),
+            };
+            if rec_len < 8
+                || rec_len % 4 != 0
+                || offset + rec_len > block.len()
+                || 8 + name_len > rec_len
+            {
+                return Err(FsError::Corrupt);
+            }
+            let name = &block[offset + 8..offset + 8 + name_len];
+            entries.push((
+                offset,
+                Entry {
+                    ino: u32_at(block, offset),
+                    rec_len,
+                    name: String::from_utf8_lossy(name).into_owned(),
+                },
+            ));
+            offset += rec_len;
+        }
+        Ok(entries)
+    }
+
+    /// Adds an entry called `name` for inode `ino` of `file_type` to a
+    /// directory, growing it by a block if needed.
+    async fn add_entry(
+        &self,
+        state: &mut State,
+        dir_ino: u32,
+        dir: &mut RawInode,
+        name: &str,
+        ino: u32,
+        file_type: FileType,
+    ) -> FsResult<()> {
+        let block_size = self.superblock.block_size;
+        let needed = entry_size(name.len());
+        let mut block = vec![0; block_size as usize];
+        for index in 0..dir.size() / block_size {
+            self.read_data(dir, index * block_size, &mut block).await?;
+            for (offset, entry) in self.parse_block(&block)? {
+                let used = match entry.ino {
+                    0 => 0,
+                    _ => entry_size(entry.name.len()),
+                };
+                if entry.rec_len - used < needed {
+                    continue;
+                }
+                if used > 0 {
+                    block[offset + 4..offset + 6].copy_from_slice(&(used as u16).to_le_bytes());
+                }
+                let entry = self.encode_entry(ino, entry.rec_len - used, name, file_type);
+                let start = offset + used;
+                block[start..start + entry.len()].copy_from_slice(&entry);
+                self.write_data(state, dir_ino, dir, index * block_size, &block)
+                    .await?;
+                return Ok(());
+            }
+        }
+
+        let entry = self.encode_entry(ino, block_size as usize, name, file_type);
+        block.fill(0);
+        block[..entry.len()].copy_from_slice(&entry);
+        let size = dir.size();
+        self.write_data(state, dir_ino, dir, size, &block).await?;
+        dir.set_size(size + block_size);
+        // The hash index doesn't know about the new entries.
+        dir.set_flags(dir.flags() & !INDEX_FL);
+        Ok(())
+    }
+
+    /// Removes the entry called `name` from a directory by merging it into
+    /// the previous one.
+    async fn remove_entry(
+        &self,
+        state: &mut State,
+        dir_ino: u32,
+        dir: &mut RawInode,
+        name: &str,
+    ) -> FsResult<()> {
+        let block_size = self.superblock.block_size;
+        let mut block = vec![0; block_size as usize];
+        for index in 0..dir.size() / block_size {
+            self.read_data(dir, index * block_size, &mut block).await?;
+            let mut previous: Option<(usize, usize)> = None;
+            for (offset, entry) in self.parse_block(&block)? {
+                if entry.ino == 0 || entry.name != name {
+                    previous = Some((offset, entry.rec_len));
+                    continue;
+                }
+                match previous {
+                    Some((previous, rec_len)) => {
+                        let merged = (rec_len + entry.rec_len) as u16;
+                        block[previous + 4..previous + 6].copy_from_slice(&merged.to_le_bytes());
+                    }
+                    None => block[offset..offset + 4].fill(0),
+                }
+                dir.set_flags(dir.flags() & !INDEX_FL);
+                return self
+                    .write_data(state, dir_ino, dir, index * block_size, &block)
+                    .await;
+            }
+        }
+        Err(FsError::NotFound)
+    }
+
+    fn encode_entry(&self, ino: u32, rec_len: usize, name: &str, file_type: FileType) -> Vec<u8> {
+        let mut entry = vec![0; entry_size(name.len())];
+        entry[0..4].copy_from_slice(&ino.to_le_bytes());
+        entry[4..6].copy_from_slice(&(rec_len as u16).to_le_bytes());
+        entry[6] = name.len() as u8;
+        if self.superblock.filetype {
+            entry[7] = match file_type {
+                FileType::Regular => 1,
+                FileType::Directory => 2,
+                FileType::CharDevice => 3,
+                FileType::BlockDevice => 4,
+                FileType::Fifo => 5,
+                FileType::Symlink => 7,
+            };
+        }
+        entry[8..8 + name.len()].copy_from_slice(name.as_bytes());
+        entry
+    }
+}
+
+impl FileSystem for Ext2Fs {
+    fn name(&self) -> &str {
+        "ext2"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        let raw = *self.root.lock();
+        self.inode(ROOT_INO, raw)
+    }
+
+    fn sync(&self) -> BoxFuture<'_, FsResult<()>> {
+        Box::pin(async move {
+            let state = self.state.lock().await;
+            if self.superblock.writable {
+                let (blocks, inodes) = state.groups.iter().fold((0u32, 0u32), |(b, i), g| {
+                    (b + u32::from(g.free_blocks), i + u32::from(g.free_inodes))
+                });
+                self.cache
+                    .write_at(SUPERBLOCK_OFFSET + 12, &blocks.to_le_bytes())
+                    .await?;
+                self.cache
+                    .write_at(SUPERBLOCK_OFFSET + 16, &inodes.to_le_bytes())
+                    .await?;
+            }
+            Ok(self.cache.sync().await?)
+        })
+    }
+}
+
+/// Returns where inode `ino` is on the device.
+fn inode_offset(superblock: &Superblock, state: &State, ino: u32) -> FsResult<u64> {
+    let index = ino.checked_sub(1).ok_or(FsError::Corrupt)?;
+    let group = state
+        .groups
+        .get((index / superblock.inodes_per_group) as usize)
+        .ok_or(FsError::Corrupt)?;
+    let offset = u64::from(index % superblock.inodes_per_group) * superblock.inode_size;
+    Ok(u64::from(group.inode_table) * superblock.block_size + offset)
+}
+
+/// A directory entry.
+struct Entry {
+    /// Zero for an unused entry.
+    ino: u32,
+    rec_len: usize,
+    name: String,
+}
+
+/// Returns the size a directory entry with a name of `len` bytes needs.
+fn entry_size(len: usize) -> usize {
+    (8 + len + 3) & !3
+}
+
+/// Returns the block pointer of the inode that block `index` of the data is
+/// reached through, and the indices into the indirect blocks on the way.
+fn block_path(index: u64, per_block: u64) -> Option<(usize, Vec<u64>)> {
+    let direct = DIRECT_BLOCKS as u64;
+    if index < direct {
+        return Some((index as usize, Vec::new()));
+    }
+    let mut index = index - direct;
+    let mut span = per_block;
+    for level in 1..=3 {
+        if index < span {
+            let path = (0..level)
+                .rev()
+                .map(|i| index / per_block.pow(i) % per_block)
+                .collect();
+            return Some((DIRECT_BLOCKS + level as usize - 1, path));
+        }
+        index -= span;
+        span *= per_block;
+    }
+    None
+}
+
+struct Node {
+    raw: RawInode,
+    /// Whether the last link to the inode went, and with it its blocks and
+    /// its bit in the inode bitmap.
+    removed: bool,
+}
+
+struct Ext2Inode {
+    fs: Arc<Ext2Fs>,
+    ino: u32,
+    node: Mutex<Node>,
+}
+
+impl Ext2Inode {
+    fn raw(&self) -> RawInode {
+        self.node.lock().raw
+    }
+
+    fn check_directory(&self) -> FsResult<RawInode> {
+        let raw = self.raw();
+        match raw.file_type() {
+            FileType::Directory => Ok(raw),
+            _ => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn check_live(&self) -> FsResult<RawInode> {
+        let node = self.node.lock();
+        match node.removed {
+            true => Err(FsError::NotFound),
+            false => Ok(node.raw),
+        }
+    }
+
+    /// Writes `raw` to disk and makes it the state of the inode.
+    async fn store(&self, state: &State, raw: RawInode) -> FsResult<()> {
+        self.fs.write_inode(state, self.ino, &raw).await?;
+        self.node.lock().raw = raw;
+        Ok(())
+    }
+
+    async fn find(&self, state: &State, name: &str) -> FsResult<Arc<Ext2Inode>> {
+        let mut raw = self.check_directory()?;
+        let entries = self.fs.entries(&mut raw).await?;
+        let (_, entry) = entries
+            .iter()
+            .find(|(_, entry)| entry.name == name)
+            .ok_or(FsError::NotFound)?;
+        self.fs.load(state, entry.ino).await
+    }
+
+    /// Creates an inode with `mode` and an entry called `name` for it.
+    ///
+    /// Symlinks get `target` as their data.
+    async fn add(&self, name: &str, mode: u16, target: &str) -> FsResult<Arc<Ext2Inode>> {
+        self.fs.check_writable()?;
+        if name.is_empty() || name.len() > MAX_NAME || name.contains('\0') {
+            return Err(FsError::InvalidArgument);
+        }
+        // The directory is read under the lock, so that it is written back
+        // with the changes of other operations on it.
+        let mut state = self.fs.state.lock().await;
+        let mut dir = self.check_live()?;
+        self.check_directory()?;
+        match self.find(&state, name).await {
+            Ok(_) => return Err(FsError::AlreadyExists),
+            Err(FsError::NotFound) => {}
+            Err(err) => return Err(err),
+        }
+
+        let file_type = RawInode::new(mode, 0).file_type();
+        let directory = file_type == FileType::Directory;
+        let goal = (self.ino - 1) / self.fs.superblock.inodes_per_group;
+        let ino = self.fs.allocate_inode(&mut state, goal, directory).await?;
+        let mut raw = RawInode::new(mode, if directory { 2 } else { 1 });
+        let inode = self.fs.inode(ino, raw);
+
+        let result = async {
+            if directory {
+                let block_size = self.fs.superblock.block_size as usize;
+                let mut block = vec![0; block_size];
+                let dot = self.fs.encode_entry(ino, 12, ".", FileType::Directory);
+                let dot_dot =
+                    self.fs
+                        .encode_entry(self.ino, block_size - 12, "..", FileType::Directory);
+                block[..dot.len()].copy_from_slice(&dot);
+                block[12..12 + dot_dot.len()].copy_from_slice(&dot_dot);
+                self.fs
+                    .write_data(&mut state, ino, &mut raw, 0, &block)
+                    .await?;
+                raw.set_size(block_size as u64);
+            } else if file_type == FileType::Symlink {
+                if target.len() < FAST_SYMLINK_SIZE {
+                    raw.inline_data()[..target.len()].copy_from_slice(target.as_bytes());
+                } else {
+                    self.fs
+                        .write_data(&mut state, ino, &mut raw, 0, target.as_bytes())
+                        .await?;
+                }
+                raw.set_size(target.len() as u64);
+            }
+            inode.store(&state, raw).await?;
+
+            self.fs
+                .add_entry(&mut state, self.ino, &mut dir, name, ino, file_type)
+                .await?;
+            if directory {
+                dir.set_links(dir.links() + 1);
+            }
+            self.store(&state, dir).await
+        };
+        if let Err(err) = result.await {
+            self.fs.release(&mut state, &inode).await?;
+            return Err(err);
+        }
+        Ok(inode)
+    }
+
+    /// Removes the entry called `name`, which must be a directory if and
+    /// only if `directory`, deleting its inode if it was the last link.
+    async fn remove(&self, name: &str, directory: bool) -> FsResult<()> {
+        self.fs.check_writable()?;
+        let mut state = self.fs.state.lock().await;
+        let mut dir = self.check_live()?;
+        let inode = self.find(&state, name).await?;
+        let mut raw = inode.raw();
+        match raw.file_type() {
+            FileType::Directory if !directory => return Err(FsError::IsADirectory),
+            FileType::Directory => {
+                if !self
+                    .fs
+                    .entries(&mut raw)
+                    .await?
+                    .iter()
+                    .all(|(_, entry)| entry.name == "." || entry.name == "..")
+                {
+                    return Err(FsError::NotEmpty);
+                }
+            }
+            _ if directory => return Err(FsError::NotADirectory),
+            _ => {}
+        }
+
+        self.fs
+            .remove_entry(&mut state, self.ino, &mut dir, name)
+            .await?;
+        if directory {
+            dir.set_links(dir.links() - 1);
+        }
+        self.store(&state, dir).await?;
+
+        let links = match directory {
+            // The entry of the directory in itself goes too.
+            true => 0,
+            false => raw.links().saturating_sub(1),
+        };
+        if links == 0 {
+            self.fs.release(&mut state, &inode).await
+        } else {
+            raw.set_links(links);
+            inode.store(&state, raw).await
+        }
+    }
+}
+
+impl Inode for Ext2Inode {
+    fn metadata(&self) -> Metadata {
+        let raw = self.raw();
+        Metadata {
+            ino: u64::from(self.ino),
+            file_type: raw.file_type(),
+            size: raw.size(),
+            mode: raw.mode() & !S_IFMT,
+            nlink: u32::from(raw.links()),
+        }
+    }
+
+    fn lookup<'a>(&'a self, name: &'a str) -> BoxFuture<'a, FsResult<Arc<dyn Inode>>> {
+        Box::pin(async move {
+            let state = self.fs.state.lock().await;
+            Ok(self.find(&state, name).await? as Arc<dyn Inode>)
+        })
+    }
+
+    fn create<'a>(
+        &'a self,
+        name: &'a str,
+        file_type: FileType,
+        mode: u16,
+    ) -> BoxFuture<'a, FsResult<Arc<dyn Inode>>> {
+        Box::pin(async move {
+            let kind = match file_type {
+                FileType::Regular => S_IFREG,
+                FileType::Directory => S_IFDIR,
+                FileType::Fifo => S_IFIFO,
+                _ => return Err(FsError::Unsupported),
+            };
+            Ok(self.add(name, kind | (mode & 0o7777), "").await? as Arc<dyn Inode>)
+        })
+    }
+
+    fn symlink<'a>(
+        &'a self,
+        name: &'a str,
+        target: &'a str,
+    ) -> BoxFuture<'a, FsResult<Arc<dyn Inode>>> {
+        Box::pin(async move {
+            if target.is_empty() || target.len() >= self.fs.superblock.block_size as usize {
+                return Err(FsError::InvalidArgument);
+            }
+            Ok(self.add(name, S_IFLNK | 0o777, target).await? as Arc<dyn Inode>)
+        })
+    }
+
+    fn unlink<'a>(&'a self, name: &'a str) -> BoxFuture<'a, FsResult<()>> {
+        Box::pin(self.remove(name, false))
+    }
+
+    fn rmdir<'a>(&'a self, name: &'a str) -> BoxFuture<'a, FsResult<()>> {
+        Box::pin(self.remove(name, true))
+    }
+
+    fn readdir(&self) -> BoxFuture<'_, FsResult<Vec<DirEntry>>> {
+        Box::pin(async move {
+            let state = self.fs.state.lock().await;
+            let mut raw = self.check_directory()?;
+            let mut entries = Vec::new();
+            for (_, entry) in self.fs.entries(&mut raw).await? {
+                if entry.name == "." || entry.name == ".." {
+                    continue;
+                }
+                let inode = self.fs.load(&state, entry.ino).await?;
+                entries.push(DirEntry {
+                    name: entry.name,
+                    ino: u64::from(entry.ino),
+                    file_type: inode.raw().file_type(),
+                });
+            }
+            Ok(entries)
+        })
+    }
+
+    fn read_at<'a>(&'a self, offset: u64, buf: &'a mut [u8]) -> BoxFuture<'a, FsResult<usize>> {
+        Box::pin(async move {
+            let _state = self.fs.state.lock().await;
+            let mut raw = self.raw();
+            if raw.file_type() == FileType::Directory {
+                return Err(FsError::IsADirectory);
+            }
+            let len = cmp::min(buf.len() as u64, raw.size().saturating_sub(offset)) as usize;
+            self.fs.read_data(&mut raw, offset, &mut buf[..len]).await?;
+            Ok(len)
+        })
+    }
+
+    fn write_at<'a>(&'a self, offset: u64, buf: &'a [u8]) -> BoxFuture<'a, FsResult<usize>> {
+        Box::pin(async move {
+            self.fs.check_writable()?;
+            let mut state = self.fs.state.lock().await;
+            let mut raw = self.check_live()?;
+            if raw.file_type() == FileType::Directory {
+                return Err(FsError::IsADirectory);
+            }
+            let end = offset
+                .checked_add(buf.len() as u64)
+                .ok_or(FsError::NoSpace)?;
+            if end > u64::from(u32::MAX) && !self.fs.superblock.large_file {
+                return Err(FsError::NoSpace);
+            }
+
+            let result = self
+                .fs
+                .write_data(&mut state, self.ino, &mut raw, offset, buf)
+                .await;
+            // Blocks allocated before a failure stay with the inode.
+            if result.is_ok() && end > raw.size() {
+                raw.set_size(end);
+            }
+            self.store(&state, raw).await?;
+            result.map(|()| buf.len())
+        })
+    }
+
+    fn truncate(&self, size: u64) -> BoxFuture<'_, FsResult<()>> {
+        Box::pin(async move {
+            self.fs.check_writable()?;
+            let mut state = self.fs.state.lock().await;
+            let mut raw = self.check_live()?;
+            if raw.file_type() == FileType::Directory {
+                return Err(FsError::IsADirectory);
+            }
+            if size > u64::from(u32::MAX) && !self.fs.superblock.large_file {
+                return Err(FsError::NoSpace);
+            }
+
+            let block_size = self.fs.superblock.block_size;
+            if size < raw.size() {
+                let keep = x86_64::align_up(size, block_size) / block_size;
+                self.fs.truncate_blocks(&mut state, &mut raw, keep).await?;
+                // Zero the rest of the last block, so growing the file again
+                // reads zeroes there.
+                let tail = (block_size - size % block_size) % block_size;
+                let block = match tail {
+                    0 => 0,
+                    _ => {
+                        let index = size / block_size;
+                        self.fs.data_block(None, &mut raw, index).await?
+                    }
+                };
+                if block != 0 {
+                    let zeroes = vec![0; tail as usize];
+                    let offset = self.fs.block_offset(block) + size % block_size;
+                    self.fs.cache.write_at(offset, &zeroes).await?;
+                }
+            }
+            raw.set_size(size);
+            self.store(&state, raw).await
+        })
+    }
+
+    fn read_link(&self) -> BoxFuture<'_, FsResult<String>> {
+        Box::pin(async move {
+            let _state = self.fs.state.lock().await;
+            let mut raw = self.raw();
+            if raw.file_type() != FileType::Symlink {
+                return Err(FsError::InvalidArgument);
+            }
+            let len = raw.size() as usize;
+            let target = if raw.is_fast_symlink(self.fs.superblock.block_size) {
+                raw.inline_data()
+                    .get(..len)
+                    .ok_or(FsError::Corrupt)?
+                    .to_vec()
+            } else {
+                let mut target = vec![0; len];
+                self.fs.read_data(&mut raw, 0, &mut target).await?;
+                target
+            };
+            String::from_utf8(target).map_err(|_| FsError::Corrupt)
+        })
+    }
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    let mut raw = [0; 4];
+    raw.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(raw)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn block_paths() {
+        assert_eq!(block_path(3, 256), Some((3, Vec::new())));
+        assert_eq!(block_path(12, 256), Some((12, vec![0])));
+        assert_eq!(block_path(12 + 255, 256), Some((12, vec![255])));
+        assert_eq!(block_path(12 + 256, 256), Some((13, vec![0, 0])));
+        assert_eq!(block_path(12 + 256 + 257, 256), Some((13, vec![1, 1])));
+        let triple = 12 + 256 + 256 * 256;
+        assert_eq!(block_path(triple, 256), Some((14, vec![0, 0, 0])));
+        assert_eq!(block_path(triple + 256 * 256 * 256, 256), None);
+    }
+
+    #[test_case]
+    fn entry_sizes() {
+        assert_eq!(entry_size(1), 12);
+        assert_eq!(entry_size(4), 12);
+        assert_eq!(entry_size(5), 16);
+    }
+
+    #[test_case]
+    fn inode_count_overflow() {
+        let mut raw = [0; 1024];
+        raw[56..58].copy_from_slice(&MAGIC.to_le_bytes());
+        raw[4..8].copy_from_slice(&16u32.to_le_bytes());
+        raw[32..36].copy_from_slice(&8u32.to_le_bytes());
+        raw[40..44].copy_from_slice(&0x8000_0000u32.to_le_bytes());
+        assert!(matches!(Superblock::parse(&raw), Err(FsError::Corrupt)));
+        raw[40..44].copy_from_slice(&16u32.to_le_bytes());
+        assert!(matches!(Superblock::parse(&raw), Err(FsError::Corrupt)));
+        raw[0..4].copy_from_slice(&32u32.to_le_bytes());
+        assert!(Superblock::parse(&raw).is_ok());
+    }
+}
@@ -0,0 +1,1283 @@
+//! The FAT12, FAT16 and FAT32 filesystems, with long file names.
+//!
+//! FAT has no inodes, so the number of an inode is the disk offset of its
+//! directory entry. Names are compared ignoring ASCII case.
+
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, BTreeSet},
+    format,
+    string::String,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use core::cmp;
+use futures_util::future::BoxFuture;
+use spin::Mutex;
+
+use crate::{
+    block::{cache::BlockCache, BlockDevice, SECTOR_SIZE},
+    task::mutex::Mutex as AsyncMutex,
+    vfs::{DirEntry, FileSystem, FileType, FsError, FsResult, Inode, Metadata},
+};
+
+/// Number of sectors kept in the block cache.
+const CACHE_SECTORS: usize = 128;
+
+const ATTR_READ_ONLY: u8 = 0x01;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+/// The attributes of a long file name entry.
+const ATTR_LONG_NAME: u8 = 0x0f;
+/// The attribute bits that make up `ATTR_LONG_NAME` and `ATTR_DIRECTORY`.
+const ATTR_LONG_NAME_MASK: u8 = 0x3f;
+
+/// Case flags of a short entry, set when the base name or extension is
+/// lowercase.
+const LOWERCASE_BASE: u8 = 0x08;
+const LOWERCASE_EXT: u8 = 0x10;
+
+const ENTRY_SIZE: usize = 32;
+/// First name byte of a deleted entry.
+const DELETED: u8 = 0xe5;
+/// Flag of the sequence number of the last long file name entry of a name.
+const LAST_LONG_ENTRY: u8 = 0x40;
+/// Offsets of the UTF-16 code units of a name in a long file name entry.
+const LONG_ENTRY_UNITS: [usize; 13] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+/// Longest name, in UTF-16 code units.
+const MAX_NAME: usize = 255;
+
+/// Written to all date fields, as there is no clock yet: 1980-01-01.
+const DATE: u16 = (1 << 5) | 1;
+
+const ROOT_INO: u64 = 1;
+
+const FS_INFO_LEAD_SIGNATURE: u32 = 0x4161_5252;
+const FS_INFO_SIGNATURE: u32 = 0x6141_7272;
+/// Value of the FSInfo hints when they are unknown.
+const UNKNOWN: u32 = 0xffff_ffff;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+impl FatType {
+    /// Returns the mask of the bits of a FAT entry.
+    fn mask(self) -> u32 {
+        match self {
+            FatType::Fat12 => 0x0fff,
+            FatType::Fat16 => 0xffff,
+            FatType::Fat32 => 0x0fff_ffff,
+        }
+    }
+
+    /// Returns the lowest FAT entry marking the end of a chain.
+    fn end_of_chain(self) -> u32 {
+        self.mask() & 0x0fff_fff8
+    }
+}
+
+/// Where the structures of the filesystem are on the device, in bytes.
+struct Layout {
+    fat_type: FatType,
+    bytes_per_sector: u64,
+    cluster_size: u64,
+    fat_start: u64,
+    fat_size: u64,
+    fat_count: u8,
+    /// The only FAT in use, if the FATs are not mirrored.
+    active_fat: Option<u8>,
+    /// The fixed root directory region of FAT12 and FAT16, and its size.
+    root_region: Option<(u64, u64)>,
+    /// The first cluster of the root directory of FAT32.
+    root_cluster: u32,
+    data_start: u64,
+    cluster_count: u32,
+    /// The FSInfo sector of FAT32, if valid.
+    fs_info: Option<u64>,
+}
+
+impl Layout {
+    fn parse(boot: &[u8], device_sectors: u64) -> FsResult<Self> {
+        if boot[510..512] != [0x55, 0xaa] {
+            return Err(FsError::Corrupt);
+        }
+        let bytes_per_sector = u64::from(u16_at(boot, 11));
+        let sectors_per_cluster = u64::from(boot[13]);
+        let reserved_sectors = u64::from(u16_at(boot, 14));
+        let fat_count = boot[16];
+        let root_entries = u64::from(u16_at(boot, 17));
+        let total_sectors = match u16_at(boot, 19) {
+            0 => u64::from(u32_at(boot, 32)),
+            sectors => u64::from(sectors),
+        };
+        let fat32 = root_entries == 0 && u16_at(boot, 22) == 0;
+        let fat_sectors = if fat32 {
+            u64::from(u32_at(boot, 36))
+        } else {
+            u64::from(u16_at(boot, 22))
+        };
+
+        if !matches!(bytes_per_sector, 512 | 1024 | 2048 | 4096)
+            || !sectors_per_cluster.is_power_of_two()
+            || reserved_sectors == 0
+            || fat_count == 0
+            || fat_sectors == 0
+            || total_sectors * bytes_per_sector > device_sectors * SECTOR_SIZE as u64
+        {
+            return Err(FsError::Corrupt);
+        }
+
+        let root_size = x86_64::align_up(root_entries * ENTRY_SIZE as u64, bytes_per_sector);
+        let fat_start = reserved_sectors * bytes_per_sector;
+        let fat_size = fat_sectors * bytes_per_sector;
+        let root_start = fat_start + u64::from(fat_count) * fat_size;
+        let data_start = root_start + root_size;
+        let cluster_size = sectors_per_cluster * bytes_per_sector;
+        let cluster_count = (total_sectors * bytes_per_sector)
+            .checked_sub(data_start)
+            .ok_or(FsError::Corrupt)?
+            / cluster_size;
+
+        // The type only depends on the number of clusters.
+        let fat_type = match cluster_count {
+            0..=4084 => FatType::Fat12,
+            4085..=65524 => FatType::Fat16,
+            _ => FatType::Fat32,
+        };
+        if (fat_type == FatType::Fat32) != fat32 || cluster_count > 0x0fff_fff5 {
+            return Err(FsError::Corrupt);
+        }
+        let cluster_count = cluster_count as u32;
+        // Entry bits are rounded up to whole bytes.
+        let fat_bits = match fat_type {
+            FatType::Fat12 => 12,
+            FatType::Fat16 => 16,
+            FatType::Fat32 => 32,
+        };
+        if fat_size * 8 < (u64::from(cluster_count) + 2) * fat_bits {
+            return Err(FsError::Corrupt);
+        }
+
+        let mut layout = Layout {
+            fat_type,
+            bytes_per_sector,
+            cluster_size,
+            fat_start,
+            fat_size,
+            fat_count,
+            active_fat: None,
+            root_region: Some((root_start, root_size)),
+            root_cluster: 0,
+            data_start,
+            cluster_count,
+            fs_info: None,
+        };
+        if fat32 {
+            let flags = u16_at(boot, 40);
+            if flags & 0x80 != 0 {
+                layout.active_fat = Some((flags & 0x0f) as u8);
+            }
+            layout.root_region = None;
+            layout.root_cluster = u32_at(boot, 44);
+            if !layout.is_cluster(layout.root_cluster) {
+                return Err(FsError::Corrupt);
+            }
+            layout.fs_info = match u64::from(u16_at(boot, 48)) {
+                0 | 0xffff => None,
+                sector => Some(sector * bytes_per_sector),
+            };
+        }
+        Ok(layout)
+    }
+
+    fn is_cluster(&self, cluster: u32) -> bool {
+        (2..self.cluster_count + 2).contains(&cluster)
+    }
+
+    fn cluster_offset(&self, cluster: u32) -> u64 {
+        self.data_start + u64::from(cluster - 2) * self.cluster_size
+    }
+
+    /// Returns the number of clusters holding `size` bytes.
+    fn clusters_for(&self, size: u64) -> usize {
+        (x86_64::align_up(size, self.cluster_size) / self.cluster_size) as usize
+    }
+}
+
+/// State of the cluster allocator.
+struct Allocator {
+    /// Where to start looking for a free cluster.
+    next_free: u32,
+    free_count: Option<u32>,
+}
+
+pub struct FatFs {
+    this: Weak<FatFs>,
+    cache: BlockCache,
+    layout: Layout,
+    /// Serializes all operations, which update several structures at once.
+    allocator: AsyncMutex<Allocator>,
+    /// The inodes in use, so each file has a single inode.
+    inodes: Mutex<BTreeMap<u64, Weak<FatInode>>>,
+}
+
+impl FatFs {
+    /// Opens the FAT filesystem on `device`, failing with
+    /// [`FsError::Corrupt`] if there is none.
+    pub async fn new(device: Arc<dyn BlockDevice>) -> FsResult<Arc<Self>> {
+        let mut boot = [0; SECTOR_SIZE];
+        device.read(0, &mut boot).await?;
+        let layout = Layout::parse(&boot, device.sector_count())?;
+        let cache = BlockCache::new(device, layout.bytes_per_sector as usize, CACHE_SECTORS);
+
+        let mut allocator = Allocator {
+            next_free: 2,
+            free_count: None,
+        };
+        if let Some(offset) = layout.fs_info {
+            let mut fs_info = [0; SECTOR_SIZE];
+            cache.read_at(offset, &mut fs_info).await?;
+            if u32_at(&fs_info, 0) == FS_INFO_LEAD_SIGNATURE
+                && u32_at(&fs_info, 484) == FS_INFO_SIGNATURE
+            {
+                let free_count = u32_at(&fs_info, 488);
+                let next_free = u32_at(&fs_info, 492);
+                if free_count <= layout.cluster_count {
+                    allocator.free_count = Some(free_count);
+                }
+                if layout.is_cluster(next_free) {
+                    allocator.next_free = next_free;
+                }
+            }
+        }
+
+        Ok(Arc::new_cyclic(|this| Self {
+            this: this.clone(),
+            cache,
+            layout,
+            allocator: AsyncMutex::new(allocator),
+            inodes: Mutex::new(BTreeMap::new()),
+        }))
+    }
+
+    pub fn fat_type(&self) -> FatType {
+        self.layout.fat_type
+    }
+
+    pub fn cluster_size(&self) -> u64 {
+        self.layout.cluster_size
+    }
+
+    /// Counts the free clusters.
+    pub async fn free_clusters(&self) -> FsResult<u32> {
+        let _allocator = self.allocator.lock().await;
+        let mut free = 0;
+        for cluster in 2..self.layout.cluster_count + 2 {
+            if self.read_fat(cluster).await? == 0 {
+                free += 1;
+            }
+        }
+        Ok(free)
+    }
+
+    /// Returns the inode of the directory entry at `ino`, or of the root.
+    fn inode(&self, ino: u64, file_type: FileType, node: Node) -> Arc<FatInode> {
+        let mut inodes = self.inodes.lock();
+        if let Some(inode) = inodes.get(&ino).and_then(Weak::upgrade) {
+            return inode;
+        }
+        inodes.retain(|_, inode| inode.strong_count() > 0);
+        let inode = Arc::new(FatInode {
+            fs: self.this.upgrade().expect("filesystem dropped"),
+            ino,
+            file_type,
+            node: Mutex::new(node),
+        });
+        inodes.insert(ino, Arc::downgrade(&inode));
+        inode
+    }
+
+    /// Returns the byte offset of entry `cluster` in FAT number `fat`.
+    fn fat_offset(&self, fat: u8, cluster: u32) -> u64 {
+        let cluster = u64::from(cluster);
+        let offset = match self.layout.fat_type {
+            FatType::Fat12 => cluster + cluster / 2,
+            FatType::Fat16 => cluster * 2,
+            FatType::Fat32 => cluster * 4,
+        };
+        self.layout.fat_start + u64::from(fat) * self.layout.fat_size + offset
+    }
+
+    async fn read_fat(&self, cluster: u32) -> FsResult<u32> {
+        let offset = self.fat_offset(self.layout.active_fat.unwrap_or(0), cluster);
+        let mut raw = [0; 4];
+        let len = match self.layout.fat_type {
+            FatType::Fat32 => 4,
+            _ => 2,
+        };
+        self.cache.read_at(offset, &mut raw[..len]).await?;
+        let value = u32::from_le_bytes(raw);
+        Ok(match self.layout.fat_type {
+            FatType::Fat12 if cluster % 2 == 1 => value >> 4,
+            fat_type => value & fat_type.mask(),
+        })
+    }
+
+    async fn write_fat(&self, cluster: u32, value: u32) -> FsResult<()> {
+        let fats = match self.layout.active_fat {
+            Some(fat) => fat..fat + 1,
+            None => 0..self.layout.fat_count,
+        };
+        for fat in fats {
+            let offset = self.fat_offset(fat, cluster);
+            match self.layout.fat_type {
+                FatType::Fat12 => {
+                    // Entries share a byte with their neighbour.
+                    let mut raw = [0; 2];
+                    self.cache.read_at(offset, &mut raw).await?;
+                    let old = u16::from_le_bytes(raw);
+                    let value = value as u16 & 0x0fff;
+                    let new = match cluster % 2 {
+                        0 => (old & 0xf000) | value,
+                        _ => (old & 0x000f) | (value << 4),
+                    };
+                    self.cache.write_at(offset, &new.to_le_bytes()).await?;
+                }
+                FatType::Fat16 => {
+                    self.cache
+                        .write_at(offset, &(value as u16).to_le_bytes())
+                        .await?;
+                }
+                FatType::Fat32 => {
+                    // The high four bits are reserved and must be kept.
+                    let mut raw = [0; 4];
+                    self.cache.read_at(offset, &mut raw).await?;
+                    let new = (u32::from_le_bytes(raw) & 0xf000_0000) | (value & 0x0fff_ffff);
+                    self.cache.write_at(offset, &new.to_le_bytes()).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the clusters of the chain starting at `first`, which is empty
+    /// for zero.
+    async fn chain(&self, first: u32) -> FsResult<Vec<u32>> {
+        let mut chain = Vec::new();
+        let mut cluster = first;
+        while cluster != 0 {
+            if !self.layout.is_cluster(cluster) || chain.len() as u32 >= self.layout.cluster_count {
+                return Err(FsError::Corrupt);
+            }
+            chain.push(cluster);
+            cluster = match self.read_fat(cluster).await? {
+                next if next >= self.layout.fat_type.end_of_chain() => 0,
+                0 => return Err(FsError::Corrupt),
+                next => next,
+            };
+        }
+        Ok(chain)
+    }
+
+    /// Allocates clusters until `chain` has `len` of them, all zeroed.
+    ///
+    /// Nothing is allocated if there are not enough free clusters.
+    async fn extend(
+        &self,
+        allocator: &mut Allocator,
+        chain: &mut Vec<u32>,
+        len: usize,
+    ) -> FsResult<()> {
+        let old_len = chain.len();
+        while chain.len() < len {
+            if let Err(err) = self.allocate(allocator, chain).await {
+                self.release(allocator, &chain[old_len..]).await?;
+                chain.truncate(old_len);
+                if let Some(&last) = chain.last() {
+                    self.write_fat(last, self.layout.fat_type.mask()).await?;
+                }
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Allocates a zeroed cluster and appends it to `chain`.
+    async fn allocate(&self, allocator: &mut Allocator, chain: &mut Vec<u32>) -> FsResult<()> {
+        let count = self.layout.cluster_count;
+        let start = allocator.next_free.clamp(2, count + 1) - 2;
+        for i in 0..count {
+            let cluster = (start + i) % count + 2;
+            if self.read_fat(cluster).await? != 0 {
+                continue;
+            }
+
+            self.write_fat(cluster, self.layout.fat_type.mask()).await?;
+            if let Some(&last) = chain.last() {
+                self.write_fat(last, cluster).await?;
+            }
+            chain.push(cluster);
+            allocator.next_free = cluster + 1;
+            if let Some(free) = &mut allocator.free_count {
+                *free = free.saturating_sub(1);
+            }
+            let zeroes = vec![0; self.layout.cluster_size as usize];
+            self.cache
+                .write_at(self.layout.cluster_offset(cluster), &zeroes)
+                .await?;
+            return Ok(());
+        }
+        Err(FsError::NoSpace)
+    }
+
+    /// Marks `clusters` as free.
+    async fn release(&self, allocator: &mut Allocator, clusters: &[u32]) -> FsResult<()> {
+        for &cluster in clusters {
+            self.write_fat(cluster, 0).await?;
+            if let Some(free) = &mut allocator.free_count {
+                *free += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the entries of the directory starting at cluster `first`, or of
+    /// the FAT12 or FAT16 root directory for zero.
+    async fn read_dir(&self, first: u32) -> FsResult<DirData> {
+        let extents = match (first, self.layout.root_region) {
+            (0, Some(region)) => vec![region],
+            (first, _) => self
+                .chain(first)
+                .await?
+                .into_iter()
+                .map(|cluster| {
+                    (
+                        self.layout.cluster_offset(cluster),
+                        self.layout.cluster_size,
+                    )
+                })
+                .collect(),
+        };
+        let mut data = vec![0; extents.iter().map(|&(_, len)| len as usize).sum()];
+        let mut done = 0;
+        for &(offset, len) in &extents {
+            self.cache
+                .read_at(offset, &mut data[done..done + len as usize])
+                .await?;
+            done += len as usize;
+        }
+        Ok(DirData { extents, data })
+    }
+
+    async fn write_entry(&self, offset: u64, entry: &[u8; ENTRY_SIZE]) -> FsResult<()> {
+        Ok(self.cache.write_at(offset, entry).await?)
+    }
+
+    /// Stores the first cluster and size of an inode in its directory entry.
+    async fn update_entry(&self, ino: u64, node: &Node) -> FsResult<()> {
+        if ino == ROOT_INO {
+            return Ok(());
+        }
+        let high = (node.first_cluster >> 16) as u16;
+        let low = node.first_cluster as u16;
+        self.cache.write_at(ino + 20, &high.to_le_bytes()).await?;
+        self.cache.write_at(ino + 26, &low.to_le_bytes()).await?;
+        self.cache
+            .write_at(ino + 28, &node.size.to_le_bytes())
+            .await?;
+        Ok(())
+    }
+}
+
+impl FileSystem for FatFs {
+    fn name(&self) -> &str {
+        match self.layout.fat_type {
+            FatType::Fat12 => "fat12",
+            FatType::Fat16 => "fat16",
+            FatType::Fat32 => "fat32",
+        }
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        let node = Node {
+            first_cluster: self.layout.root_cluster,
+            size: 0,
+            attr: ATTR_DIRECTORY,
+            removed: false,
+        };
+        self.inode(ROOT_INO, FileType::Directory, node)
+    }
+
+    fn sync(&self) -> BoxFuture<'_, FsResult<()>> {
+        Box::pin(async move {
+            let allocator = self.allocator.lock().await;
+            if let Some(offset) = self.layout.fs_info {
+                let free_count = allocator.free_count.unwrap_or(UNKNOWN);
+                self.cache
+                    .write_at(offset + 488, &free_count.to_le_bytes())
+                    .await?;
+                self.cache
+                    .write_at(offset + 492, &allocator.next_free.to_le_bytes())
+                    .await?;
+            }
+            Ok(self.cache.sync().await?)
+        })
+    }
+}
+
+/// The entries of a directory and where they are on the device.
+struct DirData {
+    /// The runs of the device holding the entries, as offset and length.
+    extents: Vec<(u64, u64)>,
+    data: Vec<u8>,
+}
+
+impl DirData {
+    fn len(&self) -> usize {
+        self.data.len() / ENTRY_SIZE
+    }
+
+    fn entry(&self, index: usize) -> &[u8] {
+        &self.data[index * ENTRY_SIZE..(index + 1) * ENTRY_SIZE]
+    }
+
+    /// Returns the disk offset of entry `index`.
+    fn offset(&self, index: usize) -> u64 {
+        let mut pos = (index * ENTRY_SIZE) as u64;
+        for &(start, len) in &self.extents {
+            if pos < len {
+                return start + pos;
+            }
+            pos -= len;
+        }
+        panic!("directory entry {} out of range", index);
+    }
+
+    /// Returns the files in the directory, skipping the volume label and the
+    /// `.` and `..` entries.
+    fn files(&self) -> Vec<Slot> {
+        struct LongName {
+            start: usize,
+            /// The sequence number of the next entry, which counts down.
+            next: u8,
+            checksum: u8,
+            units: Vec<u16>,
+        }
+
+        let mut files = Vec::new();
+        let mut long: Option<LongName> = None;
+        for index in 0..self.len() {
+            let entry = self.entry(index);
+            match entry[0] {
+                0 => break,
+                DELETED => {
+                    long = None;
+                    continue;
+                }
+                _ => {}
+            }
+
+            if entry[11] & ATTR_LONG_NAME_MASK == ATTR_LONG_NAME {
+                let seq = entry[0] & !LAST_LONG_ENTRY;
+                if entry[0] & LAST_LONG_ENTRY != 0 {
+                    long = Some(LongName {
+                        start: index,
+                        next: seq,
+                        checksum: entry[13],
+                        units: vec![0; usize::from(seq) * LONG_ENTRY_UNITS.len()],
+                    });
+                }
+                long =
+                    long.filter(|long| seq > 0 && seq == long.next && entry[13] == long.checksum);
+                if let Some(long) = &mut long {
+                    let start = usize::from(seq - 1) * LONG_ENTRY_UNITS.len();
+                    for (i, &offset) in LONG_ENTRY_UNITS.iter().enumerate() {
+                        long.units[start + i] = u16_at(entry, offset);
+                    }
+                    long.next -= 1;
+                }
+                continue;
+            }
+
+            let mut short = [0; 11];
+            short.copy_from_slice(&entry[..11]);
+            let long = long
+                .take()
+                .filter(|long| long.next == 0 && long.checksum == checksum(&short));
+            if entry[11] & ATTR_VOLUME_ID != 0 || short[0] == b'.' {
+                continue;
+            }
+
+            let short_name = short_name_string(&short, entry[12]);
+            let long_name = long.as_ref().map(|long| {
+                let len = long.units.iter().position(|&unit| unit == 0);
+                char::decode_utf16(
+                    long.units[..len.unwrap_or(long.units.len())]
+                        .iter()
+                        .copied(),
+                )
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect::<String>()
+            });
+            files.push(Slot {
+                name: long_name
+                    .filter(|name| !name.is_empty())
+                    .unwrap_or_else(|| short_name.clone()),
+                short_name,
+                short,
+                start: long.map_or(index, |long| long.start),
+                index,
+                node: Node {
+                    first_cluster: u32::from(u16_at(entry, 20)) << 16
+                        | u32::from(u16_at(entry, 26)),
+                    size: u32_at(entry, 28),
+                    attr: entry[11],
+                    removed: false,
+                },
+            });
+        }
+        files
+    }
+
+    /// Returns the index of the first of `count` free entries, which may
+    /// extend past the end of the directory.
+    fn find_free(&self, count: usize) -> usize {
+        let mut run = 0;
+        for index in 0..self.len() {
+            match self.entry(index)[0] {
+                // All entries after the end marker are free.
+                0 => return index - run,
+                DELETED => run += 1,
+                _ => run = 0,
+            }
+            if run == count {
+                return index + 1 - run;
+            }
+        }
+        self.len() - run
+    }
+}
+
+/// A file in a directory.
+struct Slot {
+    /// The long name, or the short name if there is none.
+    name: String,
+    short_name: String,
+    short: [u8; 11],
+    /// Index of the first entry of the file, which is a long name entry if
+    /// there is a long name.
+    start: usize,
+    /// Index of the short entry.
+    index: usize,
+    node: Node,
+}
+
+impl Slot {
+    fn matches(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name) || self.short_name.eq_ignore_ascii_case(name)
+    }
+
+    fn file_type(&self) -> FileType {
+        file_type(self.node.attr)
+    }
+}
+
+fn file_type(attr: u8) -> FileType {
+    match attr & ATTR_DIRECTORY {
+        0 => FileType::Regular,
+        _ => FileType::Directory,
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    first_cluster: u32,
+    size: u32,
+    attr: u8,
+    /// Whether the file was deleted, which frees its clusters right away.
+    removed: bool,
+}
+
+struct FatInode {
+    fs: Arc<FatFs>,
+    ino: u64,
+    file_type: FileType,
+    node: Mutex<Node>,
+}
+
+impl FatInode {
+    fn node(&self) -> Node {
+        *self.node.lock()
+    }
+
+    fn check_directory(&self) -> FsResult<()> {
+        match self.file_type {
+            FileType::Directory => Ok(()),
+            _ => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn check_regular(&self) -> FsResult<()> {
+        match self.file_type {
+            FileType::Directory => Err(FsError::IsADirectory),
+            _ => Ok(()),
+        }
+    }
+
+    async fn find(&self, name: &str) -> FsResult<(DirData, Slot)> {
+        self.check_directory()?;
+        let dir = self.fs.read_dir(self.node().first_cluster).await?;
+        let slot = dir
+            .files()
+            .into_iter()
+            .find(|slot| slot.matches(name))
+            .ok_or(FsError::NotFound)?;
+        Ok((dir, slot))
+    }
+
+    fn slot_inode(&self, dir: &DirData, slot: &Slot) -> Arc<FatInode> {
+        self.fs
+            .inode(dir.offset(slot.index), slot.file_type(), slot.node)
+    }
+
+    /// Adds a new entry called `name`, returning the inode of the file.
+    async fn add(&self, name: &str, attr: u8) -> FsResult<Arc<FatInode>> {
+        self.check_directory()?;
+        check_name(name)?;
+        let mut allocator = self.fs.allocator.lock().await;
+        let mut dir = self.fs.read_dir(self.node().first_cluster).await?;
+        let files = dir.files();
+        if files.iter().any(|slot| slot.matches(name)) {
+            return Err(FsError::AlreadyExists);
+        }
+
+        let (short, case, long) = match exact_short_name(name) {
+            Some((short, case)) => (short, case, Vec::new()),
+            None => {
+                let taken: BTreeSet<_> = files.iter().map(|slot| slot.short).collect();
+                let short = generate_short_name(name, |short| taken.contains(short))?;
+                (short, 0, long_entries(name, &short))
+            }
+        };
+
+        // Make room for the entries first, so a full directory doesn't
+        // leave a new directory's cluster allocated.
+        let count = long.len() + 1;
+        let mut start = dir.find_free(count);
+        if start + count > dir.len() {
+            // The FAT12 and FAT16 root directory has a fixed size.
+            if self.ino == ROOT_INO && self.fs.layout.root_region.is_some() {
+                return Err(FsError::NoSpace);
+            }
+            let mut chain = self.fs.chain(self.node().first_cluster).await?;
+            let missing = ((start + count - dir.len()) * ENTRY_SIZE) as u64;
+            let len = chain.len() + self.fs.layout.clusters_for(missing);
+            self.fs.extend(&mut allocator, &mut chain, len).await?;
+            dir = self.fs.read_dir(self.node().first_cluster).await?;
+            start = dir.find_free(count);
+        }
+
+        let mut node = Node {
+            first_cluster: 0,
+            size: 0,
+            attr,
+            removed: false,
+        };
+        if attr & ATTR_DIRECTORY != 0 {
+            let mut chain = Vec::new();
+            self.fs.extend(&mut allocator, &mut chain, 1).await?;
+            node.first_cluster = chain[0];
+            let parent = match self.ino {
+                ROOT_INO => 0,
+                _ => self.node().first_cluster,
+            };
+            let offset = self.fs.layout.cluster_offset(node.first_cluster);
+            let dot = short_entry(b".          ", ATTR_DIRECTORY, 0, node.first_cluster, 0);
+            let dot_dot = short_entry(b"..         ", ATTR_DIRECTORY, 0, parent, 0);
+            self.fs.write_entry(offset, &dot).await?;
+            self.fs
+                .write_entry(offset + ENTRY_SIZE as u64, &dot_dot)
+                .await?;
+        }
+
+        for (i, entry) in long.iter().enumerate() {
+            self.fs.write_entry(dir.offset(start + i), entry).await?;
+        }
+        let ino = dir.offset(start + long.len());
+        let entry = short_entry(&short, attr, case, node.first_cluster, 0);
+        self.fs.write_entry(ino, &entry).await?;
+        Ok(self.fs.inode(ino, file_type(attr), node))
+    }
+
+    /// Removes the entry called `name`, which must be a directory if and
+    /// only if `directory`, freeing its clusters.
+    async fn remove(&self, name: &str, directory: bool) -> FsResult<()> {
+        let mut allocator = self.fs.allocator.lock().await;
+        let (dir, slot) = self.find(name).await?;
+        match slot.file_type() {
+            FileType::Directory if !directory => return Err(FsError::IsADirectory),
+            FileType::Directory => {
+                let data = self.fs.read_dir(slot.node.first_cluster).await?;
+                if !data.files().is_empty() {
+                    return Err(FsError::NotEmpty);
+                }
+            }
+            _ if directory => return Err(FsError::NotADirectory),
+            _ => {}
+        }
+
+        for index in slot.start..=slot.index {
+            self.fs
+                .cache
+                .write_at(dir.offset(index), &[DELETED])
+                .await?;
+        }
+        let ino = dir.offset(slot.index);
+        let inode = self
+            .fs
+            .inodes
+            .lock()
+            .remove(&ino)
+            .and_then(|inode| inode.upgrade());
+        let first_cluster = match inode {
+            Some(inode) => {
+                let mut node = inode.node.lock();
+                node.removed = true;
+                node.size = 0;
+                core::mem::replace(&mut node.first_cluster, 0)
+            }
+            None => slot.node.first_cluster,
+        };
+        let chain = self.fs.chain(first_cluster).await?;
+        self.fs.release(&mut allocator, &chain).await
+    }
+
+    /// Changes the size of the file to `size`, allocating or freeing
+    /// clusters, and zeroes the bytes from the old end of the file to
+    /// `zero_to`.
+    async fn resize(&self, allocator: &mut Allocator, size: u64, zero_to: u64) -> FsResult<()> {
+        let mut node = self.node();
+        if node.removed {
+            return Err(FsError::NotFound);
+        }
+        let size = u32::try_from(size).map_err(|_| FsError::NoSpace)?;
+        let layout = &self.fs.layout;
+        let mut chain = self.fs.chain(node.first_cluster).await?;
+        let len = layout.clusters_for(u64::from(size));
+        if len > chain.len() {
+            self.fs.extend(allocator, &mut chain, len).await?;
+        } else if len < chain.len() {
+            self.fs.release(allocator, &chain[len..]).await?;
+            chain.truncate(len);
+            if let Some(&last) = chain.last() {
+                self.fs.write_fat(last, layout.fat_type.mask()).await?;
+            }
+        }
+
+        // Bytes past the old end of the file in its last cluster may hold
+        // stale data, while new clusters are already zeroed.
+        let old_size = u64::from(node.size);
+        let zero_end = cmp::min(
+            zero_to,
+            layout.clusters_for(old_size) as u64 * layout.cluster_size,
+        );
+        if zero_end > old_size {
+            let cluster = chain[(old_size / layout.cluster_size) as usize];
+            let offset = layout.cluster_offset(cluster) + old_size % layout.cluster_size;
+            let zeroes = vec![0; (zero_end - old_size) as usize];
+            self.fs.cache.write_at(offset, &zeroes).await?;
+        }
+
+        node.first_cluster = chain.first().copied().unwrap_or(0);
+        node.size = size;
+        self.fs.update_entry(self.ino, &node).await?;
+        *self.node.lock() = node;
+        Ok(())
+    }
+
+    /// Returns the disk offsets and lengths of the runs holding the bytes
+    /// `offset..offset + len` of the file.
+    async fn runs(&self, offset: u64, len: usize) -> FsResult<Vec<(u64, usize)>> {
+        let layout = &self.fs.layout;
+        let chain = self.fs.chain(self.node().first_cluster).await?;
+        let mut runs = Vec::new();
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done as u64;
+            let cluster = *chain
+                .get((pos / layout.cluster_size) as usize)
+                .ok_or(FsError::Corrupt)?;
+            let start = pos % layout.cluster_size;
+            let run = cmp::min(len - done, (layout.cluster_size - start) as usize);
+            runs.push((layout.cluster_offset(cluster) + start, run));
+            done += run;
+        }
+        Ok(runs)
+    }
+}
+
+impl Inode for FatInode {
+    fn metadata(&self) -> Metadata {
+        let node = self.node();
+        let read_only = node.attr & ATTR_READ_ONLY != 0;
+        let mode = match (self.file_type, read_only) {
+            (FileType::Directory, false) => 0o755,
+            (FileType::Directory, true) => 0o555,
+            (_, false) => 0o644,
+            (_, true) => 0o444,
+        };
+        Metadata {
+            ino: self.ino,
+            file_type: self.file_type,
+            size: u64::from(node.size),
+            mode,
+            nlink: match self.file_type {
+                FileType::Directory => 2,
+                _ => 1,
+            },
+        }
+    }
+
+    fn lookup<'a>(&'a self, name: &'a str) -> BoxFuture<'a, FsResult<Arc<dyn Inode>>> {
+        Box::pin(async move {
+            let _allocator = self.fs.allocator.lock().await;
+            let (dir, slot) = self.find(name).await?;
+            Ok(self.slot_inode(&dir, &slot) as Arc<dyn Inode>)
+        })
+    }
+
+    fn create<'a>(
+        &'a self,
+        name: &'a str,
+        file_type: FileType,
+        mode: u16,
+    ) -> BoxFuture<'a, FsResult<Arc<dyn Inode>>> {
+        Box::pin(async move {
+            let mut attr = match file_type {
+                FileType::Regular => ATTR_ARCHIVE,
+                FileType::Directory => ATTR_DIRECTORY,
+                _ => return Err(FsError::Unsupported),
+            };
+            if mode & 0o222 == 0 {
+                attr |= ATTR_READ_ONLY;
+            }
+            Ok(self.add(name, attr).await? as Arc<dyn Inode>)
+        })
+    }
+
+    fn symlink<'a>(
+        &'a self,
+        _name: &'a str,
+        _target: &'a str,
+    ) -> BoxFuture<'a, FsResult<Arc<dyn Inode>>> {
+        Box::pin(async move {
+            self.check_directory()?;
+            Err(FsError::Unsupported)
+        })
+    }
+
+    fn unlink<'a>(&'a self, name: &'a str) -> BoxFuture<'a, FsResult<()>> {
+        Box::pin(self.remove(name, false))
+    }
+
+    fn rmdir<'a>(&'a self, name: &'a str) -> BoxFuture<'a, FsResult<()>> {
+        Box::pin(self.remove(name, true))
+    }
+
+    fn readdir(&self) -> BoxFuture<'_, FsResult<Vec<DirEntry>>> {
+        Box::pin(async move {
+            self.check_directory()?;
+            let _allocator = self.fs.allocator.lock().await;
+            let dir = self.fs.read_dir(self.node().first_cluster).await?;
+            Ok(dir
+                .files()
+                .into_iter()
+                .map(|slot| DirEntry {
+                    ino: dir.offset(slot.index),
+                    file_type: slot.file_type(),
+                    name: slot.name,
+                })
+                .collect())
+        })
+    }
+
+    fn read_at<'a>(&'a self, offset: u64, buf: &'a mut [u8]) -> BoxFuture<'a, FsResult<usize>> {
+        Box::pin(async move {
+            self.check_regular()?;
+            let _allocator = self.fs.allocator.lock().await;
+            let size = u64::from(self.node().size);
+            let len = cmp::min(buf.len() as u64, size.saturating_sub(offset)) as usize;
+            let mut done = 0;
+            for (disk_offset, run) in self.runs(offset, len).await? {
+                self.fs
+                    .cache
+                    .read_at(disk_offset, &mut buf[done..done + run])
+                    .await?;
+                done += run;
+            }
+            Ok(len)
+        })
+    }
+
+    fn write_at<'a>(&'a self, offset: u64, buf: &'a [u8]) -> BoxFuture<'a, FsResult<usize>> {
+        Box::pin(async move {
+            self.check_regular()?;
+            let mut allocator = self.fs.allocator.lock().await;
+            let end = offset
+                .checked_add(buf.len() as u64)
+                .ok_or(FsError::NoSpace)?;
+            let size = u64::from(self.node().size);
+            if end > size {
+                self.resize(&mut allocator, end, offset).await?;
+            }
+            let mut done = 0;
+            for (disk_offset, run) in self.runs(offset, buf.len()).await? {
+                self.fs
+                    .cache
+                    .write_at(disk_offset, &buf[done..done + run])
+                    .await?;
+                done += run;
+            }
+            Ok(buf.len())
+        })
+    }
+
+    fn truncate(&self, size: u64) -> BoxFuture<'_, FsResult<()>> {
+        Box::pin(async move {
+            self.check_regular()?;
+            let mut allocator = self.fs.allocator.lock().await;
+            self.resize(&mut allocator, size, size).await
+        })
+    }
+}
+
+fn check_name(name: &str) -> FsResult<()> {
+    let invalid = |c: char| c < ' ' || "\"*/:<>?\\|".contains(c);
+    if name.is_empty()
+        || name.ends_with(['.', ' '])
+        || name.encode_utf16().count() > MAX_NAME
+        || name.chars().any(invalid)
+    {
+        return Err(FsError::InvalidArgument);
+    }
+    Ok(())
+}
+
+/// Returns whether `c` may be part of a short name, besides lowercase letters.
+fn is_short_char(c: char) -> bool {
+    c.is_ascii_uppercase() || c.is_ascii_digit() || "!#$%&'()-@^_`{}~".contains(c)
+}
+
+/// Splits `name` into its base name and extension at the last dot, which
+/// is not an extension separator if leading.
+fn split_extension(name: &str) -> (&str, &str) {
+    match name.rfind('.') {
+        Some(dot) if dot > 0 => (&name[..dot], &name[dot + 1..]),
+        _ => (name, ""),
+    }
+}
+
+/// Returns the short name and case flags `name` can be stored as without a
+/// long name.
+fn exact_short_name(name: &str) -> Option<([u8; 11], u8)> {
+    let (base, ext) = split_extension(name);
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 {
+        return None;
+    }
+
+    let mut short = [b' '; 11];
+    let mut case = 0;
+    for (part, offset, flag) in [(base, 0, LOWERCASE_BASE), (ext, 8, LOWERCASE_EXT)] {
+        let lower = part.chars().any(|c| c.is_ascii_lowercase());
+        let upper = part.chars().any(|c| c.is_ascii_uppercase());
+        // Mixed case can only be kept in a long name.
+        if lower && upper {
+            return None;
+        }
+        if lower {
+            case |= flag;
+        }
+        for (i, c) in part.chars().enumerate() {
+            let c = c.to_ascii_uppercase();
+            if !is_short_char(c) {
+                return None;
+            }
+            short[offset + i] = c as u8;
+        }
+    }
+    Some((short, case))
+}
+
+/// Derives a short name like `LONGNA~1.TXT` from `name` that is not
+/// `taken`.
+fn generate_short_name(name: &str, taken: impl Fn(&[u8; 11]) -> bool) -> FsResult<[u8; 11]> {
+    let clean = |part: &str| -> Vec<u8> {
+        part.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| c.to_ascii_uppercase())
+            .map(|c| if is_short_char(c) { c as u8 } else { b'_' })
+            .collect()
+    };
+    let (base, ext) = split_extension(name);
+    let base = clean(base);
+    let ext = clean(ext);
+
+    for n in 1..1_000_000 {
+        let tail = format!("~{}", n);
+        let keep = cmp::min(base.len(), 8 - tail.len());
+        let mut short = [b' '; 11];
+        short[..keep].copy_from_slice(&base[..keep]);
+        short[keep..keep + tail.len()].copy_from_slice(tail.as_bytes());
+        let ext_len = cmp::min(ext.len(), 3);
+        short[8..8 + ext_len].copy_from_slice(&ext[..ext_len]);
+        if !taken(&short) {
+            return Ok(short);
+        }
+    }
+    Err(FsError::NoSpace)
+}
+
+fn short_name_string(short: &[u8; 11], case: u8) -> String {
+    let part = |bytes: &[u8], lowercase: bool| -> String {
+        let len = bytes.iter().rposition(|&b| b != b' ').map_or(0, |i| i + 1);
+        bytes[..len]
+            .iter()
+            .map(|&b| match b {
+                // A leading 0x05 stands for a 0xe5 byte, which marks deleted
+                // entries.
+                0x05 => char::from(DELETED),
+                b if lowercase => char::from(b.to_ascii_lowercase()),
+                b => char::from(b),
+            })
+            .collect()
+    };
+    let mut name = part(&short[..8], case & LOWERCASE_BASE != 0);
+    let ext = part(&short[8..], case & LOWERCASE_EXT != 0);
+    if !ext.is_empty() {
+        name.push('.');
+        name.push_str(&ext);
+    }
+    name
+}
+
+/// The checksum of a short name stored in the long name entries of a file.
+fn checksum(short: &[u8; 11]) -> u8 {
+    short
+        .iter()
+        .fold(0u8, |sum, &b| sum.rotate_right(1).wrapping_add(b))
+}
+
+/// Returns the long name entries for `name`, in the order they are stored.
+fn long_entries(name: &str, short: &[u8; 11]) -> Vec<[u8; ENTRY_SIZE]> {
+    let units: Vec<u16> = name.encode_utf16().collect();
+    let chunks: Vec<_> = units.chunks(LONG_ENTRY_UNITS.len()).collect();
+    let checksum = checksum(short);
+    (0..chunks.len())
+        .rev()
+        .map(|i| {
+            let mut entry = [0; ENTRY_SIZE];
+            entry[0] = (i + 1) as u8;
+            if i == chunks.len() - 1 {
+                entry[0] |= LAST_LONG_ENTRY;
+            }
+            entry[11] = ATTR_LONG_NAME;
+            entry[13] = checksum;
+            for (j, &offset) in LONG_ENTRY_UNITS.iter().enumerate() {
+                // The name is terminated by a zero if it doesn't fill the
+                // entry, and padded with 0xffff after that.
+                let unit = match chunks[i].get(j) {
+                    Some(&unit) => unit,
+                    None if j == chunks[i].len() => 0,
+                    None => 0xffff,
+                };
+                entry[offset..offset + 2].copy_from_slice(&unit.to_le_bytes());
+            }
+            entry
+        })
+        .collect()
+}
+
+fn short_entry(short: &[u8; 11], attr: u8, case: u8, first_cluster: u32, size: u32) -> [u8; 32] {
+    let mut entry = [0; ENTRY_SIZE];
+    entry[..11].copy_from_slice(short);
+    entry[11] = attr;
+    entry[12] = case;
+    for offset in [16, 18, 24] {
+        entry[offset..offset + 2].copy_from_slice(&DATE.to_le_bytes());
+    }
+    entry[20..22].copy_from_slice(&((first_cluster >> 16) as u16).to_le_bytes());
+    entry[26..28].copy_from_slice(&(first_cluster as u16).to_le_bytes());
+    entry[28..32].copy_from_slice(&size.to_le_bytes());
+    entry
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    let mut raw = [0; 4];
+    raw.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(raw)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn short_names() {
+        assert_eq!(
+            exact_short_name("readme.txt"),
+            Some((*b"README  TXT", LOWERCASE_BASE | LOWERCASE_EXT))
+        );
+        assert_eq!(exact_short_name("KERNEL"), Some((*b"KERNEL     ", 0)));
+        assert_eq!(exact_short_name("ReadMe.txt"), None);
+        assert_eq!(exact_short_name("a.b.c"), None);
+        assert_eq!(exact_short_name("long name.text"), None);
+        assert_eq!(
+            short_name_string(b"README  TXT", LOWERCASE_EXT),
+            "README.txt"
+        );
+
+        let taken = |short: &[u8; 11]| short == b"LONGNA~1TEX";
+        assert_eq!(
+            generate_short_name("Long name.text", taken),
+            Ok(*b"LONGNA~2TEX")
+        );
+        assert_eq!(generate_short_name(".bashrc", taken), Ok(*b"BASHRC~1   "));
+    }
+
+    #[test_case]
+    fn long_name_entries() {
+        let short = *b"ABCDEF~1TXT";
+        let entries = long_entries("abcdefghijklmnop.txt", &short);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0][0], LAST_LONG_ENTRY | 2);
+        assert_eq!(entries[1][0], 1);
+
+        let mut data = Vec::new();
+        entries
+            .iter()
+            .for_each(|entry| data.extend_from_slice(entry));
+        data.extend_from_slice(&short_entry(&short, ATTR_ARCHIVE, 0, 5, 42));
+        data.resize(data.len() + ENTRY_SIZE, 0);
+        let dir = DirData {
+            extents: vec![(0, data.len() as u64)],
+            data,
+        };
+        let files = dir.files();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].name, "abcdefghijklmnop.txt");
+        assert_eq!(files[0].short_name, "ABCDEF~1.TXT");
+        assert_eq!((files[0].start, files[0].index), (0, 2));
+        assert_eq!(files[0].node.first_cluster, 5);
+        assert_eq!(dir.find_free(2), 3);
+    }
+}
@@ -0,0 +1,461 @@
+//! A read-only filesystem over a ustar or newc cpio archive in memory, like
+//! the one embedded in the kernel image.
+
+use alloc::{boxed::Box, collections::BTreeMap, format, string::String, sync::Arc, vec::Vec};
+use core::{cmp, str};
+use futures_util::future::BoxFuture;
+
+use crate::vfs::{self, DirEntry, FileSystem, FileType, FsError, FsResult, Inode, Metadata};
+
+/// The archive built from the `initrd` directory of the source tree.
+pub static ARCHIVE: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/initrd.tar"));
+
+const CPIO_MAGIC: &[u8] = b"07070";
+const CPIO_TRAILER: &str = "TRAILER!!!";
+const USTAR_MAGIC: &[u8] = b"ustar";
+
+const MODE_TYPE: u32 = 0o170000;
+const MODE_DIRECTORY: u32 = 0o040000;
+const MODE_REGULAR: u32 = 0o100000;
+const MODE_SYMLINK: u32 = 0o120000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveError {
+    /// The data is neither a ustar nor a newc cpio archive.
+    UnknownFormat,
+    /// A header or entry is malformed or extends past the end of the data.
+    Corrupt,
+}
+
+/// An entry of an archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry<'a> {
+    path: String,
+    mode: u16,
+    kind: EntryKind<'a>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EntryKind<'a> {
+    Regular(&'a [u8]),
+    Directory,
+    Symlink(&'a str),
+}
+
+fn parse(data: &[u8]) -> Result<Vec<Entry<'_>>, ArchiveError> {
+    if data.starts_with(CPIO_MAGIC) {
+        parse_cpio(data)
+    } else if data.get(257..262) == Some(USTAR_MAGIC) {
+        parse_ustar(data)
+    } else {
+        Err(ArchiveError::UnknownFormat)
+    }
+}
+
+fn octal(field: &[u8]) -> u64 {
+    field
+        .iter()
+        .skip_while(|&&b| b == b' ')
+        .take_while(|&&b| (b'0'..=b'7').contains(&b))
+        .fold(0, |value, &b| value * 8 + u64::from(b - b'0'))
+}
+
+/// Returns the string in a NUL padded field.
+fn field_str(field: &[u8]) -> Result<&str, ArchiveError> {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    str::from_utf8(&field[..len]).map_err(|_| ArchiveError::Corrupt)
+}
+
+fn parse_ustar(data: &[u8]) -> Result<Vec<Entry<'_>>, ArchiveError> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+
+    while let Some(header) = data.get(offset..offset + 512) {
+        // The archive ends with zeroed blocks.
+        if header[0] == 0 {
+            break;
+        }
+        let size = octal(&header[124..136]) as usize;
+        let start = offset + 512;
+        let content = data.get(start..start + size).ok_or(ArchiveError::Corrupt)?;
+        offset = start + x86_64::align_up(size as u64, 512) as usize;
+
+        // Long paths are split into a prefix and a name.
+        let prefix = field_str(&header[345..500])?;
+        let name = field_str(&header[..100])?;
+        let path = if prefix.is_empty() {
+            String::from(name)
+        } else {
+            format!("{}/{}", prefix, name)
+        };
+        let kind = match header[156] {
+            b'0' | 0 => EntryKind::Regular(content),
+            b'5' => EntryKind::Directory,
+            b'2' => EntryKind::Symlink(field_str(&header[157..257])?),
+            // Hard links, devices and extension headers are not supported.
+            _ => continue,
+        };
+        entries.push(Entry {
+            path,
+            mode: (octal(&header[100..108]) & 0o7777) as u16,
+            kind,
+        });
+    }
+    Ok(entries)
+}
+
+fn hex(field: &[u8]) -> Result<u32, ArchiveError> {
+    let field = str::from_utf8(field).map_err(|_| ArchiveError::Corrupt)?;
+    u32::from_str_radix(field, 16).map_err(|_| ArchiveError::Corrupt)
+}
+
+fn parse_cpio(data: &[u8]) -> Result<Vec<Entry<'_>>, ArchiveError> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+
+    loop {
+        let header = data
+            .get(offset..offset + 110)
+            .ok_or(ArchiveError::Corrupt)?;
+        if !header.starts_with(CPIO_MAGIC) {
+            return Err(ArchiveError::Corrupt);
+        }
+        let field = |index: usize| hex(&header[6 + 8 * index..][..8]);
+        let mode = field(1)?;
+        let size = field(6)? as usize;
+        let name_size = field(11)? as usize;
+
+        let name_start = offset + 110;
+        let name = data
+            .get(name_start..name_start + name_size)
+            .ok_or(ArchiveError::Corrupt)?;
+        let name = field_str(name)?;
+        let start = x86_64::align_up((name_start + name_size) as u64, 4) as usize;
+        let content = data.get(start..start + size).ok_or(ArchiveError::Corrupt)?;
+        offset = x86_64::align_up((start + size) as u64, 4) as usize;
+
+        if name == CPIO_TRAILER {
+            return Ok(entries);
+        }
+        let kind = match mode & MODE_TYPE {
+            MODE_REGULAR => EntryKind::Regular(content),
+            MODE_DIRECTORY => EntryKind::Directory,
+            MODE_SYMLINK => {
+                EntryKind::Symlink(str::from_utf8(content).map_err(|_| ArchiveError::Corrupt)?)
+            }
+            _ => continue,
+        };
+        entries.push(Entry {
+            path: String::from(name),
+            mode: (mode & 0o7777) as u16,
+            kind,
+        });
+    }
+}
+
+pub struct InitrdFs {
+    root: Arc<InitrdInode>,
+}
+
+impl InitrdFs {
+    /// Builds the filesystem from the archive in `data`.
+    ///
+    /// Directories missing from the archive are created as needed.
+    pub fn new(data: &'static [u8]) -> Result<Arc<Self>, ArchiveError> {
+        let mut root = Node::directory(0o755);
+        for entry in parse(data)? {
+            let mut node = &mut root;
+            let mut components = vfs::path::components(&entry.path)
+                .filter(|&component| component != ".")
+                .peekable();
+            while let Some(component) = components.next() {
+                let children = match &mut node.kind {
+                    NodeKind::Directory(children) => children,
+                    // A file is in the way, so the entry can't be placed.
+                    _ => break,
+                };
+                let last = components.peek().is_none();
+                node = children
+                    .entry(String::from(component))
+                    .or_insert_with(|| Node::directory(0o755));
+                if last {
+                    node.mode = entry.mode;
+                    match entry.kind {
+                        EntryKind::Regular(data) => node.kind = NodeKind::Regular(data),
+                        EntryKind::Symlink(target) => node.kind = NodeKind::Symlink(target),
+                        EntryKind::Directory => {}
+                    }
+                }
+            }
+        }
+
+        let mut next_ino = 1;
+        Ok(Arc::new(Self {
+            root: root.freeze(&mut next_ino),
+        }))
+    }
+}
+
+impl FileSystem for InitrdFs {
+    fn name(&self) -> &str {
+        "initrd"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+/// An inode while the tree is built.
+struct Node {
+    mode: u16,
+    kind: NodeKind,
+}
+
+enum NodeKind {
+    Regular(&'static [u8]),
+    Directory(BTreeMap<String, Node>),
+    Symlink(&'static str),
+}
+
+impl Node {
+    fn directory(mode: u16) -> Self {
+        Self {
+            mode,
+            kind: NodeKind::Directory(BTreeMap::new()),
+        }
+    }
+
+    fn freeze(self, next_ino: &mut u64) -> Arc<InitrdInode> {
+        let ino = *next_ino;
+        *next_ino += 1;
+        let kind = match self.kind {
+            NodeKind::Regular(data) => Kind::Regular(data),
+            NodeKind::Symlink(target) => Kind::Symlink(target),
+            NodeKind::Directory(children) => Kind::Directory(
+                children
+                    .into_iter()
+                    .map(|(name, node)| (name, node.freeze(next_ino)))
+                    .collect(),
+            ),
+        };
+        Arc::new(InitrdInode {
+            ino,
+            mode: self.mode,
+            kind,
+        })
+    }
+}
+
+struct InitrdInode {
+    ino: u64,
+    mode: u16,
+    kind: Kind,
+}
+
+enum Kind {
+    Regular(&'static [u8]),
+    Directory(BTreeMap<String, Arc<InitrdInode>>),
+    Symlink(&'static str),
+}
+
+impl InitrdInode {
+    fn file_type(&self) -> FileType {
+        match self.kind {
+            Kind::Regular(_) => FileType::Regular,
+            Kind::Directory(_) => FileType::Directory,
+            Kind::Symlink(_) => FileType::Symlink,
+        }
+    }
+}
+
+impl Inode for InitrdInode {
+    fn metadata(&self) -> Metadata {
+        let (size, nlink) = match &self.kind {
+            Kind::Regular(data) => (data.len(), 1),
+            Kind::Directory(children) => {
+                let subdirs = children
+                    .values()
+                    .filter(|inode| inode.file_type() == FileType::Directory)
+                    .count();
+                (children.len(), 2 + subdirs as u32)
+            }
+            Kind::Symlink(target) => (target.len(), 1),
+        };
+        Metadata {
+            ino: self.ino,
+            file_type: self.file_type(),
+            size: size as u64,
+            mode: self.mode,
+            nlink,
+        }
+    }
+
+    fn lookup<'a>(&'a self, name: &'a str) -> BoxFuture<'a, FsResult<Arc<dyn Inode>>> {
+        Box::pin(async move {
+            match &self.kind {
+                Kind::Directory(children) => children
+                    .get(name)
+                    .map(|inode| inode.clone() as Arc<dyn Inode>)
+                    .ok_or(FsError::NotFound),
+                _ => Err(FsError::NotADirectory),
+            }
+        })
+    }
+
+    fn create<'a>(
+        &'a self,
+        _name: &'a str,
+        _file_type: FileType,
+        _mode: u16,
+    ) -> BoxFuture<'a, FsResult<Arc<dyn Inode>>> {
+        Box::pin(async { Err(FsError::ReadOnly) })
+    }
+
+    fn symlink<'a>(
+        &'a self,
+        _name: &'a str,
+        _target: &'a str,
+    ) -> BoxFuture<'a, FsResult<Arc<dyn Inode>>> {
+        Box::pin(async { Err(FsError::ReadOnly) })
+    }
+
+    fn unlink<'a>(&'a self, _name: &'a str) -> BoxFuture<'a, FsResult<()>> {
+        Box::pin(async { Err(FsError::ReadOnly) })
+    }
+
+    fn rmdir<'a>(&'a self, _name: &'a str) -> BoxFuture<'a, FsResult<()>> {
+        Box::pin(async { Err(FsError::ReadOnly) })
+    }
+
+    fn readdir(&self) -> BoxFuture<'_, FsResult<Vec<DirEntry>>> {
+        Box::pin(async move {
+            match &self.kind {
+                Kind::Directory(children) => Ok(children
+                    .iter()
+                    .map(|(name, inode)| DirEntry {
+                        name: name.clone(),
+                        ino: inode.ino,
+                        file_type: inode.file_type(),
+                    })
+                    .collect()),
+                _ => Err(FsError::NotADirectory),
+            }
+        })
+    }
+
+    fn read_at<'a>(&'a self, offset: u64, buf: &'a mut [u8]) -> BoxFuture<'a, FsResult<usize>> {
+        Box::pin(async move {
+            let data = match self.kind {
+                Kind::Regular(data) => data,
+                Kind::Directory(_) => return Err(FsError::IsADirectory),
+                Kind::Symlink(_) => return Err(FsError::InvalidArgument),
+            };
+            let start = cmp::min(offset, data.len() as u64) as usize;
+            let len = cmp::min(buf.len(), data.len() - start);
+            buf[..len].copy_from_slice(&data[start..start + len]);
+            Ok(len)
+        })
+    }
+
+    fn write_at<'a>(&'a self, _offset: u64, _buf: &'a [u8]) -> BoxFuture<'a, FsResult<usize>> {
+        Box::pin(async { Err(FsError::ReadOnly) })
+    }
+
+    fn truncate(&self, _size: u64) -> BoxFuture<'_, FsResult<()>> {
+        Box::pin(async { Err(FsError::ReadOnly) })
+    }
+
+    fn read_link(&self) -> BoxFuture<'_, FsResult<String>> {
+        Box::pin(async move {
+            match self.kind {
+                Kind::Symlink(target) => Ok(String::from(target)),
+                _ => Err(FsError::InvalidArgument),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn ustar_header(name: &str, kind: u8, size: usize, link: &str) -> Vec<u8> {
+        let mut header = vec![0; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..107].copy_from_slice(b"0000644");
+        header[124..135].copy_from_slice(format!("{:011o}", size).as_bytes());
+        header[156] = kind;
+        header[157..157 + link.len()].copy_from_slice(link.as_bytes());
+        header[257..263].copy_from_slice(b"ustar\0");
+        header
+    }
+
+    fn cpio_entry(name: &str, mode: u32, content: &[u8]) -> Vec<u8> {
+        let mut entry = format!(
+            "070701{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}",
+            0,
+            mode,
+            0,
+            0,
+            1,
+            0,
+            content.len(),
+            0,
+            0,
+            0,
+            0,
+            name.len() + 1,
+            0
+        )
+        .into_bytes();
+        entry.extend_from_slice(name.as_bytes());
+        entry.push(0);
+        entry.resize(x86_64::align_up(entry.len() as u64, 4) as usize, 0);
+        entry.extend_from_slice(content);
+        entry.resize(x86_64::align_up(entry.len() as u64, 4) as usize, 0);
+        entry
+    }
+
+    #[test_case]
+    fn parses_ustar() {
+        let mut data = ustar_header("etc/", b'5', 0, "");
+        data.extend(ustar_header("etc/motd", b'0', 5, ""));
+        let mut content = b"hello".to_vec();
+        content.resize(512, 0);
+        data.extend(content);
+        data.extend(ustar_header("motd", b'2', 0, "etc/motd"));
+        data.extend(vec![0; 1024]);
+
+        let entries = parse(&data).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1].path, "etc/motd");
+        assert_eq!(entries[1].kind, EntryKind::Regular(b"hello"));
+        assert_eq!(entries[1].mode, 0o644);
+        assert_eq!(entries[2].kind, EntryKind::Symlink("etc/motd"));
+    }
+
+    #[test_case]
+    fn parses_cpio() {
+        let mut data = cpio_entry(".", 0o040755, &[]);
+        data.extend(cpio_entry("bin/sh", 0o100755, b"#!"));
+        data.extend(cpio_entry("sh", 0o120777, b"bin/sh"));
+        data.extend(cpio_entry(CPIO_TRAILER, 0, &[]));
+
+        let entries = parse(&data).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1].kind, EntryKind::Regular(b"#!"));
+        assert_eq!(entries[1].mode, 0o755);
+        assert_eq!(entries[2].kind, EntryKind::Symlink("bin/sh"));
+        assert_eq!(parse(b"garbage").unwrap_err(), ArchiveError::UnknownFormat);
+    }
+
+    #[test_case]
+    fn embedded_archive() {
+        let fs = InitrdFs::new(ARCHIVE).unwrap();
+        let root = fs.root();
+        let etc = crate::task::block_on(root.lookup("etc")).unwrap();
+        assert_eq!(etc.metadata().file_type, FileType::Directory);
+    }
+}
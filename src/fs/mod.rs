@@ -0,0 +1,96 @@
+//! Filesystem implementations plugged into the VFS.
+
+pub mod devfs;
+pub mod ext2;
+pub mod fat;
+pub mod initrd;
+pub mod procfs;
+pub mod tmpfs;
+
+use alloc::{format, sync::Arc};
+
+use self::{
+    devfs::DevFs, ext2::Ext2Fs, fat::FatFs, initrd::InitrdFs, procfs::ProcFs, tmpfs::TmpFs,
+};
+use crate::{
+    block::{self, BlockDevice},
+    process, serial_println,
+    vfs::{self, FileSystem, FsError, FsResult},
+};
+
+/// Mounts a tmpfs as the root filesystem, the devices on `/dev`, the kernel
+/// state on `/proc`, the embedded initrd on `/tmp` and the filesystem of
+/// every block device that has a known one on `/mnt/<device>`.
+pub async fn init() {
+    vfs::mount("/", TmpFs::new())
+        .await
+        .expect("failed to mount root filesystem");
+    let result = async {
+        process::current().mkdir("/dev", 0o755).await?;
+        vfs::mount("/dev", DevFs::new()).await
+    };
+    if let Err(err) = result.await {
+        serial_println!("failed to mount devfs: {:?}", err);
+    }
+    let result = async {
+        process::current().mkdir("/proc", 0o555).await?;
+        vfs::mount("/proc", ProcFs::new()).await
+    };
+    if let Err(err) = result.await {
+        serial_println!("failed to mount procfs: {:?}", err);
+    }
+
+    let initrd = match InitrdFs::new(initrd::ARCHIVE) {
+        Ok(initrd) => initrd,
+        Err(err) => {
+            serial_println!("initrd unusable: {:?}", err);
+            return;
+        }
+    };
+    let result = async {
+        process::current().mkdir("/tmp", 0o755).await?;
+        vfs::mount("/tmp", initrd).await
+    };
+    if let Err(err) = result.await {
+        serial_println!("failed to mount initrd: {:?}", err);
+    }
+
+    mount_volumes().await;
+}
+
+async fn mount_volumes() {
+    for device in block::devices() {
+        let fs = match probe(device.clone()).await {
+            Ok(fs) => fs,
+            Err(FsError::Corrupt) => continue,
+            Err(err) => {
+                serial_println!("{}: unusable filesystem: {:?}", device.name(), err);
+                continue;
+            }
+        };
+        let path = format!("/mnt/{}", device.name());
+        let result = async {
+            match process::current().mkdir("/mnt", 0o755).await {
+                Ok(()) | Err(FsError::AlreadyExists) => {}
+                Err(err) => return Err(err),
+            }
+            process::current().mkdir(&path, 0o755).await?;
+            vfs::mount(&path, fs).await
+        };
+        match result.await {
+            Ok(()) => serial_println!("{}: mounted on {}", device.name(), path),
+            Err(err) => serial_println!("failed to mount {}: {:?}", device.name(), err),
+        }
+    }
+}
+
+/// Opens the filesystem on `device`, failing with [`FsError::Corrupt`] if it
+/// has none the kernel knows.
+async fn probe(device: Arc<dyn BlockDevice>) -> FsResult<Arc<dyn FileSystem>> {
+    match FatFs::new(device.clone()).await {
+        Ok(fs) => return Ok(fs),
+        Err(FsError::Corrupt) => {}
+        Err(err) => return Err(err),
+    }
+    Ok(Ext2Fs::new(device).await?)
+}
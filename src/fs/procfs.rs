@@ -0,0 +1,402 @@
+//! The process filesystem, mounted on `/proc`: files describing the state of
+//! the kernel and a directory for every process.
+//!
+//! The contents of a file are generated when it is opened, so they stay the
+//! same while it is read.
+
+use alloc::{
+    boxed::Box,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{
+    cmp,
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use futures_util::future::BoxFuture;
+
+use crate::{
+    allocator, cpu, interrupts,
+    memory::vmm,
+    process::{self, Pid, Process},
+    task, time,
+    vfs::{
+        DirEntry, File, FileSystem, FileType, FsError, FsResult, Inode, Metadata, OpenFlags,
+        SeekFrom,
+    },
+};
+
+/// Inode numbers of the files of a process are its pid shifted by this.
+const PID_SHIFT: u32 = 8;
+
+pub struct ProcFs {
+    root: Arc<ProcInode>,
+}
+
+impl ProcFs {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            root: Arc::new(ProcInode(Node::Root)),
+        })
+    }
+}
+
+impl FileSystem for ProcFs {
+    fn name(&self) -> &str {
+        "proc"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Node {
+    Root,
+    Meminfo,
+    Interrupts,
+    Tasks,
+    Uptime,
+    Cpuinfo,
+    /// Symlink to the directory of the process reading it.
+    SelfLink,
+    Process(Pid),
+    Status(Pid),
+    /// Symlink to the working directory of a process.
+    Cwd(Pid),
+    /// The open files of a process.
+    Fds(Pid),
+}
+
+impl Node {
+    /// The entries of the root directory besides those of the processes.
+    const KERNEL: [Node; 6] = [
+        Node::Meminfo,
+        Node::Interrupts,
+        Node::Tasks,
+        Node::Uptime,
+        Node::Cpuinfo,
+        Node::SelfLink,
+    ];
+
+    fn name(self) -> String {
+        match self {
+            Node::Root => String::from("/"),
+            Node::Meminfo => String::from("meminfo"),
+            Node::Interrupts => String::from("interrupts"),
+            Node::Tasks => String::from("tasks"),
+            Node::Uptime => String::from("uptime"),
+            Node::Cpuinfo => String::from("cpuinfo"),
+            Node::SelfLink => String::from("self"),
+            Node::Process(pid) => pid.to_string(),
+            Node::Status(_) => String::from("status"),
+            Node::Cwd(_) => String::from("cwd"),
+            Node::Fds(_) => String::from("fds"),
+        }
+    }
+
+    fn ino(self) -> u64 {
+        match self {
+            Node::Root => 1,
+            Node::Meminfo => 2,
+            Node::Interrupts => 3,
+            Node::Tasks => 4,
+            Node::Uptime => 5,
+            Node::Cpuinfo => 6,
+            Node::SelfLink => 7,
+            Node::Process(pid) => (pid as u64) << PID_SHIFT,
+            Node::Status(pid) => (pid as u64) << PID_SHIFT | 1,
+            Node::Cwd(pid) => (pid as u64) << PID_SHIFT | 2,
+            Node::Fds(pid) => (pid as u64) << PID_SHIFT | 3,
+        }
+    }
+
+    fn file_type(self) -> FileType {
+        match self {
+            Node::Root | Node::Process(_) => FileType::Directory,
+            Node::SelfLink | Node::Cwd(_) => FileType::Symlink,
+            _ => FileType::Regular,
+        }
+    }
+
+    fn children(self) -> FsResult<Vec<Node>> {
+        match self {
+            Node::Root => {
+                let processes = process::all().into_iter();
+                let processes = processes.map(|process| Node::Process(process.pid()));
+                Ok(Node::KERNEL.into_iter().chain(processes).collect())
+            }
+            Node::Process(pid) => Ok(Vec::from([
+                Node::Status(pid),
+                Node::Cwd(pid),
+                Node::Fds(pid),
+            ])),
+            _ => Err(FsError::NotADirectory),
+        }
+    }
+
+    /// Generates the contents of a file.
+    fn generate(self) -> FsResult<String> {
+        let mut text = String::new();
+        match self {
+            Node::Meminfo => {
+                let (frames, free_frames) = vmm::lock().frame_allocator().stats();
+                let (heap, heap_used) = allocator::stats();
+                writeln!(text, "MemTotal:  {:>10} kB", frames * 4).unwrap();
+                writeln!(text, "MemFree:   {:>10} kB", free_frames * 4).unwrap();
+                writeln!(text, "HeapTotal: {:>10} kB", heap / 1024).unwrap();
+                writeln!(text, "HeapUsed:  {:>10} kB", heap_used / 1024).unwrap();
+            }
+            Node::Interrupts => {
+                for (vector, count) in interrupts::counts() {
+                    let name = interrupts::vector_name(vector);
+                    writeln!(text, "{:>3}: {:>10}  {}", vector, count, name).unwrap();
+                }
+            }
+            Node::Tasks => {
+                writeln!(text, "{:>6}  STATE", "ID").unwrap();
+                for (id, state) in task::tasks() {
+                    writeln!(text, "{:>6}  {}", id, state).unwrap();
+                }
+            }
+            Node::Uptime => {
+                let uptime = time::uptime();
+                let hundredths = uptime.subsec_millis() / 10;
+                writeln!(text, "{}.{:02}", uptime.as_secs(), hundredths).unwrap();
+            }
+            Node::Cpuinfo => {
+                let info = cpu::info();
+                writeln!(text, "vendor_id\t: {}", info.vendor).unwrap();
+                writeln!(text, "cpu family\t: {}", info.family).unwrap();
+                writeln!(text, "model\t\t: {}", info.model).unwrap();
+                writeln!(text, "model name\t: {}", info.brand).unwrap();
+                writeln!(text, "stepping\t: {}", info.stepping).unwrap();
+                let flags = cpu::Feature::ALL.into_iter().filter(|&f| cpu::has(f));
+                let flags: Vec<_> = flags.map(cpu::Feature::name).collect();
+                writeln!(text, "flags\t\t: {}", flags.join(" ")).unwrap();
+            }
+            Node::Status(pid) => {
+                let process = find(pid)?;
+                writeln!(text, "Pid:\t{}", pid).unwrap();
+                writeln!(text, "PGid:\t{}", process.pgid()).unwrap();
+                writeln!(text, "Cwd:\t{}", process.cwd()).unwrap();
+                writeln!(text, "FDSize:\t{}", process.files().len()).unwrap();
+                let pending = process.pending_signals();
+                let pending: Vec<_> = pending.iter().map(ToString::to_string).collect();
+                writeln!(text, "SigPnd:\t{}", pending.join(" ")).unwrap();
+            }
+            Node::Fds(pid) => {
+                for (fd, file) in find(pid)?.files() {
+                    match file.metadata() {
+                        Ok(metadata) => {
+                            let kind = type_name(metadata.file_type);
+                            writeln!(text, "{}\t{}\t{}", fd, kind, metadata.size).unwrap();
+                        }
+                        Err(_) => writeln!(text, "{}\tunknown\t0", fd).unwrap(),
+                    }
+                }
+            }
+            _ => return Err(FsError::IsADirectory),
+        }
+        Ok(text)
+    }
+}
+
+fn find(pid: Pid) -> FsResult<Arc<Process>> {
+    process::find(pid).ok_or(FsError::NotFound)
+}
+
+fn type_name(file_type: FileType) -> &'static str {
+    match file_type {
+        FileType::Regular => "file",
+        FileType::Directory => "directory",
+        FileType::Symlink => "symlink",
+        FileType::CharDevice => "char",
+        FileType::BlockDevice => "block",
+        FileType::Fifo => "fifo",
+    }
+}
+
+struct ProcInode(Node);
+
+impl Inode for ProcInode {
+    fn metadata(&self) -> Metadata {
+        let file_type = self.0.file_type();
+        let (mode, nlink) = match file_type {
+            FileType::Directory => (0o555, 2),
+            FileType::Symlink => (0o777, 1),
+            _ => (0o444, 1),
+        };
+        Metadata {
+            ino: self.0.ino(),
+            file_type,
+            // Generated files have no size until they are opened.
+            size: 0,
+            mode,
+            nlink,
+        }
+    }
+
+    fn lookup<'a>(&'a self, name: &'a str) -> BoxFuture<'a, FsResult<Arc<dyn Inode>>> {
+        Box::pin(async move {
+            let node = match (self.0, name.parse()) {
+                (Node::Root, Ok(pid)) => find(pid).map(|_| Node::Process(pid))?,
+                (node, _) => node
+                    .children()?
+                    .into_iter()
+                    .find(|child| child.name() == name)
+                    .ok_or(FsError::NotFound)?,
+            };
+            Ok(Arc::new(ProcInode(node)) as Arc<dyn Inode>)
+        })
+    }
+
+    fn create<'a>(
+        &'a self,
+        _name: &'a str,
+        _file_type: FileType,
+        _mode: u16,
+    ) -> BoxFuture<'a, FsResult<Arc<dyn Inode>>> {
+        Box::pin(async { Err(FsError::ReadOnly) })
+    }
+
+    fn symlink<'a>(
+        &'a self,
+        _name: &'a str,
+        _target: &'a str,
+    ) -> BoxFuture<'a, FsResult<Arc<dyn Inode>>> {
+        Box::pin(async { Err(FsError::ReadOnly) })
+    }
+
+    fn unlink<'a>(&'a self, _name: &'a str) -> BoxFuture<'a, FsResult<()>> {
+        Box::pin(async { Err(FsError::ReadOnly) })
+    }
+
+    fn rmdir<'a>(&'a self, _name: &'a str) -> BoxFuture<'a, FsResult<()>> {
+        Box::pin(async { Err(FsError::ReadOnly) })
+    }
+
+    fn readdir(&self) -> BoxFuture<'_, FsResult<Vec<DirEntry>>> {
+        Box::pin(async move {
+            Ok(self
+                .0
+                .children()?
+                .into_iter()
+                .map(|node| DirEntry {
+                    name: node.name(),
+                    ino: node.ino(),
+                    file_type: node.file_type(),
+                })
+                .collect())
+        })
+    }
+
+    fn read_at<'a>(&'a self, offset: u64, buf: &'a mut [u8]) -> BoxFuture<'a, FsResult<usize>> {
+        Box::pin(async move {
+            let data = self.0.generate()?;
+            let start = cmp::min(offset, data.len() as u64) as usize;
+            let len = cmp::min(buf.len(), data.len() - start);
+            buf[..len].copy_from_slice(&data.as_bytes()[start..start + len]);
+            Ok(len)
+        })
+    }
+
+    fn write_at<'a>(&'a self, _offset: u64, _buf: &'a [u8]) -> BoxFuture<'a, FsResult<usize>> {
+        Box::pin(async { Err(FsError::ReadOnly) })
+    }
+
+    fn truncate(&self, _size: u64) -> BoxFuture<'_, FsResult<()>> {
+        Box::pin(async { Err(FsError::ReadOnly) })
+    }
+
+    fn read_link(&self) -> BoxFuture<'_, FsResult<String>> {
+        Box::pin(async move {
+            match self.0 {
+                Node::SelfLink => Ok(process::current().pid().to_string()),
+                Node::Cwd(pid) => Ok(find(pid)?.cwd()),
+                _ => Err(FsError::InvalidArgument),
+            }
+        })
+    }
+
+    fn open(&self, flags: OpenFlags) -> Option<FsResult<Arc<dyn File>>> {
+        if self.0.file_type() != FileType::Regular {
+            return None;
+        }
+        if flags.contains(OpenFlags::WRITE) {
+            return Some(Err(FsError::ReadOnly));
+        }
+        let file = self.0.generate().map(|data| {
+            Arc::new(ProcFile {
+                ino: self.0.ino(),
+                data: data.into_bytes(),
+                offset: AtomicU64::new(0),
+            }) as Arc<dyn File>
+        });
+        Some(file)
+    }
+}
+
+/// An open generated file, holding what was generated.
+struct ProcFile {
+    ino: u64,
+    data: Vec<u8>,
+    offset: AtomicU64,
+}
+
+impl File for ProcFile {
+    fn read<'a>(&'a self, buf: &'a mut [u8]) -> BoxFuture<'a, FsResult<usize>> {
+        Box::pin(async move {
+            let offset = self.offset.load(Ordering::Relaxed);
+            let start = cmp::min(offset, self.data.len() as u64) as usize;
+            let len = cmp::min(buf.len(), self.data.len() - start);
+            buf[..len].copy_from_slice(&self.data[start..start + len]);
+            self.offset.fetch_add(len as u64, Ordering::Relaxed);
+            Ok(len)
+        })
+    }
+
+    fn write<'a>(&'a self, _buf: &'a [u8]) -> BoxFuture<'a, FsResult<usize>> {
+        Box::pin(async { Err(FsError::BadFileDescriptor) })
+    }
+
+    fn seek(&self, pos: SeekFrom) -> FsResult<u64> {
+        let current = self.offset.load(Ordering::Relaxed);
+        let offset = pos.resolve(current, self.data.len() as u64)?;
+        self.offset.store(offset, Ordering::Relaxed);
+        Ok(offset)
+    }
+
+    fn metadata(&self) -> FsResult<Metadata> {
+        Ok(Metadata {
+            ino: self.ino,
+            file_type: FileType::Regular,
+            size: self.data.len() as u64,
+            mode: 0o444,
+            nlink: 1,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn inode_numbers() {
+        let pids = [1, 2, 255, 256];
+        let mut nodes = Vec::from(Node::KERNEL);
+        nodes.push(Node::Root);
+        for pid in pids {
+            nodes.push(Node::Process(pid));
+            nodes.extend(Node::Process(pid).children().unwrap());
+        }
+        let mut inos: Vec<_> = nodes.iter().map(|node| node.ino()).collect();
+        inos.sort_unstable();
+        inos.dedup();
+        assert_eq!(inos.len(), nodes.len());
+    }
+}
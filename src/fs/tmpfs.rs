@@ -0,0 +1,269 @@
+//! A writable filesystem keeping everything on the kernel heap.
+
+use alloc::{boxed::Box, collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use core::{
+    cmp,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use futures_util::future::BoxFuture;
+use spin::RwLock;
+
+use crate::vfs::{DirEntry, FileSystem, FileType, FsError, FsResult, Inode, Metadata};
+
+pub struct TmpFs {
+    root: Arc<TmpInode>,
+}
+
+impl TmpFs {
+    pub fn new() -> Arc<Self> {
+        let next_ino = Arc::new(AtomicU64::new(1));
+        Arc::new(Self {
+            root: TmpInode::new(&next_ino, Kind::Directory(RwLock::default()), 0o755),
+        })
+    }
+}
+
+impl FileSystem for TmpFs {
+    fn name(&self) -> &str {
+        "tmpfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+struct TmpInode {
+    ino: u64,
+    mode: u16,
+    /// Source of inode numbers, shared by the whole filesystem.
+    next_ino: Arc<AtomicU64>,
+    kind: Kind,
+}
+
+enum Kind {
+    Regular(RwLock<Vec<u8>>),
+    Directory(RwLock<BTreeMap<String, Arc<TmpInode>>>),
+    Symlink(String),
+    Fifo,
+}
+
+impl TmpInode {
+    fn new(next_ino: &Arc<AtomicU64>, kind: Kind, mode: u16) -> Arc<Self> {
+        Arc::new(Self {
+            ino: next_ino.fetch_add(1, Ordering::Relaxed),
+            mode,
+            next_ino: next_ino.clone(),
+            kind,
+        })
+    }
+
+    fn entries(&self) -> FsResult<&RwLock<BTreeMap<String, Arc<TmpInode>>>> {
+        match &self.kind {
+            Kind::Directory(entries) => Ok(entries),
+            _ => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn data(&self) -> FsResult<&RwLock<Vec<u8>>> {
+        match &self.kind {
+            Kind::Regular(data) => Ok(data),
+            Kind::Directory(_) => Err(FsError::IsADirectory),
+            Kind::Symlink(_) | Kind::Fifo => Err(FsError::InvalidArgument),
+        }
+    }
+
+    fn file_type(&self) -> FileType {
+        match self.kind {
+            Kind::Regular(_) => FileType::Regular,
+            Kind::Directory(_) => FileType::Directory,
+            Kind::Symlink(_) => FileType::Symlink,
+            Kind::Fifo => FileType::Fifo,
+        }
+    }
+
+    /// Adds a new inode of `kind` to the directory.
+    fn add(&self, name: &str, kind: Kind, mode: u16) -> FsResult<Arc<dyn Inode>> {
+        let mut entries = self.entries()?.write();
+        if entries.contains_key(name) {
+            return Err(FsError::AlreadyExists);
+        }
+        let inode = TmpInode::new(&self.next_ino, kind, mode);
+        entries.insert(String::from(name), inode.clone());
+        Ok(inode)
+    }
+
+    /// Removes the entry called `name` if `check` accepts it.
+    fn remove(&self, name: &str, check: impl FnOnce(&TmpInode) -> FsResult<()>) -> FsResult<()> {
+        let mut entries = self.entries()?.write();
+        check(entries.get(name).ok_or(FsError::NotFound)?)?;
+        entries.remove(name);
+        Ok(())
+    }
+}
+
+impl Inode for TmpInode {
+    fn metadata(&self) -> Metadata {
+        let (size, nlink) = match &self.kind {
+            Kind::Regular(data) => (data.read().len(), 1),
+            Kind::Directory(entries) => {
+                let entries = entries.read();
+                let subdirs = entries
+                    .values()
+                    .filter(|inode| inode.file_type() == FileType::Directory)
+                    .count();
+                (entries.len(), 2 + subdirs as u32)
+            }
+            Kind::Symlink(target) => (target.len(), 1),
+            Kind::Fifo => (0, 1),
+        };
+        Metadata {
+            ino: self.ino,
+            file_type: self.file_type(),
+            size: size as u64,
+            mode: self.mode,
+            nlink,
+        }
+    }
+
+    fn lookup<'a>(&'a self, name: &'a str) -> BoxFuture<'a, FsResult<Arc<dyn Inode>>> {
+        Box::pin(async move {
+            let entries = self.entries()?.read();
+            let inode = entries.get(name).ok_or(FsError::NotFound)?;
+            Ok(inode.clone() as Arc<dyn Inode>)
+        })
+    }
+
+    fn create<'a>(
+        &'a self,
+        name: &'a str,
+        file_type: FileType,
+        mode: u16,
+    ) -> BoxFuture<'a, FsResult<Arc<dyn Inode>>> {
+        Box::pin(async move {
+            let kind = match file_type {
+                FileType::Regular => Kind::Regular(RwLock::default()),
+                FileType::Directory => Kind::Directory(RwLock::default()),
+                FileType::Fifo => Kind::Fifo,
+                _ => return Err(FsError::Unsupported),
+            };
+            self.add(name, kind, mode)
+        })
+    }
+
+    fn symlink<'a>(
+        &'a self,
+        name: &'a str,
+        target: &'a str,
+    ) -> BoxFuture<'a, FsResult<Arc<dyn Inode>>> {
+        Box::pin(async move { self.add(name, Kind::Symlink(String::from(target)), 0o777) })
+    }
+
+    fn unlink<'a>(&'a self, name: &'a str) -> BoxFuture<'a, FsResult<()>> {
+        Box::pin(async move {
+            self.remove(name, |inode| match inode.kind {
+                Kind::Directory(_) => Err(FsError::IsADirectory),
+                _ => Ok(()),
+            })
+        })
+    }
+
+    fn rmdir<'a>(&'a self, name: &'a str) -> BoxFuture<'a, FsResult<()>> {
+        Box::pin(async move {
+            self.remove(name, |inode| {
+                if inode.entries()?.read().is_empty() {
+                    Ok(())
+                } else {
+                    Err(FsError::NotEmpty)
+                }
+            })
+        })
+    }
+
+    fn readdir(&self) -> BoxFuture<'_, FsResult<Vec<DirEntry>>> {
+        Box::pin(async move {
+            let entries = self.entries()?.read();
+            Ok(entries
+                .iter()
+                .map(|(name, inode)| DirEntry {
+                    name: name.clone(),
+                    ino: inode.ino,
+                    file_type: inode.file_type(),
+                })
+                .collect())
+        })
+    }
+
+    fn read_at<'a>(&'a self, offset: u64, buf: &'a mut [u8]) -> BoxFuture<'a, FsResult<usize>> {
+        Box::pin(async move {
+            let data = self.data()?.read();
+            let start = cmp::min(offset, data.len() as u64) as usize;
+            let len = cmp::min(buf.len(), data.len() - start);
+            buf[..len].copy_from_slice(&data[start..start + len]);
+            Ok(len)
+        })
+    }
+
+    fn write_at<'a>(&'a self, offset: u64, buf: &'a [u8]) -> BoxFuture<'a, FsResult<usize>> {
+        Box::pin(async move {
+            let mut data = self.data()?.write();
+            let start = usize::try_from(offset).map_err(|_| FsError::NoSpace)?;
+            let end = start.checked_add(buf.len()).ok_or(FsError::NoSpace)?;
+            if end > data.len() {
+                data.resize(end, 0);
+            }
+            data[start..end].copy_from_slice(buf);
+            Ok(buf.len())
+        })
+    }
+
+    fn truncate(&self, size: u64) -> BoxFuture<'_, FsResult<()>> {
+        Box::pin(async move {
+            let size = usize::try_from(size).map_err(|_| FsError::NoSpace)?;
+            self.data()?.write().resize(size, 0);
+            Ok(())
+        })
+    }
+
+    fn read_link(&self) -> BoxFuture<'_, FsResult<String>> {
+        Box::pin(async move {
+            match &self.kind {
+                Kind::Symlink(target) => Ok(target.clone()),
+                _ => Err(FsError::InvalidArgument),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task;
+
+    #[test_case]
+    fn files_and_directories() {
+        let fs = TmpFs::new();
+        let root = fs.root();
+        let dir = task::block_on(root.create("dir", FileType::Directory, 0o755)).unwrap();
+        let file = task::block_on(dir.create("file", FileType::Regular, 0o644)).unwrap();
+
+        assert_eq!(task::block_on(file.write_at(4, b"data")), Ok(4));
+        let mut buf = [0xff; 16];
+        assert_eq!(task::block_on(file.read_at(2, &mut buf)), Ok(6));
+        assert_eq!(buf[..6], *b"\0\0data");
+        assert_eq!(file.metadata().size, 8);
+
+        assert_eq!(root.metadata().nlink, 3);
+        assert_eq!(
+            task::block_on(root.rmdir("dir")).err(),
+            Some(FsError::NotEmpty)
+        );
+        assert_eq!(
+            task::block_on(dir.create("file", FileType::Regular, 0o644)).err(),
+            Some(FsError::AlreadyExists)
+        );
+        task::block_on(dir.unlink("file")).unwrap();
+        task::block_on(root.rmdir("dir")).unwrap();
+        assert!(task::block_on(root.readdir()).unwrap().is_empty());
+    }
+}
@@ -1,32 +1,45 @@
 use spin::Lazy;
 use x86_64::{
-    instructions::{segmentation::CS, tables::load_tss},
+    instructions::{interrupts, segmentation::CS, tables::load_tss},
     registers::segmentation::Segment,
     structures::{
         gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector},
+        paging::{mapper::MapToError, Size4KiB},
         tss::TaskStateSegment,
     },
     VirtAddr,
 };
 
+use crate::memory::stack::KernelStack;
+
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+pub const NMI_IST_INDEX: u16 = 1;
+pub const MACHINE_CHECK_IST_INDEX: u16 = 2;
+pub const PAGE_FAULT_IST_INDEX: u16 = 3;
 
-static TSS: Lazy<TaskStateSegment> = Lazy::new(|| {
-    let mut tss = TaskStateSegment::new();
-    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-        const STACK_SIZE: usize = 4096 * 5;
-        static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+/// Interrupt stacks and the names they are reported under.
+const IST_STACKS: [(u16, &str); 4] = [
+    (DOUBLE_FAULT_IST_INDEX, "double fault"),
+    (NMI_IST_INDEX, "NMI"),
+    (MACHINE_CHECK_IST_INDEX, "machine check"),
+    (PAGE_FAULT_IST_INDEX, "page fault"),
+];
 
-        let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
-        stack_start + STACK_SIZE
-    };
-    tss
-});
+/// Size of each interrupt stack allocated by [`init_stacks`], in pages.
+const IST_STACK_PAGES: u64 = 5;
+
+/// Size of each interrupt stack used until [`init_stacks`] is called.
+const BOOTSTRAP_STACK_SIZE: usize = 4096 * 5;
+
+static mut BOOTSTRAP_STACKS: [[u8; BOOTSTRAP_STACK_SIZE]; IST_STACKS.len()] =
+    [[0; BOOTSTRAP_STACK_SIZE]; IST_STACKS.len()];
+
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
 
 static GDT: Lazy<(GlobalDescriptorTable, Selectors)> = Lazy::new(|| {
     let mut gdt = GlobalDescriptorTable::new();
     let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-    let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
+    let tss_selector = gdt.add_entry(Descriptor::tss_segment(unsafe { &TSS }));
     (
         gdt,
         Selectors {
@@ -42,9 +55,30 @@ struct Selectors {
 }
 
 pub fn init() {
+    for (index, _) in IST_STACKS {
+        let stack_start = VirtAddr::from_ptr(unsafe { &BOOTSTRAP_STACKS[usize::from(index)] });
+        set_interrupt_stack(index, stack_start + BOOTSTRAP_STACK_SIZE);
+    }
+
     GDT.0.load();
     unsafe {
         CS::set_reg(GDT.1.code_selector);
         load_tss(GDT.1.tss_selector);
     }
 }
+
+/// Replaces the bootstrap interrupt stacks, which have no guard pages, with
+/// guarded stacks allocated from the VMM.
+pub fn init_stacks() -> Result<(), MapToError<Size4KiB>> {
+    for (index, name) in IST_STACKS {
+        let stack = KernelStack::alloc(name, IST_STACK_PAGES)?;
+        set_interrupt_stack(index, stack.top());
+    }
+    Ok(())
+}
+
+fn set_interrupt_stack(index: u16, top: VirtAddr) {
+    interrupts::without_interrupts(|| unsafe {
+        TSS.interrupt_stack_table[usize::from(index)] = top;
+    });
+}
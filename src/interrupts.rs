@@ -1,11 +1,14 @@
+use alloc::{boxed::Box, vec::Vec};
+use core::sync::atomic::{AtomicU64, Ordering};
 use pic8259::ChainedPics;
-use spin::{Lazy, Mutex};
+use spin::{Lazy, Mutex, RwLock};
 use x86_64::{
-    instructions::port::Port,
-    structures::idt::{InterruptDescriptorTable, InterruptStackFrame},
+    instructions::{self, port::Port},
+    registers::control::Cr2,
+    structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode},
 };
 
-use crate::{gdt, print, println, task};
+use crate::{apic, ata, gdt, memory, println, serial, task, time};
 
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
@@ -15,6 +18,12 @@ pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     Keyboard,
+    /// COM2 and COM4.
+    SerialB = PIC_1_OFFSET + 3,
+    /// COM1 and COM3.
+    SerialA,
+    PrimaryAta = PIC_1_OFFSET + 14,
+    SecondaryAta,
 }
 
 impl InterruptIndex {
@@ -27,9 +36,31 @@ impl InterruptIndex {
     }
 }
 
+/// First vector handed out by [`allocate_vector`].
+pub const DYNAMIC_VECTOR_START: u8 = 0x50;
+/// Number of vectors available to [`allocate_vector`].
+pub const DYNAMIC_VECTORS: usize = 32;
+
+type Handler = Box<dyn Fn() + Send + Sync>;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_HANDLER: RwLock<Option<Handler>> = RwLock::new(None);
+static DYNAMIC_HANDLERS: [RwLock<Option<Handler>>; DYNAMIC_VECTORS] = [NO_HANDLER; DYNAMIC_VECTORS];
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_COUNT: AtomicU64 = AtomicU64::new(0);
+/// Number of times each vector was raised.
+static COUNTS: [AtomicU64; 256] = [NO_COUNT; 256];
+
 pub static PICS: Mutex<ChainedPics> =
     Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
 
+macro_rules! set_dynamic_handlers {
+    ($idt:expr, $($vector:literal)*) => {
+        $($idt[$vector].set_handler_fn(dynamic_interrupt_handler::<$vector>);)*
+    };
+}
+
 static IDT: Lazy<InterruptDescriptorTable> = Lazy::new(|| {
     let mut idt = InterruptDescriptorTable::new();
 
@@ -39,11 +70,29 @@ static IDT: Lazy<InterruptDescriptorTable> = Lazy::new(|| {
         idt.double_fault
             .set_handler_fn(double_fault_handler)
             .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+        idt.non_maskable_interrupt
+            .set_handler_fn(nmi_handler)
+            .set_stack_index(gdt::NMI_IST_INDEX);
+        idt.machine_check
+            .set_handler_fn(machine_check_handler)
+            .set_stack_index(gdt::MACHINE_CHECK_IST_INDEX);
+        idt.page_fault
+            .set_handler_fn(page_fault_handler)
+            .set_stack_index(gdt::PAGE_FAULT_IST_INDEX);
     }
 
     // Interrupts
     idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
     idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
+    idt[InterruptIndex::SerialB.as_usize()].set_handler_fn(serial_b_interrupt_handler);
+    idt[InterruptIndex::SerialA.as_usize()].set_handler_fn(serial_a_interrupt_handler);
+    idt[InterruptIndex::PrimaryAta.as_usize()].set_handler_fn(primary_ata_interrupt_handler);
+    idt[InterruptIndex::SecondaryAta.as_usize()].set_handler_fn(secondary_ata_interrupt_handler);
+    set_dynamic_handlers!(
+        idt, 0x50 0x51 0x52 0x53 0x54 0x55 0x56 0x57 0x58 0x59 0x5a 0x5b 0x5c 0x5d 0x5e 0x5f
+        0x60 0x61 0x62 0x63 0x64 0x65 0x66 0x67 0x68 0x69 0x6a 0x6b 0x6c 0x6d 0x6e 0x6f
+    );
+    idt[usize::from(apic::SPURIOUS_VECTOR)].set_handler_fn(spurious_interrupt_handler);
 
     idt
 });
@@ -52,8 +101,104 @@ pub fn init_idt() {
     IDT.load();
 }
 
+/// Unmasks a legacy IRQ line at the PICs.
+pub fn enable_irq(irq: u8) {
+    assert!(irq < 16, "invalid IRQ {}", irq);
+
+    instructions::interrupts::without_interrupts(|| {
+        let _pics = PICS.lock();
+        let unmask = |port: u16, line: u8| {
+            let mut port = Port::<u8>::new(port);
+            unsafe {
+                let mask = port.read();
+                port.write(mask & !(1 << line));
+            }
+        };
+        if irq < 8 {
+            unmask(0x21, irq);
+        } else {
+            unmask(0xa1, irq - 8);
+            // The secondary PIC is cascaded through IRQ 2.
+            unmask(0x21, 2);
+        }
+    });
+}
+
+/// Returns how many times each vector that was raised at least once was
+/// raised.
+pub fn counts() -> Vec<(u8, u64)> {
+    (0..=u8::MAX)
+        .map(|vector| (vector, COUNTS[usize::from(vector)].load(Ordering::Relaxed)))
+        .filter(|&(_, count)| count > 0)
+        .collect()
+}
+
+/// Returns what raises `vector`.
+pub fn vector_name(vector: u8) -> &'static str {
+    const DYNAMIC_LAST: u8 = DYNAMIC_VECTOR_START + DYNAMIC_VECTORS as u8 - 1;
+
+    match vector {
+        2 => "NMI",
+        3 => "breakpoint",
+        v if v == InterruptIndex::Timer.as_u8() => "timer",
+        v if v == InterruptIndex::Keyboard.as_u8() => "keyboard",
+        v if v == InterruptIndex::SerialB.as_u8() => "ttyS1/ttyS3",
+        v if v == InterruptIndex::SerialA.as_u8() => "ttyS0/ttyS2",
+        v if v == InterruptIndex::PrimaryAta.as_u8() => "ata0",
+        v if v == InterruptIndex::SecondaryAta.as_u8() => "ata1",
+        DYNAMIC_VECTOR_START..=DYNAMIC_LAST => "dynamic",
+        v if v == apic::SPURIOUS_VECTOR => "spurious",
+        _ => "other",
+    }
+}
+
+fn count(vector: u8) {
+    COUNTS[usize::from(vector)].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Allocates a free vector from the local APIC's dynamic range and installs
+/// `handler` for it, returning `None` if all vectors are in use.
+///
+/// `handler` runs in interrupt context, so it must not block or allocate. The
+/// end of interrupt is signaled after it returns.
+///
+/// # Panics
+///
+/// Panics if the local APIC is not enabled, since only it can deliver these
+/// vectors.
+pub fn allocate_vector(handler: impl Fn() + Send + Sync + 'static) -> Option<u8> {
+    assert!(apic::is_enabled(), "local APIC not enabled");
+
+    let mut handler = Some(Box::new(handler) as Handler);
+    instructions::interrupts::without_interrupts(|| {
+        DYNAMIC_HANDLERS
+            .iter()
+            .zip(DYNAMIC_VECTOR_START..)
+            .find_map(|(slot, vector)| {
+                let mut slot = slot.write();
+                if slot.is_none() {
+                    *slot = handler.take();
+                    Some(vector)
+                } else {
+                    None
+                }
+            })
+    })
+}
+
+/// Removes the handler of a vector returned by [`allocate_vector`] so the
+/// vector can be reused.
+///
+/// The device must no longer raise the vector.
+pub fn free_vector(vector: u8) {
+    let slot = &DYNAMIC_HANDLERS[usize::from(vector - DYNAMIC_VECTOR_START)];
+    let handler = instructions::interrupts::without_interrupts(|| slot.write().take());
+    assert!(handler.is_some(), "vector {:#x} was not allocated", vector);
+}
+
 // Exceptions
 extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
+    count(3);
     println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
 }
 
@@ -61,19 +206,85 @@ extern "x86-interrupt" fn double_fault_handler(
     stack_frame: InterruptStackFrame,
     _error_code: u64,
 ) -> ! {
+    // A fault on a guard page can escalate into a double fault if the page
+    // fault handler itself runs out of stack.
+    if let Some(stack) = memory::stack::guard_owner(Cr2::read()) {
+        panic!(
+            "EXCEPTION: DOUBLE FAULT (stack overflow on {} stack)\n{:#?}",
+            stack, stack_frame
+        );
+    }
     panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
 }
 
+extern "x86-interrupt" fn nmi_handler(stack_frame: InterruptStackFrame) {
+    count(2);
+    println!("EXCEPTION: NMI\n{:#?}", stack_frame);
+}
+
+extern "x86-interrupt" fn machine_check_handler(stack_frame: InterruptStackFrame) -> ! {
+    panic!("EXCEPTION: MACHINE CHECK\n{:#?}", stack_frame);
+}
+
+extern "x86-interrupt" fn page_fault_handler(
+    mut stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    if memory::user::handle_fault(&mut stack_frame) {
+        return;
+    }
+    if let Some(stack) = memory::stack::guard_owner(Cr2::read()) {
+        panic!(
+            "EXCEPTION: STACK OVERFLOW on {} stack\nAccessed Address: {:?}\n{:#?}",
+            stack,
+            Cr2::read(),
+            stack_frame
+        );
+    }
+
+    if memory::is_initialized() {
+        let root = memory::inspect::active_root();
+        println!(
+            "Translation: {}",
+            memory::inspect::translate(root, Cr2::read())
+        );
+    }
+    panic!(
+        "EXCEPTION: PAGE FAULT\nAccessed Address: {:?}\nError Code: {:?}\n{:#?}",
+        Cr2::read(),
+        error_code,
+        stack_frame
+    );
+}
+
 // Interrupts
 extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    print!(".");
+    count(InterruptIndex::Timer.as_u8());
+    time::tick();
     unsafe {
         PICS.lock()
             .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
     }
 }
 
+extern "x86-interrupt" fn dynamic_interrupt_handler<const VECTOR: u8>(
+    _stack_frame: InterruptStackFrame,
+) {
+    count(VECTOR);
+    let slot = &DYNAMIC_HANDLERS[usize::from(VECTOR - DYNAMIC_VECTOR_START)];
+    if let Some(handler) = slot.read().as_ref() {
+        handler();
+    }
+    apic::end_of_interrupt();
+}
+
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    count(apic::SPURIOUS_VECTOR);
+    // Spurious interrupts must not be acknowledged.
+}
+
 extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    count(InterruptIndex::Keyboard.as_u8());
     let mut port = Port::new(0x60);
     let scancode: u8 = unsafe { port.read() };
 
@@ -85,6 +296,46 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
     }
 }
 
+extern "x86-interrupt" fn serial_b_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    count(InterruptIndex::SerialB.as_u8());
+    serial::handle_interrupt(3);
+
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::SerialB.as_u8());
+    }
+}
+
+extern "x86-interrupt" fn serial_a_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    count(InterruptIndex::SerialA.as_u8());
+    serial::handle_interrupt(4);
+
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::SerialA.as_u8());
+    }
+}
+
+extern "x86-interrupt" fn primary_ata_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    count(InterruptIndex::PrimaryAta.as_u8());
+    ata::handle_interrupt(0);
+
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::PrimaryAta.as_u8());
+    }
+}
+
+extern "x86-interrupt" fn secondary_ata_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    count(InterruptIndex::SecondaryAta.as_u8());
+    ata::handle_interrupt(1);
+
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::SecondaryAta.as_u8());
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use x86_64::instructions::interrupts;
@@ -0,0 +1,86 @@
+//! The kernel log: everything printed to the screen or the serial port, kept
+//! in a ring buffer so it can be read back through `/dev/kmsg`.
+
+use core::{cmp, fmt};
+
+use spin::Mutex;
+use x86_64::instructions;
+
+/// Size of the log, after which the oldest output is overwritten.
+const SIZE: usize = 16 * 1024;
+
+static LOG: Mutex<Log> = Mutex::new(Log {
+    data: [0; SIZE],
+    end: 0,
+});
+
+struct Log {
+    data: [u8; SIZE],
+    /// Number of bytes ever written, the position of the next one.
+    end: u64,
+}
+
+impl Log {
+    fn start(&self) -> u64 {
+        self.end.saturating_sub(SIZE as u64)
+    }
+}
+
+/// Appends `bytes` to the log.
+pub fn write(bytes: &[u8]) {
+    instructions::interrupts::without_interrupts(|| {
+        let mut log = LOG.lock();
+        for &byte in bytes {
+            let index = (log.end % SIZE as u64) as usize;
+            log.data[index] = byte;
+            log.end += 1;
+        }
+    });
+}
+
+/// Copies the log from position `offset`, counted in bytes since boot, into
+/// `buf`.
+///
+/// Returns the position of the first byte copied, which is later than
+/// `offset` if that part was overwritten, and the number of bytes copied.
+pub fn read(offset: u64, buf: &mut [u8]) -> (u64, usize) {
+    instructions::interrupts::without_interrupts(|| {
+        let log = LOG.lock();
+        let start = cmp::max(offset, log.start());
+        let len = cmp::min(buf.len() as u64, log.end.saturating_sub(start)) as usize;
+        for (i, byte) in buf[..len].iter_mut().enumerate() {
+            *byte = log.data[((start + i as u64) % SIZE as u64) as usize];
+        }
+        (start, len)
+    })
+}
+
+/// A [`fmt::Write`] handle to the log, for the print macros.
+pub(crate) struct Writer;
+
+impl fmt::Write for Writer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        write(s.as_bytes());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn keeps_latest_output() {
+        write(b"marker");
+        let end = instructions::interrupts::without_interrupts(|| LOG.lock().end);
+        let mut buf = [0; 6];
+        assert_eq!(read(end - 6, &mut buf), (end - 6, 6));
+        assert_eq!(&buf, b"marker");
+
+        // Overwritten output is skipped.
+        let mut buf = [0; 2];
+        let (start, len) = read(0, &mut buf);
+        assert_eq!(len, 2);
+        assert_eq!(start, end.saturating_sub(SIZE as u64));
+    }
+}
@@ -9,13 +9,29 @@
 
 extern crate alloc;
 
+pub mod acpi;
 pub mod allocator;
+pub mod apic;
+pub mod ata;
+pub mod block;
+pub mod cp437;
+pub mod cpu;
+pub mod fs;
 pub mod gdt;
 pub mod interrupts;
+pub mod kmsg;
 pub mod memory;
+pub mod pci;
+pub mod power;
+pub mod process;
 pub mod serial;
+pub mod shell;
 pub mod task;
+pub mod time;
+pub mod tty;
+pub mod vfs;
 pub mod vga_buffer;
+pub mod virtio;
 
 use core::panic::PanicInfo;
 use x86_64::instructions::{self, port::Port};
@@ -24,6 +40,7 @@ pub fn init() {
     gdt::init();
     interrupts::init_idt();
     unsafe { interrupts::PICS.lock().initialize() };
+    time::init();
     instructions::interrupts::enable();
 }
 
@@ -83,8 +100,15 @@ use bootloader::{entry_point, BootInfo};
 entry_point!(test_kernel_main);
 
 #[cfg(test)]
-fn test_kernel_main(_boot_info: &'static BootInfo) -> ! {
+fn test_kernel_main(boot_info: &'static BootInfo) -> ! {
     init();
+    let mut mapper =
+        unsafe { memory::init(x86_64::VirtAddr::new(boot_info.physical_memory_offset)) };
+    let mut frame_allocator =
+        unsafe { memory::BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::vmm::init(mapper, frame_allocator);
+    memory::regions::init(&boot_info.memory_map);
     test_main();
     hlt_loop();
 }
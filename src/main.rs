@@ -10,10 +10,11 @@ use core::panic::PanicInfo;
 
 use bootloader::{entry_point, BootInfo};
 use rust_os::{
-    self, allocator, hlt_loop,
-    memory::{self, BootInfoFrameAllocator},
-    println,
-    task::{keyboard, Task},
+    self, acpi, allocator, apic, ata, block, fs, gdt, hlt_loop,
+    memory::{self, regions, stack::KernelStack, vmm, BootInfoFrameAllocator},
+    pci, println, serial, serial_println, shell,
+    task::{self, keyboard, Task},
+    tty, vga_buffer, virtio,
 };
 use x86_64::VirtAddr;
 
@@ -21,6 +22,10 @@ use self::rust_os::task::executor::SleepingExecutor;
 
 entry_point!(kernel_main);
 
+const BOOT_STACK_PAGES: u64 = 16;
+/// Lines kept after they scroll off the top of the console.
+const SCROLLBACK_LINES: usize = 500;
+
 fn kernel_main(boot_info: &'static BootInfo) -> ! {
     #[cfg(test)]
     test_main();
@@ -30,13 +35,51 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
 
     let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
     let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    unsafe { memory::protection::protect_kernel(&mut mapper, &boot_info.memory_map) };
     let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
 
     allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    vga_buffer::set_scrollback(SCROLLBACK_LINES);
+    vmm::init(mapper, frame_allocator);
+    regions::init(&boot_info.memory_map);
+    regions::report(&mut serial::SerialWriter).expect("memory map report failed");
+    match acpi::init() {
+        Ok(acpi) => acpi
+            .summary(&mut serial::SerialWriter)
+            .expect("ACPI summary failed"),
+        Err(err) => serial_println!("ACPI unavailable: {:?}", err),
+    }
+    if let Err(err) = apic::init() {
+        serial_println!("local APIC unavailable: {:?}", err);
+    }
+    serial::init();
+    pci::init();
+    pci::lspci(&mut serial::SerialWriter).expect("PCI listing failed");
+    virtio::init();
+    ata::init();
+    task::block_on(block::partition::probe_all());
+    task::block_on(fs::init());
+    gdt::init_stacks().expect("interrupt stack allocation failed");
+
+    let boot_stack =
+        KernelStack::alloc("boot", BOOT_STACK_PAGES).expect("boot stack allocation failed");
+    unsafe { boot_stack.switch_to(kernel_run) }
+}
+
+extern "C" fn kernel_run() -> ! {
+    let reclaimed = unsafe { regions::reclaim_boot_memory() };
+    serial_println!("reclaimed {} KiB of boot memory", reclaimed * 4);
 
     let mut executor = SleepingExecutor::new();
     executor.spawn(Task::new(print_number_task()));
-    executor.spawn(Task::new(keyboard::print_keypresses()));
+    executor.spawn(Task::new(keyboard::handle_keypresses()));
+    for port in serial::ports() {
+        executor.spawn(Task::new(serial::handle_input(port.index())));
+    }
+    executor.spawn(Task::new(shell::run(tty::console())));
+    if let Some(tty) = tty::serial(0) {
+        executor.spawn(Task::new(shell::run(tty)));
+    }
     executor.run();
 }
 
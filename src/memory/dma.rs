@@ -0,0 +1,133 @@
+use core::{cmp, ptr};
+use x86_64::{
+    structures::paging::{FrameDeallocator, PhysFrame, Size4KiB},
+    PhysAddr, VirtAddr,
+};
+
+use super::{inspect, phys_to_virt, vmm};
+
+const PAGE_SIZE: u64 = 4096;
+
+/// Zeroed, physically contiguous memory that devices can access directly.
+///
+/// The memory is accessed through the physical memory mapping and returned to
+/// the frame allocator on drop.
+#[derive(Debug)]
+pub struct DmaRegion {
+    start: PhysFrame,
+    pages: u64,
+}
+
+impl DmaRegion {
+    /// Allocates a region of at least `size` bytes.
+    pub fn alloc(size: usize) -> Option<Self> {
+        let pages = x86_64::align_up(cmp::max(size as u64, 1), PAGE_SIZE) / PAGE_SIZE;
+        let start = vmm::lock()
+            .frame_allocator()
+            .allocate_contiguous(pages as usize)?;
+
+        let region = Self { start, pages };
+        unsafe { ptr::write_bytes(region.virt().as_mut_ptr::<u8>(), 0, region.len()) };
+        Some(region)
+    }
+
+    pub fn phys(&self) -> PhysAddr {
+        self.start.start_address()
+    }
+
+    pub fn virt(&self) -> VirtAddr {
+        phys_to_virt(self.phys())
+    }
+
+    pub fn len(&self) -> usize {
+        (self.pages * PAGE_SIZE) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// Returns a pointer to the `T` at `offset` bytes into the region.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `T` doesn't fit.
+    pub fn ptr<T>(&self, offset: usize) -> *mut T {
+        assert!(
+            offset + core::mem::size_of::<T>() <= self.len(),
+            "DMA region access out of bounds"
+        );
+        (self.virt() + offset).as_mut_ptr()
+    }
+}
+
+impl Drop for DmaRegion {
+    fn drop(&mut self) {
+        let mut vmm = vmm::lock();
+        for frame in PhysFrame::<Size4KiB>::range(self.start, self.start + self.pages) {
+            unsafe { vmm.frame_allocator().deallocate_frame(frame) };
+        }
+    }
+}
+
+/// Splits the memory of `buf` into physically contiguous segments, calling
+/// `f` with the physical address and length of each.
+///
+/// # Panics
+///
+/// Panics if part of `buf` isn't mapped, which can't happen for a valid slice.
+pub fn for_each_segment(buf: &[u8], mut f: impl FnMut(PhysAddr, usize)) {
+    let root = inspect::active_root();
+    let mut segment: Option<(PhysAddr, usize)> = None;
+    let mut addr = VirtAddr::from_ptr(buf.as_ptr());
+    let end = addr + buf.len();
+
+    while addr < end {
+        let page_end = (addr + 1u64).align_up(PAGE_SIZE);
+        let len = (cmp::min(page_end, end) - addr) as usize;
+        let phys = match inspect::translate(root, addr) {
+            inspect::Translation::Mapped { phys, .. } => phys,
+            inspect::Translation::NotMapped { .. } => panic!("buffer at {:?} not mapped", addr),
+        };
+
+        segment = match segment {
+            Some((start, seg_len)) if start + seg_len as u64 == phys => {
+                Some((start, seg_len + len))
+            }
+            Some((start, seg_len)) => {
+                f(start, seg_len);
+                Some((phys, len))
+            }
+            None => Some((phys, len)),
+        };
+        addr += len;
+    }
+    if let Some((start, len)) = segment {
+        f(start, len);
+    }
+}
+
+/// Returns the number of physically contiguous segments of `buf`.
+pub fn segment_count(buf: &[u8]) -> usize {
+    let mut count = 0;
+    for_each_segment(buf, |_, _| count += 1);
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test_case]
+    fn segments_cover_buffer() {
+        let buf = vec![0u8; 3 * PAGE_SIZE as usize];
+        let mut total = 0;
+        for_each_segment(&buf, |phys, len| {
+            assert!(phys.as_u64() != 0);
+            total += len;
+        });
+        assert_eq!(total, buf.len());
+        assert!(segment_count(&buf[..1]) == 1);
+    }
+}
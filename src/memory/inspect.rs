@@ -0,0 +1,332 @@
+use core::fmt;
+use x86_64::{
+    registers::control::Cr3,
+    structures::paging::{PageTable, PageTableFlags, PhysFrame},
+    PhysAddr, VirtAddr,
+};
+
+use super::phys_to_virt;
+
+/// Flags that only apply to a mapping if they are set at every level.
+const INHERITED: PageTableFlags = PageTableFlags::USER_ACCESSIBLE.union(PageTableFlags::WRITABLE);
+
+/// Flags that describe the state of an entry rather than the mapping.
+const IGNORED: PageTableFlags = PageTableFlags::ACCESSED
+    .union(PageTableFlags::DIRTY)
+    .union(PageTableFlags::HUGE_PAGE);
+
+/// A contiguous range of virtual memory mapped to contiguous physical memory
+/// with the same effective flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mapping {
+    pub virt: VirtAddr,
+    pub phys: PhysAddr,
+    pub size: u64,
+    pub flags: PageTableFlags,
+}
+
+impl Mapping {
+    /// Extends `self` by `next` if it directly follows it, returning whether
+    /// it did.
+    fn merge(&mut self, next: &Mapping) -> bool {
+        let follows = self.virt.as_u64().wrapping_add(self.size) == next.virt.as_u64()
+            && self.phys + self.size == next.phys
+            && self.flags == next.flags;
+        if follows {
+            self.size += next.size;
+        }
+        follows
+    }
+}
+
+impl fmt::Display for Mapping {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:#018x}-{:#018x} -> {:#014x} {} {}",
+            self.virt.as_u64(),
+            self.virt.as_u64().wrapping_add(self.size - 1),
+            self.phys.as_u64(),
+            Size(self.size),
+            Flags(self.flags)
+        )
+    }
+}
+
+/// Result of translating a virtual address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Translation {
+    /// The address is mapped by an entry at `level` (1 for 4KiB pages, 2 for
+    /// 2MiB pages and 3 for 1GiB pages).
+    Mapped {
+        phys: PhysAddr,
+        level: u8,
+        flags: PageTableFlags,
+    },
+    /// The entry at `level` is not present.
+    NotMapped { level: u8 },
+}
+
+impl fmt::Display for Translation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Translation::Mapped { phys, level, flags } => write!(
+                f,
+                "{:#x} (level {} entry, {})",
+                phys.as_u64(),
+                level,
+                Flags(flags)
+            ),
+            Translation::NotMapped { level } => {
+                write!(f, "not mapped (level {} entry not present)", level)
+            }
+        }
+    }
+}
+
+/// Number of page table frames used by a hierarchy, per level.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TableCount {
+    pub level_4: usize,
+    pub level_3: usize,
+    pub level_2: usize,
+    pub level_1: usize,
+}
+
+impl TableCount {
+    pub fn total(&self) -> usize {
+        self.level_4 + self.level_3 + self.level_2 + self.level_1
+    }
+}
+
+/// Returns the root of the active page table hierarchy.
+pub fn active_root() -> PhysFrame {
+    Cr3::read().0
+}
+
+/// Translates `addr` in the hierarchy rooted at `root`.
+///
+/// `USER_ACCESSIBLE` and `WRITABLE` are only reported if they are set at every
+/// level, and `NO_EXECUTE` if it is set at any level.
+pub fn translate(root: PhysFrame, addr: VirtAddr) -> Translation {
+    let indices = [
+        addr.p4_index(),
+        addr.p3_index(),
+        addr.p2_index(),
+        addr.p1_index(),
+    ];
+
+    let mut table = table_at(root.start_address());
+    let mut flags = INHERITED;
+    for (level, index) in (1..=4).rev().zip(indices) {
+        let entry = &table[index];
+        if !entry.flags().contains(PageTableFlags::PRESENT) {
+            return Translation::NotMapped { level };
+        }
+        flags = inherit(flags, entry.flags());
+
+        if is_leaf(level, entry.flags()) {
+            let offset = addr.as_u64() & (level_size(level) - 1);
+            return Translation::Mapped {
+                phys: entry.addr() + offset,
+                level,
+                flags: flags - IGNORED,
+            };
+        }
+        table = table_at(entry.addr());
+    }
+    unreachable!("level 1 entries are always leaves")
+}
+
+/// Calls `f` for every range of mappings in the hierarchy rooted at `root`,
+/// merging adjacent pages with the same flags.
+pub fn for_each_range(root: PhysFrame, mut f: impl FnMut(&Mapping)) {
+    let mut current: Option<Mapping> = None;
+    walk(
+        table_at(root.start_address()),
+        4,
+        0,
+        INHERITED,
+        &mut |mapping| {
+            if let Some(range) = current.as_mut() {
+                if range.merge(&mapping) {
+                    return;
+                }
+                f(range);
+            }
+            current = Some(mapping);
+        },
+    );
+    if let Some(range) = current {
+        f(&range);
+    }
+}
+
+/// Writes a range-merged listing of the hierarchy rooted at `root` to `out`.
+///
+/// Neither allocates nor takes any locks, so it is safe to use while
+/// panicking.
+pub fn dump(root: PhysFrame, out: &mut dyn fmt::Write) -> fmt::Result {
+    writeln!(out, "page table at {:#x}:", root.start_address().as_u64())?;
+    let mut result = Ok(());
+    for_each_range(root, |range| {
+        if result.is_ok() {
+            result = writeln!(out, "  {}", range);
+        }
+    });
+    result?;
+
+    let count = count_tables(root);
+    writeln!(
+        out,
+        "  {} page table frames (L4: {}, L3: {}, L2: {}, L1: {})",
+        count.total(),
+        count.level_4,
+        count.level_3,
+        count.level_2,
+        count.level_1
+    )
+}
+
+/// Counts the page table frames of the hierarchy rooted at `root`.
+pub fn count_tables(root: PhysFrame) -> TableCount {
+    let mut count = TableCount::default();
+    count_level(table_at(root.start_address()), 4, &mut count);
+    count
+}
+
+fn count_level(table: &PageTable, level: u8, count: &mut TableCount) {
+    match level {
+        4 => count.level_4 += 1,
+        3 => count.level_3 += 1,
+        2 => count.level_2 += 1,
+        _ => count.level_1 += 1,
+    }
+    if level == 1 {
+        return;
+    }
+
+    for entry in table.iter() {
+        let flags = entry.flags();
+        if flags.contains(PageTableFlags::PRESENT) && !is_leaf(level, flags) {
+            count_level(table_at(entry.addr()), level - 1, count);
+        }
+    }
+}
+
+fn walk(
+    table: &PageTable,
+    level: u8,
+    base: u64,
+    inherited: PageTableFlags,
+    f: &mut impl FnMut(Mapping),
+) {
+    for (index, entry) in table.iter().enumerate() {
+        if !entry.flags().contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+        let virt = base | (index as u64) << (12 + 9 * (u64::from(level) - 1));
+        let flags = inherit(inherited, entry.flags());
+
+        if is_leaf(level, entry.flags()) {
+            f(Mapping {
+                virt: VirtAddr::new_truncate(virt),
+                phys: entry.addr(),
+                size: level_size(level),
+                flags: flags - IGNORED,
+            });
+        } else {
+            walk(table_at(entry.addr()), level - 1, virt, flags, f);
+        }
+    }
+}
+
+fn table_at(addr: PhysAddr) -> &'static PageTable {
+    unsafe { &*phys_to_virt(addr).as_ptr() }
+}
+
+fn is_leaf(level: u8, flags: PageTableFlags) -> bool {
+    level == 1 || (level < 4 && flags.contains(PageTableFlags::HUGE_PAGE))
+}
+
+fn level_size(level: u8) -> u64 {
+    1 << (12 + 9 * (u64::from(level) - 1))
+}
+
+/// Combines the flags of an entry with the flags inherited from its parents.
+fn inherit(parent: PageTableFlags, entry: PageTableFlags) -> PageTableFlags {
+    let mut flags = entry - (INHERITED - parent);
+    if parent.contains(PageTableFlags::NO_EXECUTE) {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+    flags
+}
+
+/// Formats page table flags as e.g. `rw-ug`.
+struct Flags(PageTableFlags);
+
+impl fmt::Display for Flags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flag = |flag, c| if self.0.contains(flag) { c } else { '-' };
+        write!(
+            f,
+            "r{}{}{}{}{}",
+            flag(PageTableFlags::WRITABLE, 'w'),
+            if self.0.contains(PageTableFlags::NO_EXECUTE) {
+                '-'
+            } else {
+                'x'
+            },
+            flag(PageTableFlags::USER_ACCESSIBLE, 'u'),
+            flag(PageTableFlags::GLOBAL, 'g'),
+            flag(PageTableFlags::NO_CACHE, 'c'),
+        )
+    }
+}
+
+/// Formats a size in bytes with a binary unit suffix, right-aligned.
+struct Size(u64);
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+        let mut size = self.0;
+        let mut unit = 0;
+        while size >= 1024 && size % 1024 == 0 && unit < UNITS.len() - 1 {
+            size /= 1024;
+            unit += 1;
+        }
+        write!(f, "{:>5}{:<3}", size, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn translate_matches_mapper() {
+        use crate::memory::phys_mem_offset;
+
+        let value = 0u64;
+        let addr = VirtAddr::from_ptr(&value);
+        match translate(active_root(), addr) {
+            Translation::Mapped { phys, flags, .. } => {
+                assert!(flags.contains(PageTableFlags::WRITABLE));
+                assert_eq!(
+                    unsafe { *(phys_mem_offset() + phys.as_u64()).as_ptr::<u64>() },
+                    0
+                );
+            }
+            Translation::NotMapped { .. } => panic!("stack is not mapped"),
+        }
+    }
+
+    #[test_case]
+    fn null_page_is_not_mapped() {
+        assert!(matches!(
+            translate(active_root(), VirtAddr::new(0)),
+            Translation::NotMapped { .. }
+        ));
+    }
+}
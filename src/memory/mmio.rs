@@ -0,0 +1,55 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::{
+    structures::paging::{mapper::MapToError, Page, PageTableFlags, PhysFrame, Size4KiB},
+    PhysAddr, VirtAddr,
+};
+
+use super::{
+    regions::{self, RangeKind},
+    vmm,
+};
+
+pub const MMIO_START: u64 = 0x6666_6666_0000;
+
+static NEXT_MMIO: AtomicU64 = AtomicU64::new(MMIO_START);
+
+/// Maps the `size` bytes of device memory at `phys` uncached and returns the
+/// virtual address of `phys`.
+///
+/// Mappings are never removed, so drivers should map their registers once.
+///
+/// # Panics
+///
+/// Panics if the range is ordinary RAM.
+pub fn map(phys: PhysAddr, size: u64) -> Result<VirtAddr, MapToError<Size4KiB>> {
+    assert_ne!(
+        regions::classify(phys, size),
+        RangeKind::Ram,
+        "refusing to map RAM at {:#x} as device memory",
+        phys.as_u64()
+    );
+
+    let first = PhysFrame::<Size4KiB>::containing_address(phys);
+    let last = PhysFrame::<Size4KiB>::containing_address(phys + (size.max(1) - 1));
+    let frames = PhysFrame::range_inclusive(first, last);
+    let pages = frames.count() as u64;
+
+    let start =
+        VirtAddr::new(NEXT_MMIO.fetch_add(pages * Page::<Size4KiB>::SIZE, Ordering::Relaxed));
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_CACHE
+        | PageTableFlags::WRITE_THROUGH
+        | PageTableFlags::NO_EXECUTE;
+    let mut vmm = vmm::lock();
+    for (page, frame) in Page::range(
+        Page::containing_address(start),
+        Page::containing_address(start) + pages,
+    )
+    .zip(frames)
+    {
+        vmm.map_to(page, frame, flags)?;
+    }
+
+    Ok(start + (phys - first.start_address()))
+}
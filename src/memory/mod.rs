@@ -0,0 +1,201 @@
+pub mod dma;
+pub mod inspect;
+pub mod mmio;
+pub mod protection;
+pub mod regions;
+pub mod stack;
+pub mod user;
+pub mod vmm;
+
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use core::cmp;
+use spin::Once;
+use x86_64::{
+    registers::control::Cr3,
+    structures::paging::{
+        mapper::MapToError, FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page,
+        PageTable, PageTableFlags, PhysFrame, Size4KiB,
+    },
+    PhysAddr, VirtAddr,
+};
+
+static PHYS_MEM_OFFSET: Once<VirtAddr> = Once::new();
+
+/// Initialize a new offset page table.
+///
+/// # Safety
+/// This function is unsafe because the caller must guarantee that the
+/// complete physical memory is mapped to virtual memory at the passed
+/// `phys_mem_offset`. Also, this function must be only called once
+/// to avoid aliasing `&mut` references (which is undefined behavior).
+pub unsafe fn init(phys_mem_offset: VirtAddr) -> OffsetPageTable<'static> {
+    protection::enable();
+    PHYS_MEM_OFFSET.call_once(|| phys_mem_offset);
+
+    let level_4_table = active_level_4_table(phys_mem_offset);
+    OffsetPageTable::new(level_4_table, phys_mem_offset)
+}
+
+/// Returns the virtual address at which the complete physical memory is mapped.
+///
+/// # Panics
+///
+/// Panics if called before [`init`].
+pub fn phys_mem_offset() -> VirtAddr {
+    *PHYS_MEM_OFFSET.get().expect("memory not initialized")
+}
+
+/// Returns whether [`init`] was called.
+pub fn is_initialized() -> bool {
+    PHYS_MEM_OFFSET.is_completed()
+}
+
+/// Returns the virtual address through which `addr` can be accessed.
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    phys_mem_offset() + addr.as_u64()
+}
+
+unsafe fn active_level_4_table(phys_mem_offset: VirtAddr) -> &'static mut PageTable {
+    let (level_4_table_frame, _) = Cr3::read();
+
+    let phys = level_4_table_frame.start_address();
+    let virt = phys_mem_offset + phys.as_u64();
+    let page_table_ptr: *mut PageTable = virt.as_mut_ptr();
+
+    unsafe { &mut *page_table_ptr }
+}
+
+/// Maps `page` to `frame`, enforcing the W^X invariant on `flags`.
+///
+/// # Panics
+///
+/// Panics if `flags` would make the page both writable and executable.
+///
+/// # Safety
+///
+/// Same as [`Mapper::map_to`].
+pub unsafe fn map_page(
+    mapper: &mut impl Mapper<Size4KiB>,
+    page: Page,
+    frame: PhysFrame,
+    flags: PageTableFlags,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    protection::assert_wx(flags);
+    mapper.map_to(page, frame, flags, frame_allocator)?.flush();
+    Ok(())
+}
+
+pub struct EmptyFrameAllocator;
+
+unsafe impl FrameAllocator<Size4KiB> for EmptyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        None
+    }
+}
+
+/// A FrameAllocator that returns usable frames from the bootloader's memory map.
+///
+/// Deallocated frames are kept in a list linked through the frames themselves
+/// and handed out again before any new frame from the memory map.
+pub struct BootInfoFrameAllocator {
+    memory_map: &'static MemoryMap,
+    next: usize,
+    free_list: Option<PhysFrame>,
+    /// Number of frames on the free list.
+    free_count: usize,
+}
+
+impl BootInfoFrameAllocator {
+    /// Create a FrameAllocator from the passed memory map.
+    ///
+    /// # Safety
+    /// The caller must ensure that the passed memory map is valid. The main
+    /// requirement is that all frames that are marked as `USABLE` in it are
+    /// really unused.
+    pub unsafe fn init(memory_map: &'static MemoryMap) -> Self {
+        BootInfoFrameAllocator {
+            memory_map,
+            next: 0,
+            free_list: None,
+            free_count: 0,
+        }
+    }
+
+    /// Returns the number of usable frames in the memory map and how many of
+    /// them are free.
+    pub fn stats(&self) -> (usize, usize) {
+        let total = self
+            .memory_map
+            .iter()
+            .filter(|r| r.region_type == MemoryRegionType::Usable)
+            .map(|r| ((r.range.end_addr() - r.range.start_addr()) / 4096) as usize)
+            .sum::<usize>();
+        let unused = total.saturating_sub(self.next);
+        (total, cmp::min(unused + self.free_count, total))
+    }
+
+    /// Returns an iterator over the usable frames specified in the memory map.
+    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
+        self.memory_map
+            .iter()
+            .filter(|r| r.region_type == MemoryRegionType::Usable)
+            .map(|r| r.range.start_addr()..r.range.end_addr())
+            .flat_map(|r| r.step_by(4096))
+            .map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+    }
+
+    /// Allocates `count` physically contiguous frames from the memory map,
+    /// returning the first one.
+    ///
+    /// Frames skipped while looking for a contiguous run are put on the free
+    /// list.
+    pub fn allocate_contiguous(&mut self, count: usize) -> Option<PhysFrame> {
+        assert!(count > 0, "allocating zero frames");
+        loop {
+            let mut frames = self.usable_frames().skip(self.next);
+            let start = frames.next()?;
+            let run = frames
+                .take(count - 1)
+                .zip(1..)
+                .take_while(|&(frame, i)| frame == start + i)
+                .count();
+            if run == count - 1 {
+                self.next += count;
+                return Some(start);
+            }
+
+            self.next += 1;
+            unsafe { self.deallocate_frame(start) };
+        }
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        if let Some(frame) = self.free_list {
+            let next: u64 = unsafe { *phys_to_virt(frame.start_address()).as_ptr() };
+            self.free_list = match next {
+                0 => None,
+                addr => Some(PhysFrame::containing_address(PhysAddr::new(addr))),
+            };
+            self.free_count -= 1;
+            return Some(frame);
+        }
+
+        let frame = self.usable_frames().nth(self.next);
+        self.next += 1;
+        frame
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BootInfoFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        // Frame zero is never handed out, so 0 can terminate the list.
+        let next = self.free_list.map_or(0, |f| f.start_address().as_u64());
+        let node: *mut u64 = phys_to_virt(frame.start_address()).as_mut_ptr();
+        node.write(next);
+        self.free_list = Some(frame);
+        self.free_count += 1;
+    }
+}
@@ -0,0 +1,248 @@
+use bootloader::bootinfo::MemoryMap;
+use core::{
+    arch::asm,
+    mem, slice,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use x86_64::{
+    instructions::tlb,
+    registers::{
+        control::{Cr0, Cr0Flags, Cr4, Cr4Flags},
+        model_specific::{Efer, EferFlags},
+    },
+    structures::paging::{
+        mapper::{MappedFrame, TranslateResult},
+        Mapper, OffsetPageTable, Page, PageTableFlags, PageTableIndex, Size4KiB, Translate,
+    },
+    VirtAddr,
+};
+
+use crate::cpu::{self, Feature};
+
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1 << 0;
+const PF_W: u32 = 1 << 1;
+
+#[allow(dead_code)]
+#[repr(C)]
+struct ElfHeader {
+    ident: [u8; 16],
+    kind: u16,
+    machine: u16,
+    version: u32,
+    entry: u64,
+    phoff: u64,
+    shoff: u64,
+    flags: u32,
+    ehsize: u16,
+    phentsize: u16,
+    phnum: u16,
+    shentsize: u16,
+    shnum: u16,
+    shstrndx: u16,
+}
+
+#[allow(dead_code)]
+#[repr(C)]
+struct ProgramHeader {
+    kind: u32,
+    flags: u32,
+    offset: u64,
+    vaddr: u64,
+    paddr: u64,
+    filesz: u64,
+    memsz: u64,
+    align: u64,
+}
+
+extern "C" {
+    /// Start of the kernel's own ELF header, provided by the linker.
+    static __ehdr_start: ElfHeader;
+}
+
+static SMAP_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Enables the no-execute bit in page table entries (`EFER.NXE`) and makes
+/// read-only pages apply to supervisor mode as well (`CR0.WP`).
+///
+/// SMEP, SMAP and UMIP are enabled in `CR4` as well if the CPU supports them.
+pub fn enable() {
+    unsafe {
+        Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE));
+        Cr0::update(|flags| flags.insert(Cr0Flags::WRITE_PROTECT));
+    }
+
+    let mut cr4 = Cr4Flags::empty();
+    if cpu::has(Feature::Smep) {
+        cr4 |= Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION;
+    }
+    if cpu::has(Feature::Smap) {
+        cr4 |= Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION;
+    }
+    if cpu::has(Feature::Umip) {
+        cr4 |= Cr4Flags::USER_MODE_INSTRUCTION_PREVENTION;
+    }
+    unsafe { Cr4::update(|flags| flags.insert(cr4)) };
+
+    SMAP_ENABLED.store(
+        cr4.contains(Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION),
+        Ordering::Relaxed,
+    );
+}
+
+/// Returns whether supervisor mode access prevention is active, in which case
+/// user memory may only be touched with `RFLAGS.AC` set.
+pub fn smap_enabled() -> bool {
+    SMAP_ENABLED.load(Ordering::Relaxed)
+}
+
+/// Checks the W^X invariant: no page may be writable and executable at once.
+///
+/// # Panics
+///
+/// Panics if `flags` describe a writable page without `NO_EXECUTE`.
+pub fn assert_wx(flags: PageTableFlags) {
+    assert!(
+        !flags.contains(PageTableFlags::WRITABLE) || flags.contains(PageTableFlags::NO_EXECUTE),
+        "W^X violation: writable page is executable ({:?})",
+        flags
+    );
+}
+
+/// Remaps the kernel so that its code is read-only and everything else is
+/// non-executable.
+///
+/// This covers the loadable segments of the kernel image (`.text` becomes
+/// `R+X`, `.rodata` `R`, `.data`/`.bss` `RW+NX`), the boot stack we are
+/// currently running on and the complete physical memory mapping.
+///
+/// # Safety
+///
+/// The caller must guarantee that `mapper` is the active page table, that
+/// [`enable`] was already called and that the kernel still runs on the
+/// stack provided by the bootloader.
+pub unsafe fn protect_kernel(mapper: &mut OffsetPageTable, memory_map: &MemoryMap) {
+    for segment in kernel_segments().iter().filter(|s| s.kind == PT_LOAD) {
+        let mut flags = PageTableFlags::PRESENT;
+        if segment.flags & PF_W != 0 {
+            flags |= PageTableFlags::WRITABLE;
+        }
+        if segment.flags & PF_X == 0 {
+            flags |= PageTableFlags::NO_EXECUTE;
+        }
+        assert_wx(flags);
+
+        let start = VirtAddr::new(segment.vaddr);
+        let end = start + segment.memsz - 1u64;
+        let pages = Page::<Size4KiB>::range_inclusive(
+            Page::containing_address(start),
+            Page::containing_address(end),
+        );
+        for page in pages {
+            mapper
+                .update_flags(page, flags)
+                .expect("failed to remap kernel segment")
+                .flush();
+        }
+    }
+
+    protect_boot_stack(mapper);
+    protect_physical_memory(mapper, memory_map);
+}
+
+/// Returns the program headers of the running kernel.
+fn kernel_segments() -> &'static [ProgramHeader] {
+    let header = unsafe { &__ehdr_start };
+    assert_eq!(
+        &header.ident[..4],
+        b"\x7fELF",
+        "kernel ELF header is not mapped"
+    );
+    assert_eq!(
+        usize::from(header.phentsize),
+        mem::size_of::<ProgramHeader>()
+    );
+
+    let base = header as *const ElfHeader as *const u8;
+    unsafe {
+        slice::from_raw_parts(
+            base.add(header.phoff as usize) as *const ProgramHeader,
+            usize::from(header.phnum),
+        )
+    }
+}
+
+/// Marks every page of the current stack as non-executable.
+///
+/// The stack is found by walking from `rsp` in both directions for as long as
+/// the pages are mapped and writable, which stops at the guard page below the
+/// stack and at the first unrelated mapping above it.
+unsafe fn protect_boot_stack(mapper: &mut OffsetPageTable) {
+    let rsp: u64;
+    asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack, preserves_flags));
+    let current = Page::<Size4KiB>::containing_address(VirtAddr::new(rsp));
+
+    let mut page = current;
+    while set_no_execute(mapper, page) {
+        page -= 1;
+    }
+    let mut page = current + 1;
+    while set_no_execute(mapper, page) {
+        page += 1;
+    }
+}
+
+/// Adds `NO_EXECUTE` to `page` if it is a writable 4KiB mapping, returning
+/// whether it was.
+unsafe fn set_no_execute(mapper: &mut OffsetPageTable, page: Page) -> bool {
+    match mapper.translate(page.start_address()) {
+        TranslateResult::Mapped {
+            frame: MappedFrame::Size4KiB(_),
+            flags,
+            ..
+        } if flags.contains(PageTableFlags::WRITABLE) => {
+            mapper
+                .update_flags(page, flags | PageTableFlags::NO_EXECUTE)
+                .expect("failed to remap stack page")
+                .flush();
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Marks the level 4 entries backing the physical memory mapping as
+/// non-executable, which applies to every page below them.
+///
+/// Entries shared with the kernel's executable segments are left alone.
+unsafe fn protect_physical_memory(mapper: &mut OffsetPageTable, memory_map: &MemoryMap) {
+    let phys_end = memory_map
+        .iter()
+        .map(|r| r.range.end_addr())
+        .max()
+        .unwrap_or(0);
+    if phys_end == 0 {
+        return;
+    }
+
+    let start = mapper.phys_offset();
+    let end = start + phys_end - 1u64;
+    let first = usize::from(start.p4_index());
+    let last = usize::from(end.p4_index());
+
+    let is_kernel_code = |index: usize| {
+        kernel_segments()
+            .iter()
+            .filter(|s| s.kind == PT_LOAD && s.flags & PF_X != 0)
+            .any(|s| usize::from(VirtAddr::new(s.vaddr).p4_index()) == index)
+    };
+
+    let level_4_table = mapper.level_4_table();
+    for index in (first..=last).filter(|&i| !is_kernel_code(i)) {
+        let entry = &mut level_4_table[PageTableIndex::new(index as u16)];
+        if !entry.is_unused() {
+            entry.set_flags(entry.flags() | PageTableFlags::NO_EXECUTE);
+        }
+    }
+    tlb::flush_all();
+}
@@ -0,0 +1,242 @@
+use alloc::vec::Vec;
+use bootloader::bootinfo::{MemoryMap, MemoryRegion, MemoryRegionType};
+use core::{fmt, ops::Range};
+use spin::Once;
+use x86_64::{
+    structures::paging::{FrameDeallocator, Page, PhysFrame, Size4KiB},
+    PhysAddr, VirtAddr,
+};
+
+use super::{inspect, phys_mem_offset, vmm};
+
+static MEMORY_MAP: Once<&'static MemoryMap> = Once::new();
+
+/// What a range of physical addresses is backed by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeKind {
+    /// Ordinary memory, whether it is currently in use or not.
+    Ram,
+    /// Memory reserved by the firmware, ACPI non-volatile storage or bad
+    /// memory. Parts of the range may also be RAM.
+    Reserved,
+    /// Not described by the memory map at all, so presumably device memory.
+    Mmio,
+}
+
+/// Registers the bootloader's memory map for later queries.
+pub fn init(memory_map: &'static MemoryMap) {
+    MEMORY_MAP.call_once(|| memory_map);
+}
+
+fn memory_map() -> &'static MemoryMap {
+    MEMORY_MAP.get().expect("memory map uninitialized")
+}
+
+/// Classifies the `size` bytes of physical memory starting at `start`.
+///
+/// Drivers should check that a range is [`RangeKind::Mmio`] (or reserved
+/// firmware memory they know about) before mapping it as device memory.
+pub fn classify(start: PhysAddr, size: u64) -> RangeKind {
+    classify_in(memory_map(), start.as_u64()..start.as_u64() + size)
+}
+
+fn classify_in(regions: &[MemoryRegion], range: Range<u64>) -> RangeKind {
+    let overlapping = regions
+        .iter()
+        .filter(|r| r.range.start_addr() < range.end && range.start < r.range.end_addr());
+
+    let mut covered = 0;
+    for region in overlapping {
+        if is_reserved(region.region_type) {
+            return RangeKind::Reserved;
+        }
+        covered +=
+            region.range.end_addr().min(range.end) - region.range.start_addr().max(range.start);
+    }
+
+    if covered == 0 {
+        RangeKind::Mmio
+    } else if covered < range.end - range.start {
+        // Partly RAM, partly a hole: not safe to treat as either.
+        RangeKind::Reserved
+    } else {
+        RangeKind::Ram
+    }
+}
+
+fn is_reserved(region_type: MemoryRegionType) -> bool {
+    matches!(
+        region_type,
+        MemoryRegionType::Reserved | MemoryRegionType::AcpiNvs | MemoryRegionType::BadMemory
+    )
+}
+
+/// Returns whether a region only holds data the bootloader no longer needs
+/// once the kernel runs on its own stacks and descriptor tables.
+fn is_reclaimable(region_type: MemoryRegionType) -> bool {
+    matches!(
+        region_type,
+        MemoryRegionType::Bootloader | MemoryRegionType::KernelStack
+    )
+}
+
+/// Returns a human readable name for `region_type`.
+pub fn type_name(region_type: MemoryRegionType) -> &'static str {
+    match region_type {
+        MemoryRegionType::Usable => "usable",
+        MemoryRegionType::InUse => "in use",
+        MemoryRegionType::Reserved => "reserved",
+        MemoryRegionType::AcpiReclaimable => "ACPI reclaimable",
+        MemoryRegionType::AcpiNvs => "ACPI NVS",
+        MemoryRegionType::BadMemory => "bad memory",
+        MemoryRegionType::Kernel => "kernel",
+        MemoryRegionType::KernelStack => "kernel stack",
+        MemoryRegionType::PageTable => "page tables",
+        MemoryRegionType::Bootloader => "bootloader",
+        MemoryRegionType::FrameZero => "frame zero",
+        MemoryRegionType::Empty => "empty",
+        MemoryRegionType::BootInfo => "boot info",
+        MemoryRegionType::Package => "package",
+        _ => "unknown",
+    }
+}
+
+/// Writes every region of the memory map with its size and type to `out`,
+/// followed by the total per type.
+pub fn report(out: &mut dyn fmt::Write) -> fmt::Result {
+    let memory_map = memory_map();
+    writeln!(out, "physical memory map:")?;
+    for region in memory_map.iter() {
+        writeln!(
+            out,
+            "  {:#012x}-{:#012x} {:>9} KiB  {}",
+            region.range.start_addr(),
+            region.range.end_addr() - 1,
+            region_size(region) / 1024,
+            type_name(region.region_type)
+        )?;
+    }
+
+    let mut types: Vec<MemoryRegionType> = Vec::new();
+    for region in memory_map.iter() {
+        if !types.contains(&region.region_type) {
+            types.push(region.region_type);
+        }
+    }
+    for region_type in types {
+        let total: u64 = memory_map
+            .iter()
+            .filter(|r| r.region_type == region_type)
+            .map(region_size)
+            .sum();
+        writeln!(
+            out,
+            "  total {:<16} {:>9} KiB",
+            type_name(region_type),
+            total / 1024
+        )?;
+    }
+    Ok(())
+}
+
+fn region_size(region: &MemoryRegion) -> u64 {
+    region.range.end_addr() - region.range.start_addr()
+}
+
+/// Returns the memory used by the bootloader and the boot stack to the frame
+/// allocator, returning the number of frames reclaimed.
+///
+/// Any mapping of those frames outside the physical memory mapping is removed
+/// first. Frames that can't be unmapped (because they are part of a huge page)
+/// are left alone.
+///
+/// # Safety
+///
+/// Must only be called once, after the kernel has switched away from the boot
+/// stack and loaded its own GDT and IDT.
+pub unsafe fn reclaim_boot_memory() -> usize {
+    let memory_map = memory_map();
+    let contains = |frame: PhysFrame| {
+        let addr = frame.start_address().as_u64();
+        memory_map.iter().any(|r| {
+            is_reclaimable(r.region_type)
+                && (r.range.start_addr()..r.range.end_addr()).contains(&addr)
+        })
+    };
+
+    let window_start = phys_mem_offset().as_u64();
+    let window_end = window_start
+        + memory_map
+            .iter()
+            .map(|r| r.range.end_addr())
+            .max()
+            .unwrap_or(0);
+
+    let mut stale = Vec::new();
+    inspect::for_each_range(inspect::active_root(), |mapping| {
+        let virt = mapping.virt.as_u64();
+        if (window_start..window_end).contains(&virt) {
+            return;
+        }
+        for offset in (0..mapping.size).step_by(Page::<Size4KiB>::SIZE as usize) {
+            let frame = PhysFrame::containing_address(mapping.phys + offset);
+            if contains(frame) {
+                let page = Page::<Size4KiB>::containing_address(VirtAddr::new(virt + offset));
+                stale.push((page, frame));
+            }
+        }
+    });
+
+    let mut vmm = vmm::lock();
+    let mut pinned = Vec::new();
+    for (page, frame) in stale {
+        if vmm.unmap(page).is_err() {
+            pinned.push(frame);
+        }
+    }
+
+    let mut reclaimed = 0;
+    for region in memory_map.iter().filter(|r| is_reclaimable(r.region_type)) {
+        let start = PhysFrame::containing_address(PhysAddr::new(region.range.start_addr()));
+        let end = PhysFrame::containing_address(PhysAddr::new(region.range.end_addr()));
+        let frames = PhysFrame::range(start, end)
+            .filter(|f| f.start_address().as_u64() != 0 && !pinned.contains(f));
+        for frame in frames {
+            vmm.frame_allocator().deallocate_frame(frame);
+            reclaimed += 1;
+        }
+    }
+    reclaimed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bootloader::bootinfo::FrameRange;
+
+    fn region(start: u64, end: u64, region_type: MemoryRegionType) -> MemoryRegion {
+        MemoryRegion {
+            range: FrameRange::new(start, end),
+            region_type,
+        }
+    }
+
+    #[test_case]
+    fn classify_ranges() {
+        let regions = [
+            region(0x0, 0x9f000, MemoryRegionType::Usable),
+            region(0x9f000, 0xa0000, MemoryRegionType::Reserved),
+            region(0x100000, 0x800000, MemoryRegionType::Kernel),
+            region(0x800000, 0x1000000, MemoryRegionType::Usable),
+        ];
+
+        assert_eq!(classify_in(&regions, 0x1000..0x2000), RangeKind::Ram);
+        assert_eq!(classify_in(&regions, 0x7ff000..0x801000), RangeKind::Ram);
+        assert_eq!(classify_in(&regions, 0x9e000..0xa0000), RangeKind::Reserved);
+        assert_eq!(classify_in(&regions, 0xb8000..0xb9000), RangeKind::Mmio);
+        assert_eq!(
+            classify_in(&regions, 0xfff000..0x1001000),
+            RangeKind::Reserved
+        );
+    }
+}
@@ -0,0 +1,100 @@
+use alloc::vec::Vec;
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use spin::Mutex;
+use x86_64::{
+    structures::paging::{mapper::MapToError, Page, PageTableFlags, Size4KiB},
+    VirtAddr,
+};
+
+use super::vmm;
+
+pub const STACKS_START: u64 = 0x5555_5555_0000;
+
+static NEXT_STACK: AtomicU64 = AtomicU64::new(STACKS_START);
+static STACKS: Mutex<Vec<KernelStack>> = Mutex::new(Vec::new());
+
+/// A kernel stack allocated from the VMM, with an unmapped guard page right
+/// below it.
+#[derive(Debug, Clone, Copy)]
+pub struct KernelStack {
+    name: &'static str,
+    guard: Page,
+    top: VirtAddr,
+}
+
+impl KernelStack {
+    /// Allocates and registers a new stack of `pages` pages.
+    pub fn alloc(name: &'static str, pages: u64) -> Result<Self, MapToError<Size4KiB>> {
+        let size = (pages + 1) * Page::<Size4KiB>::SIZE;
+        let start = VirtAddr::new(NEXT_STACK.fetch_add(size, Ordering::Relaxed));
+
+        let guard = Page::containing_address(start);
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+        let mut vmm = vmm::lock();
+        let pages = Page::range(guard + 1, guard + 1 + pages);
+        for page in pages {
+            if let Err(err) = vmm.map(page, flags) {
+                // Free what was mapped so far.
+                for mapped in Page::range(pages.start, page) {
+                    vmm.unmap_and_free(mapped)
+                        .expect("stack page mapped just before");
+                }
+                return Err(err);
+            }
+        }
+
+        let stack = Self {
+            name,
+            guard,
+            top: start + size,
+        };
+        STACKS.lock().push(stack);
+        Ok(stack)
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns the address just past the highest byte of the stack.
+    pub fn top(&self) -> VirtAddr {
+        self.top
+    }
+
+    /// Returns the lowest address of the stack.
+    pub fn bottom(&self) -> VirtAddr {
+        (self.guard + 1).start_address()
+    }
+
+    /// Switches to this stack and calls `entry` on it.
+    ///
+    /// # Safety
+    ///
+    /// Nothing on the current stack may be used again, since it is abandoned.
+    pub unsafe fn switch_to(&self, entry: extern "C" fn() -> !) -> ! {
+        asm!(
+            "mov rsp, {top}",
+            "xor rbp, rbp",
+            "call {entry}",
+            top = in(reg) self.top.as_u64(),
+            entry = in(reg) entry,
+            options(noreturn)
+        );
+    }
+}
+
+/// Returns the name of the stack whose guard page contains `addr`.
+///
+/// Meant to be called from fault handlers, so it never blocks: `None` is also
+/// returned if the stack registry is currently locked.
+pub fn guard_owner(addr: VirtAddr) -> Option<&'static str> {
+    let page = Page::<Size4KiB>::containing_address(addr);
+    STACKS
+        .try_lock()?
+        .iter()
+        .find(|stack| stack.guard == page)
+        .map(|stack| stack.name)
+}
@@ -0,0 +1,262 @@
+use core::{
+    arch::{asm, global_asm},
+    marker::PhantomData,
+    mem::{self, MaybeUninit},
+    slice,
+};
+use x86_64::{
+    registers::control::Cr2,
+    structures::{
+        idt::InterruptStackFrame,
+        paging::{Page, PageTableFlags, Size4KiB},
+    },
+    VirtAddr,
+};
+
+use super::{
+    inspect::{self, Translation},
+    protection,
+};
+
+/// First address past the lower canonical half of the address space.
+pub const USER_END: u64 = 0x0000_8000_0000_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserAccessError {
+    /// The range is not entirely inside the lower half of the address space.
+    OutOfRange,
+    /// The page containing the address is not mapped with the required access.
+    NotMapped(VirtAddr),
+    /// The access faulted even though the range passed validation.
+    Fault,
+}
+
+// Copies `rdx` bytes from `rsi` to `rdi`, returning the number of bytes left.
+// A page fault on `__user_copy_insn` resumes at `__user_copy_fixup` with the
+// remaining count still in `rcx`.
+global_asm!(
+    ".pushsection .text.user_copy, \"ax\"",
+    ".global __user_copy",
+    "__user_copy:",
+    "    mov rcx, rdx",
+    ".global __user_copy_insn",
+    "__user_copy_insn:",
+    "    rep movsb",
+    ".global __user_copy_fixup",
+    "__user_copy_fixup:",
+    "    mov rax, rcx",
+    "    ret",
+    ".popsection",
+);
+
+extern "C" {
+    fn __user_copy(dst: *mut u8, src: *const u8, len: usize) -> usize;
+    static __user_copy_insn: u8;
+    static __user_copy_fixup: u8;
+}
+
+/// Types that can be safely read from and written to user memory.
+///
+/// # Safety
+///
+/// Implementors must be valid for any bit pattern and contain no padding.
+pub unsafe trait Pod: Copy {}
+
+unsafe impl Pod for u8 {}
+unsafe impl Pod for u16 {}
+unsafe impl Pod for u32 {}
+unsafe impl Pod for u64 {}
+unsafe impl Pod for usize {}
+unsafe impl Pod for i8 {}
+unsafe impl Pod for i16 {}
+unsafe impl Pod for i32 {}
+unsafe impl Pod for i64 {}
+unsafe impl Pod for isize {}
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
+
+/// A pointer into user memory that can only be dereferenced through checked
+/// copies.
+#[derive(Debug)]
+pub struct UserPtr<T> {
+    addr: VirtAddr,
+    _marker: PhantomData<*mut T>,
+}
+
+impl<T> Clone for UserPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for UserPtr<T> {}
+
+impl<T: Pod> UserPtr<T> {
+    /// Creates a [`UserPtr`] from a raw address.
+    ///
+    /// Nothing is validated until the pointer is accessed.
+    pub const fn new(addr: u64) -> Self {
+        Self {
+            addr: VirtAddr::new_truncate(addr),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn addr(&self) -> VirtAddr {
+        self.addr
+    }
+
+    /// Returns a pointer to the `count`-th `T` after this one, or
+    /// [`UserAccessError::OutOfRange`] if it overflows the address space.
+    pub fn offset(&self, count: usize) -> Result<Self, UserAccessError> {
+        let addr = count
+            .checked_mul(mem::size_of::<T>())
+            .and_then(|len| self.addr.as_u64().checked_add(len as u64))
+            .ok_or(UserAccessError::OutOfRange)?;
+        Ok(Self::new(addr))
+    }
+
+    /// Reads the value from user memory.
+    pub fn read(&self) -> Result<T, UserAccessError> {
+        let mut value = MaybeUninit::<T>::uninit();
+        let bytes = unsafe {
+            slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, mem::size_of::<T>())
+        };
+        copy_from_user(bytes, self.addr)?;
+        Ok(unsafe { value.assume_init() })
+    }
+
+    /// Writes `value` to user memory.
+    pub fn write(&self, value: T) -> Result<(), UserAccessError> {
+        let bytes =
+            unsafe { slice::from_raw_parts(&value as *const T as *const u8, mem::size_of::<T>()) };
+        copy_to_user(self.addr, bytes)
+    }
+}
+
+/// Copies `dst.len()` bytes from user address `src` into `dst`.
+///
+/// The range is validated against the active address space first: it has to
+/// lie in the lower half and each page has to be accessible to user mode at
+/// every paging level. A page fault during the copy is reported as
+/// [`UserAccessError::Fault`].
+pub fn copy_from_user(dst: &mut [u8], src: VirtAddr) -> Result<(), UserAccessError> {
+    validate(src, dst.len(), false)?;
+    let remaining = user_copy(dst.as_mut_ptr(), src.as_ptr(), dst.len());
+    match remaining {
+        0 => Ok(()),
+        _ => Err(UserAccessError::Fault),
+    }
+}
+
+/// Copies `src` to user address `dst`.
+///
+/// See [`copy_from_user`] for how the range is validated.
+pub fn copy_to_user(dst: VirtAddr, src: &[u8]) -> Result<(), UserAccessError> {
+    validate(dst, src.len(), true)?;
+    let remaining = user_copy(dst.as_mut_ptr(), src.as_ptr(), src.len());
+    match remaining {
+        0 => Ok(()),
+        _ => Err(UserAccessError::Fault),
+    }
+}
+
+/// Redirects a page fault raised by a user copy to its fixup code.
+///
+/// Called by the page fault handler, returns whether the fault was handled.
+pub(crate) fn handle_fault(stack_frame: &mut InterruptStackFrame) -> bool {
+    let insn = unsafe { &__user_copy_insn as *const u8 as u64 };
+    if stack_frame.instruction_pointer.as_u64() != insn || Cr2::read().as_u64() >= USER_END {
+        return false;
+    }
+
+    let fixup = unsafe { VirtAddr::from_ptr(&__user_copy_fixup) };
+    unsafe {
+        stack_frame
+            .as_mut()
+            .update(|frame| frame.instruction_pointer = fixup)
+    };
+    true
+}
+
+fn user_copy(dst: *mut u8, src: *const u8, len: usize) -> usize {
+    let smap = protection::smap_enabled();
+    unsafe {
+        if smap {
+            asm!("stac", options(nostack));
+        }
+        let remaining = __user_copy(dst, src, len);
+        if smap {
+            asm!("clac", options(nostack));
+        }
+        remaining
+    }
+}
+
+/// Checks that `len` bytes starting at `addr` are mapped accessible to user
+/// mode, and writable if `write` is set.
+fn validate(addr: VirtAddr, len: usize, write: bool) -> Result<(), UserAccessError> {
+    if len == 0 {
+        return Ok(());
+    }
+    let end = addr
+        .as_u64()
+        .checked_add(len as u64)
+        .filter(|&end| end <= USER_END)
+        .ok_or(UserAccessError::OutOfRange)?;
+
+    let mut required = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    if write {
+        required |= PageTableFlags::WRITABLE;
+    }
+
+    let first = Page::<Size4KiB>::containing_address(addr);
+    let last = Page::<Size4KiB>::containing_address(VirtAddr::new(end - 1));
+    for page in Page::range_inclusive(first, last) {
+        match inspect::translate(inspect::active_root(), page.start_address()) {
+            Translation::Mapped { flags, .. } if flags.contains(required) => {}
+            _ => return Err(UserAccessError::NotMapped(page.start_address())),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn kernel_memory_is_rejected() {
+        let value = 0u64;
+        let ptr = UserPtr::<u64>::new(&value as *const u64 as u64);
+        assert!(matches!(ptr.read(), Err(UserAccessError::NotMapped(_))));
+    }
+
+    #[test_case]
+    fn upper_half_is_rejected() {
+        let ptr = UserPtr::<u64>::new(0xffff_8000_0000_0000);
+        assert_eq!(ptr.write(0), Err(UserAccessError::OutOfRange));
+    }
+
+    #[test_case]
+    fn offset_overflow_is_rejected() {
+        let ptr = UserPtr::<u64>::new(0x1000);
+        assert_eq!(
+            ptr.offset(2).map(|ptr| ptr.addr()),
+            Ok(VirtAddr::new(0x1010))
+        );
+        assert_eq!(
+            ptr.offset(usize::MAX / 4).map(|ptr| ptr.addr()),
+            Err(UserAccessError::OutOfRange)
+        );
+        let end = UserPtr::<u64>::new(u64::MAX - 7);
+        assert_eq!(
+            end.offset(1).map(|ptr| ptr.addr()),
+            Err(UserAccessError::OutOfRange)
+        );
+    }
+
+    #[test_case]
+    fn empty_copy_succeeds() {
+        assert_eq!(copy_from_user(&mut [], VirtAddr::new(0)), Ok(()));
+    }
+}
@@ -0,0 +1,99 @@
+use spin::{Mutex, MutexGuard, Once};
+use x86_64::structures::paging::{
+    mapper::{MapToError, UnmapError},
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTableFlags, PhysFrame,
+    Size4KiB,
+};
+
+use super::{map_page, BootInfoFrameAllocator};
+
+static VMM: Once<Mutex<Vmm>> = Once::new();
+
+/// The kernel's virtual memory manager, owning the active page table and the
+/// frame allocator once early boot is done.
+pub struct Vmm {
+    mapper: OffsetPageTable<'static>,
+    frame_allocator: BootInfoFrameAllocator,
+}
+
+impl Vmm {
+    /// Maps `page` to a newly allocated frame.
+    pub fn map(&mut self, page: Page, flags: PageTableFlags) -> Result<(), MapToError<Size4KiB>> {
+        let frame = self
+            .frame_allocator
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)?;
+        let result = self.map_to(page, frame, flags);
+        if result.is_err() {
+            unsafe { self.frame_allocator.deallocate_frame(frame) };
+        }
+        result
+    }
+
+    /// Unmaps `page` and frees the frame it was mapped to.
+    pub fn unmap_and_free(&mut self, page: Page) -> Result<(), UnmapError> {
+        let frame = self.unmap(page)?;
+        unsafe { self.frame_allocator.deallocate_frame(frame) };
+        Ok(())
+    }
+
+    /// Maps `page` to `frame`.
+    pub fn map_to(
+        &mut self,
+        page: Page,
+        frame: PhysFrame,
+        flags: PageTableFlags,
+    ) -> Result<(), MapToError<Size4KiB>> {
+        unsafe {
+            map_page(
+                &mut self.mapper,
+                page,
+                frame,
+                flags,
+                &mut self.frame_allocator,
+            )
+        }
+    }
+
+    /// Unmaps `page`, returning the frame it was mapped to.
+    pub fn unmap(&mut self, page: Page) -> Result<PhysFrame, UnmapError> {
+        let (frame, flush) = self.mapper.unmap(page)?;
+        flush.flush();
+        Ok(frame)
+    }
+
+    pub fn mapper(&mut self) -> &mut OffsetPageTable<'static> {
+        &mut self.mapper
+    }
+
+    pub fn frame_allocator(&mut self) -> &mut BootInfoFrameAllocator {
+        &mut self.frame_allocator
+    }
+}
+
+/// Hands the kernel page table and frame allocator over to the VMM.
+///
+/// # Panics
+///
+/// Panics if called more than once.
+pub fn init(mapper: OffsetPageTable<'static>, frame_allocator: BootInfoFrameAllocator) {
+    if VMM.is_completed() {
+        panic!("vmm::init called more than once");
+    }
+
+    VMM.call_once(|| {
+        Mutex::new(Vmm {
+            mapper,
+            frame_allocator,
+        })
+    });
+}
+
+/// Locks the VMM.
+///
+/// # Panics
+///
+/// Panics if called before [`init`].
+pub fn lock() -> MutexGuard<'static, Vmm> {
+    VMM.get().expect("vmm uninitialized").lock()
+}
@@ -0,0 +1,147 @@
+//! Access to PCI configuration space, through the enhanced configuration
+//! access mechanism (ECAM) where the ACPI MCFG table describes it and the
+//! legacy 0xCF8/0xCFC I/O ports otherwise.
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use spin::{Mutex, Once};
+use x86_64::{instructions::port::Port, VirtAddr};
+
+use super::PciAddress;
+use crate::{
+    acpi::{self, mcfg::McfgEntry},
+    memory::mmio,
+};
+
+const CONFIG_ADDRESS: u16 = 0xcf8;
+const CONFIG_DATA: u16 = 0xcfc;
+
+/// Size of the configuration space of all functions on one bus.
+const ECAM_BUS_SIZE: u64 = 1 << 20;
+
+static MECHANISM: Once<Mechanism> = Once::new();
+static LEGACY_PORTS: Mutex<(Port<u32>, Port<u32>)> =
+    Mutex::new((Port::new(CONFIG_ADDRESS), Port::new(CONFIG_DATA)));
+
+enum Mechanism {
+    Legacy,
+    Ecam {
+        regions: Vec<McfgEntry>,
+        /// Buses whose configuration space has been mapped so far.
+        mapped: Mutex<BTreeMap<(u16, u8), VirtAddr>>,
+    },
+}
+
+/// Selects ECAM if the MCFG table is available.
+///
+/// Until this is called (or without ECAM) configuration space is accessed
+/// through the legacy I/O ports, which only reach segment 0 and the first 256
+/// bytes of each function.
+pub fn init() {
+    MECHANISM.call_once(|| match acpi::get().and_then(|acpi| acpi.mcfg.as_ref()) {
+        Some(mcfg) if !mcfg.entries.is_empty() => Mechanism::Ecam {
+            regions: mcfg.entries.clone(),
+            mapped: Mutex::new(BTreeMap::new()),
+        },
+        _ => Mechanism::Legacy,
+    });
+}
+
+/// Returns whether ECAM is used.
+pub fn is_ecam() -> bool {
+    matches!(MECHANISM.get(), Some(Mechanism::Ecam { .. }))
+}
+
+/// Returns the segments that can be accessed, with the first bus of each.
+pub fn segments() -> Vec<(u16, u8)> {
+    match MECHANISM.get() {
+        Some(Mechanism::Ecam { regions, .. }) => regions
+            .iter()
+            .map(|region| (region.segment, region.start_bus))
+            .collect(),
+        _ => alloc::vec![(0, 0)],
+    }
+}
+
+/// Reads the dword at `offset` (rounded down to a multiple of 4).
+///
+/// Returns all ones for functions or offsets that can't be reached, like
+/// reads of absent functions do.
+pub fn read(addr: PciAddress, offset: u16) -> u32 {
+    let offset = offset & !3;
+    match ecam_address(addr, offset) {
+        Some(virt) => unsafe { virt.as_ptr::<u32>().read_volatile() },
+        None if addr.segment == 0 && offset < 256 => {
+            let mut ports = LEGACY_PORTS.lock();
+            unsafe {
+                ports.0.write(legacy_address(addr, offset));
+                ports.1.read()
+            }
+        }
+        None => u32::MAX,
+    }
+}
+
+/// Writes the dword at `offset` (rounded down to a multiple of 4).
+///
+/// # Safety
+///
+/// Writing configuration space can reconfigure or disable the device.
+pub unsafe fn write(addr: PciAddress, offset: u16, value: u32) {
+    let offset = offset & !3;
+    match ecam_address(addr, offset) {
+        Some(virt) => virt.as_mut_ptr::<u32>().write_volatile(value),
+        None if addr.segment == 0 && offset < 256 => {
+            let mut ports = LEGACY_PORTS.lock();
+            ports.0.write(legacy_address(addr, offset));
+            ports.1.write(value);
+        }
+        None => {}
+    }
+}
+
+fn legacy_address(addr: PciAddress, offset: u16) -> u32 {
+    1 << 31
+        | u32::from(addr.bus) << 16
+        | u32::from(addr.device) << 11
+        | u32::from(addr.function) << 8
+        | u32::from(offset)
+}
+
+/// Returns the virtual address of `offset` in the configuration space of
+/// `addr`, mapping the bus on first use.
+fn ecam_address(addr: PciAddress, offset: u16) -> Option<VirtAddr> {
+    let (regions, mapped) = match MECHANISM.get()? {
+        Mechanism::Ecam { regions, mapped } => (regions, mapped),
+        Mechanism::Legacy => return None,
+    };
+    if offset >= 4096 {
+        return None;
+    }
+
+    let mut mapped = mapped.lock();
+    let bus = match mapped.get(&(addr.segment, addr.bus)) {
+        Some(&bus) => bus,
+        None => {
+            let phys = regions
+                .iter()
+                .filter(|region| region.segment == addr.segment)
+                .find_map(|region| region.function_address(addr.bus, 0, 0))?;
+            let bus = mmio::map(phys, ECAM_BUS_SIZE).ok()?;
+            mapped.insert((addr.segment, addr.bus), bus);
+            bus
+        }
+    };
+    let function = u64::from(addr.device) << 15 | u64::from(addr.function) << 12;
+    Some(bus + function + u64::from(offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn legacy_address_encoding() {
+        let addr = PciAddress::new(0, 0x12, 0x1f, 3);
+        assert_eq!(legacy_address(addr, 0x3c), 0x8012_fb3c);
+    }
+}
@@ -0,0 +1,573 @@
+pub mod config;
+pub mod msi;
+
+use alloc::vec::Vec;
+use core::fmt;
+use spin::Once;
+use x86_64::PhysAddr;
+
+static DEVICES: Once<Vec<PciDevice>> = Once::new();
+
+pub const VENDOR_ID: u16 = 0x00;
+pub const DEVICE_ID: u16 = 0x02;
+pub const COMMAND: u16 = 0x04;
+pub const STATUS: u16 = 0x06;
+pub const REVISION: u16 = 0x08;
+pub const HEADER_TYPE: u16 = 0x0e;
+pub const BAR0: u16 = 0x10;
+pub const SECONDARY_BUS: u16 = 0x19;
+pub const CAPABILITIES: u16 = 0x34;
+pub const INTERRUPT_LINE: u16 = 0x3c;
+
+pub const COMMAND_IO: u16 = 1 << 0;
+pub const COMMAND_MEMORY: u16 = 1 << 1;
+pub const COMMAND_BUS_MASTER: u16 = 1 << 2;
+pub const COMMAND_INTX_DISABLE: u16 = 1 << 10;
+
+const STATUS_CAPABILITIES: u16 = 1 << 4;
+const HEADER_MULTIFUNCTION: u8 = 0x80;
+const HEADER_BRIDGE: u8 = 0x01;
+
+/// Location of a function in PCI configuration space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PciAddress {
+    pub segment: u16,
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl PciAddress {
+    pub const fn new(segment: u16, bus: u8, device: u8, function: u8) -> Self {
+        Self {
+            segment,
+            bus,
+            device,
+            function,
+        }
+    }
+
+    pub fn read_u32(&self, offset: u16) -> u32 {
+        config::read(*self, offset)
+    }
+
+    pub fn read_u16(&self, offset: u16) -> u16 {
+        (self.read_u32(offset) >> ((offset & 2) * 8)) as u16
+    }
+
+    pub fn read_u8(&self, offset: u16) -> u8 {
+        (self.read_u32(offset) >> ((offset & 3) * 8)) as u8
+    }
+
+    /// # Safety
+    ///
+    /// Writing configuration space can reconfigure or disable the device.
+    pub unsafe fn write_u32(&self, offset: u16, value: u32) {
+        config::write(*self, offset, value)
+    }
+
+    /// Writes a word by rewriting the dword containing it.
+    ///
+    /// # Safety
+    ///
+    /// See [`write_u32`](Self::write_u32). Write-one-to-clear bits in the
+    /// other half of the dword are cleared if they are set.
+    pub unsafe fn write_u16(&self, offset: u16, value: u16) {
+        let shift = (offset & 2) * 8;
+        let dword = self.read_u32(offset) & !(0xffff << shift) | u32::from(value) << shift;
+        self.write_u32(offset, dword)
+    }
+}
+
+impl fmt::Display for PciAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04x}:{:02x}:{:02x}.{}",
+            self.segment, self.bus, self.device, self.function
+        )
+    }
+}
+
+/// A base address register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    Memory {
+        address: PhysAddr,
+        size: u64,
+        prefetchable: bool,
+        is_64bit: bool,
+    },
+    Io {
+        port: u16,
+        size: u16,
+    },
+}
+
+impl fmt::Display for Bar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Bar::Memory {
+                address,
+                size,
+                prefetchable,
+                is_64bit,
+            } => write!(
+                f,
+                "memory at {:#x} ({}-bit, {}prefetchable) [size={}K]",
+                address.as_u64(),
+                if is_64bit { 64 } else { 32 },
+                if prefetchable { "" } else { "non-" },
+                size / 1024
+            ),
+            Bar::Io { port, size } => write!(f, "I/O ports at {:#x} [size={}]", port, size),
+        }
+    }
+}
+
+/// A capability from a function's capability list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    PowerManagement {
+        offset: u16,
+        version: u8,
+    },
+    Msi {
+        offset: u16,
+        is_64bit: bool,
+        per_vector_masking: bool,
+        /// Number of vectors the function can use.
+        vectors: u8,
+    },
+    MsiX {
+        offset: u16,
+        /// Number of entries in the MSI-X table.
+        table_size: u16,
+        table_bar: u8,
+        table_offset: u32,
+        pba_bar: u8,
+        pba_offset: u32,
+    },
+    Other {
+        id: u8,
+        offset: u16,
+    },
+}
+
+impl Capability {
+    pub const ID_POWER_MANAGEMENT: u8 = 0x01;
+    pub const ID_MSI: u8 = 0x05;
+    pub const ID_VENDOR: u8 = 0x09;
+    pub const ID_MSI_X: u8 = 0x11;
+
+    fn parse(addr: PciAddress, id: u8, offset: u16) -> Self {
+        let control = addr.read_u16(offset + 2);
+        match id {
+            Self::ID_POWER_MANAGEMENT => Capability::PowerManagement {
+                offset,
+                version: (control & 0b111) as u8,
+            },
+            Self::ID_MSI => Capability::Msi {
+                offset,
+                is_64bit: control & 1 << 7 != 0,
+                per_vector_masking: control & 1 << 8 != 0,
+                vectors: 1 << (control >> 1 & 0b111),
+            },
+            Self::ID_MSI_X => {
+                let table = addr.read_u32(offset + 4);
+                let pba = addr.read_u32(offset + 8);
+                Capability::MsiX {
+                    offset,
+                    table_size: (control & 0x7ff) + 1,
+                    table_bar: (table & 0b111) as u8,
+                    table_offset: table & !0b111,
+                    pba_bar: (pba & 0b111) as u8,
+                    pba_offset: pba & !0b111,
+                }
+            }
+            _ => Capability::Other { id, offset },
+        }
+    }
+
+    pub fn id(&self) -> u8 {
+        match *self {
+            Capability::PowerManagement { .. } => Self::ID_POWER_MANAGEMENT,
+            Capability::Msi { .. } => Self::ID_MSI,
+            Capability::MsiX { .. } => Self::ID_MSI_X,
+            Capability::Other { id, .. } => id,
+        }
+    }
+
+    pub fn offset(&self) -> u16 {
+        match *self {
+            Capability::PowerManagement { offset, .. }
+            | Capability::Msi { offset, .. }
+            | Capability::MsiX { offset, .. }
+            | Capability::Other { offset, .. } => offset,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Capability::PowerManagement { .. } => "Power Management",
+            Capability::Msi { .. } => "MSI",
+            Capability::MsiX { .. } => "MSI-X",
+            Capability::Other {
+                id: Self::ID_VENDOR,
+                ..
+            } => "Vendor Specific",
+            Capability::Other { id: 0x10, .. } => "Express",
+            Capability::Other { .. } => "?",
+        }
+    }
+}
+
+/// A PCI function found during enumeration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PciDevice {
+    pub address: PciAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision: u8,
+    pub header_type: u8,
+    pub interrupt_line: u8,
+    /// Legacy interrupt pin (1 for INTA# to 4 for INTD#), 0 if none.
+    pub interrupt_pin: u8,
+    pub bars: [Option<Bar>; 6],
+    pub capabilities: Vec<Capability>,
+}
+
+impl PciDevice {
+    fn probe(address: PciAddress) -> Option<Self> {
+        let vendor_id = address.read_u16(VENDOR_ID);
+        if vendor_id == 0xffff {
+            return None;
+        }
+
+        let class = address.read_u32(REVISION);
+        let header_type = address.read_u8(HEADER_TYPE);
+        let bar_count = match header_type & !HEADER_MULTIFUNCTION {
+            0x00 => 6,
+            HEADER_BRIDGE => 2,
+            _ => 0,
+        };
+        let interrupt = address.read_u16(INTERRUPT_LINE);
+
+        Some(Self {
+            address,
+            vendor_id,
+            device_id: address.read_u16(DEVICE_ID),
+            class: (class >> 24) as u8,
+            subclass: (class >> 16) as u8,
+            prog_if: (class >> 8) as u8,
+            revision: class as u8,
+            header_type,
+            interrupt_line: interrupt as u8,
+            interrupt_pin: (interrupt >> 8) as u8,
+            bars: unsafe { probe_bars(address, bar_count) },
+            capabilities: read_capabilities(address),
+        })
+    }
+
+    /// Returns the first capability with the given ID.
+    pub fn capability(&self, id: u8) -> Option<Capability> {
+        self.capabilities.iter().copied().find(|cap| cap.id() == id)
+    }
+
+    /// Sets `flags` in the command register.
+    ///
+    /// # Safety
+    ///
+    /// The device must not be able to corrupt memory once it is allowed to
+    /// decode addresses or master the bus.
+    pub unsafe fn enable(&self, flags: u16) {
+        let command = self.address.read_u16(COMMAND);
+        self.address.write_u16(COMMAND, command | flags);
+    }
+
+    pub fn is_bridge(&self) -> bool {
+        self.header_type & !HEADER_MULTIFUNCTION == HEADER_BRIDGE
+    }
+}
+
+impl fmt::Display for PciDevice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} [{:02x}{:02x}]: {} [{:04x}:{:04x}]",
+            self.address,
+            class_name(self.class, self.subclass),
+            self.class,
+            self.subclass,
+            vendor_name(self.vendor_id),
+            self.vendor_id,
+            self.device_id
+        )?;
+        if self.revision != 0 {
+            write!(f, " (rev {:02x})", self.revision)?;
+        }
+        if self.prog_if != 0 {
+            write!(f, " (prog-if {:02x})", self.prog_if)?;
+        }
+        Ok(())
+    }
+}
+
+/// Sizes the first `count` BARs of `addr` by writing all ones to them, with
+/// decoding disabled while doing so.
+///
+/// # Safety
+///
+/// The function must not be in use by a driver.
+unsafe fn probe_bars(addr: PciAddress, count: u16) -> [Option<Bar>; 6] {
+    let mut bars = [None; 6];
+    let command = addr.read_u16(COMMAND);
+    addr.write_u16(COMMAND, command & !(COMMAND_IO | COMMAND_MEMORY));
+
+    let mut index = 0;
+    while index < count {
+        let offset = BAR0 + index * 4;
+        let original = addr.read_u32(offset);
+        let mask = size_mask(addr, offset);
+
+        if original & 1 != 0 {
+            // Devices may only decode the low 16 bits of the port number.
+            let mask = mask & !0b11 | 0xffff_0000;
+            if mask != 0xffff_0000 {
+                bars[usize::from(index)] = Some(Bar::Io {
+                    port: (original & !0b11) as u16,
+                    size: (!mask).wrapping_add(1) as u16,
+                });
+            }
+        } else {
+            let is_64bit = original >> 1 & 0b11 == 0b10;
+            let (address, mask) = if is_64bit && index + 1 < count {
+                let high = addr.read_u32(offset + 4);
+                let high_mask = size_mask(addr, offset + 4);
+                (
+                    u64::from(high) << 32 | u64::from(original & !0xf),
+                    u64::from(high_mask) << 32 | u64::from(mask & !0xf),
+                )
+            } else {
+                (
+                    u64::from(original & !0xf),
+                    u64::from(mask & !0xf) | 0xffff_ffff_0000_0000,
+                )
+            };
+            // Unimplemented BARs read back as zero.
+            if mask != 0 && mask != 0xffff_ffff_0000_0000 {
+                bars[usize::from(index)] = Some(Bar::Memory {
+                    address: PhysAddr::new(address),
+                    size: (!mask).wrapping_add(1),
+                    prefetchable: original & 1 << 3 != 0,
+                    is_64bit,
+                });
+            }
+            if is_64bit {
+                index += 1;
+            }
+        }
+        index += 1;
+    }
+
+    addr.write_u16(COMMAND, command);
+    bars
+}
+
+/// Returns the value read back after writing all ones to the BAR at `offset`,
+/// restoring it afterwards.
+unsafe fn size_mask(addr: PciAddress, offset: u16) -> u32 {
+    let original = addr.read_u32(offset);
+    addr.write_u32(offset, u32::MAX);
+    let mask = addr.read_u32(offset);
+    addr.write_u32(offset, original);
+    mask
+}
+
+fn read_capabilities(addr: PciAddress) -> Vec<Capability> {
+    let mut capabilities = Vec::new();
+    if addr.read_u16(STATUS) & STATUS_CAPABILITIES == 0 {
+        return capabilities;
+    }
+
+    let mut offset = u16::from(addr.read_u8(CAPABILITIES) & !0b11);
+    // The list lives in the 192 bytes after the header, bounding its length
+    // even if it is malformed and loops.
+    for _ in 0..48 {
+        if offset < 0x40 {
+            break;
+        }
+        let header = addr.read_u16(offset);
+        capabilities.push(Capability::parse(addr, header as u8, offset));
+        offset = (header >> 8) & !0b11;
+    }
+    capabilities
+}
+
+/// Enumerates all reachable functions and fills the device registry.
+///
+/// Uses ECAM if the ACPI tables describe it, so [`crate::acpi::init`] should
+/// be called first.
+pub fn init() {
+    config::init();
+    DEVICES.call_once(|| {
+        let mut devices = Vec::new();
+        for (segment, start_bus) in config::segments() {
+            let mut scanned = [false; 256];
+            scan_root(segment, start_bus, &mut scanned, &mut devices);
+        }
+        devices.sort_by_key(|device: &PciDevice| device.address);
+        devices
+    });
+}
+
+fn scan_root(segment: u16, bus: u8, scanned: &mut [bool; 256], devices: &mut Vec<PciDevice>) {
+    let host = PciAddress::new(segment, bus, 0, 0);
+    if host.read_u8(HEADER_TYPE) & HEADER_MULTIFUNCTION == 0 {
+        scan_bus(segment, bus, scanned, devices);
+        return;
+    }
+
+    // Each function of a multi-function host bridge is the host controller
+    // of another bus.
+    for function in 0..8 {
+        if PciAddress::new(segment, bus, 0, function).read_u16(VENDOR_ID) != 0xffff {
+            scan_bus(segment, bus.wrapping_add(function), scanned, devices);
+        }
+    }
+}
+
+fn scan_bus(segment: u16, bus: u8, scanned: &mut [bool; 256], devices: &mut Vec<PciDevice>) {
+    if scanned[usize::from(bus)] {
+        return;
+    }
+    scanned[usize::from(bus)] = true;
+
+    for device in 0..32 {
+        let first = PciAddress::new(segment, bus, device, 0);
+        if first.read_u16(VENDOR_ID) == 0xffff {
+            continue;
+        }
+        let functions = if first.read_u8(HEADER_TYPE) & HEADER_MULTIFUNCTION != 0 {
+            8
+        } else {
+            1
+        };
+
+        for function in 0..functions {
+            let address = PciAddress::new(segment, bus, device, function);
+            if let Some(found) = PciDevice::probe(address) {
+                if found.is_bridge() {
+                    let secondary = address.read_u8(SECONDARY_BUS);
+                    devices.push(found);
+                    scan_bus(segment, secondary, scanned, devices);
+                } else {
+                    devices.push(found);
+                }
+            }
+        }
+    }
+}
+
+/// Returns all functions found by [`init`].
+pub fn devices() -> &'static [PciDevice] {
+    DEVICES.get().map_or(&[], Vec::as_slice)
+}
+
+/// Returns the functions with the given vendor and device ID.
+pub fn find_by_id(vendor_id: u16, device_id: u16) -> impl Iterator<Item = &'static PciDevice> {
+    devices()
+        .iter()
+        .filter(move |device| device.vendor_id == vendor_id && device.device_id == device_id)
+}
+
+/// Returns the functions with the given class and subclass.
+pub fn find_by_class(class: u8, subclass: u8) -> impl Iterator<Item = &'static PciDevice> {
+    devices()
+        .iter()
+        .filter(move |device| device.class == class && device.subclass == subclass)
+}
+
+/// Writes an `lspci -v` style listing of all functions to `out`.
+pub fn lspci(out: &mut dyn fmt::Write) -> fmt::Result {
+    writeln!(
+        out,
+        "PCI devices (via {}):",
+        if config::is_ecam() {
+            "ECAM"
+        } else {
+            "I/O ports"
+        }
+    )?;
+    for device in devices() {
+        writeln!(out, "  {}", device)?;
+        if device.interrupt_pin != 0 {
+            writeln!(
+                out,
+                "    interrupt: pin {} routed to IRQ {}",
+                (b'A' + device.interrupt_pin - 1) as char,
+                device.interrupt_line
+            )?;
+        }
+        for (index, bar) in device.bars.iter().enumerate() {
+            if let Some(bar) = bar {
+                writeln!(out, "    BAR{}: {}", index, bar)?;
+            }
+        }
+        for cap in &device.capabilities {
+            writeln!(out, "    capability [{:02x}] {}", cap.offset(), cap.name())?;
+        }
+    }
+    Ok(())
+}
+
+fn class_name(class: u8, subclass: u8) -> &'static str {
+    match (class, subclass) {
+        (0x01, 0x00) => "SCSI storage controller",
+        (0x01, 0x01) => "IDE interface",
+        (0x01, 0x06) => "SATA controller",
+        (0x01, 0x08) => "Non-Volatile memory controller",
+        (0x01, _) => "Mass storage controller",
+        (0x02, 0x00) => "Ethernet controller",
+        (0x02, _) => "Network controller",
+        (0x03, 0x00) => "VGA compatible controller",
+        (0x03, _) => "Display controller",
+        (0x04, 0x03) => "Audio device",
+        (0x04, _) => "Multimedia controller",
+        (0x06, 0x00) => "Host bridge",
+        (0x06, 0x01) => "ISA bridge",
+        (0x06, 0x04) => "PCI bridge",
+        (0x06, _) => "Bridge",
+        (0x08, _) => "System peripheral",
+        (0x0c, 0x03) => "USB controller",
+        (0x0c, 0x05) => "SMBus",
+        (0x0c, _) => "Serial bus controller",
+        _ => "Unclassified device",
+    }
+}
+
+fn vendor_name(vendor_id: u16) -> &'static str {
+    match vendor_id {
+        0x1022 => "AMD",
+        0x10de => "NVIDIA",
+        0x10ec => "Realtek",
+        0x1234 => "QEMU",
+        0x1af4 | 0x1b36 => "Red Hat",
+        0x8086 => "Intel",
+        _ => "Unknown vendor",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn host_bridge_present() {
+        let host = PciDevice::probe(PciAddress::new(0, 0, 0, 0)).expect("no host bridge");
+        assert_eq!((host.class, host.subclass), (0x06, 0x00));
+    }
+}
@@ -0,0 +1,214 @@
+//! Message signaled interrupts, delivered straight to the local APIC instead
+//! of through the 8259 PICs.
+
+use core::ptr;
+use x86_64::{
+    structures::paging::{mapper::MapToError, Size4KiB},
+    VirtAddr,
+};
+
+use super::{Bar, Capability, PciDevice, COMMAND_BUS_MASTER, COMMAND_INTX_DISABLE, COMMAND_MEMORY};
+use crate::{apic, interrupts, memory::mmio};
+
+const MSI_ENABLE: u16 = 1 << 0;
+const MSI_MULTIPLE_MESSAGE_ENABLE: u16 = 0b111 << 4;
+const MSI_X_FUNCTION_MASK: u16 = 1 << 14;
+const MSI_X_ENABLE: u16 = 1 << 15;
+
+const MSI_X_ENTRY_SIZE: u64 = 16;
+const MSI_X_VECTOR_MASKED: u32 = 1 << 0;
+
+#[derive(Debug)]
+pub enum MsiError {
+    /// The function lacks the capability.
+    Unsupported,
+    /// No IDT vectors are left.
+    NoVector,
+    /// The BAR holding the MSI-X table is missing or not memory.
+    InvalidBar,
+    Map(MapToError<Size4KiB>),
+}
+
+/// Address and data a function writes to raise an interrupt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Message {
+    pub address: u64,
+    pub data: u32,
+}
+
+impl Message {
+    /// Returns the message for a fixed, edge-triggered interrupt with
+    /// `vector` on the local APIC with ID `apic_id`.
+    pub fn new(vector: u8, apic_id: u8) -> Self {
+        Self {
+            address: 0xfee0_0000 | u64::from(apic_id) << 12,
+            data: u32::from(vector),
+        }
+    }
+}
+
+impl PciDevice {
+    /// Allocates a vector for `handler` and enables MSI to raise it, with a
+    /// single message. Legacy interrupts are disabled.
+    ///
+    /// Returns the vector.
+    ///
+    /// # Safety
+    ///
+    /// The function gains bus mastering, so it must not be able to corrupt
+    /// memory.
+    pub unsafe fn enable_msi(
+        &self,
+        handler: impl Fn() + Send + Sync + 'static,
+    ) -> Result<u8, MsiError> {
+        let (offset, is_64bit) = match self.capability(Capability::ID_MSI) {
+            Some(Capability::Msi {
+                offset, is_64bit, ..
+            }) => (offset, is_64bit),
+            _ => return Err(MsiError::Unsupported),
+        };
+        let vector = interrupts::allocate_vector(handler).ok_or(MsiError::NoVector)?;
+        let message = Message::new(vector, apic::id());
+
+        let addr = self.address;
+        addr.write_u32(offset + 4, message.address as u32);
+        if is_64bit {
+            addr.write_u32(offset + 8, (message.address >> 32) as u32);
+            addr.write_u16(offset + 12, message.data as u16);
+        } else {
+            addr.write_u16(offset + 8, message.data as u16);
+        }
+        let control = addr.read_u16(offset + 2) & !MSI_MULTIPLE_MESSAGE_ENABLE;
+        addr.write_u16(offset + 2, control | MSI_ENABLE);
+        self.enable(COMMAND_BUS_MASTER | COMMAND_INTX_DISABLE);
+        Ok(vector)
+    }
+
+    /// Enables MSI-X with one vector per handler, programming table entry `n`
+    /// with the vector of `handlers[n]`. Legacy interrupts are disabled.
+    ///
+    /// # Safety
+    ///
+    /// See [`enable_msi`](Self::enable_msi).
+    pub unsafe fn enable_msix<H>(
+        &self,
+        handlers: impl IntoIterator<Item = H>,
+    ) -> Result<MsiXTable, MsiError>
+    where
+        H: Fn() + Send + Sync + 'static,
+    {
+        let (offset, table_size, table_bar, table_offset) =
+            match self.capability(Capability::ID_MSI_X) {
+                Some(Capability::MsiX {
+                    offset,
+                    table_size,
+                    table_bar,
+                    table_offset,
+                    ..
+                }) => (offset, table_size, table_bar, table_offset),
+                _ => return Err(MsiError::Unsupported),
+            };
+        let bar_address = match self.bars.get(usize::from(table_bar)) {
+            Some(Some(Bar::Memory { address, .. })) => *address,
+            _ => return Err(MsiError::InvalidBar),
+        };
+
+        let size = u64::from(table_size) * MSI_X_ENTRY_SIZE;
+        let base = mmio::map(bar_address + u64::from(table_offset), size).map_err(MsiError::Map)?;
+        let table = MsiXTable {
+            base,
+            size: table_size,
+        };
+
+        // Mask everything while the table is being programmed.
+        let addr = self.address;
+        let control = addr.read_u16(offset + 2);
+        addr.write_u16(offset + 2, control | MSI_X_ENABLE | MSI_X_FUNCTION_MASK);
+        self.enable(COMMAND_MEMORY | COMMAND_BUS_MASTER | COMMAND_INTX_DISABLE);
+
+        for entry in 0..table_size {
+            table.mask(entry);
+        }
+        for (entry, handler) in (0..table_size).zip(handlers) {
+            let vector = match interrupts::allocate_vector(handler) {
+                Some(vector) => vector,
+                None => {
+                    // Undo the entries programmed so far.
+                    for programmed in 0..entry {
+                        table.mask(programmed);
+                        interrupts::free_vector(table.vector(programmed));
+                    }
+                    addr.write_u16(offset + 2, control);
+                    return Err(MsiError::NoVector);
+                }
+            };
+            table.set(entry, Message::new(vector, apic::id()));
+            table.unmask(entry);
+        }
+
+        addr.write_u16(offset + 2, (control | MSI_X_ENABLE) & !MSI_X_FUNCTION_MASK);
+        Ok(table)
+    }
+}
+
+/// A function's mapped MSI-X table.
+#[derive(Debug)]
+pub struct MsiXTable {
+    base: VirtAddr,
+    size: u16,
+}
+
+impl MsiXTable {
+    pub fn len(&self) -> u16 {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Returns the vector table entry `entry` is programmed with.
+    pub fn vector(&self, entry: u16) -> u8 {
+        unsafe { ptr::read_volatile(self.word(entry, 2)) as u8 }
+    }
+
+    /// Programs table entry `entry` with `message`.
+    pub fn set(&self, entry: u16, message: Message) {
+        unsafe {
+            ptr::write_volatile(self.word(entry, 0), message.address as u32);
+            ptr::write_volatile(self.word(entry, 1), (message.address >> 32) as u32);
+            ptr::write_volatile(self.word(entry, 2), message.data);
+        }
+    }
+
+    pub fn mask(&self, entry: u16) {
+        self.update_control(entry, |control| control | MSI_X_VECTOR_MASKED);
+    }
+
+    pub fn unmask(&self, entry: u16) {
+        self.update_control(entry, |control| control & !MSI_X_VECTOR_MASKED);
+    }
+
+    fn update_control(&self, entry: u16, f: impl FnOnce(u32) -> u32) {
+        let control = self.word(entry, 3);
+        unsafe { ptr::write_volatile(control, f(ptr::read_volatile(control))) };
+    }
+
+    /// Returns a pointer to dword `word` of table entry `entry`.
+    fn word(&self, entry: u16, word: u64) -> *mut u32 {
+        assert!(entry < self.size, "MSI-X entry {} out of range", entry);
+        (self.base + u64::from(entry) * MSI_X_ENTRY_SIZE + word * 4).as_mut_ptr()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn message_encoding() {
+        let message = Message::new(0x51, 3);
+        assert_eq!(message.address, 0xfee0_3000);
+        assert_eq!(message.data, 0x51);
+    }
+}
@@ -0,0 +1,139 @@
+use core::{arch::asm, hint};
+use x86_64::{
+    instructions::{interrupts, port::Port, tables::lidt},
+    structures::DescriptorTablePointer,
+    VirtAddr,
+};
+
+use crate::{
+    acpi::{self, AcpiError},
+    hlt_loop, println,
+};
+
+/// Sleep state number of soft off.
+const S5: u8 = 5;
+
+const SCI_EN: u16 = 1;
+const SLP_TYP_SHIFT: u16 = 10;
+const SLP_EN: u16 = 1 << 13;
+
+/// Number of polls to wait for the hardware to react to a request.
+const POLLS: usize = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerError {
+    /// ACPI tables or a required FADT field are missing.
+    Unsupported,
+    /// No `\_S5` package was found in the DSDT or SSDTs.
+    NoSleepType,
+    /// The firmware didn't switch to ACPI mode.
+    AcpiEnableTimeout,
+    /// The hardware ignored the request.
+    Ignored,
+    Acpi(AcpiError),
+}
+
+impl From<AcpiError> for PowerError {
+    fn from(err: AcpiError) -> Self {
+        PowerError::Acpi(err)
+    }
+}
+
+/// Turns the machine off by entering the ACPI S5 sleep state.
+///
+/// If that fails, interrupts stay disabled and the CPU is halted.
+pub fn shutdown() -> ! {
+    interrupts::disable();
+    let err = unsafe { acpi_shutdown() }.unwrap_err();
+    println!("ACPI shutdown failed: {:?}", err);
+    println!("It is now safe to turn off your computer.");
+    hlt_loop();
+}
+
+/// Resets the machine.
+///
+/// Tries the ACPI reset register, then pulsing the reset line through the
+/// 8042 keyboard controller and finally forces a triple fault.
+pub fn reboot() -> ! {
+    interrupts::disable();
+
+    if let Err(err) = unsafe { acpi_reset() } {
+        println!("ACPI reset failed: {:?}", err);
+    }
+    settle();
+
+    unsafe { keyboard_controller_reset() };
+    settle();
+
+    unsafe { triple_fault() }
+}
+
+/// Only returns on failure.
+unsafe fn acpi_shutdown() -> Result<(), PowerError> {
+    let acpi = acpi::get().ok_or(PowerError::Unsupported)?;
+    let fadt = acpi.fadt.ok_or(PowerError::Unsupported)?;
+    let pm1a = fadt.pm1a_control.ok_or(PowerError::Unsupported)?;
+    let sleep_type = acpi.sleep_type(S5).ok_or(PowerError::NoSleepType)?;
+
+    if pm1a.read()? as u16 & SCI_EN == 0 && fadt.smi_command != 0 && fadt.acpi_enable != 0 {
+        Port::<u8>::new(fadt.smi_command as u16).write(fadt.acpi_enable);
+        let enabled = (0..POLLS).any(|_| {
+            hint::spin_loop();
+            matches!(pm1a.read(), Ok(value) if value as u16 & SCI_EN != 0)
+        });
+        if !enabled {
+            return Err(PowerError::AcpiEnableTimeout);
+        }
+    }
+
+    let enter = |value: u64, sleep_type: u8| {
+        let value = value as u16 & !(0b111 << SLP_TYP_SHIFT);
+        u64::from(value | u16::from(sleep_type) << SLP_TYP_SHIFT | SLP_EN)
+    };
+    pm1a.write(enter(pm1a.read()?, sleep_type.a))?;
+    if let Some(pm1b) = fadt.pm1b_control {
+        pm1b.write(enter(pm1b.read()?, sleep_type.b))?;
+    }
+    settle();
+    Err(PowerError::Ignored)
+}
+
+unsafe fn acpi_reset() -> Result<(), PowerError> {
+    let fadt = acpi::get()
+        .and_then(|acpi| acpi.fadt)
+        .ok_or(PowerError::Unsupported)?;
+    let reset = fadt.reset_register.ok_or(PowerError::Unsupported)?;
+    reset.write(fadt.reset_value.into())?;
+    Ok(())
+}
+
+unsafe fn keyboard_controller_reset() {
+    const INPUT_BUFFER_FULL: u8 = 1 << 1;
+    const PULSE_RESET: u8 = 0xfe;
+
+    let mut status = Port::<u8>::new(0x64);
+    for _ in 0..POLLS {
+        if status.read() & INPUT_BUFFER_FULL == 0 {
+            break;
+        }
+        hint::spin_loop();
+    }
+    status.write(PULSE_RESET);
+}
+
+/// Loads an empty IDT and raises an exception, which can't be delivered and
+/// escalates to a triple fault.
+unsafe fn triple_fault() -> ! {
+    lidt(&DescriptorTablePointer {
+        limit: 0,
+        base: VirtAddr::zero(),
+    });
+    asm!("int3", options(noreturn));
+}
+
+/// Gives the hardware a moment to act on a request.
+fn settle() {
+    for _ in 0..POLLS {
+        hint::spin_loop();
+    }
+}
@@ -0,0 +1,384 @@
+//! Processes, which own a working directory and a file descriptor table, and
+//! belong to a process group that terminals send signals to.
+//!
+//! There is no scheduler yet, so everything runs as the kernel process, and
+//! signals are only recorded as pending until something checks for them.
+
+use alloc::{
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{
+    fmt,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+use spin::{Lazy, Mutex, RwLock};
+
+use crate::vfs::{
+    self, DirEntry, File, FileType, FsError, FsResult, Metadata, OpenFlags, SeekFrom,
+};
+
+pub type Pid = usize;
+/// A file descriptor, an index into the file descriptor table.
+pub type Fd = usize;
+
+static KERNEL: Lazy<Arc<Process>> = Lazy::new(|| Process::new(String::from("/")));
+
+/// Every process created, with those since dropped removed by [`all`].
+static PROCESSES: Mutex<Vec<Weak<Process>>> = Mutex::new(Vec::new());
+
+/// Returns the process the caller runs as.
+pub fn current() -> Arc<Process> {
+    KERNEL.clone()
+}
+
+/// Returns every process that exists, ordered by pid.
+pub fn all() -> Vec<Arc<Process>> {
+    // Make sure the kernel process is listed.
+    Lazy::force(&KERNEL);
+    let mut processes = PROCESSES.lock();
+    processes.retain(|process| process.strong_count() > 0);
+    processes.iter().filter_map(Weak::upgrade).collect()
+}
+
+/// Returns the process with `pid`.
+pub fn find(pid: Pid) -> Option<Arc<Process>> {
+    all().into_iter().find(|process| process.pid == pid)
+}
+
+/// Sends `signal` to every process in the group `pgid`, returning how many
+/// there are.
+pub fn signal_group(pgid: Pid, signal: Signal) -> usize {
+    let members = all().into_iter().filter(|process| process.pgid() == pgid);
+    members.map(|process| process.signal(signal)).count()
+}
+
+/// The signals a terminal generates, numbered like on Linux.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    /// Ctrl+C.
+    Interrupt = 2,
+    /// Ctrl+Backslash.
+    Quit = 3,
+    /// Ctrl+Z.
+    Suspend = 20,
+}
+
+impl Signal {
+    pub const ALL: [Signal; 3] = [Signal::Interrupt, Signal::Quit, Signal::Suspend];
+
+    pub fn number(self) -> u32 {
+        self as u32
+    }
+
+    fn mask(self) -> u64 {
+        1 << (self.number() - 1)
+    }
+}
+
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            Signal::Interrupt => "SIGINT",
+            Signal::Quit => "SIGQUIT",
+            Signal::Suspend => "SIGTSTP",
+        })
+    }
+}
+
+pub struct Process {
+    pid: Pid,
+    /// The process group, named by the pid of its first member.
+    pgid: AtomicUsize,
+    /// Signals sent and not taken yet, with bit `n - 1` for signal `n`.
+    pending: AtomicU64,
+    cwd: RwLock<String>,
+    files: Mutex<FdTable>,
+}
+
+impl Process {
+    fn new(cwd: String) -> Arc<Self> {
+        static NEXT_PID: AtomicUsize = AtomicUsize::new(1);
+
+        let pid = NEXT_PID.fetch_add(1, Ordering::Relaxed);
+        let process = Arc::new(Self {
+            pid,
+            pgid: AtomicUsize::new(pid),
+            pending: AtomicU64::new(0),
+            cwd: RwLock::new(cwd),
+            files: Mutex::new(FdTable::default()),
+        });
+        PROCESSES.lock().push(Arc::downgrade(&process));
+        process
+    }
+
+    /// Creates a process sharing the working directory, open files and
+    /// process group of this one.
+    pub fn fork(&self) -> Arc<Self> {
+        let child = Self::new(self.cwd());
+        *child.files.lock() = self.files.lock().clone();
+        child.setpgid(self.pgid());
+        child
+    }
+
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    pub fn pgid(&self) -> Pid {
+        self.pgid.load(Ordering::Relaxed)
+    }
+
+    /// Moves the process to the group `pgid`, which is a new one if it is
+    /// its pid.
+    pub fn setpgid(&self, pgid: Pid) {
+        self.pgid.store(pgid, Ordering::Relaxed);
+    }
+
+    /// Marks `signal` as pending for the process.
+    pub fn signal(&self, signal: Signal) {
+        self.pending.fetch_or(signal.mask(), Ordering::Relaxed);
+    }
+
+    /// Returns the pending signals.
+    pub fn pending_signals(&self) -> Vec<Signal> {
+        let pending = self.pending.load(Ordering::Relaxed);
+        let signals = Signal::ALL.into_iter();
+        signals
+            .filter(|signal| pending & signal.mask() != 0)
+            .collect()
+    }
+
+    /// Returns whether `signal` was pending, clearing it.
+    pub fn take_signal(&self, signal: Signal) -> bool {
+        self.pending.fetch_and(!signal.mask(), Ordering::Relaxed) & signal.mask() != 0
+    }
+
+    /// Returns the canonical path of the working directory.
+    pub fn cwd(&self) -> String {
+        self.cwd.read().clone()
+    }
+
+    pub async fn chdir(&self, path: &str) -> FsResult<()> {
+        let (path, inode) = vfs::resolve(&self.cwd(), path, true).await?;
+        if inode.metadata().file_type != FileType::Directory {
+            return Err(FsError::NotADirectory);
+        }
+        *self.cwd.write() = path;
+        Ok(())
+    }
+
+    pub async fn open(&self, path: &str, flags: OpenFlags, mode: u16) -> FsResult<Fd> {
+        let file = vfs::open(&self.cwd(), path, flags, mode).await?;
+        Ok(self.install(file))
+    }
+
+    /// Creates a pipe, returning the descriptors of its read end and its
+    /// write end.
+    pub fn pipe(&self, flags: OpenFlags) -> (Fd, Fd) {
+        let (reader, writer) = vfs::pipe(flags);
+        (self.install(reader), self.install(writer))
+    }
+
+    /// Adds `file` to the table under the lowest free descriptor.
+    pub fn install(&self, file: Arc<dyn File>) -> Fd {
+        self.files.lock().insert(file)
+    }
+
+    pub fn file(&self, fd: Fd) -> FsResult<Arc<dyn File>> {
+        self.files.lock().get(fd).ok_or(FsError::BadFileDescriptor)
+    }
+
+    /// Returns the open files with their descriptors.
+    pub fn files(&self) -> Vec<(Fd, Arc<dyn File>)> {
+        let files = self.files.lock();
+        let open = files.files.iter().enumerate();
+        open.filter_map(|(fd, file)| Some((fd, file.clone()?)))
+            .collect()
+    }
+
+    pub fn close(&self, fd: Fd) -> FsResult<()> {
+        self.files
+            .lock()
+            .remove(fd)
+            .map(drop)
+            .ok_or(FsError::BadFileDescriptor)
+    }
+
+    pub async fn read(&self, fd: Fd, buf: &mut [u8]) -> FsResult<usize> {
+        self.file(fd)?.read(buf).await
+    }
+
+    pub async fn write(&self, fd: Fd, buf: &[u8]) -> FsResult<usize> {
+        self.file(fd)?.write(buf).await
+    }
+
+    pub fn seek(&self, fd: Fd, pos: SeekFrom) -> FsResult<u64> {
+        self.file(fd)?.seek(pos)
+    }
+
+    pub async fn ioctl(&self, fd: Fd, cmd: u32, arg: u64) -> FsResult<u64> {
+        self.file(fd)?.ioctl(cmd, arg).await
+    }
+
+    pub async fn readdir(&self, fd: Fd) -> FsResult<Vec<DirEntry>> {
+        self.file(fd)?.readdir().await
+    }
+
+    pub fn fstat(&self, fd: Fd) -> FsResult<Metadata> {
+        self.file(fd)?.metadata()
+    }
+
+    /// Returns the metadata of the file at `path`, following a final symlink.
+    pub async fn stat(&self, path: &str) -> FsResult<Metadata> {
+        let (_, inode) = vfs::resolve(&self.cwd(), path, true).await?;
+        Ok(inode.metadata())
+    }
+
+    /// Returns the metadata of the file at `path`, or of the symlink there.
+    pub async fn lstat(&self, path: &str) -> FsResult<Metadata> {
+        let (_, inode) = vfs::resolve(&self.cwd(), path, false).await?;
+        Ok(inode.metadata())
+    }
+
+    pub async fn mkdir(&self, path: &str, mode: u16) -> FsResult<()> {
+        let (dir, name) = vfs::resolve_parent(&self.cwd(), path).await?;
+        dir.create(&name, FileType::Directory, mode).await?;
+        Ok(())
+    }
+
+    pub async fn mkfifo(&self, path: &str, mode: u16) -> FsResult<()> {
+        let (dir, name) = vfs::resolve_parent(&self.cwd(), path).await?;
+        dir.create(&name, FileType::Fifo, mode).await?;
+        Ok(())
+    }
+
+    pub async fn unlink(&self, path: &str) -> FsResult<()> {
+        let (dir, name) = vfs::resolve_parent(&self.cwd(), path).await?;
+        dir.unlink(&name).await
+    }
+
+    pub async fn rmdir(&self, path: &str) -> FsResult<()> {
+        let (dir, name) = vfs::resolve_parent(&self.cwd(), path).await?;
+        dir.rmdir(&name).await
+    }
+
+    /// Creates a symlink at `path` pointing to `target`.
+    pub async fn symlink(&self, target: &str, path: &str) -> FsResult<()> {
+        let (dir, name) = vfs::resolve_parent(&self.cwd(), path).await?;
+        dir.symlink(&name, target).await?;
+        Ok(())
+    }
+
+    pub async fn readlink(&self, path: &str) -> FsResult<String> {
+        let (_, inode) = vfs::resolve(&self.cwd(), path, false).await?;
+        inode.read_link().await
+    }
+}
+
+#[derive(Clone, Default)]
+struct FdTable {
+    files: Vec<Option<Arc<dyn File>>>,
+}
+
+impl FdTable {
+    fn insert(&mut self, file: Arc<dyn File>) -> Fd {
+        match self.files.iter().position(Option::is_none) {
+            Some(fd) => {
+                self.files[fd] = Some(file);
+                fd
+            }
+            None => {
+                self.files.push(Some(file));
+                self.files.len() - 1
+            }
+        }
+    }
+
+    fn get(&self, fd: Fd) -> Option<Arc<dyn File>> {
+        self.files.get(fd)?.clone()
+    }
+
+    fn remove(&mut self, fd: Fd) -> Option<Arc<dyn File>> {
+        let file = self.files.get_mut(fd)?.take();
+        while let Some(None) = self.files.last() {
+            self.files.pop();
+        }
+        file
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::boxed::Box;
+    use futures_util::future::BoxFuture;
+
+    struct NullFile;
+
+    impl File for NullFile {
+        fn read<'a>(&'a self, _buf: &'a mut [u8]) -> BoxFuture<'a, FsResult<usize>> {
+            Box::pin(async { Ok(0) })
+        }
+
+        fn write<'a>(&'a self, buf: &'a [u8]) -> BoxFuture<'a, FsResult<usize>> {
+            Box::pin(async move { Ok(buf.len()) })
+        }
+
+        fn metadata(&self) -> FsResult<Metadata> {
+            Err(FsError::Unsupported)
+        }
+    }
+
+    #[test_case]
+    fn lowest_descriptor_is_reused() {
+        let process = Process::new(String::from("/"));
+        let fds: Vec<_> = (0..3)
+            .map(|_| process.install(Arc::new(NullFile)))
+            .collect();
+        assert_eq!(fds, [0, 1, 2]);
+
+        process.close(1).unwrap();
+        assert_eq!(process.close(1), Err(FsError::BadFileDescriptor));
+        assert_eq!(process.install(Arc::new(NullFile)), 1);
+        assert!(matches!(process.file(3), Err(FsError::BadFileDescriptor)));
+
+        let child = process.fork();
+        assert_ne!(child.pid(), process.pid());
+        assert!(child.file(2).is_ok());
+        let fds: Vec<_> = child.files().into_iter().map(|(fd, _)| fd).collect();
+        assert_eq!(fds, [0, 1, 2]);
+    }
+
+    #[test_case]
+    fn registry() {
+        let process = current().fork();
+        let pid = process.pid();
+        assert!(find(current().pid()).is_some());
+        assert!(Arc::ptr_eq(&find(pid).unwrap(), &process));
+        drop(process);
+        assert!(find(pid).is_none());
+    }
+
+    #[test_case]
+    fn signals() {
+        let parent = Process::new(String::from("/"));
+        let child = parent.fork();
+        let other = Process::new(String::from("/"));
+        assert_eq!(child.pgid(), parent.pid());
+        assert_eq!(signal_group(parent.pid(), Signal::Interrupt), 2);
+        assert_eq!(child.pending_signals(), [Signal::Interrupt]);
+        assert!(other.pending_signals().is_empty());
+
+        child.setpgid(child.pid());
+        assert_eq!(signal_group(parent.pid(), Signal::Suspend), 1);
+        assert_eq!(
+            parent.pending_signals(),
+            [Signal::Interrupt, Signal::Suspend]
+        );
+        assert!(parent.take_signal(Signal::Interrupt));
+        assert!(!parent.take_signal(Signal::Interrupt));
+        assert_eq!(parent.pending_signals(), [Signal::Suspend]);
+    }
+}
@@ -1,19 +1,367 @@
-use core::fmt::{self, Write};
+//! The serial ports COM1 to COM4.
+//!
+//! Kernel output goes to COM1 through [`SERIAL1`], waiting for the port
+//! between bytes so it works anywhere. Everything else is interrupt-driven:
+//! the interrupt handler queues the bytes received for a [`SerialStream`],
+//! and sends the bytes queued by [`ComPort::write`] whenever the transmitter
+//! is empty.
 
-use spin::{Lazy, Mutex};
+use alloc::vec::Vec;
+use core::{
+    fmt::{self, Write},
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+use crossbeam_queue::ArrayQueue;
+use futures_util::{future::poll_fn, task::AtomicWaker, Stream, StreamExt};
+use spin::{Lazy, Mutex, Once};
 use uart_16550::SerialPort;
-use x86_64::instructions;
+use x86_64::instructions::{self, port::Port};
+
+use crate::{interrupts, kmsg, serial_println, tty};
+
+const COM1: u16 = 0x3f8;
+
+/// The I/O ports and IRQs of COM1 to COM4.
+const COM_PORTS: [(u16, u8); 4] = [(COM1, 4), (0x2f8, 3), (0x3e8, 4), (0x2e8, 3)];
+
+/// Number of bytes received that are kept for the reader.
+pub const RECEIVE_QUEUE_SIZE: usize = 256;
+/// Number of bytes queued for sending before writers wait.
+const TRANSMIT_QUEUE_SIZE: usize = 512;
+/// Bytes the transmitter FIFO holds.
+const FIFO_SIZE: usize = 16;
+
+// Registers, as offsets from the base port.
+const DATA: u16 = 0;
+const INTERRUPT_ENABLE: u16 = 1;
+const INTERRUPT_ID: u16 = 2;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+const MODEM_STATUS: u16 = 6;
+const SCRATCH: u16 = 7;
+
+const IER_RECEIVED: u8 = 1 << 0;
+const IER_TRANSMIT_EMPTY: u8 = 1 << 1;
+/// Data terminal ready, request to send and the OUT2 line that connects the
+/// interrupt to the PIC, as set by `SerialPort::init`.
+const MCR_NORMAL: u8 = 0x0b;
+const MCR_LOOPBACK: u8 = 1 << 4;
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_OVERRUN: u8 = 1 << 1;
+const LSR_TRANSMIT_EMPTY: u8 = 1 << 5;
 
 pub static SERIAL1: Lazy<Mutex<SerialPort>> = Lazy::new(|| {
-    let mut serial = unsafe { SerialPort::new(0x3f8) };
+    let mut serial = unsafe { SerialPort::new(COM1) };
     serial.init();
     Mutex::new(serial)
 });
 
+static PORTS: Once<Vec<ComPort>> = Once::new();
+
+/// A [`fmt::Write`] handle to [`SERIAL1`] for functions that report to a
+/// generic writer.
+pub struct SerialWriter;
+
+impl fmt::Write for SerialWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        _print(format_args!("{}", s));
+        Ok(())
+    }
+}
+
+/// Detects the serial ports and enables their interrupts.
+pub fn init() {
+    for port in ports() {
+        serial_println!(
+            "serial: ttyS{} at {:#x}, IRQ {}",
+            port.index,
+            port.base,
+            port.irq
+        );
+    }
+}
+
+/// Returns the serial ports that exist, detecting them on the first call.
+pub fn ports() -> &'static [ComPort] {
+    let mut detected = false;
+    let ports = PORTS.call_once(|| {
+        detected = true;
+        // Initialize COM1 the way kernel output expects first.
+        Lazy::force(&SERIAL1);
+        let ports = COM_PORTS.iter().enumerate();
+        ports
+            .filter_map(|(index, &(base, irq))| ComPort::probe(index, base, irq))
+            .collect()
+    });
+    if detected {
+        for irq in [3, 4] {
+            if ports.iter().any(|port| port.irq == irq) {
+                interrupts::enable_irq(irq);
+            }
+        }
+        // The PICs only see an edge, which came before the IRQ was unmasked
+        // for what the ports received meanwhile.
+        instructions::interrupts::without_interrupts(|| ports.iter().for_each(ComPort::service));
+    }
+    ports
+}
+
+/// Returns `ttyS<index>`, the port at the I/O ports of COM<index + 1>.
+pub fn port(index: usize) -> Option<&'static ComPort> {
+    ports().iter().find(|port| port.index == index)
+}
+
+/// Called by the interrupt handlers of IRQ 3 and 4, which two ports share.
+///
+/// NOTE: Must not block or allocate.
+pub(crate) fn handle_interrupt(irq: u8) {
+    let ports = PORTS.get().map_or(&[][..], Vec::as_slice);
+    for port in ports.iter().filter(|port| port.irq == irq) {
+        port.service();
+    }
+}
+
+/// Passes the bytes received on `ttyS<index>` to its terminal.
+pub async fn handle_input(index: usize) {
+    let (port, tty) = match (port(index), tty::serial(index)) {
+        (Some(port), Some(tty)) => (port, tty),
+        _ => return,
+    };
+    let mut chunks = SerialStream::new(port).ready_chunks(64);
+    while let Some(bytes) = chunks.next().await {
+        tty.input(&bytes).await;
+    }
+}
+
+pub struct ComPort {
+    index: usize,
+    base: u16,
+    irq: u8,
+    received: ArrayQueue<u8>,
+    receive_waker: AtomicWaker,
+    /// Whether a [`SerialStream`] was created for the port.
+    streaming: AtomicBool,
+    transmit: ArrayQueue<u8>,
+    /// Tasks waiting for room in the transmit queue, only locked with
+    /// interrupts disabled.
+    transmit_wakers: Mutex<Vec<Waker>>,
+    /// Number of bytes received and lost, because the queue or the receiver
+    /// of the port was full.
+    overruns: AtomicU64,
+}
+
+impl ComPort {
+    /// Returns the port at `base` if its scratch register keeps what is
+    /// written to it, initialized with the receive interrupt enabled.
+    fn probe(index: usize, base: u16, irq: u8) -> Option<Self> {
+        let mut scratch = Port::<u8>::new(base + SCRATCH);
+        let present = unsafe {
+            scratch.write(0xae);
+            scratch.read() == 0xae
+        };
+        if !present {
+            return None;
+        }
+        if base != COM1 {
+            // This enables the receive interrupt too.
+            unsafe { SerialPort::new(base) }.init();
+        }
+        Some(Self {
+            index,
+            base,
+            irq,
+            received: ArrayQueue::new(RECEIVE_QUEUE_SIZE),
+            receive_waker: AtomicWaker::new(),
+            streaming: AtomicBool::new(false),
+            transmit: ArrayQueue::new(TRANSMIT_QUEUE_SIZE),
+            transmit_wakers: Mutex::new(Vec::new()),
+            overruns: AtomicU64::new(0),
+        })
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn overruns(&self) -> u64 {
+        self.overruns.load(Ordering::Relaxed)
+    }
+
+    fn read_register(&self, register: u16) -> u8 {
+        unsafe { Port::new(self.base + register).read() }
+    }
+
+    fn write_register(&self, register: u16, value: u8) {
+        unsafe { Port::new(self.base + register).write(value) }
+    }
+
+    /// Handles what the port interrupts for.
+    fn service(&self) {
+        // Bit 0 of the interrupt identification is clear while one is
+        // pending, and absent ports read as all ones.
+        for _ in 0..FIFO_SIZE {
+            let id = self.read_register(INTERRUPT_ID);
+            if id & 1 != 0 {
+                break;
+            }
+            match (id >> 1) & 0b111 {
+                // Modem status changes, acknowledged by reading it.
+                0b000 => {
+                    self.read_register(MODEM_STATUS);
+                }
+                // The transmitter empty condition is acknowledged by reading
+                // the identification.
+                0b001 => self.send(),
+                _ => self.receive(),
+            }
+        }
+    }
+
+    /// Reads the line status register, counting the overrun it reports,
+    /// which reading clears.
+    fn line_status(&self) -> u8 {
+        let status = self.read_register(LINE_STATUS);
+        if status & LSR_OVERRUN != 0 {
+            self.overruns.fetch_add(1, Ordering::Relaxed);
+        }
+        status
+    }
+
+    /// Moves the bytes received to the queue.
+    fn receive(&self) {
+        loop {
+            if self.line_status() & LSR_DATA_READY == 0 {
+                break;
+            }
+            let byte = self.read_register(DATA);
+            if self.received.push(byte).is_err() {
+                self.overruns.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        if !self.received.is_empty() {
+            self.receive_waker.wake();
+        }
+    }
+
+    /// Fills the transmitter from the queue if it is empty, turning the
+    /// interrupt for it off once the queue is.
+    fn send(&self) {
+        if self.line_status() & LSR_TRANSMIT_EMPTY != 0 {
+            for _ in 0..FIFO_SIZE {
+                match self.transmit.pop() {
+                    Some(byte) => self.write_register(DATA, byte),
+                    None => break,
+                }
+            }
+        }
+        if self.transmit.is_empty() {
+            self.write_register(INTERRUPT_ENABLE, IER_RECEIVED);
+        }
+        if let Some(mut wakers) = self.transmit_wakers.try_lock() {
+            wakers.drain(..).for_each(Waker::wake);
+        }
+    }
+
+    /// Queues as much of `bytes` as fits, returning how many bytes that is.
+    fn queue(&self, bytes: &[u8]) -> usize {
+        instructions::interrupts::without_interrupts(|| {
+            let queued = bytes
+                .iter()
+                .take_while(|&&byte| self.transmit.push(byte).is_ok())
+                .count();
+            if queued > 0 {
+                // This interrupts right away if the transmitter is empty.
+                self.write_register(INTERRUPT_ENABLE, IER_RECEIVED | IER_TRANSMIT_EMPTY);
+            }
+            queued
+        })
+    }
+
+    /// Sends `bytes`, waiting while the transmit queue is full.
+    pub async fn write(&self, bytes: &[u8]) {
+        let mut written = 0;
+        poll_fn(|cx| {
+            written += self.queue(&bytes[written..]);
+            if written == bytes.len() {
+                return Poll::Ready(());
+            }
+            instructions::interrupts::without_interrupts(|| {
+                let mut wakers = self.transmit_wakers.lock();
+                if !wakers.iter().any(|other| other.will_wake(cx.waker())) {
+                    wakers.push(cx.waker().clone());
+                }
+            });
+            // Room may have been made before the waker was registered.
+            written += self.queue(&bytes[written..]);
+            match written == bytes.len() {
+                true => Poll::Ready(()),
+                false => Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    /// Connects the transmitter of the port to its receiver, or the port to
+    /// the line again, for testing.
+    pub fn set_loopback(&self, loopback: bool) {
+        let control = match loopback {
+            true => MCR_NORMAL | MCR_LOOPBACK,
+            false => MCR_NORMAL,
+        };
+        self.write_register(MODEM_CONTROL, control);
+    }
+}
+
+/// The bytes received on a serial port.
+pub struct SerialStream {
+    port: &'static ComPort,
+}
+
+impl SerialStream {
+    /// Creates a new [`SerialStream`] for `port`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called more than once for the same port.
+    pub fn new(port: &'static ComPort) -> Self {
+        if port.streaming.swap(true, Ordering::Relaxed) {
+            panic!(
+                "SerialStream::new called more than once for ttyS{}",
+                port.index
+            );
+        }
+        Self { port }
+    }
+}
+
+impl Stream for SerialStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let port = self.port;
+        if let Some(byte) = port.received.pop() {
+            return Poll::Ready(Some(byte));
+        }
+
+        port.receive_waker.register(cx.waker());
+        match port.received.pop() {
+            Some(byte) => {
+                port.receive_waker.take();
+                Poll::Ready(Some(byte))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
     instructions::interrupts::without_interrupts(|| {
         SERIAL1.lock().write_fmt(args).unwrap();
+        kmsg::Writer.write_fmt(args).unwrap();
     });
 }
 
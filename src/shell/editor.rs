@@ -0,0 +1,405 @@
+//! Line editing for terminals that only need to move the cursor back with
+//! backspace and to overwrite what is under it, so it works the same on the
+//! VGA console and on a serial terminal.
+
+use alloc::{collections::VecDeque, string::String, vec::Vec};
+
+/// Number of lines kept in the history.
+const HISTORY_SIZE: usize = 32;
+/// Longest line, short enough to never wrap after the prompt on the screen.
+pub const MAX_LINE: usize = 72;
+
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7f;
+const ESCAPE: u8 = 0x1b;
+
+/// What the input fed to an [`Editor`] completed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// A line was entered.
+    Line(String),
+    /// Tab was pressed to complete the word before the cursor.
+    Complete,
+    /// Ctrl+C abandoned the line.
+    Interrupt,
+    /// Ctrl+D was pressed on an empty line.
+    EndOfInput,
+}
+
+/// Progress through an escape sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    None,
+    /// After the escape character.
+    Started,
+    /// In a control sequence, with its numeric parameter so far.
+    Sequence(u32),
+}
+
+pub struct Editor {
+    prompt: &'static str,
+    line: Vec<char>,
+    cursor: usize,
+    history: VecDeque<String>,
+    /// The history entry shown and the line edited before browsing it.
+    browsing: Option<(usize, String)>,
+    escape: Escape,
+    /// Whether the last byte ended a line with a carriage return, so a
+    /// newline right after it is part of the same line ending.
+    after_return: bool,
+}
+
+impl Editor {
+    pub fn new(prompt: &'static str) -> Self {
+        Self {
+            prompt,
+            line: Vec::new(),
+            cursor: 0,
+            history: VecDeque::new(),
+            browsing: None,
+            escape: Escape::None,
+            after_return: false,
+        }
+    }
+
+    /// Writes the prompt and the line being edited to `out`, on a new line.
+    pub fn redraw(&self, out: &mut String) {
+        out.push_str(self.prompt);
+        out.extend(&self.line);
+        move_back(out, self.line.len() - self.cursor);
+    }
+
+    /// Processes a byte of input, writing what changes on the terminal to
+    /// `out`.
+    pub fn feed(&mut self, byte: u8, out: &mut String) -> Option<Event> {
+        let after_return = core::mem::replace(&mut self.after_return, false);
+        match self.escape {
+            Escape::Started => {
+                self.escape = match byte {
+                    b'[' | b'O' => Escape::Sequence(0),
+                    _ => Escape::None,
+                };
+                return None;
+            }
+            Escape::Sequence(param) => {
+                self.escape = match byte {
+                    b'0'..=b'9' => {
+                        let digit = u32::from(byte - b'0');
+                        Escape::Sequence(param.saturating_mul(10).saturating_add(digit))
+                    }
+                    // Parameter bytes this editor doesn't use.
+                    0x20..=0x3f => Escape::Sequence(param),
+                    _ => {
+                        self.sequence(byte, param, out);
+                        Escape::None
+                    }
+                };
+                return None;
+            }
+            Escape::None => {}
+        }
+
+        match byte {
+            b'\r' | b'\n' => {
+                if byte == b'\n' && after_return {
+                    return None;
+                }
+                self.after_return = byte == b'\r';
+                return Some(self.enter(out));
+            }
+            b'\t' => return Some(Event::Complete),
+            // Ctrl+A and Ctrl+E.
+            0x01 => self.home(out),
+            0x05 => self.end(out),
+            0x03 => {
+                out.push_str("^C\n");
+                self.reset();
+                return Some(Event::Interrupt);
+            }
+            0x04 if self.line.is_empty() => return Some(Event::EndOfInput),
+            0x04 => self.delete(out),
+            BACKSPACE | DELETE => self.backspace(out),
+            ESCAPE => self.escape = Escape::Started,
+            0x20..=0x7e => self.insert(&[char::from(byte)], out),
+            _ => {}
+        }
+        None
+    }
+
+    /// Returns the word before the cursor and whether it is the first one.
+    pub fn word(&self) -> (String, bool) {
+        let before = &self.line[..self.cursor];
+        let start = before.iter().rposition(|&c| c == ' ').map_or(0, |i| i + 1);
+        let first = before[..start].iter().all(|&c| c == ' ');
+        (before[start..].iter().collect(), first)
+    }
+
+    /// Completes the word before the cursor with the longest prefix shared by
+    /// the `candidates` it starts, listing them if that adds nothing.
+    ///
+    /// A single candidate is followed by a space unless it ends with `/`.
+    pub fn complete(&mut self, candidates: &[String], out: &mut String) {
+        let (word, _) = self.word();
+        let matches: Vec<&str> = candidates
+            .iter()
+            .map(String::as_str)
+            .filter(|candidate| candidate.starts_with(word.as_str()))
+            .collect();
+        let (first, rest) = match matches.split_first() {
+            Some(split) => split,
+            None => return,
+        };
+
+        let mut common = first.len();
+        for other in rest {
+            common = first
+                .bytes()
+                .zip(other.bytes())
+                .take(common)
+                .take_while(|(a, b)| a == b)
+                .count();
+        }
+        // Candidates can share the first bytes of different characters.
+        while !first.is_char_boundary(common) {
+            common -= 1;
+        }
+        let mut insert: Vec<char> = first[word.len()..common].chars().collect();
+        if rest.is_empty() && !first.ends_with('/') {
+            insert.push(' ');
+        }
+
+        if !insert.is_empty() {
+            self.insert(&insert, out);
+        } else if !rest.is_empty() {
+            out.push('\n');
+            out.push_str(&matches.join("  "));
+            out.push('\n');
+            self.redraw(out);
+        }
+    }
+
+    fn sequence(&mut self, last: u8, param: u32, out: &mut String) {
+        match (last, param) {
+            (b'A', _) => self.history_previous(out),
+            (b'B', _) => self.history_next(out),
+            (b'C', _) => {
+                if self.cursor < self.line.len() {
+                    out.push(self.line[self.cursor]);
+                    self.cursor += 1;
+                }
+            }
+            (b'D', _) => {
+                if self.cursor > 0 {
+                    move_back(out, 1);
+                    self.cursor -= 1;
+                }
+            }
+            (b'H', _) | (b'~', 1) | (b'~', 7) => self.home(out),
+            (b'F', _) | (b'~', 4) | (b'~', 8) => self.end(out),
+            (b'~', 3) => self.delete(out),
+            _ => {}
+        }
+    }
+
+    fn enter(&mut self, out: &mut String) -> Event {
+        out.push('\n');
+        let line: String = self.line.iter().collect();
+        if !line.trim().is_empty() && self.history.back() != Some(&line) {
+            if self.history.len() == HISTORY_SIZE {
+                self.history.pop_front();
+            }
+            self.history.push_back(line.clone());
+        }
+        self.reset();
+        Event::Line(line)
+    }
+
+    fn reset(&mut self) {
+        self.line.clear();
+        self.cursor = 0;
+        self.browsing = None;
+    }
+
+    fn insert(&mut self, chars: &[char], out: &mut String) {
+        let len = chars.len().min(MAX_LINE - self.line.len());
+        let cursor = self.cursor;
+        self.line
+            .splice(cursor..cursor, chars[..len].iter().copied());
+        self.cursor += len;
+        self.print_from(cursor, 0, out);
+        move_back(out, self.line.len() - self.cursor);
+    }
+
+    fn backspace(&mut self, out: &mut String) {
+        if self.cursor > 0 {
+            move_back(out, 1);
+            self.cursor -= 1;
+            self.line.remove(self.cursor);
+            self.print_from(self.cursor, 1, out);
+        }
+    }
+
+    fn delete(&mut self, out: &mut String) {
+        if self.cursor < self.line.len() {
+            self.line.remove(self.cursor);
+            self.print_from(self.cursor, 1, out);
+        }
+    }
+
+    fn home(&mut self, out: &mut String) {
+        move_back(out, self.cursor);
+        self.cursor = 0;
+    }
+
+    fn end(&mut self, out: &mut String) {
+        self.print_from(self.cursor, 0, out);
+        self.cursor = self.line.len();
+    }
+
+    /// Prints the line from `start`, where the cursor is, followed by
+    /// `erase` spaces over what is left of a longer line, and moves the
+    /// cursor back to `start` unless nothing was erased.
+    fn print_from(&self, start: usize, erase: usize, out: &mut String) {
+        out.extend(&self.line[start..]);
+        for _ in 0..erase {
+            out.push(' ');
+        }
+        if erase > 0 {
+            move_back(out, self.line.len() - start + erase);
+        }
+    }
+
+    /// Replaces the line with `line`, putting the cursor at its end.
+    fn replace(&mut self, line: &str, out: &mut String) {
+        self.home(out);
+        let old = core::mem::replace(&mut self.line, line.chars().collect());
+        let erase = old.len().saturating_sub(self.line.len());
+        self.print_from(0, erase, out);
+        if erase > 0 {
+            self.end(out);
+        }
+        self.cursor = self.line.len();
+    }
+
+    fn history_previous(&mut self, out: &mut String) {
+        let index = match &self.browsing {
+            Some((0, _)) => return,
+            Some((index, _)) => index - 1,
+            None if self.history.is_empty() => return,
+            None => self.history.len() - 1,
+        };
+        let edited = match self.browsing.take() {
+            Some((_, edited)) => edited,
+            None => self.line.iter().collect(),
+        };
+        let line = self.history[index].clone();
+        self.replace(&line, out);
+        self.browsing = Some((index, edited));
+    }
+
+    fn history_next(&mut self, out: &mut String) {
+        match self.browsing.take() {
+            Some((index, edited)) if index + 1 == self.history.len() => {
+                self.replace(&edited, out);
+            }
+            Some((index, edited)) => {
+                let line = self.history[index + 1].clone();
+                self.replace(&line, out);
+                self.browsing = Some((index + 1, edited));
+            }
+            None => {}
+        }
+    }
+}
+
+/// Moves the cursor `count` characters to the left.
+fn move_back(out: &mut String, count: usize) {
+    for _ in 0..count {
+        out.push(char::from(BACKSPACE));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds `input` to `editor`, returning the last event and the output.
+    fn feed(editor: &mut Editor, input: &[u8]) -> (Option<Event>, String) {
+        let mut out = String::new();
+        let mut event = None;
+        for &byte in input {
+            event = editor.feed(byte, &mut out).or(event);
+        }
+        (event, out)
+    }
+
+    fn line(text: &str) -> Option<Event> {
+        Some(Event::Line(String::from(text)))
+    }
+
+    #[test_case]
+    fn editing() {
+        let mut editor = Editor::new("> ");
+        let (event, out) = feed(&mut editor, b"helo\x1b[D\x1b[Dl");
+        assert_eq!(event, None);
+        assert_eq!(out, "helo\x08\x08llo\x08\x08");
+        // Backspace and delete redraw the rest of the line.
+        let (_, out) = feed(&mut editor, b"\x7f\x1b[3~");
+        assert_eq!(out, "\x08lo \x08\x08\x08o \x08\x08");
+        assert_eq!(feed(&mut editor, b"\x01x\x05y\r\n").0, line("xheoy"));
+        assert_eq!(feed(&mut editor, b"\r").0, line(""));
+
+        assert_eq!(feed(&mut editor, b"abc\x03").0, Some(Event::Interrupt));
+        assert_eq!(feed(&mut editor, b"\x04").0, Some(Event::EndOfInput));
+    }
+
+    #[test_case]
+    fn history() {
+        let mut editor = Editor::new("> ");
+        feed(&mut editor, b"first\nsecond\nsecond\n");
+        assert_eq!(
+            feed(&mut editor, b"new\x1b[A\x1b[A\x1b[A\n").0,
+            line("first")
+        );
+        let (event, out) = feed(&mut editor, b"x\x1b[A\x1b[B\x1b[B\x1b[B\n");
+        assert_eq!(event, line("x"));
+        assert!(out.starts_with("x\x08first\x08\x08\x08\x08\x08"));
+    }
+
+    #[test_case]
+    fn completion() {
+        let mut editor = Editor::new("> ");
+        let candidates = [String::from("cat"), String::from("cd")];
+        feed(&mut editor, b"c");
+        assert_eq!(editor.word(), (String::from("c"), true));
+        let mut out = String::new();
+        editor.complete(&candidates, &mut out);
+        assert_eq!(out, "\ncat  cd\n> c");
+
+        let dirs = [String::from("/proc/"), String::from("/dev/")];
+        feed(&mut editor, b"at /p");
+        assert_eq!(editor.word(), (String::from("/p"), false));
+        let mut out = String::new();
+        editor.complete(&dirs, &mut out);
+        assert_eq!(out, "roc/");
+        assert_eq!(feed(&mut editor, b"\n").0, line("cat /proc/"));
+    }
+
+    #[test_case]
+    fn completion_stops_at_characters() {
+        let mut editor = Editor::new("> ");
+        let candidates = [String::from("é1"), String::from("è2")];
+        let mut out = String::new();
+        editor.complete(&candidates, &mut out);
+        assert_eq!(out, "\né1  è2\n> ");
+
+        // Completed characters are edited as a whole.
+        let candidates = [String::from("été")];
+        let mut out = String::new();
+        editor.complete(&candidates, &mut out);
+        assert_eq!(out, "été ");
+        let (_, out) = feed(&mut editor, b"\x7f\x1b[D\x7fx");
+        assert_eq!(out, "\x08 \x08\x08\x08é \x08\x08xé\x08");
+        assert_eq!(feed(&mut editor, b"\n").0, line("éxé"));
+    }
+}
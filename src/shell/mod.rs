@@ -0,0 +1,432 @@
+//! A shell to inspect and control the kernel, run on the VGA console and on
+//! the first serial port alike so it can be scripted from the host.
+
+pub mod editor;
+
+use alloc::{
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::{fmt, time::Duration};
+use futures_util::future;
+use x86_64::{instructions::port::Port, VirtAddr};
+
+use self::editor::{Editor, Event};
+use crate::{
+    allocator,
+    memory::{inspect, vmm},
+    pci, power, process, task, time,
+    tty::Tty,
+    vfs::{FileType, OpenFlags},
+};
+
+const PROMPT: &str = "> ";
+
+struct Command {
+    name: &'static str,
+    args: &'static str,
+    help: &'static str,
+}
+
+const COMMANDS: [Command; 15] = [
+    Command {
+        name: "help",
+        args: "",
+        help: "list the commands",
+    },
+    Command {
+        name: "echo",
+        args: "[text]",
+        help: "print text",
+    },
+    Command {
+        name: "mem",
+        args: "",
+        help: "show memory and heap usage",
+    },
+    Command {
+        name: "tasks",
+        args: "",
+        help: "list the executor tasks",
+    },
+    Command {
+        name: "pt",
+        args: "[address]",
+        help: "dump the page tables or translate an address",
+    },
+    Command {
+        name: "lspci",
+        args: "",
+        help: "list the PCI devices",
+    },
+    Command {
+        name: "inb",
+        args: "<port>",
+        help: "read a byte from an I/O port",
+    },
+    Command {
+        name: "inw",
+        args: "<port>",
+        help: "read a word from an I/O port",
+    },
+    Command {
+        name: "inl",
+        args: "<port>",
+        help: "read a double word from an I/O port",
+    },
+    Command {
+        name: "ls",
+        args: "[path]",
+        help: "list a directory",
+    },
+    Command {
+        name: "cat",
+        args: "<path>...",
+        help: "print files",
+    },
+    Command {
+        name: "uptime",
+        args: "",
+        help: "show the time since boot",
+    },
+    Command {
+        name: "test",
+        args: "[name]...",
+        help: "run the self tests",
+    },
+    Command {
+        name: "reboot",
+        args: "",
+        help: "reset the machine",
+    },
+    Command {
+        name: "shutdown",
+        args: "",
+        help: "turn the machine off",
+    },
+];
+
+/// Runs a shell on `tty`, forever.
+///
+/// The shell edits lines itself with the terminal in raw mode, and gives
+/// commands the settings it had before.
+pub async fn run(tty: &'static Tty) {
+    let saved = tty.termios();
+    tty.set_termios(saved.raw());
+    let mut editor = Editor::new(PROMPT);
+    let mut out = String::from("rustos shell, type `help` for the commands\n");
+    editor.redraw(&mut out);
+    tty.write(out.as_bytes()).await;
+
+    let mut buf = [0; 64];
+    loop {
+        let len = tty.read(&mut buf).await;
+        for &byte in &buf[..len] {
+            let mut out = String::new();
+            match editor.feed(byte, &mut out) {
+                Some(Event::Line(line)) => {
+                    tty.write(out.as_bytes()).await;
+                    out.clear();
+                    tty.set_termios(saved);
+                    // Commands write to a `fmt::Write`, which can't wait for
+                    // the terminal, so their output is sent once they are done.
+                    execute(&line, &mut out).await;
+                    tty.write(out.as_bytes()).await;
+                    out.clear();
+                    tty.set_termios(saved.raw());
+                    editor.redraw(&mut out);
+                }
+                Some(Event::Complete) => {
+                    let (word, first) = editor.word();
+                    let candidates = completions(&word, first).await;
+                    editor.complete(&candidates, &mut out);
+                }
+                Some(Event::Interrupt) => editor.redraw(&mut out),
+                // The shell can't be left.
+                Some(Event::EndOfInput) | None => {}
+            }
+            tty.write(out.as_bytes()).await;
+        }
+    }
+}
+
+/// Returns the commands, or with `first` unset the paths, `word` could be
+/// completed to.
+async fn completions(word: &str, first: bool) -> Vec<String> {
+    if first {
+        return COMMANDS
+            .iter()
+            .map(|command| String::from(command.name))
+            .collect();
+    }
+
+    let (dir, _) = word.split_at(word.rfind('/').map_or(0, |i| i + 1));
+    let process = process::current();
+    let path = if dir.is_empty() { "." } else { dir };
+    let fd = match process
+        .open(path, OpenFlags::READ | OpenFlags::DIRECTORY, 0)
+        .await
+    {
+        Ok(fd) => fd,
+        Err(_) => return Vec::new(),
+    };
+    let entries = process.readdir(fd).await.unwrap_or_default();
+    process.close(fd).ok();
+    entries
+        .into_iter()
+        .map(|entry| match entry.file_type {
+            FileType::Directory => format!("{}{}/", dir, entry.name),
+            _ => format!("{}{}", dir, entry.name),
+        })
+        .collect()
+}
+
+/// Runs the command `line`, writing its output and any error to `out`.
+pub async fn execute(line: &str, out: &mut dyn fmt::Write) {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let (&name, args) = match words.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    if let Err(err) = run_command(name, args, out).await {
+        writeln!(out, "{}: {}", name, err).ok();
+    }
+}
+
+async fn run_command(name: &str, args: &[&str], out: &mut dyn fmt::Write) -> Result<(), String> {
+    match name {
+        "help" => {
+            for command in &COMMANDS {
+                let usage = format!("{} {}", command.name, command.args);
+                writeln!(out, "  {:<20} {}", usage, command.help).ok();
+            }
+        }
+        "echo" => {
+            writeln!(out, "{}", args.join(" ")).ok();
+        }
+        "mem" => {
+            let (frames, free_frames) = vmm::lock().frame_allocator().stats();
+            let (heap, heap_used) = allocator::stats();
+            writeln!(
+                out,
+                "memory: {} KiB free of {} KiB",
+                free_frames * 4,
+                frames * 4
+            )
+            .ok();
+            writeln!(
+                out,
+                "heap:   {} KiB used of {} KiB",
+                heap_used / 1024,
+                heap / 1024
+            )
+            .ok();
+        }
+        "tasks" => {
+            for (id, state) in task::tasks() {
+                writeln!(out, "{:>6}  {}", id, state).ok();
+            }
+        }
+        "pt" => {
+            let root = inspect::active_root();
+            match args.first() {
+                Some(address) => {
+                    let address = VirtAddr::try_new(parse_number(address)?)
+                        .map_err(|_| String::from("non-canonical address"))?;
+                    writeln!(out, "{}", inspect::translate(root, address)).ok();
+                }
+                None => {
+                    inspect::dump(root, out).ok();
+                }
+            }
+        }
+        "lspci" => {
+            pci::lspci(out).ok();
+        }
+        "inb" | "inw" | "inl" => {
+            let port = args.first().ok_or("missing port")?;
+            let port = u16::try_from(parse_number(port)?).map_err(|_| "port out of range")?;
+            let value = unsafe {
+                match name {
+                    "inb" => u32::from(Port::<u8>::new(port).read()),
+                    "inw" => u32::from(Port::<u16>::new(port).read()),
+                    _ => Port::<u32>::new(port).read(),
+                }
+            };
+            writeln!(out, "{:#x}", value).ok();
+        }
+        "ls" => {
+            let process = process::current();
+            let path = args.first().copied().unwrap_or(".");
+            let flags = OpenFlags::READ | OpenFlags::DIRECTORY;
+            let fd = process.open(path, flags, 0).await.map_err(describe)?;
+            let entries = process.readdir(fd).await;
+            process.close(fd).ok();
+            let mut entries = entries.map_err(describe)?;
+            entries.sort_by(|a, b| a.name.cmp(&b.name));
+            for entry in entries {
+                let suffix = match entry.file_type {
+                    FileType::Directory => "/",
+                    _ => "",
+                };
+                writeln!(out, "{}{}", entry.name, suffix).ok();
+            }
+        }
+        "cat" => {
+            if args.is_empty() {
+                return Err(String::from("missing path"));
+            }
+            let process = process::current();
+            let mut buf = vec![0; 512];
+            for path in args {
+                let fd = process
+                    .open(path, OpenFlags::READ, 0)
+                    .await
+                    .map_err(describe)?;
+                loop {
+                    match process.read(fd, &mut buf).await {
+                        Ok(0) => break,
+                        Ok(len) => {
+                            write!(out, "{}", String::from_utf8_lossy(&buf[..len])).ok();
+                        }
+                        Err(err) => {
+                            process.close(fd).ok();
+                            return Err(describe(err));
+                        }
+                    }
+                }
+                process.close(fd).ok();
+            }
+        }
+        "uptime" => {
+            let uptime = time::uptime();
+            writeln!(
+                out,
+                "up {}.{:03} s",
+                uptime.as_secs(),
+                uptime.subsec_millis()
+            )
+            .ok();
+        }
+        "test" => {
+            let names = match args.is_empty() {
+                true => SELF_TESTS.to_vec(),
+                false => args.to_vec(),
+            };
+            let mut failed = 0;
+            for name in names {
+                write!(out, "{}... ", name).ok();
+                match self_test(name).await {
+                    Ok(()) => writeln!(out, "ok").ok(),
+                    Err(err) => {
+                        failed += 1;
+                        writeln!(out, "FAILED: {}", err).ok()
+                    }
+                };
+            }
+            if failed > 0 {
+                return Err(format!("{} failed", failed));
+            }
+        }
+        "reboot" => power::reboot(),
+        "shutdown" => power::shutdown(),
+        _ => return Err(String::from("command not found")),
+    }
+    Ok(())
+}
+
+/// Parses a decimal number, or a hexadecimal one starting with `0x`.
+fn parse_number(text: &str) -> Result<u64, String> {
+    let result = match text.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    result.map_err(|_| format!("invalid number: {}", text))
+}
+
+fn describe(err: impl fmt::Debug) -> String {
+    format!("{:?}", err)
+}
+
+/// The checks `test` runs without arguments.
+const SELF_TESTS: [&str; 4] = ["heap", "timer", "tmpfs", "pipe"];
+
+async fn self_test(name: &str) -> Result<(), String> {
+    let check = |ok: bool, what: &str| match ok {
+        true => Ok(()),
+        false => Err(what.to_string()),
+    };
+    match name {
+        "heap" => {
+            let values: Vec<u64> = (0..1000).collect();
+            let boxed = Box::new(values.iter().sum::<u64>());
+            check(*boxed == 499_500, "wrong sum")
+        }
+        "timer" => {
+            let start = time::ticks();
+            time::sleep(Duration::from_millis(30)).await;
+            check(time::ticks() >= start + 3, "too few ticks")
+        }
+        "tmpfs" => {
+            let process = process::current();
+            let path = "/shell-self-test";
+            let flags = OpenFlags::READ | OpenFlags::WRITE | OpenFlags::CREATE;
+            let fd = process.open(path, flags, 0o644).await.map_err(describe)?;
+            let written = process.write(fd, b"self test").await;
+            process.close(fd).ok();
+            let mut buf = [0; 16];
+            let fd = process
+                .open(path, OpenFlags::READ, 0)
+                .await
+                .map_err(describe)?;
+            let read = process.read(fd, &mut buf).await;
+            process.close(fd).ok();
+            process.unlink(path).await.map_err(describe)?;
+            check(written == Ok(9), "short write")?;
+            check(read == Ok(9) && &buf[..9] == b"self test", "wrong data")
+        }
+        "pipe" => {
+            let process = process::current();
+            let (reader, writer) = process.pipe(OpenFlags::empty());
+            let mut buf = [0; 8];
+            let (written, read) = future::join(
+                process.write(writer, b"through"),
+                process.read(reader, &mut buf),
+            )
+            .await;
+            process.close(reader).ok();
+            process.close(writer).ok();
+            check(written == Ok(7), "short write")?;
+            check(read == Ok(7) && &buf[..7] == b"through", "wrong data")
+        }
+        _ => Err(String::from("no such test")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn numbers() {
+        assert_eq!(parse_number("42"), Ok(42));
+        assert_eq!(parse_number("0x3f8"), Ok(0x3f8));
+        assert!(parse_number("0xg").is_err());
+    }
+
+    #[test_case]
+    fn commands() {
+        let mut out = String::new();
+        task::block_on(execute("echo  hello   world", &mut out));
+        task::block_on(execute("", &mut out));
+        task::block_on(execute("frobnicate", &mut out));
+        assert_eq!(out, "hello world\nfrobnicate: command not found\n");
+
+        let mut out = String::new();
+        task::block_on(execute("help", &mut out));
+        assert_eq!(out.lines().count(), COMMANDS.len());
+    }
+}
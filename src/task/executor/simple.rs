@@ -42,7 +42,11 @@ impl SimpleExecutor {
             let mut context = Context::from_waker(&waker);
             match task.poll(&mut context) {
                 Poll::Ready(()) => {}
-                Poll::Pending => self.queue.push_back(task),
+                Poll::Pending => {
+                    // Tasks are polled again whether they were woken or not.
+                    task.id.wake();
+                    self.queue.push_back(task);
+                }
             }
         }
     }
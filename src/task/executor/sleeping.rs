@@ -80,6 +80,7 @@ impl TaskWaker {
     }
 
     fn wake_task(&self) {
+        self.task_id.wake();
         self.task_queue.push(self.task_id).expect("queue full");
     }
 }
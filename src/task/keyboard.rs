@@ -5,10 +5,10 @@ use core::{
 
 use crossbeam_queue::ArrayQueue;
 use futures_util::{task::AtomicWaker, Stream, StreamExt};
-use pc_keyboard::{layouts, DecodedKey, Keyboard, ScancodeSet1};
+use pc_keyboard::{layouts, DecodedKey, KeyCode, KeyEvent, KeyState, Keyboard, ScancodeSet1};
 use spin::Once;
 
-use crate::{print, println};
+use crate::{power, println, tty, vga_buffer};
 
 static SCANCODE_QUEUE: Once<ArrayQueue<u8>> = Once::new();
 static WAKER: AtomicWaker = AtomicWaker::new();
@@ -34,26 +34,99 @@ pub(crate) fn add_scancode(scancode: u8) {
     }
 }
 
-pub async fn print_keypresses() {
+/// Decodes the keys typed on the keyboard and passes them to the console
+/// terminal, encoded like a terminal sends them: as UTF-8, with escape
+/// sequences for cursor keys.
+///
+/// Shift+PageUp and Shift+PageDown scroll the console by half a screen
+/// instead, and other keys scroll it back down.
+pub async fn handle_keypresses() {
     let mut scancodes = ScancodeStream::new();
     let mut keyboard = Keyboard::new(
         layouts::Us104Key,
         ScancodeSet1,
-        pc_keyboard::HandleControl::Ignore,
+        pc_keyboard::HandleControl::MapLettersToUnicode,
     );
+    let mut modifiers = Modifiers::default();
+    let page = (vga_buffer::size().0 / 2) as isize;
 
     while let Some(scancode) = scancodes.next().await {
         if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
+            match modifiers.update(&key_event) {
+                Some(Shortcut::Reboot) => {
+                    println!("Ctrl+Alt+Del: rebooting");
+                    power::reboot();
+                }
+                Some(Shortcut::ScrollUp) => vga_buffer::scroll_view(page),
+                Some(Shortcut::ScrollDown) => vga_buffer::scroll_view(-page),
+                None => {}
+            }
             if let Some(key) = keyboard.process_keyevent(key_event) {
-                match key {
-                    DecodedKey::Unicode(c) => print!("{}", c),
-                    DecodedKey::RawKey(key) => print!("{:?}", key),
+                let mut buf = [0; 4];
+                let bytes = encode_key(key, &mut buf);
+                if !bytes.is_empty() {
+                    vga_buffer::reset_view();
+                    tty::console().input(bytes).await;
                 }
             }
         }
     }
 }
 
+/// Returns the bytes a VT100-style terminal sends for `key`, which are
+/// nothing for keys it doesn't have.
+fn encode_key(key: DecodedKey, buf: &mut [u8; 4]) -> &[u8] {
+    match key {
+        // The layout maps Delete to the character Backspace sends.
+        DecodedKey::Unicode('\u{7f}') | DecodedKey::RawKey(KeyCode::Delete) => b"\x1b[3~",
+        DecodedKey::Unicode(c) => c.encode_utf8(buf).as_bytes(),
+        DecodedKey::RawKey(KeyCode::ArrowUp) => b"\x1b[A",
+        DecodedKey::RawKey(KeyCode::ArrowDown) => b"\x1b[B",
+        DecodedKey::RawKey(KeyCode::ArrowRight) => b"\x1b[C",
+        DecodedKey::RawKey(KeyCode::ArrowLeft) => b"\x1b[D",
+        DecodedKey::RawKey(KeyCode::Home) => b"\x1b[H",
+        DecodedKey::RawKey(KeyCode::End) => b"\x1b[F",
+        DecodedKey::RawKey(_) => b"",
+    }
+}
+
+/// Key combinations the kernel handles itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Shortcut {
+    /// Ctrl+Alt+Del.
+    Reboot,
+    /// Shift+PageUp.
+    ScrollUp,
+    /// Shift+PageDown.
+    ScrollDown,
+}
+
+/// Tracks the modifiers needed to detect shortcuts, which `Keyboard`
+/// doesn't expose.
+#[derive(Debug, Default)]
+struct Modifiers {
+    ctrl: bool,
+    alt: bool,
+    shift: bool,
+}
+
+impl Modifiers {
+    /// Updates the state with `event`, returning the shortcut it completes.
+    fn update(&mut self, event: &KeyEvent) -> Option<Shortcut> {
+        let down = event.state == KeyState::Down;
+        match event.code {
+            KeyCode::ControlLeft | KeyCode::ControlRight => self.ctrl = down,
+            KeyCode::AltLeft | KeyCode::AltRight => self.alt = down,
+            KeyCode::ShiftLeft | KeyCode::ShiftRight => self.shift = down,
+            KeyCode::Delete if down && self.ctrl && self.alt => return Some(Shortcut::Reboot),
+            KeyCode::PageUp if down && self.shift => return Some(Shortcut::ScrollUp),
+            KeyCode::PageDown if down && self.shift => return Some(Shortcut::ScrollDown),
+            _ => {}
+        }
+        None
+    }
+}
+
 pub struct ScancodeStream {
     _sealed: (),
 }
@@ -95,3 +168,23 @@ impl Stream for ScancodeStream {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn terminal_encoding() {
+        let mut buf = [0; 4];
+        assert_eq!(encode_key(DecodedKey::Unicode('a'), &mut buf), b"a");
+        assert_eq!(encode_key(DecodedKey::Unicode('\u{8}'), &mut buf), b"\x08");
+        assert_eq!(
+            encode_key(DecodedKey::Unicode('\u{7f}'), &mut buf),
+            b"\x1b[3~"
+        );
+        let up = DecodedKey::RawKey(KeyCode::ArrowUp);
+        assert_eq!(encode_key(up, &mut buf), b"\x1b[A");
+        let f1 = DecodedKey::RawKey(KeyCode::F1);
+        assert_eq!(encode_key(f1, &mut buf), b"");
+    }
+}
@@ -1,22 +1,76 @@
 pub mod executor;
 pub mod keyboard;
+pub mod mutex;
 
-use alloc::boxed::Box;
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, task::Wake, vec::Vec};
 use core::{
+    fmt,
     future::Future,
     pin::Pin,
-    sync::atomic::{AtomicUsize, Ordering},
-    task::{Context, Poll},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    task::{Context, Poll, Waker},
 };
+use spin::{Lazy, Mutex};
+use x86_64::instructions::interrupts;
+
+/// The state of every task that exists, which wakers update from interrupt
+/// handlers too.
+static TASKS: Lazy<Mutex<BTreeMap<TaskId, TaskState>>> = Lazy::new(|| Mutex::new(BTreeMap::new()));
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-struct TaskId(usize);
+pub struct TaskId(usize);
 
 impl TaskId {
     fn new() -> Self {
         static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
         TaskId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
     }
+
+    /// Marks the task as woken up.
+    fn wake(self) {
+        interrupts::without_interrupts(|| {
+            if let Some(state) = TASKS.lock().get_mut(&self) {
+                *state = TaskState::Ready;
+            }
+        });
+    }
+}
+
+impl fmt::Display for TaskId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskState {
+    /// Waiting to be polled.
+    Ready,
+    /// Being polled.
+    Running,
+    /// Waiting to be woken up.
+    Waiting,
+}
+
+impl fmt::Display for TaskState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            TaskState::Ready => "ready",
+            TaskState::Running => "running",
+            TaskState::Waiting => "waiting",
+        })
+    }
+}
+
+/// Returns every task with its state.
+pub fn tasks() -> Vec<(TaskId, TaskState)> {
+    interrupts::without_interrupts(|| {
+        TASKS
+            .lock()
+            .iter()
+            .map(|(&id, &state)| (id, state))
+            .collect()
+    })
 }
 
 pub struct Task {
@@ -26,13 +80,101 @@ pub struct Task {
 
 impl Task {
     pub fn new(f: impl Future<Output = ()> + 'static) -> Self {
+        let id = TaskId::new();
+        interrupts::without_interrupts(|| TASKS.lock().insert(id, TaskState::Ready));
         Self {
-            id: TaskId::new(),
+            id,
             future: Box::pin(f),
         }
     }
 
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
     fn poll(&mut self, context: &mut Context) -> Poll<()> {
-        self.future.as_mut().poll(context)
+        self.set_state(|_| TaskState::Running);
+        let result = self.future.as_mut().poll(context);
+        // A task that woke itself up while running stays ready.
+        self.set_state(|state| match state {
+            TaskState::Running => TaskState::Waiting,
+            state => state,
+        });
+        result
+    }
+
+    fn set_state(&self, update: impl FnOnce(TaskState) -> TaskState) {
+        interrupts::without_interrupts(|| {
+            if let Some(state) = TASKS.lock().get_mut(&self.id) {
+                *state = update(*state);
+            }
+        });
+    }
+}
+
+impl Drop for Task {
+    fn drop(&mut self) {
+        interrupts::without_interrupts(|| TASKS.lock().remove(&self.id));
+    }
+}
+
+/// Runs `future` to completion outside of any executor, halting the CPU until
+/// an interrupt wakes the future whenever it is pending.
+///
+/// Interrupts must be enabled, or the future may never be woken.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    struct Flag(AtomicBool);
+
+    impl Wake for Flag {
+        fn wake(self: Arc<Self>) {
+            self.0.store(true, Ordering::Release);
+        }
+    }
+
+    let flag = Arc::new(Flag(AtomicBool::new(false)));
+    let waker = Waker::from(flag.clone());
+    let mut context = Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+
+        interrupts::disable();
+        if flag.0.swap(false, Ordering::Acquire) {
+            interrupts::enable();
+        } else {
+            interrupts::enable_and_hlt();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::{future, task::noop_waker_ref};
+
+    fn state(id: TaskId) -> Option<TaskState> {
+        tasks()
+            .into_iter()
+            .find(|&(other, _)| other == id)
+            .map(|(_, state)| state)
+    }
+
+    #[test_case]
+    fn task_states() {
+        let mut task = Task::new(future::pending());
+        let id = task.id();
+        assert_eq!(state(id), Some(TaskState::Ready));
+
+        let mut context = Context::from_waker(noop_waker_ref());
+        assert_eq!(task.poll(&mut context), Poll::Pending);
+        assert_eq!(state(id), Some(TaskState::Waiting));
+        id.wake();
+        assert_eq!(state(id), Some(TaskState::Ready));
+
+        drop(task);
+        assert_eq!(state(id), None);
     }
 }
@@ -0,0 +1,126 @@
+//! A mutex for data that is held across `.await` points.
+
+use alloc::vec::Vec;
+use core::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Poll, Waker},
+};
+use futures_util::future::poll_fn;
+
+/// A mutex that makes tasks wait for it instead of spinning, so it can be
+/// held while a task waits on something else.
+pub struct Mutex<T> {
+    locked: AtomicBool,
+    /// Tasks waiting for the mutex to be unlocked.
+    waiters: spin::Mutex<Vec<Waker>>,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for Mutex<T> {}
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            waiters: spin::Mutex::new(Vec::new()),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Waits until the mutex is unlocked and locks it.
+    pub async fn lock(&self) -> MutexGuard<'_, T> {
+        poll_fn(|cx| {
+            if self.try_lock_inner() {
+                return Poll::Ready(());
+            }
+            {
+                let mut waiters = self.waiters.lock();
+                let waker = cx.waker();
+                if !waiters.iter().any(|other| other.will_wake(waker)) {
+                    waiters.push(waker.clone());
+                }
+            }
+            // Retry in case the mutex got unlocked before the waker was added.
+            if self.try_lock_inner() {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await;
+        MutexGuard { mutex: self }
+    }
+
+    /// Locks the mutex if it is unlocked.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self.try_lock_inner().then_some(MutexGuard { mutex: self })
+    }
+
+    fn try_lock_inner(&self) -> bool {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+}
+
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.locked.store(false, Ordering::Release);
+        let waiters = core::mem::take(&mut *self.mutex.waiters.lock());
+        waiters.into_iter().for_each(Waker::wake);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task;
+    use alloc::boxed::Box;
+    use core::task::Context;
+    use futures_util::{task::noop_waker_ref, FutureExt};
+
+    #[test_case]
+    fn exclusive() {
+        let mutex = Mutex::new(1);
+        let mut guard = task::block_on(mutex.lock());
+        assert!(mutex.try_lock().is_none());
+        *guard += 1;
+        drop(guard);
+        assert_eq!(*mutex.try_lock().unwrap(), 2);
+    }
+
+    #[test_case]
+    fn waiter_added_once() {
+        let mutex = Mutex::new(());
+        let guard = mutex.try_lock().unwrap();
+        let mut lock = Box::pin(mutex.lock());
+        let mut cx = Context::from_waker(noop_waker_ref());
+        for _ in 0..3 {
+            assert!(lock.poll_unpin(&mut cx).is_pending());
+        }
+        assert_eq!(mutex.waiters.lock().len(), 1);
+        drop(guard);
+        assert!(lock.poll_unpin(&mut cx).is_ready());
+    }
+}
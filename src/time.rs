@@ -0,0 +1,146 @@
+//! Time since boot, counted in ticks of the programmable interval timer.
+
+use alloc::vec::Vec;
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+use spin::Mutex;
+use x86_64::instructions::{interrupts, port::Port};
+
+/// Number of timer interrupts per second.
+pub const TICK_HZ: u64 = 100;
+
+/// Frequency of the clock driving the timer.
+const PIT_HZ: u64 = 1_193_182;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Tasks waiting in [`sleep`] with the tick to wake them up at.
+static SLEEPERS: Mutex<Vec<(u64, Waker)>> = Mutex::new(Vec::new());
+
+/// Programs the timer to interrupt [`TICK_HZ`] times per second.
+pub fn init() {
+    let divisor = (PIT_HZ / TICK_HZ) as u16;
+    let mut command = Port::<u8>::new(0x43);
+    let mut channel0 = Port::<u8>::new(0x40);
+    unsafe {
+        // Channel 0, low byte then high byte, rate generator.
+        command.write(0x34);
+        channel0.write(divisor as u8);
+        channel0.write((divisor >> 8) as u8);
+    }
+}
+
+/// Called by the timer interrupt handler.
+pub(crate) fn tick() {
+    let now = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    let mut sleepers = SLEEPERS.lock();
+    let mut i = 0;
+    while i < sleepers.len() {
+        if sleepers[i].0 <= now {
+            sleepers.swap_remove(i).1.wake();
+        } else {
+            i += 1;
+        }
+    }
+}
+
+/// Returns the number of timer interrupts since boot.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Returns the time since the timer was started.
+pub fn uptime() -> Duration {
+    let ticks = ticks();
+    Duration::from_secs(ticks / TICK_HZ) + Duration::from_millis(ticks % TICK_HZ * 1000 / TICK_HZ)
+}
+
+/// Waits for at least `duration`.
+pub async fn sleep(duration: Duration) {
+    // One more tick makes up for the part of the current one that is over.
+    let ticks = duration.as_millis() as u64 * TICK_HZ / 1000 + 1;
+    Sleeper {
+        deadline: self::ticks() + ticks,
+        waker: None,
+    }
+    .await
+}
+
+/// A task waiting in [`sleep`], which leaves [`SLEEPERS`] when dropped.
+struct Sleeper {
+    deadline: u64,
+    /// The waker this added to [`SLEEPERS`].
+    waker: Option<Waker>,
+}
+
+impl Sleeper {
+    fn deregister(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            let deadline = self.deadline;
+            interrupts::without_interrupts(|| {
+                let mut sleepers = SLEEPERS.lock();
+                // Missing if the timer already woke it up.
+                let found = sleepers
+                    .iter()
+                    .position(|(at, other)| *at == deadline && other.will_wake(&waker));
+                if let Some(i) = found {
+                    sleepers.swap_remove(i);
+                }
+            });
+        }
+    }
+}
+
+impl Future for Sleeper {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if ticks() >= self.deadline {
+            self.deregister();
+            return Poll::Ready(());
+        }
+        let waker = cx.waker();
+        if !matches!(&self.waker, Some(old) if old.will_wake(waker)) {
+            self.deregister();
+            let deadline = self.deadline;
+            interrupts::without_interrupts(|| {
+                SLEEPERS.lock().push((deadline, waker.clone()));
+            });
+            self.waker = Some(waker.clone());
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleeper {
+    fn drop(&mut self) {
+        self.deregister();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::boxed::Box;
+    use futures_util::{task::noop_waker_ref, FutureExt};
+
+    #[test_case]
+    fn sleeper_added_once_and_removed_on_drop() {
+        let mut sleep = Box::pin(sleep(Duration::from_secs(60)));
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let before = interrupts::without_interrupts(|| SLEEPERS.lock().len());
+        for _ in 0..3 {
+            assert!(sleep.poll_unpin(&mut cx).is_pending());
+        }
+        let after = interrupts::without_interrupts(|| SLEEPERS.lock().len());
+        assert_eq!(after, before + 1);
+        drop(sleep);
+        let after = interrupts::without_interrupts(|| SLEEPERS.lock().len());
+        assert_eq!(after, before);
+    }
+}
@@ -0,0 +1,401 @@
+//! The line discipline: what a terminal does with the bytes typed on it
+//! before a reader gets them, and with the bytes written to it.
+
+use alloc::{collections::VecDeque, vec::Vec};
+use core::ops::BitOr;
+
+use crate::process::Signal;
+
+/// Most bytes of input held, counting the line being edited.
+pub const MAX_INPUT: usize = 4096;
+
+const BACKSPACE: u8 = 0x08;
+
+/// The local modes of a terminal, with the values of the `c_lflag` bits of
+/// Linux.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalFlags(u32);
+
+impl LocalFlags {
+    /// Generate signals for the interrupt, quit and suspend characters.
+    pub const ISIG: LocalFlags = LocalFlags(0o1);
+    /// Canonical mode: input is read a line at a time, and can be edited
+    /// with the erase and kill characters until then.
+    pub const ICANON: LocalFlags = LocalFlags(0o2);
+    /// Echo the input.
+    pub const ECHO: LocalFlags = LocalFlags(0o10);
+    /// With `ICANON`, erase characters from the screen rather than echo the
+    /// erase character.
+    pub const ECHOE: LocalFlags = LocalFlags(0o20);
+    /// With `ICANON`, erase the line from the screen rather than echo the
+    /// kill character.
+    pub const ECHOK: LocalFlags = LocalFlags(0o40);
+    /// Echo control characters as `^X`.
+    pub const ECHOCTL: LocalFlags = LocalFlags(0o1000);
+
+    const ALL: LocalFlags = LocalFlags(0o1073);
+
+    pub const fn empty() -> Self {
+        LocalFlags(0)
+    }
+
+    /// Returns the flags for `bits`, unless there are unknown ones.
+    pub const fn from_bits(bits: u32) -> Option<Self> {
+        match bits & !Self::ALL.0 {
+            0 => Some(LocalFlags(bits)),
+            _ => None,
+        }
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub const fn contains(self, other: LocalFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn difference(self, other: LocalFlags) -> Self {
+        LocalFlags(self.0 & !other.0)
+    }
+}
+
+impl BitOr for LocalFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        LocalFlags(self.0 | rhs.0)
+    }
+}
+
+/// The characters that edit the input or generate signals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ControlChars {
+    pub interrupt: u8,
+    pub quit: u8,
+    pub erase: u8,
+    pub kill: u8,
+    pub eof: u8,
+    pub suspend: u8,
+    pub word_erase: u8,
+}
+
+impl Default for ControlChars {
+    fn default() -> Self {
+        Self {
+            interrupt: 0x03,
+            quit: 0x1c,
+            erase: 0x7f,
+            kill: 0x15,
+            eof: 0x04,
+            suspend: 0x1a,
+            word_erase: 0x17,
+        }
+    }
+}
+
+/// The settings of a terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Termios {
+    pub flags: LocalFlags,
+    pub chars: ControlChars,
+}
+
+impl Termios {
+    /// Returns these settings without line editing, echo and signals, so
+    /// every byte typed is read as it is.
+    pub fn raw(self) -> Self {
+        let cooked = LocalFlags::ICANON | LocalFlags::ECHO | LocalFlags::ISIG;
+        Self {
+            flags: self.flags.difference(cooked),
+            ..self
+        }
+    }
+}
+
+impl Default for Termios {
+    fn default() -> Self {
+        let flags = LocalFlags::ISIG
+            | LocalFlags::ICANON
+            | LocalFlags::ECHO
+            | LocalFlags::ECHOE
+            | LocalFlags::ECHOK
+            | LocalFlags::ECHOCTL;
+        Self {
+            flags,
+            chars: ControlChars::default(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct LineDiscipline {
+    termios: Termios,
+    /// The line being edited in canonical mode.
+    line: Vec<u8>,
+    /// Input ready to be read. In canonical mode these are whole lines, with
+    /// an empty one where end of file was typed.
+    lines: VecDeque<Vec<u8>>,
+    /// Input ready to be read outside canonical mode.
+    raw: VecDeque<u8>,
+}
+
+impl LineDiscipline {
+    pub fn termios(&self) -> Termios {
+        self.termios
+    }
+
+    /// Changes the settings. Leaving canonical mode makes what was typed so
+    /// far readable; entering it turns what wasn't read into a line.
+    pub fn set_termios(&mut self, termios: Termios) {
+        let was_canonical = self.canonical();
+        self.termios = termios;
+        match (was_canonical, self.canonical()) {
+            (true, false) => {
+                for line in self.lines.drain(..) {
+                    self.raw.extend(line);
+                }
+                self.raw.extend(self.line.drain(..));
+            }
+            (false, true) if !self.raw.is_empty() => {
+                self.lines.push_back(self.raw.drain(..).collect());
+            }
+            _ => {}
+        }
+    }
+
+    fn canonical(&self) -> bool {
+        self.termios.flags.contains(LocalFlags::ICANON)
+    }
+
+    fn buffered(&self) -> usize {
+        let lines: usize = self.lines.iter().map(Vec::len).sum();
+        lines + self.line.len() + self.raw.len()
+    }
+
+    /// Processes a byte typed on the terminal, writing what to echo to
+    /// `echo` and returning the signal it generates, if any.
+    ///
+    /// Input that doesn't fit is dropped.
+    pub fn receive(&mut self, byte: u8, echo: &mut Vec<u8>) -> Option<Signal> {
+        let flags = self.termios.flags;
+        let chars = self.termios.chars;
+
+        if flags.contains(LocalFlags::ISIG) {
+            let signal = match byte {
+                _ if byte == chars.interrupt => Some(Signal::Interrupt),
+                _ if byte == chars.quit => Some(Signal::Quit),
+                _ if byte == chars.suspend => Some(Signal::Suspend),
+                _ => None,
+            };
+            if signal.is_some() {
+                self.line.clear();
+                self.lines.clear();
+                self.raw.clear();
+                self.echo(byte, echo);
+                return signal;
+            }
+        }
+
+        if !self.canonical() {
+            if self.buffered() < MAX_INPUT {
+                self.raw.push_back(byte);
+                self.echo(byte, echo);
+            }
+            return None;
+        }
+
+        match byte {
+            _ if byte == chars.erase || byte == BACKSPACE => {
+                // Remove the continuation bytes of a UTF-8 character with it.
+                let start = self.line.iter().rposition(|&b| b & 0xc0 != 0x80);
+                self.erase_to(start.unwrap_or(0), echo);
+            }
+            _ if byte == chars.word_erase => {
+                let spaces = self.line.iter().rev().take_while(|&&b| b == b' ').count();
+                let end = self.line.len() - spaces;
+                let word = self.line[..end].iter().rev().take_while(|&&b| b != b' ');
+                self.erase_to(end - word.count(), echo);
+            }
+            _ if byte == chars.kill => {
+                if flags.contains(LocalFlags::ECHOK) {
+                    self.erase_to(0, echo);
+                } else {
+                    self.line.clear();
+                    self.echo(byte, echo);
+                    self.echo(b'\n', echo);
+                }
+            }
+            _ if byte == chars.eof => {
+                // Whatever was typed can be read without a newline, and a
+                // read returns nothing on an empty line.
+                self.lines.push_back(core::mem::take(&mut self.line));
+            }
+            b'\r' | b'\n' => {
+                // The newline still fits when the line is full.
+                if self.buffered() <= MAX_INPUT {
+                    self.line.push(b'\n');
+                    self.lines.push_back(core::mem::take(&mut self.line));
+                    self.echo(b'\n', echo);
+                }
+            }
+            _ if self.buffered() < MAX_INPUT - 1 => {
+                self.line.push(byte);
+                self.echo(byte, echo);
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// Shortens the line being edited to `len` bytes, a character boundary.
+    fn erase_to(&mut self, len: usize, echo: &mut Vec<u8>) {
+        let flags = self.termios.flags;
+        if !flags.contains(LocalFlags::ECHO) || len >= self.line.len() {
+            self.line.truncate(len);
+            return;
+        }
+        if !flags.contains(LocalFlags::ECHOE) {
+            self.line.truncate(len);
+            self.echo(self.termios.chars.erase, echo);
+            return;
+        }
+
+        // Columns taken by the echo of each character erased.
+        let erased = self.line.drain(len..);
+        let columns: usize = erased
+            .filter(|&b| b & 0xc0 != 0x80)
+            .map(
+                |b| match is_control(b) && flags.contains(LocalFlags::ECHOCTL) {
+                    true => 2,
+                    false => 1,
+                },
+            )
+            .sum();
+        for _ in 0..columns {
+            echo.extend_from_slice(&[BACKSPACE, b' ', BACKSPACE]);
+        }
+    }
+
+    fn echo(&self, byte: u8, echo: &mut Vec<u8>) {
+        let flags = self.termios.flags;
+        if !flags.contains(LocalFlags::ECHO) {
+            return;
+        }
+        if is_control(byte) && flags.contains(LocalFlags::ECHOCTL) {
+            echo.extend_from_slice(&[b'^', byte ^ 0x40]);
+        } else {
+            echo.push(byte);
+        }
+    }
+
+    /// Reads as much of the input as fits into `buf`, returning its length,
+    /// or `None` if there is nothing to read yet.
+    ///
+    /// In canonical mode a read returns at most one line, and `Some(0)` at
+    /// end of file.
+    pub fn read(&mut self, buf: &mut [u8]) -> Option<usize> {
+        if self.canonical() {
+            let line = self.lines.front_mut()?;
+            let len = line.len().min(buf.len());
+            for (dest, byte) in buf.iter_mut().zip(line.drain(..len)) {
+                *dest = byte;
+            }
+            if line.is_empty() {
+                self.lines.pop_front();
+            }
+            Some(len)
+        } else if self.raw.is_empty() {
+            None
+        } else {
+            let len = self.raw.len().min(buf.len());
+            for (dest, byte) in buf.iter_mut().zip(self.raw.drain(..len)) {
+                *dest = byte;
+            }
+            Some(len)
+        }
+    }
+}
+
+/// Returns whether `byte` is echoed as `^X`, which tabs and newlines aren't.
+fn is_control(byte: u8) -> bool {
+    (byte < 0x20 && byte != b'\t' && byte != b'\n') || byte == 0x7f
+}
+
+/// Appends `bytes` to `out` as they are sent to the terminal, where a newline
+/// also returns to the start of the line.
+pub fn process_output(bytes: &[u8], out: &mut Vec<u8>) {
+    for &byte in bytes {
+        if byte == b'\n' {
+            out.push(b'\r');
+        }
+        out.push(byte);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds `input` to `discipline`, returning the last signal and the echo.
+    fn receive(discipline: &mut LineDiscipline, input: &[u8]) -> (Option<Signal>, Vec<u8>) {
+        let mut echo = Vec::new();
+        let mut signal = None;
+        for &byte in input {
+            signal = discipline.receive(byte, &mut echo).or(signal);
+        }
+        (signal, echo)
+    }
+
+    fn read(discipline: &mut LineDiscipline) -> Option<Vec<u8>> {
+        let mut buf = [0; 64];
+        let len = discipline.read(&mut buf)?;
+        Some(Vec::from(&buf[..len]))
+    }
+
+    #[test_case]
+    fn canonical_mode() {
+        let mut discipline = LineDiscipline::default();
+        let (_, echo) = receive(&mut discipline, b"lx\x7fs -l\x01");
+        assert_eq!(echo, b"lx\x08 \x08s -l^A");
+        assert_eq!(read(&mut discipline), None);
+
+        let (_, echo) = receive(&mut discipline, b"\x7f\r");
+        assert_eq!(echo, b"\x08 \x08\x08 \x08\n");
+        assert_eq!(read(&mut discipline), Some(Vec::from(*b"ls -l\n")));
+
+        receive(&mut discipline, b"one two  \x17three\x15four\nfive\x04\x04");
+        assert_eq!(read(&mut discipline), Some(Vec::from(*b"four\n")));
+        assert_eq!(read(&mut discipline), Some(Vec::from(*b"five")));
+        assert_eq!(read(&mut discipline), Some(Vec::new()));
+        assert_eq!(read(&mut discipline), None);
+
+        // Erasing removes whole characters.
+        receive(&mut discipline, "añ\x7f\n".as_bytes());
+        assert_eq!(read(&mut discipline), Some(Vec::from(*b"a\n")));
+    }
+
+    #[test_case]
+    fn signals_and_raw_mode() {
+        let mut discipline = LineDiscipline::default();
+        let (signal, echo) = receive(&mut discipline, b"abc\x03");
+        assert_eq!(signal, Some(Signal::Interrupt));
+        assert_eq!(echo, b"abc^C");
+        assert_eq!(receive(&mut discipline, b"\x1a").0, Some(Signal::Suspend));
+
+        receive(&mut discipline, b"partial");
+        discipline.set_termios(Termios::default().raw());
+        let (signal, echo) = receive(&mut discipline, b"\x03\x04");
+        assert_eq!(signal, None);
+        assert!(echo.is_empty());
+        assert_eq!(read(&mut discipline), Some(Vec::from(*b"partial\x03\x04")));
+        assert_eq!(read(&mut discipline), None);
+    }
+
+    #[test_case]
+    fn output_processing() {
+        let mut out = Vec::new();
+        process_output(b"one\ntwo\n", &mut out);
+        assert_eq!(out, b"one\r\ntwo\r\n");
+    }
+}
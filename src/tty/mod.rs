@@ -0,0 +1,229 @@
+//! Terminals: the VGA console with the keyboard, and the serial ports.
+//!
+//! Input goes through a [`LineDiscipline`] before it is read, and the
+//! interrupt, quit and suspend characters send signals to the foreground
+//! process group of the terminal. Background processes are not kept from
+//! reading, as nothing would stop them.
+
+pub mod discipline;
+
+use alloc::{boxed::Box, format, string::String, vec::Vec};
+use core::{
+    sync::atomic::{AtomicUsize, Ordering},
+    task::{Poll, Waker},
+};
+use futures_util::future::{poll_fn, BoxFuture};
+use spin::{Lazy, Mutex};
+use x86_64::instructions;
+
+pub use self::discipline::{ControlChars, LineDiscipline, LocalFlags, Termios};
+use crate::{
+    process::{self, Pid},
+    serial::{self, ComPort},
+    vga_buffer,
+};
+
+/// Sends what is written to a terminal to its hardware.
+pub trait Driver: Send + Sync {
+    /// Sends `bytes`, waiting while the hardware has no room for them.
+    fn write<'a>(&'a self, bytes: &'a [u8]) -> BoxFuture<'a, ()>;
+
+    /// Returns the number of rows and columns of the screen, if known.
+    fn size(&self) -> Option<(usize, usize)> {
+        None
+    }
+}
+
+pub struct Tty {
+    name: String,
+    driver: Box<dyn Driver>,
+    state: Mutex<State>,
+    /// The process group that gets the signals and is meant to read.
+    foreground: AtomicUsize,
+}
+
+struct State {
+    discipline: LineDiscipline,
+    /// Tasks waiting for input.
+    read_wakers: Vec<Waker>,
+}
+
+static CONSOLE: Lazy<Tty> = Lazy::new(|| Tty::new(String::from("console"), Box::new(Console)));
+static SERIAL: Lazy<Vec<Tty>> = Lazy::new(|| {
+    let ports = serial::ports().iter();
+    ports
+        .map(|port| Tty::new(format!("ttyS{}", port.index()), Box::new(Serial(port))))
+        .collect()
+});
+
+/// Returns the terminal of the VGA console and the keyboard.
+pub fn console() -> &'static Tty {
+    &CONSOLE
+}
+
+/// Returns the terminal of the serial port `ttyS<index>`, if it exists.
+pub fn serial(index: usize) -> Option<&'static Tty> {
+    let mut ports = serial::ports().iter();
+    let position = ports.position(|port| port.index() == index)?;
+    SERIAL.get(position)
+}
+
+/// Returns the terminals of the serial ports.
+pub fn serials() -> &'static [Tty] {
+    &SERIAL
+}
+
+impl Tty {
+    /// Creates a terminal in canonical mode whose foreground process group
+    /// is the one of the kernel.
+    pub fn new(name: String, driver: Box<dyn Driver>) -> Self {
+        Self {
+            name,
+            driver,
+            state: Mutex::new(State {
+                discipline: LineDiscipline::default(),
+                read_wakers: Vec::new(),
+            }),
+            foreground: AtomicUsize::new(process::current().pgid()),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn size(&self) -> Option<(usize, usize)> {
+        self.driver.size()
+    }
+
+    pub fn termios(&self) -> Termios {
+        self.state.lock().discipline.termios()
+    }
+
+    pub fn set_termios(&self, termios: Termios) {
+        let mut state = self.state.lock();
+        state.discipline.set_termios(termios);
+        // Input may have become readable.
+        state.read_wakers.drain(..).for_each(Waker::wake);
+    }
+
+    pub fn foreground(&self) -> Pid {
+        self.foreground.load(Ordering::Relaxed)
+    }
+
+    pub fn set_foreground(&self, pgid: Pid) {
+        self.foreground.store(pgid, Ordering::Relaxed);
+    }
+
+    /// Handles `bytes` typed on the terminal.
+    pub async fn input(&self, bytes: &[u8]) {
+        let mut echo = Vec::new();
+        let mut signals = Vec::new();
+        {
+            let mut state = self.state.lock();
+            for &byte in bytes {
+                signals.extend(state.discipline.receive(byte, &mut echo));
+            }
+            state.read_wakers.drain(..).for_each(Waker::wake);
+        }
+        self.write(&echo).await;
+        for signal in signals {
+            process::signal_group(self.foreground(), signal);
+        }
+    }
+
+    /// Waits for input and reads as much of it as fits into `buf`, returning
+    /// its length, which is zero at end of file.
+    pub async fn read(&self, buf: &mut [u8]) -> usize {
+        if buf.is_empty() {
+            return 0;
+        }
+        poll_fn(|cx| {
+            let mut state = self.state.lock();
+            match state.discipline.read(buf) {
+                Some(len) => Poll::Ready(len),
+                None => {
+                    let waker = cx.waker();
+                    if !state.read_wakers.iter().any(|other| other.will_wake(waker)) {
+                        state.read_wakers.push(waker.clone());
+                    }
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+
+    /// Writes `bytes` to the terminal.
+    pub async fn write(&self, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        let mut out = Vec::with_capacity(bytes.len());
+        discipline::process_output(bytes, &mut out);
+        self.driver.write(&out).await;
+    }
+}
+
+/// The screen, written through the VGA text buffer.
+struct Console;
+
+impl Driver for Console {
+    fn write<'a>(&'a self, bytes: &'a [u8]) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            instructions::interrupts::without_interrupts(|| {
+                vga_buffer::WRITER.lock().write_bytes(bytes);
+            });
+        })
+    }
+
+    fn size(&self) -> Option<(usize, usize)> {
+        Some(vga_buffer::size())
+    }
+}
+
+/// A serial port.
+struct Serial(&'static ComPort);
+
+impl Driver for Serial {
+    fn write<'a>(&'a self, bytes: &'a [u8]) -> BoxFuture<'a, ()> {
+        Box::pin(self.0.write(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{process::Signal, task};
+    use alloc::sync::Arc;
+
+    /// Keeps what is written for the test to check.
+    struct Capture(Arc<Mutex<Vec<u8>>>);
+
+    impl Driver for Capture {
+        fn write<'a>(&'a self, bytes: &'a [u8]) -> BoxFuture<'a, ()> {
+            Box::pin(async move { self.0.lock().extend_from_slice(bytes) })
+        }
+    }
+
+    #[test_case]
+    fn foreground_signals() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let tty = Tty::new(String::from("test"), Box::new(Capture(output.clone())));
+        let group = process::current().fork();
+        group.setpgid(group.pid());
+        tty.set_foreground(group.pid());
+
+        task::block_on(tty.input(b"ls\r"));
+        let mut buf = [0; 8];
+        assert_eq!(task::block_on(tty.read(&mut buf)), 3);
+        assert_eq!(&buf[..3], b"ls\n");
+        assert_eq!(*output.lock(), b"ls\r\n");
+
+        task::block_on(tty.input(b"\x1c"));
+        assert_eq!(group.pending_signals(), [Signal::Quit]);
+        assert!(!process::current().take_signal(Signal::Quit));
+        task::block_on(tty.input(b"\x04"));
+        assert_eq!(task::block_on(tty.read(&mut buf)), 0);
+    }
+}
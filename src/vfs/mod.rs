@@ -0,0 +1,399 @@
+//! The virtual filesystem: one tree of inodes made of mounted filesystems,
+//! and the open files processes access them through.
+
+pub mod mount;
+pub mod path;
+pub mod pipe;
+
+use alloc::{boxed::Box, string::String, sync::Arc, vec::Vec};
+use core::{
+    ops::BitOr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use futures_util::future::BoxFuture;
+
+use crate::block::BlockError;
+
+pub use self::{
+    mount::{mount, mounts, unmount},
+    path::{resolve, resolve_parent},
+    pipe::pipe,
+};
+
+pub type FsResult<T> = Result<T, FsError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    NotFound,
+    NotADirectory,
+    IsADirectory,
+    AlreadyExists,
+    /// The directory to remove is not empty.
+    NotEmpty,
+    /// Too many symlinks were followed, which likely means they loop.
+    TooManyLinks,
+    /// The file descriptor is not open, or not for the access.
+    BadFileDescriptor,
+    ReadOnly,
+    NoSpace,
+    /// A mount point or filesystem is in use.
+    Busy,
+    NotSeekable,
+    InvalidArgument,
+    Unsupported,
+    /// The data of the filesystem on its device is inconsistent.
+    Corrupt,
+    /// The file is non-blocking and the access would have to wait.
+    WouldBlock,
+    /// Written to a pipe nobody has open for reading.
+    BrokenPipe,
+    Io(BlockError),
+}
+
+impl From<BlockError> for FsError {
+    fn from(err: BlockError) -> Self {
+        match err {
+            BlockError::ReadOnly => FsError::ReadOnly,
+            err => FsError::Io(err),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    Regular,
+    Directory,
+    Symlink,
+    CharDevice,
+    BlockDevice,
+    Fifo,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    /// Number of the inode, unique within its filesystem.
+    pub ino: u64,
+    pub file_type: FileType,
+    pub size: u64,
+    /// Permission bits, like `0o644`.
+    pub mode: u16,
+    pub nlink: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub ino: u64,
+    pub file_type: FileType,
+}
+
+/// How a file is opened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpenFlags(u32);
+
+impl OpenFlags {
+    pub const READ: OpenFlags = OpenFlags(1 << 0);
+    pub const WRITE: OpenFlags = OpenFlags(1 << 1);
+    /// Create the file if it doesn't exist.
+    pub const CREATE: OpenFlags = OpenFlags(1 << 2);
+    /// With `CREATE`, fail if the file exists.
+    pub const EXCLUSIVE: OpenFlags = OpenFlags(1 << 3);
+    /// Truncate a regular file opened for writing.
+    pub const TRUNCATE: OpenFlags = OpenFlags(1 << 4);
+    /// Write at the end of the file.
+    pub const APPEND: OpenFlags = OpenFlags(1 << 5);
+    /// Fail if the file is not a directory.
+    pub const DIRECTORY: OpenFlags = OpenFlags(1 << 6);
+    /// Open a symlink itself rather than its target.
+    pub const NO_FOLLOW: OpenFlags = OpenFlags(1 << 7);
+    /// Fail with `WouldBlock` instead of waiting, for pipes and FIFOs.
+    pub const NON_BLOCK: OpenFlags = OpenFlags(1 << 8);
+
+    pub const fn empty() -> Self {
+        OpenFlags(0)
+    }
+
+    pub const fn contains(self, other: OpenFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for OpenFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        OpenFlags(self.0 | rhs.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(u64),
+    End(i64),
+    Current(i64),
+}
+
+impl SeekFrom {
+    /// Returns the offset this position is at in a file of size `end` whose
+    /// offset is `current`.
+    pub fn resolve(self, current: u64, end: u64) -> FsResult<u64> {
+        let (base, delta) = match self {
+            SeekFrom::Start(offset) => (offset, 0),
+            SeekFrom::End(delta) => (end, delta),
+            SeekFrom::Current(delta) => (current, delta),
+        };
+        if delta < 0 {
+            base.checked_sub(delta.unsigned_abs())
+        } else {
+            base.checked_add(delta as u64)
+        }
+        .ok_or(FsError::InvalidArgument)
+    }
+}
+
+fn unsupported<'a, T: 'a>(err: FsError) -> BoxFuture<'a, FsResult<T>> {
+    Box::pin(async move { Err(err) })
+}
+
+/// A file, directory or other object of a filesystem.
+///
+/// Directory entries never include `.` and `..`, which the VFS handles.
+pub trait Inode: Send + Sync {
+    fn metadata(&self) -> Metadata;
+
+    /// Returns the entry called `name` of a directory.
+    fn lookup<'a>(&'a self, _name: &'a str) -> BoxFuture<'a, FsResult<Arc<dyn Inode>>> {
+        unsupported(FsError::NotADirectory)
+    }
+
+    /// Creates an empty regular file, directory or FIFO in a directory.
+    fn create<'a>(
+        &'a self,
+        _name: &'a str,
+        _file_type: FileType,
+        _mode: u16,
+    ) -> BoxFuture<'a, FsResult<Arc<dyn Inode>>> {
+        unsupported(FsError::NotADirectory)
+    }
+
+    /// Creates a symlink pointing to `target` in a directory.
+    fn symlink<'a>(
+        &'a self,
+        _name: &'a str,
+        _target: &'a str,
+    ) -> BoxFuture<'a, FsResult<Arc<dyn Inode>>> {
+        unsupported(FsError::NotADirectory)
+    }
+
+    /// Removes an entry that is not a directory from a directory.
+    fn unlink<'a>(&'a self, _name: &'a str) -> BoxFuture<'a, FsResult<()>> {
+        unsupported(FsError::NotADirectory)
+    }
+
+    /// Removes an empty directory from a directory.
+    fn rmdir<'a>(&'a self, _name: &'a str) -> BoxFuture<'a, FsResult<()>> {
+        unsupported(FsError::NotADirectory)
+    }
+
+    fn readdir(&self) -> BoxFuture<'_, FsResult<Vec<DirEntry>>> {
+        unsupported(FsError::NotADirectory)
+    }
+
+    /// Reads from `offset`, returning how many bytes were read, which is zero
+    /// at the end of the file.
+    fn read_at<'a>(&'a self, _offset: u64, _buf: &'a mut [u8]) -> BoxFuture<'a, FsResult<usize>> {
+        unsupported(FsError::Unsupported)
+    }
+
+    /// Writes at `offset`, extending the file if needed.
+    fn write_at<'a>(&'a self, _offset: u64, _buf: &'a [u8]) -> BoxFuture<'a, FsResult<usize>> {
+        unsupported(FsError::Unsupported)
+    }
+
+    fn truncate(&self, _size: u64) -> BoxFuture<'_, FsResult<()>> {
+        unsupported(FsError::Unsupported)
+    }
+
+    /// Returns the target of a symlink.
+    fn read_link(&self) -> BoxFuture<'_, FsResult<String>> {
+        unsupported(FsError::InvalidArgument)
+    }
+
+    /// Returns the file to access the inode through if it is not its data,
+    /// like for devices.
+    fn open(&self, _flags: OpenFlags) -> Option<FsResult<Arc<dyn File>>> {
+        None
+    }
+}
+
+/// An open file.
+pub trait File: Send + Sync {
+    fn read<'a>(&'a self, buf: &'a mut [u8]) -> BoxFuture<'a, FsResult<usize>>;
+
+    fn write<'a>(&'a self, buf: &'a [u8]) -> BoxFuture<'a, FsResult<usize>>;
+
+    /// Moves the offset of the next read or write, returning it.
+    fn seek(&self, _pos: SeekFrom) -> FsResult<u64> {
+        Err(FsError::NotSeekable)
+    }
+
+    fn readdir(&self) -> BoxFuture<'_, FsResult<Vec<DirEntry>>> {
+        unsupported(FsError::NotADirectory)
+    }
+
+    /// Runs the device-specific request `cmd` with argument `arg`, returning
+    /// its result.
+    fn ioctl(&self, _cmd: u32, _arg: u64) -> BoxFuture<'_, FsResult<u64>> {
+        unsupported(FsError::Unsupported)
+    }
+
+    fn metadata(&self) -> FsResult<Metadata>;
+}
+
+pub trait FileSystem: Send + Sync {
+    /// Returns the type of the filesystem, like `tmpfs`.
+    fn name(&self) -> &str;
+
+    fn root(&self) -> Arc<dyn Inode>;
+
+    /// Writes all modified data to the underlying device.
+    fn sync(&self) -> BoxFuture<'_, FsResult<()>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// An inode opened to access its data, with the offset of the next access.
+pub struct InodeFile {
+    inode: Arc<dyn Inode>,
+    flags: OpenFlags,
+    offset: AtomicU64,
+}
+
+impl InodeFile {
+    pub fn new(inode: Arc<dyn Inode>, flags: OpenFlags) -> Self {
+        Self {
+            inode,
+            flags,
+            offset: AtomicU64::new(0),
+        }
+    }
+
+    pub fn inode(&self) -> &Arc<dyn Inode> {
+        &self.inode
+    }
+
+    fn check_data(&self, access: OpenFlags) -> FsResult<()> {
+        if !self.flags.contains(access) {
+            Err(FsError::BadFileDescriptor)
+        } else if self.inode.metadata().file_type == FileType::Directory {
+            Err(FsError::IsADirectory)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl File for InodeFile {
+    fn read<'a>(&'a self, buf: &'a mut [u8]) -> BoxFuture<'a, FsResult<usize>> {
+        Box::pin(async move {
+            self.check_data(OpenFlags::READ)?;
+            let offset = self.offset.load(Ordering::Relaxed);
+            let read = self.inode.read_at(offset, buf).await?;
+            self.offset.fetch_add(read as u64, Ordering::Relaxed);
+            Ok(read)
+        })
+    }
+
+    fn write<'a>(&'a self, buf: &'a [u8]) -> BoxFuture<'a, FsResult<usize>> {
+        Box::pin(async move {
+            self.check_data(OpenFlags::WRITE)?;
+            if self.flags.contains(OpenFlags::APPEND) {
+                self.offset
+                    .store(self.inode.metadata().size, Ordering::Relaxed);
+            }
+            let offset = self.offset.load(Ordering::Relaxed);
+            let written = self.inode.write_at(offset, buf).await?;
+            self.offset.fetch_add(written as u64, Ordering::Relaxed);
+            Ok(written)
+        })
+    }
+
+    fn seek(&self, pos: SeekFrom) -> FsResult<u64> {
+        let current = self.offset.load(Ordering::Relaxed);
+        let offset = pos.resolve(current, self.inode.metadata().size)?;
+        self.offset.store(offset, Ordering::Relaxed);
+        Ok(offset)
+    }
+
+    fn readdir(&self) -> BoxFuture<'_, FsResult<Vec<DirEntry>>> {
+        self.inode.readdir()
+    }
+
+    fn metadata(&self) -> FsResult<Metadata> {
+        Ok(self.inode.metadata())
+    }
+}
+
+/// Opens the file at `path`, relative to `cwd`.
+///
+/// `mode` is used for the permission bits if the file is created.
+pub async fn open(cwd: &str, path: &str, flags: OpenFlags, mode: u16) -> FsResult<Arc<dyn File>> {
+    let follow = !flags.contains(OpenFlags::NO_FOLLOW);
+    let inode = match resolve(cwd, path, follow).await {
+        Ok(_) if flags.contains(OpenFlags::CREATE | OpenFlags::EXCLUSIVE) => {
+            return Err(FsError::AlreadyExists)
+        }
+        Ok((_, inode)) => inode,
+        Err(FsError::NotFound) if flags.contains(OpenFlags::CREATE) => {
+            let (dir, name) = resolve_parent(cwd, path).await?;
+            dir.create(&name, FileType::Regular, mode).await?
+        }
+        Err(err) => return Err(err),
+    };
+
+    let file_type = inode.metadata().file_type;
+    if flags.contains(OpenFlags::DIRECTORY) && file_type != FileType::Directory {
+        return Err(FsError::NotADirectory);
+    }
+    if flags.contains(OpenFlags::WRITE) && file_type == FileType::Directory {
+        return Err(FsError::IsADirectory);
+    }
+    if file_type == FileType::Fifo {
+        return pipe::open_fifo(inode, flags).await;
+    }
+    if let Some(file) = inode.open(flags) {
+        return file;
+    }
+    if flags.contains(OpenFlags::WRITE | OpenFlags::TRUNCATE) && file_type == FileType::Regular {
+        inode.truncate(0).await?;
+    }
+    Ok(Arc::new(InodeFile::new(inode, flags)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn open_flags() {
+        let flags = OpenFlags::READ | OpenFlags::CREATE;
+        assert!(flags.contains(OpenFlags::READ));
+        assert!(!flags.contains(OpenFlags::READ | OpenFlags::WRITE));
+        assert!(flags.contains(OpenFlags::empty()));
+    }
+
+    #[test_case]
+    fn seek_from_resolve() {
+        assert_eq!(SeekFrom::Start(7).resolve(3, 10), Ok(7));
+        assert_eq!(SeekFrom::Current(-3).resolve(3, 10), Ok(0));
+        assert_eq!(SeekFrom::End(5).resolve(3, 10), Ok(15));
+        assert_eq!(
+            SeekFrom::End(-11).resolve(3, 10),
+            Err(FsError::InvalidArgument)
+        );
+        assert_eq!(
+            SeekFrom::Current(1).resolve(u64::MAX, 10),
+            Err(FsError::InvalidArgument)
+        );
+    }
+}
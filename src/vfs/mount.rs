@@ -0,0 +1,136 @@
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::RwLock;
+
+use super::{
+    path::{self, Location},
+    FileSystem, FileType, FsError, FsResult, Inode,
+};
+
+pub type MountId = usize;
+
+static MOUNTS: RwLock<Vec<Mount>> = RwLock::new(Vec::new());
+
+struct Mount {
+    id: MountId,
+    /// Canonical path of the mount point.
+    path: String,
+    fs: Arc<dyn FileSystem>,
+    root: Arc<dyn Inode>,
+    /// The mount and inode number of the directory the filesystem is mounted
+    /// on, or `None` for the root filesystem.
+    covers: Option<(MountId, u64)>,
+}
+
+/// A mounted filesystem, as listed by [`mounts`].
+#[derive(Debug, Clone)]
+pub struct MountInfo {
+    pub path: String,
+    pub fs_name: String,
+}
+
+/// Returns the root directory of the root filesystem.
+pub(super) fn root() -> FsResult<Location> {
+    MOUNTS
+        .read()
+        .iter()
+        .find(|mount| mount.covers.is_none())
+        .map(|mount| Location {
+            mount: mount.id,
+            inode: mount.root.clone(),
+        })
+        .ok_or(FsError::NotFound)
+}
+
+/// Returns where `inode`, found in mount `mount`, leads: the root of the
+/// filesystem mounted on it, if any, or the inode itself.
+pub(super) fn enter(mount: MountId, inode: Arc<dyn Inode>) -> Location {
+    let mut location = Location { mount, inode };
+    if location.inode.metadata().file_type != FileType::Directory {
+        return location;
+    }
+
+    let mounts = MOUNTS.read();
+    // Filesystems can be mounted on top of each other, so keep going.
+    while let Some(mount) = mounts
+        .iter()
+        .find(|mount| mount.covers == Some((location.mount, location.inode.metadata().ino)))
+    {
+        location = Location {
+            mount: mount.id,
+            inode: mount.root.clone(),
+        };
+    }
+    location
+}
+
+/// Mounts `fs` on the directory at `path`. The first filesystem must be
+/// mounted on `/`.
+pub async fn mount(path: &str, fs: Arc<dyn FileSystem>) -> FsResult<()> {
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+    let root = fs.root();
+    let has_root = MOUNTS.read().iter().any(|mount| mount.covers.is_none());
+    let (path, covers) = if has_root {
+        let (path, location) = path::resolve_location("/", path, true).await?;
+        let metadata = location.inode.metadata();
+        if metadata.file_type != FileType::Directory {
+            return Err(FsError::NotADirectory);
+        }
+        (path, Some((location.mount, metadata.ino)))
+    } else if path::components(path).next().is_none() {
+        (String::from("/"), None)
+    } else {
+        return Err(FsError::NotFound);
+    };
+
+    MOUNTS.write().push(Mount {
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        path,
+        fs,
+        root,
+        covers,
+    });
+    Ok(())
+}
+
+/// Unmounts the filesystem mounted at `path` after syncing it.
+///
+/// Files that are still open keep working, but the filesystem is no longer
+/// reachable through paths.
+pub async fn unmount(path: &str) -> FsResult<()> {
+    let (_, location) = path::resolve_location("/", path, true).await?;
+    let fs = {
+        let mounts = MOUNTS.read();
+        let mount = mounts
+            .iter()
+            .find(|mount| mount.id == location.mount)
+            .ok_or(FsError::NotFound)?;
+        if mount.root.metadata().ino != location.inode.metadata().ino {
+            return Err(FsError::InvalidArgument);
+        }
+        let has_children = mounts
+            .iter()
+            .any(|other| matches!(other.covers, Some((id, _)) if id == mount.id));
+        if mount.covers.is_none() || has_children {
+            return Err(FsError::Busy);
+        }
+        mount.fs.clone()
+    };
+
+    fs.sync().await?;
+    MOUNTS.write().retain(|mount| mount.id != location.mount);
+    Ok(())
+}
+
+/// Returns the mounted filesystems, in the order they were mounted.
+pub fn mounts() -> Vec<MountInfo> {
+    MOUNTS
+        .read()
+        .iter()
+        .map(|mount| MountInfo {
+            path: mount.path.clone(),
+            fs_name: String::from(mount.fs.name()),
+        })
+        .collect()
+}
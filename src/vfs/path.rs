@@ -0,0 +1,169 @@
+use alloc::{collections::VecDeque, format, string::String, sync::Arc, vec, vec::Vec};
+
+use super::{mount, FileType, FsError, FsResult, Inode};
+
+/// Symlinks followed in one resolution before giving up.
+const MAX_SYMLINKS: usize = 40;
+
+/// An inode together with the mount it was reached through.
+#[derive(Clone)]
+pub(super) struct Location {
+    pub mount: mount::MountId,
+    pub inode: Arc<dyn Inode>,
+}
+
+/// Splits `path` into its components, skipping empty ones.
+pub fn components(path: &str) -> impl DoubleEndedIterator<Item = &str> {
+    path.split('/').filter(|component| !component.is_empty())
+}
+
+/// Returns `path` as an absolute path, without resolving anything.
+pub fn absolute(cwd: &str, path: &str) -> String {
+    if path.starts_with('/') {
+        String::from(path)
+    } else {
+        format!("{}/{}", cwd.trim_end_matches('/'), path)
+    }
+}
+
+/// The directories from the root to the current position of a resolution,
+/// with the names they were reached by.
+struct Walk {
+    stack: Vec<(String, Location)>,
+    symlinks: usize,
+}
+
+impl Walk {
+    fn new() -> FsResult<Self> {
+        Ok(Self {
+            stack: vec![(String::new(), mount::root()?)],
+            symlinks: 0,
+        })
+    }
+
+    fn current(&self) -> &Location {
+        &self.stack.last().expect("walk left the root").1
+    }
+
+    /// Returns the canonical path of the current position.
+    fn path(&self) -> String {
+        if self.stack.len() == 1 {
+            return String::from("/");
+        }
+        self.stack
+            .iter()
+            .skip(1)
+            .map(|(name, _)| format!("/{}", name))
+            .collect()
+    }
+
+    /// Follows `path`, resolving a final symlink only if `follow`.
+    async fn walk(&mut self, path: &str, follow: bool) -> FsResult<()> {
+        let mut pending: VecDeque<String> = components(path).map(String::from).collect();
+
+        while let Some(name) = pending.pop_front() {
+            match name.as_str() {
+                "." => continue,
+                ".." => {
+                    // The parent of the root is the root itself.
+                    if self.stack.len() > 1 {
+                        self.stack.pop();
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+
+            let dir = self.current();
+            if dir.inode.metadata().file_type != FileType::Directory {
+                return Err(FsError::NotADirectory);
+            }
+            let inode = dir.inode.lookup(&name).await?;
+            let location = mount::enter(dir.mount, inode);
+
+            let is_symlink = location.inode.metadata().file_type == FileType::Symlink;
+            if is_symlink && (follow || !pending.is_empty()) {
+                self.symlinks += 1;
+                if self.symlinks > MAX_SYMLINKS {
+                    return Err(FsError::TooManyLinks);
+                }
+                let target = location.inode.read_link().await?;
+                if target.starts_with('/') {
+                    self.stack.truncate(1);
+                }
+                for component in components(&target).rev() {
+                    pending.push_front(String::from(component));
+                }
+                continue;
+            }
+            self.stack.push((name, location));
+        }
+        Ok(())
+    }
+}
+
+/// Resolves `path` relative to the directory `cwd`, which must be absolute.
+///
+/// A final symlink is only followed if `follow`. Returns the canonical path
+/// along with the inode.
+pub async fn resolve(cwd: &str, path: &str, follow: bool) -> FsResult<(String, Arc<dyn Inode>)> {
+    let (path, location) = resolve_location(cwd, path, follow).await?;
+    Ok((path, location.inode))
+}
+
+pub(super) async fn resolve_location(
+    cwd: &str,
+    path: &str,
+    follow: bool,
+) -> FsResult<(String, Location)> {
+    if path.is_empty() {
+        return Err(FsError::NotFound);
+    }
+
+    let mut walk = Walk::new()?;
+    walk.walk(&absolute(cwd, path), follow).await?;
+    let location = walk.current().clone();
+    if path.ends_with('/') && location.inode.metadata().file_type != FileType::Directory {
+        return Err(FsError::NotADirectory);
+    }
+    Ok((walk.path(), location))
+}
+
+/// Resolves the directory containing `path` and returns it along with the
+/// last component of `path`, which must be a name.
+pub async fn resolve_parent(cwd: &str, path: &str) -> FsResult<(Arc<dyn Inode>, String)> {
+    let name = match components(path).next_back() {
+        Some("." | "..") => return Err(FsError::InvalidArgument),
+        Some(name) => String::from(name),
+        None => return Err(FsError::InvalidArgument),
+    };
+
+    let mut walk = Walk::new()?;
+    let parent = absolute(cwd, path);
+    let parent = &parent[..parent.trim_end_matches('/').len() - name.len()];
+    walk.walk(parent, true).await?;
+    let dir = walk.current().inode.clone();
+    if dir.metadata().file_type != FileType::Directory {
+        return Err(FsError::NotADirectory);
+    }
+    Ok((dir, name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn splits_components() {
+        let parts: Vec<_> = components("//usr/./lib//").collect();
+        assert_eq!(parts, ["usr", ".", "lib"]);
+        assert_eq!(components("/").count(), 0);
+    }
+
+    #[test_case]
+    fn absolute_paths() {
+        assert_eq!(absolute("/", "etc"), "/etc");
+        assert_eq!(absolute("/home/user", "../etc"), "/home/user/../etc");
+        assert_eq!(absolute("/home", "/etc"), "/etc");
+    }
+}
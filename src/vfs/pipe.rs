@@ -0,0 +1,357 @@
+//! Pipes: bounded byte streams from a write end to a read end, created with
+//! [`pipe`] or by opening a FIFO.
+//!
+//! Blocked reads, writes and opens wait on wakers, so they work with
+//! [`block_on`](crate::task::block_on) and the executors. There is no thread
+//! scheduler yet to block otherwise.
+
+use alloc::{
+    boxed::Box,
+    collections::VecDeque,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{
+    cmp,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{Context, Poll, Waker},
+};
+use futures_util::future::{poll_fn, BoxFuture};
+use spin::Mutex;
+
+use super::{File, FileType, FsError, FsResult, Inode, Metadata, OpenFlags};
+
+/// Number of bytes a pipe holds before writers wait.
+pub const CAPACITY: usize = 4096;
+/// Writes of at most this many bytes are done at once, never interleaved with
+/// other writes.
+pub const PIPE_BUF: usize = 512;
+
+/// The pipes of the FIFOs open somewhere, by the address of their inode.
+static FIFOS: Mutex<Vec<(usize, Weak<Pipe>)>> = Mutex::new(Vec::new());
+
+struct Pipe {
+    ino: u64,
+    state: Mutex<State>,
+    /// The inode of a FIFO, kept so its address identifies the pipe.
+    _inode: Option<Arc<dyn Inode>>,
+}
+
+#[derive(Default)]
+struct State {
+    buf: VecDeque<u8>,
+    readers: usize,
+    writers: usize,
+    /// Number of times the pipe was opened for reading and writing, which
+    /// FIFOs opened for one wait to change.
+    read_opens: u64,
+    write_opens: u64,
+    /// Tasks waiting for data or a writer.
+    read_wakers: Vec<Waker>,
+    /// Tasks waiting for space or a reader.
+    write_wakers: Vec<Waker>,
+}
+
+fn register(wakers: &mut Vec<Waker>, waker: &Waker) {
+    if !wakers.iter().any(|other| other.will_wake(waker)) {
+        wakers.push(waker.clone());
+    }
+}
+
+fn wake_all(wakers: &mut Vec<Waker>) {
+    wakers.drain(..).for_each(Waker::wake);
+}
+
+impl Pipe {
+    fn new(inode: Option<Arc<dyn Inode>>) -> Arc<Self> {
+        static NEXT_INO: AtomicU64 = AtomicU64::new(1);
+
+        let ino = match &inode {
+            Some(inode) => inode.metadata().ino,
+            None => NEXT_INO.fetch_add(1, Ordering::Relaxed),
+        };
+        Arc::new(Self {
+            ino,
+            state: Mutex::new(State::default()),
+            _inode: inode,
+        })
+    }
+
+    fn poll_read(
+        &self,
+        cx: &mut Context,
+        buf: &mut [u8],
+        non_block: bool,
+    ) -> Poll<FsResult<usize>> {
+        let mut state = self.state.lock();
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        if state.buf.is_empty() {
+            if state.writers == 0 {
+                return Poll::Ready(Ok(0));
+            }
+            if non_block {
+                return Poll::Ready(Err(FsError::WouldBlock));
+            }
+            register(&mut state.read_wakers, cx.waker());
+            return Poll::Pending;
+        }
+
+        let len = cmp::min(buf.len(), state.buf.len());
+        for (dst, src) in buf.iter_mut().zip(state.buf.drain(..len)) {
+            *dst = src;
+        }
+        wake_all(&mut state.write_wakers);
+        Poll::Ready(Ok(len))
+    }
+
+    /// Writes from `buf[*written..]`, adding what was written to `written`.
+    fn poll_write(
+        &self,
+        cx: &mut Context,
+        buf: &[u8],
+        written: &mut usize,
+        non_block: bool,
+    ) -> Poll<FsResult<usize>> {
+        let mut state = self.state.lock();
+        let partial = |written: usize, err| if written > 0 { Ok(written) } else { Err(err) };
+        if state.readers == 0 {
+            return Poll::Ready(partial(*written, FsError::BrokenPipe));
+        }
+
+        let space = CAPACITY - state.buf.len();
+        let remaining = buf.len() - *written;
+        let fits = match buf.len() <= PIPE_BUF {
+            true => space >= remaining,
+            false => space > 0,
+        };
+        if fits {
+            let len = cmp::min(space, remaining);
+            state.buf.extend(&buf[*written..*written + len]);
+            *written += len;
+            wake_all(&mut state.read_wakers);
+        }
+        if *written == buf.len() {
+            return Poll::Ready(Ok(*written));
+        }
+        if non_block {
+            return Poll::Ready(partial(*written, FsError::WouldBlock));
+        }
+        register(&mut state.write_wakers, cx.waker());
+        Poll::Pending
+    }
+}
+
+/// Creates a pipe, returning its read end and its write end.
+///
+/// Only [`OpenFlags::NON_BLOCK`] of `flags` is used, for both ends.
+pub fn pipe(flags: OpenFlags) -> (Arc<dyn File>, Arc<dyn File>) {
+    let pipe = Pipe::new(None);
+    let non_block = flags.contains(OpenFlags::NON_BLOCK);
+    let reader = PipeEnd::new(pipe.clone(), true, false, non_block);
+    let writer = PipeEnd::new(pipe, false, true, non_block);
+    (Arc::new(reader), Arc::new(writer))
+}
+
+/// Opens the FIFO `inode`.
+///
+/// Opening only one end waits until the other is opened too, unless `flags`
+/// has [`OpenFlags::NON_BLOCK`]. Then the write end fails with
+/// [`FsError::WouldBlock`] if there is no reader.
+pub(super) async fn open_fifo(inode: Arc<dyn Inode>, flags: OpenFlags) -> FsResult<Arc<dyn File>> {
+    let pipe = {
+        let mut fifos = FIFOS.lock();
+        fifos.retain(|(_, pipe)| pipe.strong_count() > 0);
+        let key = Arc::as_ptr(&inode) as *const () as usize;
+        let open = fifos
+            .iter()
+            .filter(|&&(other, _)| other == key)
+            .find_map(|(_, pipe)| pipe.upgrade());
+        match open {
+            Some(pipe) => pipe,
+            None => {
+                let pipe = Pipe::new(Some(inode));
+                fifos.push((key, Arc::downgrade(&pipe)));
+                pipe
+            }
+        }
+    };
+
+    let read = flags.contains(OpenFlags::READ);
+    let write = flags.contains(OpenFlags::WRITE);
+    let non_block = flags.contains(OpenFlags::NON_BLOCK);
+    let (read_opens, write_opens) = {
+        let state = pipe.state.lock();
+        (state.read_opens, state.write_opens)
+    };
+    let end = PipeEnd::new(pipe.clone(), read, write, non_block);
+    if read == write {
+        return Ok(Arc::new(end));
+    }
+
+    poll_fn(|cx| {
+        let mut state = pipe.state.lock();
+        if read && (state.writers > 0 || state.write_opens != write_opens) {
+            return Poll::Ready(Ok(()));
+        }
+        if write && (state.readers > 0 || state.read_opens != read_opens) {
+            return Poll::Ready(Ok(()));
+        }
+        if non_block {
+            // Readers don't wait for writers, they just read nothing.
+            return Poll::Ready(if read {
+                Ok(())
+            } else {
+                Err(FsError::WouldBlock)
+            });
+        }
+        match read {
+            true => register(&mut state.read_wakers, cx.waker()),
+            false => register(&mut state.write_wakers, cx.waker()),
+        }
+        Poll::Pending
+    })
+    .await?;
+    Ok(Arc::new(end))
+}
+
+/// An open end of a pipe, or both for a FIFO opened for reading and writing.
+struct PipeEnd {
+    pipe: Arc<Pipe>,
+    read: bool,
+    write: bool,
+    non_block: bool,
+}
+
+impl PipeEnd {
+    fn new(pipe: Arc<Pipe>, read: bool, write: bool, non_block: bool) -> Self {
+        {
+            let mut state = pipe.state.lock();
+            if read {
+                state.readers += 1;
+                state.read_opens += 1;
+                wake_all(&mut state.write_wakers);
+            }
+            if write {
+                state.writers += 1;
+                state.write_opens += 1;
+                wake_all(&mut state.read_wakers);
+            }
+        }
+        Self {
+            pipe,
+            read,
+            write,
+            non_block,
+        }
+    }
+}
+
+impl Drop for PipeEnd {
+    fn drop(&mut self) {
+        let mut state = self.pipe.state.lock();
+        if self.read {
+            state.readers -= 1;
+            if state.readers == 0 {
+                // Writers fail now, and nobody will read what is left.
+                state.buf.clear();
+                wake_all(&mut state.write_wakers);
+            }
+        }
+        if self.write {
+            state.writers -= 1;
+            if state.writers == 0 {
+                wake_all(&mut state.read_wakers);
+            }
+        }
+    }
+}
+
+impl File for PipeEnd {
+    fn read<'a>(&'a self, buf: &'a mut [u8]) -> BoxFuture<'a, FsResult<usize>> {
+        Box::pin(async move {
+            if !self.read {
+                return Err(FsError::BadFileDescriptor);
+            }
+            poll_fn(|cx| self.pipe.poll_read(cx, buf, self.non_block)).await
+        })
+    }
+
+    fn write<'a>(&'a self, buf: &'a [u8]) -> BoxFuture<'a, FsResult<usize>> {
+        Box::pin(async move {
+            if !self.write {
+                return Err(FsError::BadFileDescriptor);
+            }
+            if buf.is_empty() {
+                return Ok(0);
+            }
+            let mut written = 0;
+            poll_fn(|cx| self.pipe.poll_write(cx, buf, &mut written, self.non_block)).await
+        })
+    }
+
+    fn metadata(&self) -> FsResult<Metadata> {
+        Ok(Metadata {
+            ino: self.pipe.ino,
+            file_type: FileType::Fifo,
+            size: self.pipe.state.lock().buf.len() as u64,
+            mode: 0o600,
+            nlink: 1,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task;
+
+    fn non_blocking() -> (Arc<dyn File>, Arc<dyn File>) {
+        pipe(OpenFlags::NON_BLOCK)
+    }
+
+    #[test_case]
+    fn bounded_buffer() {
+        let (reader, writer) = non_blocking();
+        let mut buf = [0; 16];
+        assert_eq!(
+            task::block_on(reader.read(&mut buf)),
+            Err(FsError::WouldBlock)
+        );
+
+        let data = [7; CAPACITY + 100];
+        assert_eq!(task::block_on(writer.write(&data)), Ok(CAPACITY));
+        assert_eq!(writer.metadata().unwrap().size, CAPACITY as u64);
+        assert_eq!(task::block_on(writer.write(b"x")), Err(FsError::WouldBlock));
+        assert_eq!(task::block_on(reader.read(&mut buf)), Ok(16));
+        // Small writes are done whole or not at all.
+        assert_eq!(
+            task::block_on(writer.write(&[1; 17])),
+            Err(FsError::WouldBlock)
+        );
+        assert_eq!(task::block_on(writer.write(&[1; 16])), Ok(16));
+        assert_eq!(
+            task::block_on(reader.write(b"x")),
+            Err(FsError::BadFileDescriptor)
+        );
+    }
+
+    #[test_case]
+    fn closing_ends() {
+        let (reader, writer) = non_blocking();
+        assert_eq!(task::block_on(writer.write(b"last")), Ok(4));
+        drop(writer);
+        let mut buf = [0; 16];
+        assert_eq!(task::block_on(reader.read(&mut buf)), Ok(4));
+        assert_eq!(&buf[..4], b"last");
+        assert_eq!(task::block_on(reader.read(&mut buf)), Ok(0));
+
+        let (reader, writer) = pipe(OpenFlags::empty());
+        drop(reader);
+        assert_eq!(
+            task::block_on(writer.write(b"lost")),
+            Err(FsError::BrokenPipe)
+        );
+    }
+}
@@ -1,16 +1,69 @@
+use alloc::collections::VecDeque;
 use core::fmt::{self, Write};
 use spin::{Lazy, Mutex};
 use volatile::Volatile;
-use x86_64::instructions;
+use x86_64::instructions::{self, port::Port};
+
+use crate::{cp437, kmsg};
 
 const BUFFER_HEGHT: usize = 25;
 const BUFFER_WIDTH: usize = 80;
 
+/// Colours of text before any escape sequence sets others.
+const DEFAULT_FOREGROUND: Color = Color::Cyan;
+const DEFAULT_BACKGROUND: Color = Color::Black;
+/// Most parameters of a control sequence kept, the rest being ignored.
+const MAX_PARAMS: usize = 8;
+const TAB_WIDTH: usize = 8;
+
+/// The index and data ports of the CRT controller, which shows the cursor.
+const CRTC_INDEX: u16 = 0x3d4;
+const CRTC_DATA: u16 = 0x3d5;
+const CURSOR_START: u8 = 0x0a;
+/// The bit of the cursor start register that hides the cursor.
+const CURSOR_DISABLE: u8 = 1 << 5;
+const CURSOR_LOCATION_HIGH: u8 = 0x0e;
+const CURSOR_LOCATION_LOW: u8 = 0x0f;
+
+/// The attribute controller, which takes an index and then the data on the
+/// same port, until reading the input status register resets it to an index.
+const ATTRIBUTE_ADDRESS: u16 = 0x3c0;
+const ATTRIBUTE_DATA_READ: u16 = 0x3c1;
+const INPUT_STATUS: u16 = 0x3da;
+/// The bit of an attribute index that keeps the screen on.
+const ATTRIBUTE_SCREEN_ON: u8 = 1 << 5;
+const ATTRIBUTE_MODE: u8 = 0x10;
+/// The bit of the mode register that makes bit 7 of the attribute of a
+/// character blink it instead of brightening its background.
+const MODE_BLINK: u8 = 1 << 3;
+
 pub static WRITER: Lazy<Mutex<Writer>> = Lazy::new(|| {
+    let buffer = unsafe { &mut *(0xb8000 as *mut Buffer) };
+    // Keep what is on the screen already.
+    let mut screen = [[ScreenChar::default(); BUFFER_WIDTH]; BUFFER_HEGHT];
+    for (line, chars) in screen.iter_mut().zip(&buffer.chars) {
+        for (character, cell) in line.iter_mut().zip(chars) {
+            *character = cell.read();
+        }
+    }
+    set_hardware_cursor(None);
+    disable_blink();
     Mutex::new(Writer {
+        row_position: BUFFER_HEGHT - 1,
         column_position: 0,
-        color_code: ColorCode::new(Color::Cyan, Color::Black),
-        buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
+        color_code: ColorCode::new(DEFAULT_FOREGROUND, DEFAULT_BACKGROUND),
+        attributes: Attributes::default(),
+        saved: None,
+        escape: Escape::None,
+        utf8: None,
+        screen,
+        scrollback: VecDeque::new(),
+        scrollback_limit: 0,
+        view_offset: 0,
+        stale: false,
+        cursor_visible: true,
+        hardware_cursor: None,
+        buffer,
     })
 });
 
@@ -46,6 +99,185 @@ impl ColorCode {
     }
 }
 
+impl Color {
+    /// Returns the colour of an ANSI colour number from 0 to 7, in its
+    /// bright variant if `bright` is set.
+    fn from_ansi(number: u16, bright: bool) -> Color {
+        const COLORS: [[Color; 8]; 2] = [
+            [
+                Color::Black,
+                Color::Red,
+                Color::Green,
+                Color::Brown,
+                Color::Blue,
+                Color::Magenta,
+                Color::Cyan,
+                Color::LightGray,
+            ],
+            [
+                Color::DarkGray,
+                Color::LightRed,
+                Color::LightGreen,
+                Color::Yellow,
+                Color::LightBlue,
+                Color::Pink,
+                Color::LightCyan,
+                Color::White,
+            ],
+        ];
+        COLORS[usize::from(bright)][usize::from(number % 8)]
+    }
+
+    fn bright(self) -> Color {
+        match self {
+            Color::Black => Color::DarkGray,
+            Color::Blue => Color::LightBlue,
+            Color::Green => Color::LightGreen,
+            Color::Cyan => Color::LightCyan,
+            Color::Red => Color::LightRed,
+            Color::Magenta => Color::Pink,
+            Color::Brown => Color::Yellow,
+            Color::LightGray => Color::White,
+            bright => bright,
+        }
+    }
+}
+
+/// The rendition set by SGR escape sequences.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Attributes {
+    foreground: Color,
+    background: Color,
+    bold: bool,
+    reverse: bool,
+}
+
+impl Default for Attributes {
+    fn default() -> Self {
+        Self {
+            foreground: DEFAULT_FOREGROUND,
+            background: DEFAULT_BACKGROUND,
+            bold: false,
+            reverse: false,
+        }
+    }
+}
+
+impl Attributes {
+    fn color_code(self) -> ColorCode {
+        let foreground = match self.bold {
+            true => self.foreground.bright(),
+            false => self.foreground,
+        };
+        match self.reverse {
+            true => ColorCode::new(self.background, foreground),
+            false => ColorCode::new(foreground, self.background),
+        }
+    }
+
+    /// Applies the SGR parameters `params`.
+    fn select(&mut self, params: &[u16]) {
+        // No parameters mean a reset.
+        let params = match params.is_empty() {
+            true => &[0][..],
+            false => params,
+        };
+        for &param in params {
+            match param {
+                0 => *self = Self::default(),
+                1 => self.bold = true,
+                22 => self.bold = false,
+                7 => self.reverse = true,
+                27 => self.reverse = false,
+                30..=37 => self.foreground = Color::from_ansi(param - 30, false),
+                39 => self.foreground = DEFAULT_FOREGROUND,
+                40..=47 => self.background = Color::from_ansi(param - 40, false),
+                49 => self.background = DEFAULT_BACKGROUND,
+                90..=97 => self.foreground = Color::from_ansi(param - 90, true),
+                100..=107 => self.background = Color::from_ansi(param - 100, true),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Progress through an escape sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    None,
+    /// After the escape character.
+    Started,
+    /// In a control sequence, after `ESC [`.
+    Sequence(Params),
+}
+
+/// The numeric parameters of a control sequence so far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Params {
+    values: [u16; MAX_PARAMS],
+    /// Index of the parameter being read.
+    current: usize,
+    /// Whether any parameter was given.
+    given: bool,
+    /// Whether the sequence is a private one, starting with `?` or the like,
+    /// which the writer ignores.
+    private: bool,
+}
+
+impl Params {
+    fn new() -> Self {
+        Self {
+            values: [0; MAX_PARAMS],
+            current: 0,
+            given: false,
+            private: false,
+        }
+    }
+
+    fn as_slice(&self) -> &[u16] {
+        match self.given {
+            true => &self.values[..=self.current],
+            false => &[],
+        }
+    }
+
+    /// Returns parameter `index`, or `default` if it is missing or zero.
+    fn get(&self, index: usize, default: u16) -> u16 {
+        match self.as_slice().get(index) {
+            Some(&value) if value != 0 => value,
+            _ => default,
+        }
+    }
+}
+
+/// A cursor position with the rendition, kept by `ESC 7` or `ESC [ s`.
+#[derive(Debug, Clone, Copy)]
+struct SavedCursor {
+    row: usize,
+    column: usize,
+    attributes: Attributes,
+}
+
+/// The part of a UTF-8 sequence decoded so far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Utf8 {
+    code_point: u32,
+    /// Number of continuation bytes still to come.
+    remaining: u8,
+    /// The lowest code point encoded with as many bytes.
+    min: u32,
+}
+
+impl Utf8 {
+    fn new(bits: u8, remaining: u8, min: u32) -> Self {
+        Self {
+            code_point: u32::from(bits),
+            remaining,
+            min,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(C)]
 struct ScreenChar {
@@ -53,70 +285,466 @@ struct ScreenChar {
     color_code: ColorCode,
 }
 
+impl Default for ScreenChar {
+    fn default() -> Self {
+        Self {
+            ascii_character: b' ',
+            color_code: ColorCode::new(DEFAULT_FOREGROUND, DEFAULT_BACKGROUND),
+        }
+    }
+}
+
+type Line = [ScreenChar; BUFFER_WIDTH];
+
 #[repr(transparent)]
 struct Buffer {
     chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEGHT],
 }
 
+/// Writes UTF-8 text to the screen, interpreting the VT100 and ANSI escape
+/// sequences for colours, cursor movement and erasing.
+///
+/// Lines scrolled off the top are kept in a scrollback, which the view of
+/// the screen can be scrolled back into.
 pub struct Writer {
+    row_position: usize,
     column_position: usize,
     color_code: ColorCode,
+    attributes: Attributes,
+    saved: Option<SavedCursor>,
+    escape: Escape,
+    /// The UTF-8 sequence being decoded.
+    utf8: Option<Utf8>,
+    /// What is on the screen, which `buffer` shows unless scrolled back.
+    screen: [Line; BUFFER_HEGHT],
+    /// Lines scrolled off the top of the screen, the oldest first.
+    scrollback: VecDeque<Line>,
+    scrollback_limit: usize,
+    /// Number of lines the view is scrolled back by.
+    view_offset: usize,
+    /// Whether `buffer` is behind the view.
+    stale: bool,
+    cursor_visible: bool,
+    /// Where the hardware cursor is, or `None` if it is hidden.
+    hardware_cursor: Option<u16>,
     buffer: &'static mut Buffer,
 }
 
 impl Writer {
+    /// Writes a byte of UTF-8 text.
+    ///
+    /// Characters are shown with their glyph in code page 437, and those
+    /// without one or that are not valid UTF-8 with a replacement glyph.
+    /// The hardware cursor stays until the next [`Writer::write_bytes`].
     pub fn write_byte(&mut self, byte: u8) {
-        match byte {
-            b'\n' => self.new_line(),
-            byte => {
-                if self.column_position >= BUFFER_WIDTH {
-                    self.new_line();
+        if byte < 0x80 {
+            if self.utf8.take().is_some() {
+                self.write_char(None);
+            }
+            return self.write_ascii(byte);
+        }
+
+        match (self.utf8.take(), byte) {
+            (Some(mut partial), 0x80..=0xbf) => {
+                partial.code_point = partial.code_point << 6 | u32::from(byte & 0x3f);
+                partial.remaining -= 1;
+                if partial.remaining > 0 {
+                    self.utf8 = Some(partial);
+                } else if partial.code_point < partial.min {
+                    // Overlong encodings are not valid.
+                    self.write_char(None);
+                } else {
+                    self.write_char(char::from_u32(partial.code_point));
+                }
+            }
+            (partial, _) => {
+                if partial.is_some() {
+                    self.write_char(None);
                 }
+                self.utf8 = match byte {
+                    0xc2..=0xdf => Some(Utf8::new(byte & 0x1f, 1, 0x80)),
+                    0xe0..=0xef => Some(Utf8::new(byte & 0x0f, 2, 0x800)),
+                    0xf0..=0xf4 => Some(Utf8::new(byte & 0x07, 3, 0x10000)),
+                    _ => {
+                        self.write_char(None);
+                        None
+                    }
+                };
+            }
+        }
+    }
 
-                let row = BUFFER_HEGHT - 1;
-                let col = self.column_position;
+    /// Writes `bytes` of UTF-8 text and moves the hardware cursor after them.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write_byte(byte);
+        }
+        self.sync();
+    }
 
-                let color_code = self.color_code;
-                self.buffer.chars[row][col].write(ScreenChar {
-                    ascii_character: byte,
+    pub fn write_string(&mut self, s: impl AsRef<str>) {
+        self.write_bytes(s.as_ref().as_bytes());
+    }
+
+    /// Writes `text` in the given colours from `row` and `column` to the end
+    /// of the row at most, without moving the cursor or scrolling, for
+    /// full-screen programs like a status bar.
+    ///
+    /// Escape sequences are not interpreted, and control characters show as
+    /// the replacement glyph.
+    pub fn write_at(
+        &mut self,
+        row: usize,
+        column: usize,
+        text: &str,
+        foreground: Color,
+        background: Color,
+    ) {
+        if row >= BUFFER_HEGHT {
+            return;
+        }
+        let color_code = ColorCode::new(foreground, background);
+        for (col, c) in (column..BUFFER_WIDTH).zip(text.chars()) {
+            let glyph = cp437::encode(c).unwrap_or(cp437::REPLACEMENT);
+            self.put(
+                row,
+                col,
+                ScreenChar {
+                    ascii_character: glyph,
                     color_code,
-                });
-                self.column_position += 1;
+                },
+            );
+        }
+        self.sync();
+    }
+
+    /// Keeps up to `lines` lines scrolled off the top of the screen.
+    ///
+    /// Nothing is kept until this is called, as the heap may not exist yet.
+    /// Room for all the lines is allocated here, so that output never
+    /// allocates, which could deadlock when printing from an interrupt.
+    pub fn set_scrollback(&mut self, lines: usize) {
+        self.reset_view();
+        self.scrollback_limit = lines;
+        let mut scrollback = VecDeque::with_capacity(lines);
+        let excess = self.scrollback.len().saturating_sub(lines);
+        scrollback.extend(self.scrollback.drain(excess..));
+        self.scrollback = scrollback;
+    }
+
+    /// Scrolls the view `lines` back into the scrollback, or towards the
+    /// screen if negative.
+    pub fn scroll_view(&mut self, lines: isize) {
+        let offset = match usize::try_from(lines) {
+            Ok(lines) => self.view_offset.saturating_add(lines),
+            Err(_) => self.view_offset.saturating_sub(lines.unsigned_abs()),
+        };
+        self.set_view(offset.min(self.scrollback.len()));
+    }
+
+    /// Scrolls the view back to the screen.
+    pub fn reset_view(&mut self) {
+        self.set_view(0);
+    }
+
+    fn set_view(&mut self, offset: usize) {
+        if offset != self.view_offset {
+            self.view_offset = offset;
+            self.stale = true;
+        }
+        self.sync();
+    }
+
+    /// Writes an ASCII character, which may be part of an escape sequence.
+    fn write_ascii(&mut self, byte: u8) {
+        match self.escape {
+            Escape::Started => return self.escape_byte(byte),
+            Escape::Sequence(params) if byte >= 0x20 => return self.sequence_byte(params, byte),
+            // Control characters end a sequence and are handled below.
+            Escape::Sequence(_) => self.escape = Escape::None,
+            Escape::None => {}
+        }
+
+        match byte {
+            b'\n' => self.new_line(),
+            b'\r' => self.column_position = 0,
+            // Backspace only moves back, like on a terminal.
+            0x08 => {
+                self.column_position = self.column_position.min(BUFFER_WIDTH - 1).saturating_sub(1)
             }
+            b'\t' => {
+                let next = (self.column_position / TAB_WIDTH + 1) * TAB_WIDTH;
+                self.column_position = next.min(BUFFER_WIDTH - 1);
+            }
+            0x1b => self.escape = Escape::Started,
+            // Other control characters do nothing.
+            0x00..=0x1f | 0x7f => {}
+            byte => self.write_glyph(byte),
         }
     }
 
-    pub fn write_string(&mut self, s: impl AsRef<str>) {
-        for byte in s.as_ref().bytes() {
-            match byte {
-                // ASCII or newline
-                0x20..=0x7e | b'\n' => self.write_byte(byte),
-                // Not ASCII
-                _ => self.write_byte(0xfe),
+    /// Writes the glyph of a decoded character, or the replacement glyph for
+    /// `None`.
+    fn write_char(&mut self, c: Option<char>) {
+        // Escape sequences are made of ASCII characters only.
+        self.escape = Escape::None;
+        let glyph = c.and_then(cp437::encode);
+        self.write_glyph(glyph.unwrap_or(cp437::REPLACEMENT));
+    }
+
+    fn write_glyph(&mut self, glyph: u8) {
+        if self.column_position >= BUFFER_WIDTH {
+            self.new_line();
+        }
+
+        let row = self.row_position;
+        let col = self.column_position;
+
+        let color_code = self.color_code;
+        self.put(
+            row,
+            col,
+            ScreenChar {
+                ascii_character: glyph,
+                color_code,
+            },
+        );
+        self.column_position += 1;
+    }
+
+    /// Handles the byte after an escape character.
+    fn escape_byte(&mut self, byte: u8) {
+        self.escape = Escape::None;
+        match byte {
+            b'[' => self.escape = Escape::Sequence(Params::new()),
+            b'7' => self.save_cursor(),
+            b'8' => self.restore_cursor(),
+            // Reset to the initial state.
+            b'c' => {
+                self.set_attributes(Attributes::default());
+                self.saved = None;
+                self.cursor_visible = true;
+                self.erase_display(2);
+                self.move_to(0, 0);
             }
+            _ => {}
         }
     }
 
-    fn new_line(&mut self) {
-        // TODO: Check if simple shift left would suffice
-        // self.buffer.chars.rotate_left(1);
-        for row in 1..BUFFER_HEGHT {
-            for col in 0..BUFFER_WIDTH {
-                let character = self.buffer.chars[row][col].read();
-                self.buffer.chars[row - 1][col].write(character);
+    /// Handles a byte of a control sequence with the parameters so far.
+    fn sequence_byte(&mut self, mut params: Params, byte: u8) {
+        match byte {
+            b'0'..=b'9' => {
+                let value = &mut params.values[params.current];
+                *value = value
+                    .saturating_mul(10)
+                    .saturating_add(u16::from(byte - b'0'));
+                params.given = true;
+            }
+            b';' => {
+                params.current = (params.current + 1).min(MAX_PARAMS - 1);
+                params.given = true;
             }
+            b'<' | b'=' | b'>' | b'?' => params.private = true,
+            // Intermediate bytes and subparameters, which no sequence
+            // handled uses.
+            0x20..=0x2f | b':' => {}
+            _ => {
+                self.escape = Escape::None;
+                if params.private {
+                    self.execute_private(&params, byte);
+                } else {
+                    self.execute(&params, byte);
+                }
+                return;
+            }
+        }
+        self.escape = Escape::Sequence(params);
+    }
+
+    /// Runs the control sequence ending with `last`.
+    fn execute(&mut self, params: &Params, last: u8) {
+        let row = self.row_position;
+        let column = self.column_position.min(BUFFER_WIDTH - 1);
+        let count = usize::from(params.get(0, 1));
+        match last {
+            b'A' => self.move_to(row.saturating_sub(count), column),
+            b'B' => self.move_to(row + count, column),
+            b'C' => self.move_to(row, column + count),
+            b'D' => self.move_to(row, column.saturating_sub(count)),
+            b'E' => self.move_to(row + count, 0),
+            b'F' => self.move_to(row.saturating_sub(count), 0),
+            b'G' => self.move_to(row, count - 1),
+            b'H' | b'f' => {
+                let column = usize::from(params.get(1, 1));
+                self.move_to(count - 1, column - 1);
+            }
+            b'J' => self.erase_display(params.get(0, 0)),
+            b'K' => self.erase_line(params.get(0, 0)),
+            b'm' => {
+                let mut attributes = self.attributes;
+                attributes.select(params.as_slice());
+                self.set_attributes(attributes);
+            }
+            b's' => self.save_cursor(),
+            b'u' => self.restore_cursor(),
+            _ => {}
         }
-        self.clear_row(BUFFER_HEGHT - 1);
+    }
+
+    /// Runs the private control sequence ending with `last`, of which only
+    /// those showing and hiding the cursor do something.
+    fn execute_private(&mut self, params: &Params, last: u8) {
+        match (params.get(0, 0), last) {
+            (25, b'h') => self.cursor_visible = true,
+            (25, b'l') => self.cursor_visible = false,
+            _ => {}
+        }
+    }
+
+    /// Moves the cursor, staying on the screen.
+    fn move_to(&mut self, row: usize, column: usize) {
+        self.row_position = row.min(BUFFER_HEGHT - 1);
+        self.column_position = column.min(BUFFER_WIDTH - 1);
+    }
+
+    fn set_attributes(&mut self, attributes: Attributes) {
+        self.attributes = attributes;
+        self.color_code = attributes.color_code();
+    }
+
+    fn save_cursor(&mut self) {
+        self.saved = Some(SavedCursor {
+            row: self.row_position,
+            column: self.column_position,
+            attributes: self.attributes,
+        });
+    }
+
+    fn restore_cursor(&mut self) {
+        let saved = self.saved.unwrap_or(SavedCursor {
+            row: 0,
+            column: 0,
+            attributes: Attributes::default(),
+        });
+        self.move_to(saved.row, saved.column);
+        self.set_attributes(saved.attributes);
+    }
+
+    /// Erases from the cursor to the end of the screen with `mode` 0, from
+    /// the start of the screen to the cursor with 1, all of it with 2, and
+    /// the scrollback instead with 3.
+    fn erase_display(&mut self, mode: u16) {
+        if mode == 3 {
+            self.scrollback.clear();
+            self.view_offset = 0;
+            self.stale = true;
+            return;
+        }
+        let rows = match mode {
+            0 => self.row_position + 1..BUFFER_HEGHT,
+            1 => 0..self.row_position,
+            _ => 0..BUFFER_HEGHT,
+        };
+        for row in rows {
+            self.clear_row(row);
+        }
+        if mode < 2 {
+            self.erase_line(mode);
+        }
+    }
+
+    /// Erases from the cursor to the end of the line with `mode` 0, from
+    /// the start of the line to the cursor with 1, and all of it with 2.
+    fn erase_line(&mut self, mode: u16) {
+        let column = self.column_position.min(BUFFER_WIDTH - 1);
+        let columns = match mode {
+            0 => column..BUFFER_WIDTH,
+            1 => 0..column + 1,
+            _ => 0..BUFFER_WIDTH,
+        };
+        let blank = self.blank();
+        for col in columns {
+            self.put(self.row_position, col, blank);
+        }
+    }
+
+    fn new_line(&mut self) {
         self.column_position = 0;
+        if self.row_position < BUFFER_HEGHT - 1 {
+            self.row_position += 1;
+            return;
+        }
+        let top = self.screen[0];
+        self.screen.copy_within(1.., 0);
+        self.screen[BUFFER_HEGHT - 1] = [self.blank(); BUFFER_WIDTH];
+        if self.scrollback_limit > 0 {
+            if self.scrollback.len() == self.scrollback_limit {
+                self.scrollback.pop_front();
+            }
+            self.scrollback.push_back(top);
+            // Keep showing the same lines when scrolled back.
+            if self.view_offset > 0 {
+                self.view_offset = (self.view_offset + 1).min(self.scrollback.len());
+            }
+        }
+        self.stale = true;
+        if self.view_offset == 0 {
+            self.render();
+        }
     }
 
-    fn clear_row(&mut self, row: usize) {
-        let blank = ScreenChar {
+    fn blank(&self) -> ScreenChar {
+        ScreenChar {
             ascii_character: b' ',
             color_code: self.color_code,
-        };
+        }
+    }
+
+    fn clear_row(&mut self, row: usize) {
+        let blank = self.blank();
         for col in 0..BUFFER_WIDTH {
-            self.buffer.chars[row][col].write(blank);
+            self.put(row, col, blank);
+        }
+    }
+
+    /// Puts `character` on the screen, showing it unless scrolled back.
+    fn put(&mut self, row: usize, col: usize, character: ScreenChar) {
+        self.screen[row][col] = character;
+        if self.view_offset == 0 {
+            self.buffer.chars[row][col].write(character);
+        } else {
+            self.stale = true;
+        }
+    }
+
+    /// Shows the lines in view.
+    fn render(&mut self) {
+        let first = self.scrollback.len() - self.view_offset;
+        for (row, chars) in self.buffer.chars.iter_mut().enumerate() {
+            let index = first + row;
+            let line = match index.checked_sub(self.scrollback.len()) {
+                Some(row) => &self.screen[row],
+                None => &self.scrollback[index],
+            };
+            for (cell, &character) in chars.iter_mut().zip(line) {
+                cell.write(character);
+            }
+        }
+        self.stale = false;
+    }
+
+    /// Brings the view and the hardware cursor up to date.
+    fn sync(&mut self) {
+        if self.stale {
+            self.render();
+        }
+        let shown = self.view_offset == 0 && self.cursor_visible;
+        let column = self.column_position.min(BUFFER_WIDTH - 1);
+        let cursor = shown.then(|| (self.row_position * BUFFER_WIDTH + column) as u16);
+        if cursor != self.hardware_cursor {
+            set_hardware_cursor(cursor);
+            self.hardware_cursor = cursor;
         }
     }
 }
@@ -128,6 +756,63 @@ impl fmt::Write for Writer {
     }
 }
 
+/// Returns the number of rows and columns of the screen.
+pub fn size() -> (usize, usize) {
+    (BUFFER_HEGHT, BUFFER_WIDTH)
+}
+
+/// Scrolls the view of the screen `lines` back into the scrollback, or
+/// towards the screen if negative.
+pub fn scroll_view(lines: isize) {
+    instructions::interrupts::without_interrupts(|| WRITER.lock().scroll_view(lines));
+}
+
+/// Keeps up to `lines` lines scrolled off the top of the screen.
+pub fn set_scrollback(lines: usize) {
+    instructions::interrupts::without_interrupts(|| WRITER.lock().set_scrollback(lines));
+}
+
+/// Scrolls the view back to the screen.
+pub fn reset_view() {
+    instructions::interrupts::without_interrupts(|| WRITER.lock().reset_view());
+}
+
+/// Moves the hardware cursor to `position`, counted in characters from the
+/// top left corner, or hides it.
+fn set_hardware_cursor(position: Option<u16>) {
+    let mut index = Port::<u8>::new(CRTC_INDEX);
+    let mut data = Port::<u8>::new(CRTC_DATA);
+    unsafe {
+        index.write(CURSOR_START);
+        let start = data.read();
+        match position {
+            Some(position) => {
+                data.write(start & !CURSOR_DISABLE);
+                index.write(CURSOR_LOCATION_HIGH);
+                data.write((position >> 8) as u8);
+                index.write(CURSOR_LOCATION_LOW);
+                data.write(position as u8);
+            }
+            None => data.write(start | CURSOR_DISABLE),
+        }
+    }
+}
+
+/// Lets bit 7 of the attributes select the bright backgrounds, which SGR
+/// parameters 100 to 107 and reversed bold text use.
+fn disable_blink() {
+    let mut status = Port::<u8>::new(INPUT_STATUS);
+    let mut address = Port::<u8>::new(ATTRIBUTE_ADDRESS);
+    let mut data = Port::<u8>::new(ATTRIBUTE_DATA_READ);
+    unsafe {
+        status.read();
+        address.write(ATTRIBUTE_SCREEN_ON | ATTRIBUTE_MODE);
+        let mode = data.read();
+        // The data is written to the address port.
+        address.write(mode & !MODE_BLINK);
+    }
+}
+
 #[macro_export]
 macro_rules! print {
     ($($arg:tt)*) => ($crate::vga_buffer::_print(format_args!($($arg)*)));
@@ -143,12 +828,14 @@ macro_rules! println {
 pub fn _print(args: fmt::Arguments) {
     instructions::interrupts::without_interrupts(|| {
         WRITER.lock().write_fmt(args).unwrap();
+        kmsg::Writer.write_fmt(args).unwrap();
     });
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use alloc::vec::Vec;
 
     #[test_case]
     fn println_simple() {
@@ -175,4 +862,122 @@ mod tests {
             }
         });
     }
+
+    #[test_case]
+    fn cursor_movement() {
+        instructions::interrupts::without_interrupts(|| {
+            let mut writer = WRITER.lock();
+            write!(writer, "\nabc\x08\x08x\ry").expect("write failed");
+            for (i, c) in "yxc".chars().enumerate() {
+                let screen_char = writer.buffer.chars[BUFFER_HEGHT - 1][i].read();
+                assert_eq!(char::from(screen_char.ascii_character), c);
+            }
+        });
+    }
+
+    #[test_case]
+    fn escape_sequences() {
+        instructions::interrupts::without_interrupts(|| {
+            let mut writer = WRITER.lock();
+            let at = |writer: &Writer, row: usize, col: usize| writer.buffer.chars[row][col].read();
+            let default = ColorCode::new(DEFAULT_FOREGROUND, DEFAULT_BACKGROUND);
+
+            write!(
+                writer,
+                "\x1b[2J\x1b[Hplain \x1b[1;31mred\x1b[0m\tx\x1b[44;7mR\x1b[m"
+            )
+            .unwrap();
+            assert_eq!(at(&writer, 0, 0).ascii_character, b'p');
+            let red = at(&writer, 0, 6);
+            assert_eq!(red.ascii_character, b'r');
+            assert_eq!(
+                red.color_code,
+                ColorCode::new(Color::LightRed, Color::Black)
+            );
+            assert_eq!(at(&writer, 0, 9).color_code, default);
+            assert_eq!(at(&writer, 0, 16).ascii_character, b'x');
+            let reverse = at(&writer, 0, 17).color_code;
+            assert_eq!(reverse, ColorCode::new(Color::Blue, DEFAULT_FOREGROUND));
+
+            // Positioning, saving the cursor and erasing.
+            write!(writer, "\x1b[5;10Hab\x1b7\x1b[2;1Hzz\x1b8c\x1b[2D\x1b[K").unwrap();
+            assert_eq!(at(&writer, 1, 0).ascii_character, b'z');
+            assert_eq!(at(&writer, 4, 9).ascii_character, b'a');
+            assert_eq!(at(&writer, 4, 10).ascii_character, b' ');
+            assert_eq!(at(&writer, 4, 11).ascii_character, b' ');
+            write!(writer, "\x1b[99A\x1b[99C").unwrap();
+            assert_eq!((writer.row_position, writer.column_position), (0, 79));
+
+            write!(writer, "\x1b[2J\x1b[25;1H").unwrap();
+            assert_eq!(at(&writer, 0, 0).ascii_character, b' ');
+            assert_eq!(writer.row_position, BUFFER_HEGHT - 1);
+        });
+    }
+
+    #[test_case]
+    fn unicode() {
+        instructions::interrupts::without_interrupts(|| {
+            let mut writer = WRITER.lock();
+            write!(writer, "\n╔═╗ ñ € Ω").unwrap();
+            // A character split across writes, and bytes that are not UTF-8.
+            for &byte in "é".as_bytes().iter().chain(b"\xff\xc3!\xe0\x80\x80") {
+                writer.write_byte(byte);
+            }
+            let row = writer.row_position;
+            let glyphs: Vec<u8> = (0..14)
+                .map(|col| writer.buffer.chars[row][col].read().ascii_character)
+                .collect();
+            assert_eq!(
+                glyphs,
+                [
+                    0xc9, 0xcd, 0xbb, b' ', 0xa4, b' ', 0xfe, b' ', 0xea, 0x82, 0xfe, 0xfe, b'!',
+                    0xfe
+                ]
+            );
+        });
+    }
+
+    #[test_case]
+    fn scrollback_and_positioned_writes() {
+        instructions::interrupts::without_interrupts(|| {
+            let mut writer = WRITER.lock();
+            let at = |writer: &Writer, row: usize, col: usize| {
+                writer.buffer.chars[row][col].read().ascii_character
+            };
+            writer.set_scrollback(4);
+            let capacity = writer.scrollback.capacity();
+            write!(writer, "\x1b[2J\x1b[25;1Hfirst").unwrap();
+            for _ in 0..BUFFER_HEGHT {
+                writer.write_string("\n");
+            }
+            assert_eq!(writer.scrollback.len(), 4);
+            assert_eq!(writer.scrollback.capacity(), capacity);
+            let cursor = (BUFFER_HEGHT - 1) * BUFFER_WIDTH;
+            assert_eq!(writer.hardware_cursor, Some(cursor as u16));
+
+            // The view stays on the same lines while output goes on.
+            writer.scroll_view(1);
+            assert_eq!(at(&writer, 0, 0), b'f');
+            assert_eq!(writer.hardware_cursor, None);
+            writer.write_string("x\n");
+            assert_eq!(at(&writer, 0, 0), b'f');
+            writer.scroll_view(-1);
+            assert_eq!(at(&writer, BUFFER_HEGHT - 1, 0), b'x');
+            writer.scroll_view(10);
+            assert_eq!(writer.view_offset, 4);
+            writer.reset_view();
+            assert_eq!(at(&writer, BUFFER_HEGHT - 2, 0), b'x');
+
+            writer.write_at(0, BUFFER_WIDTH - 2, "ab€", Color::White, Color::Blue);
+            assert_eq!(at(&writer, 0, BUFFER_WIDTH - 2), b'a');
+            assert_eq!(at(&writer, 0, BUFFER_WIDTH - 1), b'b');
+            assert_eq!(writer.hardware_cursor, Some(cursor as u16));
+
+            write!(writer, "\x1b[?25l\x1b[3J").unwrap();
+            assert_eq!(writer.hardware_cursor, None);
+            assert!(writer.scrollback.is_empty());
+            write!(writer, "\x1b[?25h").unwrap();
+            writer.set_scrollback(0);
+        });
+    }
 }
@@ -0,0 +1,351 @@
+use alloc::{boxed::Box, string::String, sync::Arc, vec::Vec};
+use core::{fmt, hint, ptr, task::Poll, time::Duration};
+use futures_util::{
+    future::{poll_fn, BoxFuture},
+    task::AtomicWaker,
+};
+use spin::{Mutex, Once};
+
+use super::{
+    queue::{Buffer, VirtQueue},
+    Notifier, Transport, VirtioError, STATUS_DRIVER_OK,
+};
+use crate::{
+    apic,
+    block::{self, BlockDevice, BlockError, SECTOR_SIZE},
+    memory::dma::{self, DmaRegion},
+    pci::{msi::MsiError, PciDevice},
+    time,
+};
+
+/// Legacy (transitional) and modern PCI device IDs.
+pub const DEVICE_IDS: [u16; 2] = [0x1001, 0x1042];
+
+const F_RO: u64 = 1 << 5;
+const F_FLUSH: u64 = 1 << 9;
+
+const T_IN: u32 = 0;
+const T_OUT: u32 = 1;
+const T_FLUSH: u32 = 4;
+
+const S_OK: u8 = 0;
+const S_UNSUPP: u8 = 2;
+
+/// Largest queue used on the modern interface.
+const MAX_QUEUE_SIZE: u16 = 128;
+
+/// Space per request for the header (16 bytes) and the status byte, indexed
+/// by the head descriptor of the request.
+const REQUEST_STRIDE: usize = 32;
+const STATUS_OFFSET: usize = 16;
+
+/// How long a request waits between checks for its completion when the
+/// device has no interrupt.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// A virtio block device.
+pub struct VirtioBlk {
+    name: String,
+    transport: Transport,
+    notifier: Notifier,
+    capacity: u64,
+    features: u64,
+    inner: Mutex<Inner>,
+    wakers: Arc<Wakers>,
+    /// Whether completions are signaled with an interrupt. Requests are
+    /// polled otherwise.
+    interrupts: bool,
+}
+
+struct Inner {
+    queue: VirtQueue,
+    requests: DmaRegion,
+    /// Status of completed requests by head descriptor, until collected.
+    completed: Vec<Option<u8>>,
+}
+
+/// Wakers of the requests in flight, by head descriptor, and of requests
+/// waiting for free descriptors.
+struct Wakers {
+    /// Set once the queue size is known.
+    requests: Once<Vec<AtomicWaker>>,
+    descriptors: AtomicWaker,
+}
+
+impl Wakers {
+    fn wake_all(&self) {
+        if let Some(requests) = self.requests.get() {
+            requests.iter().for_each(AtomicWaker::wake);
+        }
+        self.descriptors.wake();
+    }
+
+    fn request(&self, head: u16) -> &AtomicWaker {
+        &self.requests.get().expect("queue not set up")[usize::from(head)]
+    }
+}
+
+impl VirtioBlk {
+    pub fn new(device: &PciDevice, name: String) -> Result<Self, VirtioError> {
+        let wakers = Arc::new(Wakers {
+            requests: Once::new(),
+            descriptors: AtomicWaker::new(),
+        });
+
+        // MSI-X has to be enabled first since it moves the legacy registers.
+        let msix = apic::is_enabled() && {
+            let wakers = wakers.clone();
+            match unsafe { device.enable_msix([move || wakers.wake_all()]) } {
+                Ok(_) => true,
+                Err(MsiError::Map(err)) => return Err(VirtioError::Map(err)),
+                Err(_) => false,
+            }
+        };
+
+        let transport = Transport::new(device, msix)?;
+        transport.reset();
+        let features = transport.negotiate(F_RO | F_FLUSH)?;
+        let (queue, notifier, interrupts) =
+            transport.setup_queue(0, MAX_QUEUE_SIZE, msix.then_some(0))?;
+        let size = usize::from(queue.size());
+        let requests = DmaRegion::alloc(size * REQUEST_STRIDE).ok_or(VirtioError::OutOfMemory)?;
+        wakers
+            .requests
+            .call_once(|| (0..size).map(|_| AtomicWaker::new()).collect());
+        transport.add_status(STATUS_DRIVER_OK);
+
+        Ok(Self {
+            name,
+            capacity: transport.config_u64(0),
+            transport,
+            notifier,
+            features,
+            inner: Mutex::new(Inner {
+                queue,
+                requests,
+                completed: alloc::vec![None; size],
+            }),
+            wakers,
+            interrupts,
+        })
+    }
+
+    /// Submits a request and waits for it to complete.
+    ///
+    /// `data` is the data buffer, with whether the device writes to it.
+    async fn request(
+        &self,
+        kind: u32,
+        sector: u64,
+        data: Option<(&[u8], bool)>,
+    ) -> Result<(), BlockError> {
+        let mut buffers = Vec::new();
+        if let Some((data, device_writable)) = data {
+            dma::for_each_segment(data, |addr, len| {
+                buffers.push(Buffer {
+                    addr,
+                    len: len as u32,
+                    device_writable,
+                })
+            });
+        }
+        let needed = buffers.len() as u16 + 2;
+
+        // Without interrupts, polls that find the device not done yet
+        // return `None` and the request sleeps until the next try.
+        let head = loop {
+            let head = poll_fn(|cx| {
+                let mut inner = self.inner.lock();
+                inner.collect();
+                if inner.queue.size() < needed {
+                    return Poll::Ready(Err(BlockError::InvalidBuffer));
+                }
+                if inner.queue.free_count() < needed {
+                    if !self.interrupts {
+                        return Poll::Ready(Ok(None));
+                    }
+                    self.wakers.descriptors.register(cx.waker());
+                    return Poll::Pending;
+                }
+                Poll::Ready(Ok(Some(inner.submit(kind, sector, &buffers))))
+            })
+            .await?;
+            match head {
+                Some(head) => break head,
+                None => time::sleep(POLL_INTERVAL).await,
+            }
+        };
+        self.notifier.notify();
+
+        let mut in_flight = InFlight { blk: self, head };
+        let status = loop {
+            let status = poll_fn(|cx| {
+                if let Some(status) = in_flight.try_complete() {
+                    return Poll::Ready(Some(status));
+                }
+                if !self.interrupts {
+                    return Poll::Ready(None);
+                }
+                self.wakers.request(head).register(cx.waker());
+                match in_flight.try_complete() {
+                    Some(status) => Poll::Ready(Some(status)),
+                    None => Poll::Pending,
+                }
+            })
+            .await;
+            match status {
+                Some(status) => break status,
+                None => time::sleep(POLL_INTERVAL).await,
+            }
+        };
+
+        match status {
+            S_OK => Ok(()),
+            S_UNSUPP => Err(BlockError::Unsupported),
+            _ => Err(BlockError::Io),
+        }
+    }
+}
+
+impl Inner {
+    /// Writes the request header for the next free chain and adds the chain.
+    fn submit(&mut self, kind: u32, sector: u64, data: &[Buffer]) -> u16 {
+        let head = self.queue.next_head().expect("no free descriptors");
+        let offset = usize::from(head) * REQUEST_STRIDE;
+        unsafe {
+            ptr::write_volatile(self.requests.ptr(offset), kind);
+            ptr::write_volatile(self.requests.ptr(offset + 4), 0u32);
+            ptr::write_volatile(self.requests.ptr(offset + 8), sector);
+            ptr::write_volatile(self.requests.ptr(offset + STATUS_OFFSET), 0xffu8);
+        }
+
+        let header = Buffer {
+            addr: self.requests.phys() + offset,
+            len: 16,
+            device_writable: false,
+        };
+        let status = Buffer {
+            addr: self.requests.phys() + offset + STATUS_OFFSET,
+            len: 1,
+            device_writable: true,
+        };
+        let mut chain = Vec::with_capacity(data.len() + 2);
+        chain.push(header);
+        chain.extend_from_slice(data);
+        chain.push(status);
+
+        let added = self.queue.add(&chain).expect("no free descriptors");
+        debug_assert_eq!(added, head);
+        head
+    }
+
+    /// Records the status of every request the device completed.
+    fn collect(&mut self) {
+        while let Some((head, _)) = self.queue.pop_used() {
+            let offset = usize::from(head) * REQUEST_STRIDE + STATUS_OFFSET;
+            let status = unsafe { ptr::read_volatile(self.requests.ptr::<u8>(offset)) };
+            self.completed[usize::from(head)] = Some(status);
+        }
+    }
+}
+
+/// A submitted request. Dropping it before it completes waits for the device,
+/// since the device may still access the request's buffers.
+struct InFlight<'a> {
+    blk: &'a VirtioBlk,
+    head: u16,
+}
+
+impl InFlight<'_> {
+    fn try_complete(&mut self) -> Option<u8> {
+        let mut inner = self.blk.inner.lock();
+        inner.collect();
+        let status = inner.completed[usize::from(self.head)].take()?;
+        inner.queue.recycle(self.head);
+        self.blk.wakers.descriptors.wake();
+        // Mark as done for the drop check.
+        self.head = u16::MAX;
+        Some(status)
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        while self.head != u16::MAX && self.try_complete().is_none() {
+            hint::spin_loop();
+        }
+    }
+}
+
+impl BlockDevice for VirtioBlk {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.capacity
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.features & F_RO != 0
+    }
+
+    fn read<'a>(&'a self, sector: u64, buf: &'a mut [u8]) -> BoxFuture<'a, Result<(), BlockError>> {
+        Box::pin(async move {
+            block::check_request(self, sector, buf.len())?;
+            if buf.is_empty() {
+                return Ok(());
+            }
+            self.request(T_IN, sector, Some((buf, true))).await
+        })
+    }
+
+    fn write<'a>(&'a self, sector: u64, buf: &'a [u8]) -> BoxFuture<'a, Result<(), BlockError>> {
+        Box::pin(async move {
+            block::check_request(self, sector, buf.len())?;
+            if self.is_read_only() {
+                return Err(BlockError::ReadOnly);
+            }
+            if buf.is_empty() {
+                return Ok(());
+            }
+            self.request(T_OUT, sector, Some((buf, false))).await
+        })
+    }
+
+    fn flush(&self) -> BoxFuture<'_, Result<(), BlockError>> {
+        Box::pin(async move {
+            if self.features & F_FLUSH == 0 {
+                // Without the feature, the device has no volatile write cache.
+                return Ok(());
+            }
+            self.request(T_FLUSH, 0, None).await
+        })
+    }
+}
+
+impl fmt::Display for VirtioBlk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "virtio-blk ({}), {} sectors ({} MiB){}{}",
+            if self.transport.is_modern() {
+                "modern"
+            } else {
+                "legacy"
+            },
+            self.capacity,
+            self.capacity * SECTOR_SIZE as u64 / (1024 * 1024),
+            if self.is_read_only() {
+                ", read-only"
+            } else {
+                ""
+            },
+            if self.interrupts {
+                ", MSI-X"
+            } else {
+                ", polled"
+            }
+        )
+    }
+}
@@ -0,0 +1,373 @@
+//! Virtio devices on the PCI bus, through either the legacy (0.9.5) I/O port
+//! interface or the modern (1.0) interface described by vendor capabilities.
+
+pub mod blk;
+pub mod queue;
+
+use alloc::sync::Arc;
+use core::ptr;
+use x86_64::{
+    instructions::port::Port,
+    structures::paging::{mapper::MapToError, Size4KiB},
+    VirtAddr,
+};
+
+use self::{blk::VirtioBlk, queue::VirtQueue};
+use crate::{
+    block,
+    memory::mmio,
+    pci::{self, Bar, Capability, PciDevice, COMMAND_BUS_MASTER, COMMAND_IO, COMMAND_MEMORY},
+    serial_println,
+};
+
+pub const VENDOR_ID: u16 = 0x1af4;
+
+pub const STATUS_ACKNOWLEDGE: u8 = 1;
+pub const STATUS_DRIVER: u8 = 2;
+pub const STATUS_DRIVER_OK: u8 = 4;
+pub const STATUS_FEATURES_OK: u8 = 8;
+pub const STATUS_FAILED: u8 = 128;
+
+/// Feature bit set by devices that comply with virtio 1.0 or later.
+pub const F_VERSION_1: u64 = 1 << 32;
+
+/// MSI-X vector number meaning "no vector".
+const NO_VECTOR: u16 = 0xffff;
+
+// Legacy I/O port register offsets.
+const LEGACY_DEVICE_FEATURES: u16 = 0x00;
+const LEGACY_DRIVER_FEATURES: u16 = 0x04;
+const LEGACY_QUEUE_PFN: u16 = 0x08;
+const LEGACY_QUEUE_SIZE: u16 = 0x0c;
+const LEGACY_QUEUE_SELECT: u16 = 0x0e;
+const LEGACY_QUEUE_NOTIFY: u16 = 0x10;
+const LEGACY_STATUS: u16 = 0x12;
+const LEGACY_QUEUE_VECTOR: u16 = 0x16;
+
+// Modern common configuration offsets.
+const DEVICE_FEATURE_SELECT: usize = 0x00;
+const DEVICE_FEATURE: usize = 0x04;
+const DRIVER_FEATURE_SELECT: usize = 0x08;
+const DRIVER_FEATURE: usize = 0x0c;
+const DEVICE_STATUS: usize = 0x14;
+const QUEUE_SELECT: usize = 0x16;
+const QUEUE_SIZE: usize = 0x18;
+const QUEUE_MSIX_VECTOR: usize = 0x1a;
+const QUEUE_ENABLE: usize = 0x1c;
+const QUEUE_NOTIFY_OFF: usize = 0x1e;
+const QUEUE_DESC: usize = 0x20;
+const QUEUE_DRIVER: usize = 0x28;
+const QUEUE_DEVICE: usize = 0x30;
+
+// Modern capability types.
+const CAP_COMMON_CFG: u8 = 1;
+const CAP_NOTIFY_CFG: u8 = 2;
+const CAP_DEVICE_CFG: u8 = 4;
+
+#[derive(Debug)]
+pub enum VirtioError {
+    /// Neither interface is usable.
+    NoTransport,
+    /// The device rejected the negotiated features.
+    FeaturesRejected,
+    /// A required feature is missing.
+    MissingFeature(u64),
+    /// The queue doesn't exist.
+    NoQueue(u16),
+    /// Memory for a queue couldn't be allocated.
+    OutOfMemory,
+    Map(MapToError<Size4KiB>),
+}
+
+/// How the registers of a virtio PCI device are accessed.
+#[derive(Debug)]
+pub enum Transport {
+    Legacy {
+        base: u16,
+        /// Whether MSI-X is enabled, which moves the device configuration.
+        msix: bool,
+    },
+    Modern {
+        common: VirtAddr,
+        notify: VirtAddr,
+        notify_multiplier: u32,
+        device: VirtAddr,
+    },
+}
+
+/// Where to write to notify the device of new buffers in a queue.
+#[derive(Debug, Clone, Copy)]
+pub struct Notifier {
+    queue: u16,
+    target: NotifyTarget,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum NotifyTarget {
+    Port(u16),
+    Mmio(VirtAddr),
+}
+
+impl Notifier {
+    pub fn notify(&self) {
+        match self.target {
+            NotifyTarget::Port(port) => unsafe { Port::<u16>::new(port).write(self.queue) },
+            NotifyTarget::Mmio(addr) => unsafe {
+                ptr::write_volatile(addr.as_mut_ptr::<u16>(), self.queue)
+            },
+        }
+    }
+}
+
+impl Transport {
+    /// Picks the modern interface if the device offers it and enables the
+    /// device's BARs and bus mastering.
+    ///
+    /// `msix` tells whether MSI-X has been enabled on the device.
+    pub fn new(device: &PciDevice, msix: bool) -> Result<Self, VirtioError> {
+        if let Some(transport) = Self::modern(device)? {
+            unsafe { device.enable(COMMAND_MEMORY | COMMAND_BUS_MASTER) };
+            return Ok(transport);
+        }
+
+        match device.bars[0] {
+            Some(Bar::Io { port, .. }) => {
+                unsafe { device.enable(COMMAND_IO | COMMAND_BUS_MASTER) };
+                Ok(Transport::Legacy { base: port, msix })
+            }
+            _ => Err(VirtioError::NoTransport),
+        }
+    }
+
+    fn modern(device: &PciDevice) -> Result<Option<Self>, VirtioError> {
+        let mut common = None;
+        let mut notify = None;
+        let mut device_cfg = None;
+        let mut notify_multiplier = 0;
+
+        for cap in &device.capabilities {
+            let offset = match *cap {
+                Capability::Other {
+                    id: Capability::ID_VENDOR,
+                    offset,
+                } => offset,
+                _ => continue,
+            };
+            let addr = device.address;
+            let cfg_type = addr.read_u8(offset + 3);
+            let slot = match cfg_type {
+                CAP_COMMON_CFG => &mut common,
+                CAP_NOTIFY_CFG => {
+                    notify_multiplier = addr.read_u32(offset + 16);
+                    &mut notify
+                }
+                CAP_DEVICE_CFG => &mut device_cfg,
+                _ => continue,
+            };
+            if slot.is_some() {
+                continue;
+            }
+            let bar = match device.bars.get(usize::from(addr.read_u8(offset + 4))) {
+                Some(Some(Bar::Memory { address, .. })) => *address,
+                _ => continue,
+            };
+            let phys = bar + u64::from(addr.read_u32(offset + 8));
+            let len = u64::from(addr.read_u32(offset + 12));
+            *slot = Some(mmio::map(phys, len).map_err(VirtioError::Map)?);
+        }
+
+        Ok(match (common, notify, device_cfg) {
+            (Some(common), Some(notify), Some(device)) => Some(Transport::Modern {
+                common,
+                notify,
+                notify_multiplier,
+                device,
+            }),
+            _ => None,
+        })
+    }
+
+    pub fn is_modern(&self) -> bool {
+        matches!(self, Transport::Modern { .. })
+    }
+
+    pub fn status(&self) -> u8 {
+        match *self {
+            Transport::Legacy { base, .. } => unsafe { Port::new(base + LEGACY_STATUS).read() },
+            Transport::Modern { common, .. } => unsafe { read(common, DEVICE_STATUS) },
+        }
+    }
+
+    pub fn set_status(&self, status: u8) {
+        match *self {
+            Transport::Legacy { base, .. } => unsafe {
+                Port::new(base + LEGACY_STATUS).write(status)
+            },
+            Transport::Modern { common, .. } => unsafe { write(common, DEVICE_STATUS, status) },
+        }
+    }
+
+    pub fn add_status(&self, status: u8) {
+        self.set_status(self.status() | status);
+    }
+
+    /// Resets the device and acknowledges it.
+    pub fn reset(&self) {
+        self.set_status(0);
+        while self.status() != 0 {
+            core::hint::spin_loop();
+        }
+        self.add_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+    }
+
+    /// Accepts the features in `supported` that the device offers, plus
+    /// [`F_VERSION_1`] on the modern interface, and returns them.
+    pub fn negotiate(&self, supported: u64) -> Result<u64, VirtioError> {
+        let features = match *self {
+            Transport::Legacy { base, .. } => {
+                let offered = unsafe { Port::<u32>::new(base + LEGACY_DEVICE_FEATURES).read() };
+                let accepted = u64::from(offered) & supported;
+                unsafe { Port::<u32>::new(base + LEGACY_DRIVER_FEATURES).write(accepted as u32) };
+                return Ok(accepted);
+            }
+            Transport::Modern { common, .. } => unsafe {
+                write::<u32>(common, DEVICE_FEATURE_SELECT, 0);
+                let low: u32 = read(common, DEVICE_FEATURE);
+                write::<u32>(common, DEVICE_FEATURE_SELECT, 1);
+                let high: u32 = read(common, DEVICE_FEATURE);
+                let offered = u64::from(high) << 32 | u64::from(low);
+                if offered & F_VERSION_1 == 0 {
+                    return Err(VirtioError::MissingFeature(F_VERSION_1));
+                }
+
+                let accepted = offered & (supported | F_VERSION_1);
+                write::<u32>(common, DRIVER_FEATURE_SELECT, 0);
+                write(common, DRIVER_FEATURE, accepted as u32);
+                write::<u32>(common, DRIVER_FEATURE_SELECT, 1);
+                write(common, DRIVER_FEATURE, (accepted >> 32) as u32);
+                accepted
+            },
+        };
+
+        self.add_status(STATUS_FEATURES_OK);
+        if self.status() & STATUS_FEATURES_OK == 0 {
+            self.add_status(STATUS_FAILED);
+            return Err(VirtioError::FeaturesRejected);
+        }
+        Ok(features)
+    }
+
+    /// Creates queue `index` and hands it to the device, with interrupts
+    /// signaled through MSI-X table entry `vector` if given.
+    ///
+    /// Returns the queue, its notifier and whether the vector was accepted.
+    pub fn setup_queue(
+        &self,
+        index: u16,
+        max_size: u16,
+        vector: Option<u16>,
+    ) -> Result<(VirtQueue, Notifier, bool), VirtioError> {
+        let vector = vector.unwrap_or(NO_VECTOR);
+        match *self {
+            Transport::Legacy { base, msix } => unsafe {
+                Port::<u16>::new(base + LEGACY_QUEUE_SELECT).write(index);
+                // The legacy interface can't shrink queues.
+                let size = Port::<u16>::new(base + LEGACY_QUEUE_SIZE).read();
+                if size == 0 {
+                    return Err(VirtioError::NoQueue(index));
+                }
+                let queue = VirtQueue::new(size).ok_or(VirtioError::OutOfMemory)?;
+
+                let mut vector_accepted = false;
+                if msix {
+                    let mut queue_vector = Port::<u16>::new(base + LEGACY_QUEUE_VECTOR);
+                    queue_vector.write(vector);
+                    vector_accepted = vector != NO_VECTOR && queue_vector.read() == vector;
+                }
+                let pfn = queue.descriptors_phys().as_u64() >> 12;
+                Port::<u32>::new(base + LEGACY_QUEUE_PFN).write(pfn as u32);
+
+                let notifier = Notifier {
+                    queue: index,
+                    target: NotifyTarget::Port(base + LEGACY_QUEUE_NOTIFY),
+                };
+                Ok((queue, notifier, vector_accepted))
+            },
+            Transport::Modern {
+                common,
+                notify,
+                notify_multiplier,
+                ..
+            } => unsafe {
+                write(common, QUEUE_SELECT, index);
+                let size: u16 = read(common, QUEUE_SIZE);
+                if size == 0 {
+                    return Err(VirtioError::NoQueue(index));
+                }
+                let size = size.min(max_size);
+                let queue = VirtQueue::new(size).ok_or(VirtioError::OutOfMemory)?;
+
+                write(common, QUEUE_SIZE, size);
+                write(common, QUEUE_MSIX_VECTOR, vector);
+                let vector_accepted =
+                    vector != NO_VECTOR && read::<u16>(common, QUEUE_MSIX_VECTOR) == vector;
+                write(common, QUEUE_DESC, queue.descriptors_phys().as_u64());
+                write(common, QUEUE_DRIVER, queue.available_phys().as_u64());
+                write(common, QUEUE_DEVICE, queue.used_phys().as_u64());
+                write::<u16>(common, QUEUE_ENABLE, 1);
+
+                let notify_off: u16 = read(common, QUEUE_NOTIFY_OFF);
+                let notifier = Notifier {
+                    queue: index,
+                    target: NotifyTarget::Mmio(
+                        notify + u64::from(notify_off) * u64::from(notify_multiplier),
+                    ),
+                };
+                Ok((queue, notifier, vector_accepted))
+            },
+        }
+    }
+
+    /// Reads the dword at `offset` in the device specific configuration.
+    pub fn config_u32(&self, offset: u16) -> u32 {
+        match *self {
+            Transport::Legacy { base, msix } => {
+                let config = base + if msix { 0x18 } else { 0x14 };
+                unsafe { Port::new(config + offset).read() }
+            }
+            Transport::Modern { device, .. } => unsafe { read(device, usize::from(offset)) },
+        }
+    }
+
+    /// Reads the qword at `offset` in the device specific configuration.
+    pub fn config_u64(&self, offset: u16) -> u64 {
+        u64::from(self.config_u32(offset + 4)) << 32 | u64::from(self.config_u32(offset))
+    }
+}
+
+unsafe fn read<T>(base: VirtAddr, offset: usize) -> T {
+    ptr::read_volatile((base + offset).as_ptr())
+}
+
+unsafe fn write<T>(base: VirtAddr, offset: usize, value: T) {
+    ptr::write_volatile((base + offset).as_mut_ptr(), value)
+}
+
+/// Probes all virtio devices on the PCI bus that have a driver and registers
+/// them with their subsystem.
+pub fn init() {
+    for device in pci::devices().iter().filter(|d| d.vendor_id == VENDOR_ID) {
+        if blk::DEVICE_IDS.contains(&device.device_id) {
+            let name = block::next_name("vd");
+            match VirtioBlk::new(device, name.clone()) {
+                Ok(blk) => {
+                    serial_println!("{}: {}", name, blk);
+                    block::register(Arc::new(blk));
+                }
+                Err(err) => {
+                    serial_println!("{}: virtio-blk init failed: {:?}", device.address, err)
+                }
+            }
+        }
+    }
+}
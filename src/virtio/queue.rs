@@ -0,0 +1,276 @@
+use alloc::vec::Vec;
+use core::{
+    mem::size_of,
+    ptr,
+    sync::atomic::{fence, Ordering},
+};
+use x86_64::PhysAddr;
+
+use crate::memory::dma::DmaRegion;
+
+const DESC_F_NEXT: u16 = 1;
+const DESC_F_WRITE: u16 = 2;
+
+/// Alignment of the used ring required by the legacy interface.
+const LEGACY_ALIGN: usize = 4096;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct UsedElement {
+    id: u32,
+    len: u32,
+}
+
+/// A buffer handed to the device.
+#[derive(Debug, Clone, Copy)]
+pub struct Buffer {
+    pub addr: PhysAddr,
+    pub len: u32,
+    /// Whether the device writes to the buffer (rather than reading it).
+    pub device_writable: bool,
+}
+
+/// A split virtqueue: descriptor table, available ring and used ring in one
+/// physically contiguous region laid out as the legacy interface requires.
+#[derive(Debug)]
+pub struct VirtQueue {
+    region: DmaRegion,
+    size: u16,
+    available_offset: usize,
+    used_offset: usize,
+    free_head: u16,
+    free_count: u16,
+    available_idx: u16,
+    last_used_idx: u16,
+}
+
+impl VirtQueue {
+    pub fn new(size: u16) -> Option<Self> {
+        let n = usize::from(size);
+        let available_offset = n * size_of::<Descriptor>();
+        let available_end = available_offset + 2 * (3 + n);
+        let used_offset = align_up(available_end, LEGACY_ALIGN);
+        let used_end = used_offset + 2 * 3 + n * size_of::<UsedElement>();
+
+        let queue = Self {
+            region: DmaRegion::alloc(used_end)?,
+            size,
+            available_offset,
+            used_offset,
+            free_head: 0,
+            free_count: size,
+            available_idx: 0,
+            last_used_idx: 0,
+        };
+        for i in 0..size {
+            queue.set_descriptor(
+                i,
+                Descriptor {
+                    addr: 0,
+                    len: 0,
+                    flags: 0,
+                    next: i.wrapping_add(1),
+                },
+            );
+        }
+        Some(queue)
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    pub fn free_count(&self) -> u16 {
+        self.free_count
+    }
+
+    pub fn descriptors_phys(&self) -> PhysAddr {
+        self.region.phys()
+    }
+
+    pub fn available_phys(&self) -> PhysAddr {
+        self.region.phys() + self.available_offset
+    }
+
+    pub fn used_phys(&self) -> PhysAddr {
+        self.region.phys() + self.used_offset
+    }
+
+    /// Returns the descriptor the next chain added will start with.
+    pub fn next_head(&self) -> Option<u16> {
+        (self.free_count > 0).then_some(self.free_head)
+    }
+
+    /// Chains `buffers` and makes them available to the device, returning the
+    /// head of the chain. Returns `None` if not enough descriptors are free.
+    ///
+    /// The device still has to be notified.
+    pub fn add(&mut self, buffers: &[Buffer]) -> Option<u16> {
+        if buffers.is_empty() || buffers.len() > usize::from(self.free_count) {
+            return None;
+        }
+
+        let head = self.free_head;
+        let mut index = head;
+        for (i, buffer) in buffers.iter().enumerate() {
+            let next = self.descriptor(index).next;
+            let mut flags = if buffer.device_writable {
+                DESC_F_WRITE
+            } else {
+                0
+            };
+            if i + 1 < buffers.len() {
+                flags |= DESC_F_NEXT;
+            }
+            self.set_descriptor(
+                index,
+                Descriptor {
+                    addr: buffer.addr.as_u64(),
+                    len: buffer.len,
+                    flags,
+                    next,
+                },
+            );
+            if i + 1 < buffers.len() {
+                index = next;
+            } else {
+                self.free_head = next;
+            }
+        }
+        self.free_count -= buffers.len() as u16;
+
+        let slot = usize::from(self.available_idx % self.size);
+        unsafe {
+            ptr::write_volatile(self.region.ptr(self.available_offset + 4 + 2 * slot), head);
+        }
+        // The ring entry must be visible before the index that publishes it.
+        fence(Ordering::SeqCst);
+        self.available_idx = self.available_idx.wrapping_add(1);
+        unsafe {
+            ptr::write_volatile(
+                self.region.ptr(self.available_offset + 2),
+                self.available_idx,
+            )
+        };
+        fence(Ordering::SeqCst);
+        Some(head)
+    }
+
+    /// Returns the head and written length of the next chain the device is
+    /// done with.
+    ///
+    /// The chain stays allocated until it is passed to [`recycle`](Self::recycle).
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        fence(Ordering::SeqCst);
+        let used_idx: u16 = unsafe { ptr::read_volatile(self.region.ptr(self.used_offset + 2)) };
+        if used_idx == self.last_used_idx {
+            return None;
+        }
+        fence(Ordering::SeqCst);
+
+        let slot = usize::from(self.last_used_idx % self.size);
+        let element: UsedElement = unsafe {
+            ptr::read_volatile(
+                self.region
+                    .ptr(self.used_offset + 4 + slot * size_of::<UsedElement>()),
+            )
+        };
+        self.last_used_idx = self.last_used_idx.wrapping_add(1);
+        Some((element.id as u16, element.len))
+    }
+
+    /// Returns the descriptors of the chain starting at `head` to the free
+    /// list.
+    pub fn recycle(&mut self, head: u16) {
+        let mut index = head;
+        let mut count = 1;
+        loop {
+            let descriptor = self.descriptor(index);
+            if descriptor.flags & DESC_F_NEXT == 0 {
+                break;
+            }
+            index = descriptor.next;
+            count += 1;
+        }
+
+        let mut last = self.descriptor(index);
+        last.next = self.free_head;
+        self.set_descriptor(index, last);
+        self.free_head = head;
+        self.free_count += count;
+    }
+
+    /// Returns the buffers of the chain starting at `head`.
+    pub fn chain(&self, head: u16) -> Vec<Buffer> {
+        let mut buffers = Vec::new();
+        let mut index = head;
+        loop {
+            let descriptor = self.descriptor(index);
+            buffers.push(Buffer {
+                addr: PhysAddr::new(descriptor.addr),
+                len: descriptor.len,
+                device_writable: descriptor.flags & DESC_F_WRITE != 0,
+            });
+            if descriptor.flags & DESC_F_NEXT == 0 {
+                return buffers;
+            }
+            index = descriptor.next;
+        }
+    }
+
+    fn descriptor(&self, index: u16) -> Descriptor {
+        unsafe {
+            ptr::read_volatile(
+                self.region
+                    .ptr(usize::from(index) * size_of::<Descriptor>()),
+            )
+        }
+    }
+
+    fn set_descriptor(&self, index: u16, descriptor: Descriptor) {
+        unsafe {
+            ptr::write_volatile(
+                self.region
+                    .ptr(usize::from(index) * size_of::<Descriptor>()),
+                descriptor,
+            )
+        }
+    }
+}
+
+fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn descriptors_are_recycled() {
+        let mut queue = VirtQueue::new(8).unwrap();
+        let buffer = Buffer {
+            addr: PhysAddr::new(0x1000),
+            len: 16,
+            device_writable: false,
+        };
+
+        let head = queue.add(&[buffer; 3]).unwrap();
+        assert_eq!(queue.free_count(), 5);
+        assert_eq!(queue.chain(head).len(), 3);
+        assert_eq!(queue.add(&[buffer; 6]), None);
+
+        queue.recycle(head);
+        assert_eq!(queue.free_count(), 8);
+        assert!(queue.add(&[buffer; 8]).is_some());
+    }
+}
@@ -0,0 +1,80 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{sync::Arc, vec, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_os::{
+    allocator, ata,
+    block::{self, BlockDevice, SECTOR_SIZE},
+    hlt_loop,
+    memory::{self, vmm, BootInfoFrameAllocator},
+    task,
+};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rust_os::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    vmm::init(mapper, frame_allocator);
+    ata::init();
+
+    test_main();
+    hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+/// The test image, attached as the primary slave behind the boot disk.
+fn disk() -> Arc<dyn BlockDevice> {
+    block::find("hdb").expect("no ATA test disk")
+}
+
+#[test_case]
+fn boot_disk_has_boot_signature() {
+    let disk = block::find("hda").expect("no ATA boot disk");
+    let mut buf = vec![0; SECTOR_SIZE];
+    task::block_on(disk.read(0, &mut buf)).expect("read failed");
+    assert_eq!(buf[510..], [0x55, 0xaa]);
+}
+
+#[test_case]
+fn reads_sectors() {
+    let disk = disk();
+    assert_eq!(disk.sector_count(), 2048);
+
+    let mut buf = vec![0; 8 * SECTOR_SIZE];
+    task::block_on(disk.read(20, &mut buf)).expect("read failed");
+    for (i, sector) in buf.chunks(SECTOR_SIZE).enumerate() {
+        let number = 20 + i as u64;
+        assert_eq!(sector[..8], number.to_le_bytes());
+        assert_eq!(sector[9], (9 ^ number) as u8);
+    }
+}
+
+#[test_case]
+fn writes_sectors() {
+    let disk = disk();
+    let data: Vec<u8> = (0..3 * SECTOR_SIZE).map(|i| (i * 7) as u8).collect();
+    task::block_on(disk.write(200, &data)).expect("write failed");
+    task::block_on(disk.flush()).expect("flush failed");
+
+    let mut buf = vec![0; 3 * SECTOR_SIZE];
+    task::block_on(disk.read(200, &mut buf)).expect("read failed");
+    assert_eq!(buf, data);
+}
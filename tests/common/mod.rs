@@ -1,7 +1,35 @@
+//! Code shared by the integration tests, each of which uses only some of it.
+#![allow(dead_code)]
+
+use bootloader::BootInfo;
 use core::any;
-use rust_os::serial_print;
+use rust_os::{
+    acpi, allocator, apic, fs,
+    memory::{self, regions, vmm, BootInfoFrameAllocator},
+    pci, serial_print, task, virtio,
+};
+use x86_64::VirtAddr;
 
 /// Convinience wrapper for consistent logging between tests with or without the harness.
-pub fn print_test_name<T>(test: T) {
-    serial_print!("{}...\t", any::type_name_of_val(&test));
+pub fn print_test_name<T>(_test: T) {
+    serial_print!("{}...\t", any::type_name::<T>());
+}
+
+/// Sets up memory, the interrupt controllers, the devices and the
+/// filesystems, for the tests that need all of the kernel.
+pub fn boot(boot_info: &'static BootInfo) {
+    rust_os::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    vmm::init(mapper, frame_allocator);
+    regions::init(&boot_info.memory_map);
+    acpi::init().expect("ACPI initialization failed");
+    apic::init().expect("local APIC initialization failed");
+    pci::init();
+    virtio::init();
+    task::block_on(fs::init());
 }
@@ -0,0 +1,202 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{string::String, vec, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_os::{
+    fs::devfs,
+    hlt_loop,
+    process::{self, Fd},
+    serial_println, task, tty,
+    vfs::{FileType, FsError, OpenFlags, SeekFrom},
+};
+
+mod common;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    common::boot(boot_info);
+
+    test_main();
+    hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+async fn open(path: &str, flags: OpenFlags) -> Fd {
+    process::current().open(path, flags, 0).await.unwrap()
+}
+
+#[test_case]
+fn lists_devices() {
+    task::block_on(async {
+        let process = process::current();
+        let fd = open("/dev", OpenFlags::READ | OpenFlags::DIRECTORY).await;
+        let entries = process.readdir(fd).await.unwrap();
+        process.close(fd).unwrap();
+
+        for name in ["console", "ttyS0", "null", "zero", "random", "kmsg"] {
+            let entry = entries.iter().find(|entry| entry.name == name).unwrap();
+            assert_eq!(entry.file_type, FileType::CharDevice);
+        }
+        let vda = entries.iter().find(|entry| entry.name == "vda").unwrap();
+        assert_eq!(vda.file_type, FileType::BlockDevice);
+
+        assert_eq!(
+            process.mkdir("/dev/new", 0o755).await.err(),
+            Some(FsError::ReadOnly)
+        );
+    });
+}
+
+#[test_case]
+fn null_and_zero() {
+    task::block_on(async {
+        let process = process::current();
+        let flags = OpenFlags::READ | OpenFlags::WRITE;
+        let mut buf = [1; 64];
+
+        let null = open("/dev/null", flags).await;
+        assert_eq!(process.read(null, &mut buf).await, Ok(0));
+        assert_eq!(process.write(null, b"discarded").await, Ok(9));
+        assert_eq!(
+            process.seek(null, SeekFrom::Start(0)),
+            Err(FsError::NotSeekable)
+        );
+        assert_eq!(
+            process.ioctl(null, devfs::TIOCGWINSZ, 0).await,
+            Err(FsError::Unsupported)
+        );
+        process.close(null).unwrap();
+
+        let zero = open("/dev/zero", OpenFlags::READ).await;
+        assert_eq!(process.read(zero, &mut buf).await, Ok(64));
+        assert!(buf.iter().all(|&b| b == 0));
+        assert_eq!(
+            process.write(zero, b"x").await,
+            Err(FsError::BadFileDescriptor)
+        );
+        process.close(zero).unwrap();
+    });
+}
+
+#[test_case]
+fn random() {
+    task::block_on(async {
+        let process = process::current();
+        let fd = open("/dev/random", OpenFlags::READ).await;
+        let mut first = [0; 37];
+        let mut second = [0; 37];
+        assert_eq!(process.read(fd, &mut first).await, Ok(37));
+        assert_eq!(process.read(fd, &mut second).await, Ok(37));
+        assert_ne!(first, second);
+        process.close(fd).unwrap();
+    });
+}
+
+#[test_case]
+fn kernel_log() {
+    task::block_on(async {
+        let process = process::current();
+        serial_println!("kmsg marker");
+        let fd = open("/dev/kmsg", OpenFlags::READ | OpenFlags::WRITE).await;
+        assert_eq!(process.write(fd, b"written marker\n").await, Ok(15));
+
+        let mut log = Vec::new();
+        let mut buf = vec![0; 1024];
+        loop {
+            let len = process.read(fd, &mut buf).await.unwrap();
+            if len == 0 {
+                break;
+            }
+            log.extend_from_slice(&buf[..len]);
+        }
+        process.close(fd).unwrap();
+        let log = String::from_utf8_lossy(&log);
+        assert!(log.contains("kmsg marker\n"));
+        assert!(log.ends_with("written marker\n"));
+    });
+}
+
+#[test_case]
+fn terminals() {
+    task::block_on(async {
+        let process = process::current();
+        let console = open("/dev/console", OpenFlags::WRITE).await;
+        assert_eq!(process.write(console, b"console\n").await, Ok(8));
+        let size = process.ioctl(console, devfs::TIOCGWINSZ, 0).await;
+        assert_eq!(size, Ok(25 << 16 | 80));
+        process.close(console).unwrap();
+
+        let serial = open("/dev/ttyS0", OpenFlags::READ | OpenFlags::WRITE).await;
+        assert_eq!(process.write(serial, b"ttyS0 ").await, Ok(6));
+        assert_eq!(
+            process.ioctl(serial, devfs::TIOCGWINSZ, 0).await,
+            Err(FsError::Unsupported)
+        );
+        let pgrp = process.ioctl(serial, devfs::TIOCGPGRP, 0).await;
+        assert_eq!(pgrp, Ok(process.pgid() as u64));
+
+        // Typed input is read a line at a time, until end of file.
+        tty::serial(0).unwrap().input(b"typed\x7fs\rline\x04").await;
+        let mut buf = [0; 16];
+        assert_eq!(process.read(serial, &mut buf).await, Ok(6));
+        assert_eq!(&buf[..6], b"types\n");
+        assert_eq!(process.read(serial, &mut buf).await, Ok(4));
+
+        let flags = process.ioctl(serial, devfs::TCGETS, 0).await.unwrap();
+        let raw = tty::Termios::default().raw().flags.bits();
+        assert_eq!(
+            process.ioctl(serial, devfs::TCSETS, raw.into()).await,
+            Ok(0)
+        );
+        tty::serial(0).unwrap().input(b"\x03").await;
+        assert_eq!(process.read(serial, &mut buf).await, Ok(1));
+        assert_eq!(process.ioctl(serial, devfs::TCSETS, flags).await, Ok(0));
+        assert_eq!(
+            process.ioctl(serial, devfs::TCSETS, 1 << 31).await,
+            Err(FsError::InvalidArgument)
+        );
+        process.close(serial).unwrap();
+    });
+}
+
+#[test_case]
+fn block_device() {
+    task::block_on(async {
+        let process = process::current();
+        let fd = open("/dev/vda", OpenFlags::READ).await;
+        let size = process.ioctl(fd, devfs::BLKGETSIZE64, 0).await.unwrap();
+        assert_eq!(process.fstat(fd).unwrap().size, size);
+        assert_eq!(process.ioctl(fd, devfs::BLKSSZGET, 0).await, Ok(512));
+
+        // Each sector of the test disk starts with its number.
+        assert_eq!(process.seek(fd, SeekFrom::Start(3 * 512)), Ok(3 * 512));
+        let mut buf = [0; 8];
+        assert_eq!(process.read(fd, &mut buf).await, Ok(8));
+        assert_eq!(u64::from_le_bytes(buf), 3);
+        // Reads need not be aligned to sectors.
+        assert_eq!(
+            process.seek(fd, SeekFrom::Current(512 - 12)),
+            Ok(4 * 512 - 4)
+        );
+        assert_eq!(process.read(fd, &mut buf).await, Ok(8));
+        assert_eq!(buf[0], (508 ^ 3) as u8);
+        assert_eq!(buf[4..], [4, 0, 0, 0]);
+
+        assert_eq!(process.seek(fd, SeekFrom::End(-4)), Ok(size - 4));
+        assert_eq!(process.read(fd, &mut buf).await, Ok(4));
+        assert_eq!(process.read(fd, &mut buf).await, Ok(0));
+        process.close(fd).unwrap();
+    });
+}
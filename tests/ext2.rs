@@ -0,0 +1,261 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{format, vec, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use fs_checks::{check_directory, check_read_write_truncate, contents, names, write_file};
+use futures_util::future;
+use rust_os::{
+    block,
+    fs::ext2::Ext2Fs,
+    hlt_loop, process, task,
+    vfs::{self, FileSystem, FileType, OpenFlags},
+};
+
+mod common;
+mod fs_checks;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    common::boot(boot_info);
+
+    test_main();
+    hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+/// The images formatted by `mke2fs` in the build script with their block
+/// sizes, in the order they are attached.
+const VOLUMES: [(&str, u64); 2] = [("/mnt/vde", 1024), ("/mnt/vdf", 4096)];
+
+/// Checks `big.bin`, which is too big to read in one go on the heap.
+async fn assert_big_file(path: &str) {
+    let process = process::current();
+    let fd = process.open(path, OpenFlags::READ, 0).await.unwrap();
+    assert_eq!(process.fstat(fd).unwrap().size, 300_000);
+    let mut buf = vec![0; 4096];
+    let mut offset = 0;
+    loop {
+        let len = process.read(fd, &mut buf).await.unwrap();
+        if len == 0 {
+            break;
+        }
+        for (i, &byte) in buf[..len].iter().enumerate() {
+            assert_eq!(byte, ((offset + i) % 253) as u8);
+        }
+        offset += len;
+    }
+    assert_eq!(offset, 300_000);
+    process.close(fd).unwrap();
+}
+
+#[test_case]
+fn mounted_at_boot() {
+    let mounts = vfs::mounts();
+    for (path, _) in VOLUMES {
+        assert!(mounts
+            .iter()
+            .any(|mount| mount.path == path && mount.fs_name == "ext2"));
+    }
+}
+
+#[test_case]
+fn host_files() {
+    task::block_on(async {
+        for (root, _) in VOLUMES {
+            assert_eq!(
+                names(root).await,
+                ["big.bin", "docs", "fast", "hello.txt", "lost+found", "slow"]
+            );
+            let hello = contents(&format!("{}/hello.txt", root)).await;
+            assert_eq!(hello, b"Hello from the host!\n");
+            assert_big_file(&format!("{}/big.bin", root)).await;
+            let deep = contents(&format!("{}/docs/nested/deep.txt", root)).await;
+            assert_eq!(deep, b"deep");
+        }
+    });
+}
+
+#[test_case]
+fn symlinks() {
+    task::block_on(async {
+        let process = process::current();
+        for (root, _) in VOLUMES {
+            let hello = contents(&format!("{}/hello.txt", root)).await;
+            // `fast` fits in its inode, `slow` takes a block.
+            let fast = format!("{}/fast", root);
+            assert_eq!(process.readlink(&fast).await.as_deref(), Ok("hello.txt"));
+            assert_eq!(contents(&fast).await, hello);
+            let slow = format!("{}/slow", root);
+            let target = format!("{}hello.txt", "./".repeat(40));
+            assert_eq!(process.readlink(&slow).await, Ok(target));
+            assert_eq!(contents(&slow).await, hello);
+
+            let link = format!("{}/link", root);
+            process.symlink("docs/nested", &link).await.unwrap();
+            assert_eq!(names(&link).await, ["deep.txt"]);
+            let stat = process.lstat(&link).await.unwrap();
+            assert_eq!(stat.file_type, FileType::Symlink);
+            process.unlink(&link).await.unwrap();
+        }
+    });
+}
+
+#[test_case]
+fn hard_links() {
+    task::block_on(async {
+        let process = process::current();
+        for (root, _) in VOLUMES {
+            let hello = format!("{}/hello.txt", root);
+            let copy = format!("{}/docs/hello.txt", root);
+            let stat = process.stat(&hello).await.unwrap();
+            assert_eq!(stat.nlink, 2);
+            assert_eq!(process.stat(&copy).await.unwrap().ino, stat.ino);
+
+            // A write through one name shows through the other.
+            let original = contents(&hello).await;
+            write_file(&copy, b"Hello again\n").await;
+            assert_eq!(contents(&hello).await, b"Hello again\n");
+            write_file(&hello, &original).await;
+            assert_eq!(contents(&copy).await, original);
+        }
+    });
+}
+
+#[test_case]
+fn directories() {
+    task::block_on(async {
+        let process = process::current();
+        for (root, _) in VOLUMES {
+            let dir = format!("{}/new directory", root);
+            process.mkdir(&dir, 0o755).await.unwrap();
+            // One link from the parent, one from its own `.`.
+            assert_eq!(process.stat(&dir).await.unwrap().nlink, 2);
+            let parent = process.stat(root).await.unwrap().nlink;
+            check_directory(&dir, 100).await;
+            assert_eq!(process.stat(root).await.unwrap().nlink, parent - 1);
+        }
+    });
+}
+
+#[test_case]
+fn read_write_truncate() {
+    task::block_on(async {
+        for (root, _) in VOLUMES {
+            check_read_write_truncate(&format!("{}/data.bin", root), 100_000, 1_000_000).await;
+        }
+    });
+}
+
+#[test_case]
+fn block_sizes_and_indirect_blocks() {
+    task::block_on(async {
+        for (root, block_size) in VOLUMES {
+            // Unmounted, so that no other instance caches the bitmaps meanwhile.
+            vfs::unmount(root).await.unwrap();
+            let name = &root["/mnt/".len()..];
+            let ext2 = Ext2Fs::new(block::find(name).unwrap()).await.unwrap();
+            assert_eq!(ext2.block_size(), block_size);
+            let root_dir = ext2.root();
+            let free = ext2.free_counts().await;
+            let file = root_dir
+                .create("sparse", FileType::Regular, 0o644)
+                .await
+                .unwrap();
+            assert_eq!(ext2.free_counts().await, (free.0, free.1 - 1));
+
+            // The first block after the 12 direct ones needs an indirect
+            // block, and the first after those a double indirect one too.
+            let single = 12 * block_size;
+            let double = (12 + block_size / 4) * block_size;
+            assert_eq!(file.write_at(single, b"one").await, Ok(3));
+            assert_eq!(ext2.free_counts().await, (free.0 - 2, free.1 - 1));
+            assert_eq!(file.write_at(double, b"two").await, Ok(3));
+            assert_eq!(ext2.free_counts().await, (free.0 - 5, free.1 - 1));
+            let mut buf = [0; 3];
+            assert_eq!(file.read_at(double, &mut buf).await, Ok(3));
+            assert_eq!(&buf, b"two");
+
+            file.truncate(single + 3).await.unwrap();
+            assert_eq!(ext2.free_counts().await, (free.0 - 2, free.1 - 1));
+            assert_eq!(file.read_at(single, &mut buf).await, Ok(3));
+            assert_eq!(&buf, b"one");
+            file.truncate(0).await.unwrap();
+            assert_eq!(ext2.free_counts().await, (free.0, free.1 - 1));
+            drop(file);
+            root_dir.unlink("sparse").await.unwrap();
+            assert_eq!(ext2.free_counts().await, free);
+            vfs::mount(root, ext2).await.unwrap();
+        }
+    });
+}
+
+#[test_case]
+fn concurrent_creates() {
+    task::block_on(async {
+        vfs::unmount("/mnt/vde").await.unwrap();
+        let ext2 = Ext2Fs::new(block::find("vde").unwrap()).await.unwrap();
+        let root = ext2.root();
+        let dir = root
+            .create("racing", FileType::Directory, 0o755)
+            .await
+            .unwrap();
+        // Enough long names to take several blocks, so both sides grow it.
+        let name = |i| format!("a file created concurrently, number {}", i);
+        for i in 0..40 {
+            let (left, right) = future::join(
+                dir.create(&name(2 * i), FileType::Regular, 0o644),
+                dir.create(&name(2 * i + 1), FileType::Regular, 0o644),
+            )
+            .await;
+            left.unwrap();
+            right.unwrap();
+        }
+        let mut names: Vec<_> = dir
+            .readdir()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect();
+        names.sort();
+        let mut expected: Vec<_> = (0..80).map(name).collect();
+        expected.sort();
+        assert_eq!(names, expected);
+
+        for name in expected {
+            dir.unlink(&name).await.unwrap();
+        }
+        drop(dir);
+        root.rmdir("racing").await.unwrap();
+        vfs::mount("/mnt/vde", ext2).await.unwrap();
+    });
+}
+
+#[test_case]
+fn persists_across_mounts() {
+    task::block_on(async {
+        write_file("/mnt/vdf/notes.txt", b"still here").await;
+
+        vfs::unmount("/mnt/vdf").await.unwrap();
+        let ext2 = Ext2Fs::new(block::find("vdf").unwrap()).await.unwrap();
+        vfs::mount("/mnt/vdf", ext2).await.unwrap();
+
+        assert_eq!(contents("/mnt/vdf/notes.txt").await, b"still here");
+        process::current()
+            .unlink("/mnt/vdf/notes.txt")
+            .await
+            .unwrap();
+    });
+}
@@ -0,0 +1,134 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{format, vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use fs_checks::{check_directory, check_read_write_truncate, contents, names, write_file};
+use rust_os::{
+    block,
+    fs::fat::{FatFs, FatType},
+    hlt_loop, process, task,
+    vfs::{self, FileSystem, FileType},
+};
+
+mod common;
+mod fs_checks;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    common::boot(boot_info);
+
+    test_main();
+    hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+/// The images formatted by `mkfs.fat` in the build script, in the order
+/// they are attached.
+const VOLUMES: [(&str, FatType); 3] = [
+    ("/mnt/vdb", FatType::Fat12),
+    ("/mnt/vdc", FatType::Fat16),
+    ("/mnt/vdd", FatType::Fat32),
+];
+
+#[test_case]
+fn mounted_at_boot() {
+    let mounts = vfs::mounts();
+    for (path, fat_type) in VOLUMES {
+        let name = match fat_type {
+            FatType::Fat12 => "fat12",
+            FatType::Fat16 => "fat16",
+            FatType::Fat32 => "fat32",
+        };
+        assert!(mounts
+            .iter()
+            .any(|mount| mount.path == path && mount.fs_name == name));
+    }
+}
+
+#[test_case]
+fn long_names_and_directories() {
+    task::block_on(async {
+        let process = process::current();
+        for (root, _) in VOLUMES {
+            // The volume label set by mkfs.fat is not a file.
+            assert!(names(root).await.is_empty());
+
+            let dir = format!("{}/A directory with a long name", root);
+            let readme = format!("{}/readme.txt", root);
+            process.mkdir(&dir, 0o755).await.unwrap();
+            process.mkdir(&readme, 0o755).await.unwrap();
+            assert_eq!(
+                names(root).await,
+                ["A directory with a long name", "readme.txt"]
+            );
+            // Names are not case sensitive.
+            let stat = process.stat(&format!("{}/README.TXT", root)).await.unwrap();
+            assert_eq!(stat.file_type, FileType::Directory);
+            process.rmdir(&readme).await.unwrap();
+
+            check_directory(&dir, 40).await;
+            assert!(names(root).await.is_empty());
+        }
+    });
+}
+
+#[test_case]
+fn read_write_truncate() {
+    task::block_on(async {
+        for (root, _) in VOLUMES {
+            check_read_write_truncate(&format!("{}/data file.bin", root), 20_000, 30_000).await;
+        }
+    });
+}
+
+#[test_case]
+fn deleting_frees_clusters() {
+    task::block_on(async {
+        // Unmounted, so that no other instance caches the FAT meanwhile.
+        vfs::unmount("/mnt/vdc").await.unwrap();
+        let fat = FatFs::new(block::find("vdc").unwrap()).await.unwrap();
+        assert_eq!(fat.fat_type(), FatType::Fat16);
+        let root = fat.root();
+        let free = fat.free_clusters().await.unwrap();
+
+        let file = root.create("big", FileType::Regular, 0o644).await.unwrap();
+        let len = fat.cluster_size() as usize * 10;
+        assert_eq!(file.write_at(0, &vec![7; len]).await, Ok(len));
+        assert_eq!(fat.free_clusters().await.unwrap(), free - 10);
+        file.truncate(1).await.unwrap();
+        assert_eq!(fat.free_clusters().await.unwrap(), free - 1);
+        root.unlink("big").await.unwrap();
+        assert_eq!(fat.free_clusters().await.unwrap(), free);
+        vfs::mount("/mnt/vdc", fat).await.unwrap();
+    });
+}
+
+#[test_case]
+fn persists_across_mounts() {
+    task::block_on(async {
+        write_file("/mnt/vdd/Persistent Notes.txt", b"still here").await;
+
+        vfs::unmount("/mnt/vdd").await.unwrap();
+        let fat = FatFs::new(block::find("vdd").unwrap()).await.unwrap();
+        vfs::mount("/mnt/vdd", fat).await.unwrap();
+
+        let notes = contents("/mnt/vdd/persistent notes.txt").await;
+        assert_eq!(notes, b"still here");
+        process::current()
+            .unlink("/mnt/vdd/Persistent Notes.txt")
+            .await
+            .unwrap();
+    });
+}
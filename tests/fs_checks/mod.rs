@@ -0,0 +1,96 @@
+//! Checks of the VFS operations that every disk filesystem supports.
+
+use alloc::{format, string::String, vec, vec::Vec};
+use rust_os::{
+    process,
+    vfs::{FsError, OpenFlags, SeekFrom},
+};
+
+pub async fn names(path: &str) -> Vec<String> {
+    let process = process::current();
+    let fd = process
+        .open(path, OpenFlags::READ | OpenFlags::DIRECTORY, 0)
+        .await
+        .unwrap();
+    let mut names: Vec<_> = process
+        .readdir(fd)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.name)
+        .collect();
+    process.close(fd).unwrap();
+    names.sort();
+    names
+}
+
+pub async fn contents(path: &str) -> Vec<u8> {
+    let process = process::current();
+    let fd = process.open(path, OpenFlags::READ, 0).await.unwrap();
+    let mut data = vec![0; process.fstat(fd).unwrap().size as usize + 1];
+    let len = process.read(fd, &mut data).await.unwrap();
+    data.truncate(len);
+    process.close(fd).unwrap();
+    data
+}
+
+pub async fn write_file(path: &str, data: &[u8]) {
+    let process = process::current();
+    let flags = OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE;
+    let fd = process.open(path, flags, 0o644).await.unwrap();
+    assert_eq!(process.write(fd, data).await, Ok(data.len()));
+    process.close(fd).unwrap();
+}
+
+/// Fills the empty directory `dir` with `count` files, checks that it can
+/// only be removed once empty, and removes it.
+pub async fn check_directory(dir: &str, count: usize) {
+    let process = process::current();
+    let path = |i| format!("{}/file with a long name number {}.text", dir, i);
+    for i in 0..count {
+        let flags = OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::EXCLUSIVE;
+        let fd = process.open(&path(i), flags, 0o644).await.unwrap();
+        process.close(fd).unwrap();
+    }
+    assert_eq!(names(dir).await.len(), count);
+    assert_eq!(process.rmdir(dir).await.err(), Some(FsError::NotEmpty));
+    assert_eq!(process.unlink(dir).await.err(), Some(FsError::IsADirectory));
+
+    for i in 0..count {
+        process.unlink(&path(i)).await.unwrap();
+    }
+    process.rmdir(dir).await.unwrap();
+    assert_eq!(process.stat(dir).await.err(), Some(FsError::NotFound));
+}
+
+/// Writes `len` bytes to a new file, reads them back, writes again at `end`
+/// to leave a hole after them, and truncates and removes the file.
+pub async fn check_read_write_truncate(path: &str, len: usize, end: u64) {
+    let process = process::current();
+    let data: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+    let flags = OpenFlags::READ | OpenFlags::WRITE | OpenFlags::CREATE;
+    let fd = process.open(path, flags, 0o644).await.unwrap();
+    assert_eq!(process.write(fd, &data).await, Ok(len));
+
+    let mut buf = vec![0; len + 10];
+    assert_eq!(process.seek(fd, SeekFrom::Start(0)), Ok(0));
+    assert_eq!(process.read(fd, &mut buf).await, Ok(len));
+    assert_eq!(buf[..len], data[..]);
+
+    // Writing past the end leaves a hole of zeroes.
+    assert_eq!(process.seek(fd, SeekFrom::Start(end)), Ok(end));
+    assert_eq!(process.write(fd, b"end").await, Ok(3));
+    assert_eq!(process.fstat(fd).unwrap().size, end + 3);
+    process.seek(fd, SeekFrom::Start(len as u64)).unwrap();
+    let mut hole = [1; 100];
+    assert_eq!(process.read(fd, &mut hole).await, Ok(100));
+    assert!(hole.iter().all(|&b| b == 0));
+    process.close(fd).unwrap();
+
+    let flags = OpenFlags::WRITE | OpenFlags::TRUNCATE;
+    let fd = process.open(path, flags, 0).await.unwrap();
+    assert_eq!(process.fstat(fd).unwrap().size, 0);
+    process.close(fd).unwrap();
+    process.unlink(path).await.unwrap();
+    assert_eq!(process.stat(path).await.err(), Some(FsError::NotFound));
+}
@@ -0,0 +1,92 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+#![feature(type_name_of_val)]
+
+mod common;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_os::{
+    allocator, exit_qemu, gdt, hlt_loop,
+    memory::{self, stack::KernelStack, vmm, BootInfoFrameAllocator},
+    serial_println, QemuExitCode,
+};
+use spin::Lazy;
+use volatile::Volatile;
+use x86_64::{
+    registers::control::Cr2,
+    structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode},
+    VirtAddr,
+};
+
+const STACK_NAME: &str = "overflow test";
+
+static TEST_IDT: Lazy<InterruptDescriptorTable> = Lazy::new(|| {
+    let mut idt = InterruptDescriptorTable::new();
+    unsafe {
+        idt.page_fault
+            .set_handler_fn(test_page_fault_handler)
+            .set_stack_index(gdt::PAGE_FAULT_IST_INDEX);
+    }
+    idt
+});
+
+extern "x86-interrupt" fn test_page_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    _error_code: PageFaultErrorCode,
+) {
+    match memory::stack::guard_owner(Cr2::read()) {
+        Some(STACK_NAME) => {
+            serial_println!("[ok]");
+            exit_qemu(QemuExitCode::Success);
+        }
+        owner => {
+            serial_println!("[failed]\n");
+            serial_println!("Error: fault reported for {:?} stack\n", owner);
+            exit_qemu(QemuExitCode::Failed);
+        }
+    }
+    hlt_loop();
+}
+
+fn init_test_idt() {
+    TEST_IDT.load();
+}
+
+#[allow(unconditional_recursion)]
+fn stack_overflow() {
+    stack_overflow();
+    Volatile::new(0).read();
+}
+
+extern "C" fn overflow_guarded_stack() -> ! {
+    stack_overflow();
+
+    panic!("[Execution continued after stack overflow]");
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    common::print_test_name(stack_overflow);
+
+    gdt::init();
+    init_test_idt();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    vmm::init(mapper, frame_allocator);
+    gdt::init_stacks().expect("interrupt stack allocation failed");
+
+    let stack = KernelStack::alloc(STACK_NAME, 4).expect("stack allocation failed");
+    unsafe { stack.switch_to(overflow_guarded_stack) }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
@@ -0,0 +1,90 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+#![feature(type_name_of_val)]
+
+extern crate alloc;
+
+mod common;
+
+use alloc::boxed::Box;
+use bootloader::{entry_point, BootInfo};
+use core::{mem, panic::PanicInfo};
+use rust_os::{
+    allocator, exit_qemu, gdt, hlt_loop,
+    memory::{self, BootInfoFrameAllocator},
+    serial_println, QemuExitCode,
+};
+use spin::Lazy;
+use x86_64::{
+    registers::control::Cr2,
+    structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode},
+    VirtAddr,
+};
+
+static TEST_IDT: Lazy<InterruptDescriptorTable> = Lazy::new(|| {
+    let mut idt = InterruptDescriptorTable::new();
+    idt.page_fault.set_handler_fn(test_page_fault_handler);
+    idt
+});
+
+extern "x86-interrupt" fn test_page_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    let addr = Cr2::read().as_u64() as usize;
+    let in_heap =
+        (allocator::HEAP_START..allocator::HEAP_START + allocator::HEAP_SIZE).contains(&addr);
+
+    if in_heap && error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]\n");
+        serial_println!(
+            "Error: unexpected page fault at {:#x} ({:?})\n",
+            addr,
+            error_code
+        );
+        exit_qemu(QemuExitCode::Failed);
+    }
+    hlt_loop();
+}
+
+fn init_test_idt() {
+    TEST_IDT.load();
+}
+
+fn execute_from_heap() {
+    // `ret`
+    let code = Box::new([0xc3_u8; 16]);
+    let f: extern "C" fn() = unsafe { mem::transmute(code.as_ptr()) };
+    f();
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    common::print_test_name(execute_from_heap);
+
+    gdt::init();
+    init_test_idt();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    unsafe { memory::protection::protect_kernel(&mut mapper, &boot_info.memory_map) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    execute_from_heap();
+
+    serial_println!("[test did not fault]");
+    exit_qemu(QemuExitCode::Failed);
+    hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
@@ -0,0 +1,111 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{format, vec, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use futures_util::future;
+use rust_os::{
+    hlt_loop,
+    process::{self, Fd},
+    task,
+    vfs::{pipe::CAPACITY, FileType, FsError, OpenFlags},
+};
+
+mod common;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    common::boot(boot_info);
+
+    test_main();
+    hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+/// Writes `data` to `fd` and closes it.
+async fn send(fd: Fd, data: &[u8]) {
+    let process = process::current();
+    assert_eq!(process.write(fd, data).await, Ok(data.len()));
+    process.close(fd).unwrap();
+}
+
+/// Reads `fd` until the end and closes it.
+async fn receive(fd: Fd) -> Vec<u8> {
+    let process = process::current();
+    let mut data = Vec::new();
+    let mut buf = vec![0; 1000];
+    loop {
+        let len = process.read(fd, &mut buf).await.unwrap();
+        if len == 0 {
+            break;
+        }
+        data.extend_from_slice(&buf[..len]);
+    }
+    process.close(fd).unwrap();
+    data
+}
+
+#[test_case]
+fn anonymous_pipe() {
+    let process = process::current();
+    let (reader, writer) = process.pipe(OpenFlags::empty());
+    assert_eq!(process.fstat(reader).unwrap().file_type, FileType::Fifo);
+
+    // The writer has to wait for the reader to make room.
+    let data: Vec<u8> = (0..CAPACITY * 3).map(|i| i as u8).collect();
+    let (_, received) = task::block_on(future::join(send(writer, &data), receive(reader)));
+    assert_eq!(received, data);
+}
+
+#[test_case]
+fn broken_pipe() {
+    task::block_on(async {
+        let process = process::current();
+        let (reader, writer) = process.pipe(OpenFlags::empty());
+        process.close(reader).unwrap();
+        assert_eq!(process.write(writer, b"x").await, Err(FsError::BrokenPipe));
+        process.close(writer).unwrap();
+    });
+}
+
+#[test_case]
+fn named_fifos() {
+    task::block_on(async {
+        let process = process::current();
+        for path in ["/fifo", "/mnt/vde/fifo"] {
+            process.mkfifo(path, 0o644).await.unwrap();
+            assert_eq!(process.stat(path).await.unwrap().file_type, FileType::Fifo);
+
+            let flags = OpenFlags::WRITE | OpenFlags::NON_BLOCK;
+            let result = process.open(path, flags, 0).await;
+            assert_eq!(result.err(), Some(FsError::WouldBlock));
+
+            // Each end waits for the other to be opened.
+            let (reader, writer) = future::join(
+                process.open(path, OpenFlags::READ, 0),
+                process.open(path, OpenFlags::WRITE, 0),
+            )
+            .await;
+            let message = format!("through {}", path);
+            let (_, received) = future::join(
+                send(writer.unwrap(), message.as_bytes()),
+                receive(reader.unwrap()),
+            )
+            .await;
+            assert_eq!(received, message.as_bytes());
+
+            process.unlink(path).await.unwrap();
+        }
+    });
+}
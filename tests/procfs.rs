@@ -0,0 +1,165 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{format, string::String, vec, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_os::{
+    allocator, hlt_loop, process,
+    task::{self, Task},
+    time,
+    vfs::{FileType, FsError, OpenFlags},
+};
+
+mod common;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    common::boot(boot_info);
+
+    test_main();
+    hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+async fn contents(path: &str) -> String {
+    let process = process::current();
+    let fd = process.open(path, OpenFlags::READ, 0).await.unwrap();
+    let mut data = vec![0; process.fstat(fd).unwrap().size as usize + 1];
+    let len = process.read(fd, &mut data).await.unwrap();
+    data.truncate(len);
+    process.close(fd).unwrap();
+    String::from_utf8(data).unwrap()
+}
+
+/// Returns the value of `key` in a file of `key: value` lines.
+fn field<'a>(text: &'a str, key: &str) -> Option<&'a str> {
+    text.lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim() == key)
+        .map(|(_, value)| value.trim())
+}
+
+#[test_case]
+fn kernel_files() {
+    task::block_on(async {
+        let process = process::current();
+        let fd = process
+            .open("/proc", OpenFlags::READ | OpenFlags::DIRECTORY, 0)
+            .await
+            .unwrap();
+        let entries = process.readdir(fd).await.unwrap();
+        process.close(fd).unwrap();
+        for name in ["meminfo", "interrupts", "tasks", "uptime", "cpuinfo"] {
+            let entry = entries.iter().find(|entry| entry.name == name).unwrap();
+            assert_eq!(entry.file_type, FileType::Regular);
+        }
+
+        assert_eq!(
+            process
+                .open("/proc/uptime", OpenFlags::WRITE, 0)
+                .await
+                .err(),
+            Some(FsError::ReadOnly)
+        );
+        assert_eq!(
+            process.mkdir("/proc/new", 0o755).await.err(),
+            Some(FsError::ReadOnly)
+        );
+    });
+}
+
+#[test_case]
+fn meminfo() {
+    let meminfo = task::block_on(contents("/proc/meminfo"));
+    let value = |key| -> usize {
+        let value = field(&meminfo, key).unwrap();
+        value.strip_suffix(" kB").unwrap().parse().unwrap()
+    };
+    assert!(value("MemFree") < value("MemTotal"));
+    assert_eq!(value("HeapTotal"), allocator::HEAP_SIZE / 1024);
+    assert!(value("HeapUsed") < value("HeapTotal"));
+}
+
+#[test_case]
+fn interrupts_and_uptime() {
+    // Wait for a few timer interrupts.
+    let start = time::ticks();
+    while time::ticks() < start + 3 {
+        x86_64::instructions::hlt();
+    }
+
+    let interrupts = task::block_on(contents("/proc/interrupts"));
+    let timer = interrupts
+        .lines()
+        .find(|line| line.ends_with("timer"))
+        .unwrap();
+    let count: u64 = timer.split_whitespace().nth(1).unwrap().parse().unwrap();
+    assert!(count >= 3);
+
+    let uptime = task::block_on(contents("/proc/uptime"));
+    let (seconds, hundredths) = uptime.trim().split_once('.').unwrap();
+    assert!(seconds.parse::<u64>().is_ok());
+    assert_eq!(hundredths.len(), 2);
+}
+
+#[test_case]
+fn tasks() {
+    let task = Task::new(async {});
+    let tasks = task::block_on(contents("/proc/tasks"));
+    let line = format!("{:>6}  ready", task.id());
+    assert!(tasks.lines().any(|other| other == line));
+    drop(task);
+}
+
+#[test_case]
+fn cpuinfo() {
+    let cpuinfo = task::block_on(contents("/proc/cpuinfo"));
+    assert_eq!(field(&cpuinfo, "vendor_id").unwrap().len(), 12);
+    assert!(field(&cpuinfo, "cpu family").is_some());
+    let flags: Vec<_> = field(&cpuinfo, "flags").unwrap().split(' ').collect();
+    assert!(flags.contains(&"apic"));
+}
+
+#[test_case]
+fn processes() {
+    task::block_on(async {
+        let process = process::current();
+        let pid = process.pid();
+        assert_eq!(process.readlink("/proc/self").await, Ok(format!("{}", pid)));
+
+        process.chdir("/dev").await.unwrap();
+        let fd = process.open("null", OpenFlags::READ, 0).await.unwrap();
+        let status = contents("/proc/self/status").await;
+        assert_eq!(field(&status, "Pid"), Some(format!("{}", pid).as_str()));
+        assert_eq!(field(&status, "Cwd"), Some("/dev"));
+        assert_eq!(
+            process.readlink("/proc/self/cwd").await.as_deref(),
+            Ok("/dev")
+        );
+
+        let fds = contents(&format!("/proc/{}/fds", pid)).await;
+        assert!(fds.lines().any(|line| line == format!("{}\tchar\t0", fd)));
+        process.close(fd).unwrap();
+        process.chdir("/").await.unwrap();
+
+        let child = process.fork();
+        let dir = format!("/proc/{}", child.pid());
+        assert_eq!(
+            process.stat(&dir).await.unwrap().file_type,
+            FileType::Directory
+        );
+        drop(child);
+        assert_eq!(process.stat(&dir).await.err(), Some(FsError::NotFound));
+    });
+}
@@ -0,0 +1,77 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::{panic::PanicInfo, time::Duration};
+use futures_util::{FutureExt, StreamExt};
+use rust_os::{
+    allocator, hlt_loop,
+    memory::{self, vmm, BootInfoFrameAllocator},
+    serial::{self, SerialStream},
+    task, time,
+};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rust_os::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    vmm::init(mapper, frame_allocator);
+    serial::init();
+
+    test_main();
+    hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn detection() {
+    let ports = serial::ports();
+    assert_eq!(ports.first().map(|port| port.index()), Some(0));
+    assert!(serial::port(0).is_some());
+    // QEMU only has COM1 unless more `-serial` options are given.
+    assert!(ports.len() <= 4);
+}
+
+#[test_case]
+fn loopback() {
+    let port = serial::port(0).expect("COM1 not found");
+    let mut bytes = SerialStream::new(port);
+    port.set_loopback(true);
+    task::block_on(port.write(b"ping"));
+    let received: Vec<u8> = (0..4)
+        .map(|_| task::block_on(bytes.next()).unwrap())
+        .collect();
+    assert_eq!(received, b"ping");
+    assert_eq!(port.overruns(), 0);
+
+    // What nobody reads is lost once the receive queue is full, but writes
+    // wait for the transmit queue to have room.
+    task::block_on(async {
+        port.write(&[b'x'; 1000]).await;
+        time::sleep(Duration::from_millis(500)).await;
+    });
+    port.set_loopback(false);
+    let mut kept = 0;
+    while bytes.next().now_or_never().is_some() {
+        kept += 1;
+    }
+    assert!(kept <= serial::RECEIVE_QUEUE_SIZE);
+    assert!(port.overruns() > 0);
+}
@@ -0,0 +1,69 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, format, string::String};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_os::{hlt_loop, shell, task};
+
+mod common;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    common::boot(boot_info);
+
+    test_main();
+    hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+fn run(line: &str) -> String {
+    let mut out = String::new();
+    task::block_on(shell::execute(line, &mut out));
+    out
+}
+
+#[test_case]
+fn inspection() {
+    assert!(run("mem").starts_with("memory: "));
+    assert!(run("uptime").starts_with("up "));
+    assert!(run("lspci").starts_with("PCI devices"));
+    // The status register of the keyboard controller.
+    assert!(run("inb 0x64").starts_with("0x"));
+    assert_eq!(run("inb 0x10000"), "inb: port out of range\n");
+
+    let heap = Box::new(0u64);
+    let translation = run(&format!("pt {:p}", heap));
+    assert!(!translation.contains("not mapped"));
+    assert!(run("pt").starts_with("page table at "));
+}
+
+#[test_case]
+fn files() {
+    let devices = run("ls /dev");
+    assert!(devices.lines().any(|line| line == "null"));
+    assert!(run("ls /").lines().any(|line| line == "proc/"));
+    assert!(run("cat /proc/uptime").ends_with('\n'));
+    assert!(run("cat /missing").starts_with("cat: NotFound"));
+}
+
+#[test_case]
+fn self_tests() {
+    let out = run("test");
+    assert_eq!(out.lines().count(), 4);
+    assert!(out.lines().all(|line| line.ends_with("... ok")));
+    assert_eq!(
+        run("test nothing"),
+        "nothing... FAILED: no such test\ntest: 1 failed\n"
+    );
+}
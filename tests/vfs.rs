@@ -0,0 +1,155 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{string::String, vec, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_os::{
+    allocator, fs, hlt_loop,
+    memory::{self, BootInfoFrameAllocator},
+    process, task,
+    vfs::{self, FileType, FsError, OpenFlags, SeekFrom},
+};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rust_os::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    task::block_on(fs::init());
+
+    test_main();
+    hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn mounted_at_boot() {
+    let mounts: Vec<_> = vfs::mounts()
+        .into_iter()
+        .map(|mount| (mount.path, mount.fs_name))
+        .collect();
+    assert_eq!(
+        mounts,
+        [
+            (String::from("/"), String::from("tmpfs")),
+            (String::from("/tmp"), String::from("initrd")),
+        ]
+    );
+}
+
+#[test_case]
+fn read_write_seek() {
+    task::block_on(async {
+        let process = process::current();
+        let flags = OpenFlags::READ | OpenFlags::WRITE | OpenFlags::CREATE;
+        let fd = process.open("/notes", flags, 0o644).await.unwrap();
+        assert_eq!(process.write(fd, b"hello world").await, Ok(11));
+
+        assert_eq!(process.seek(fd, SeekFrom::Start(6)), Ok(6));
+        let mut buf = [0; 16];
+        assert_eq!(process.read(fd, &mut buf).await, Ok(5));
+        assert_eq!(&buf[..5], b"world");
+        assert_eq!(process.read(fd, &mut buf).await, Ok(0));
+        assert_eq!(process.seek(fd, SeekFrom::End(-5)), Ok(6));
+        assert_eq!(
+            process.seek(fd, SeekFrom::Current(-7)),
+            Err(FsError::InvalidArgument)
+        );
+
+        assert_eq!(process.fstat(fd).unwrap().size, 11);
+        process.close(fd).unwrap();
+        assert_eq!(process.close(fd), Err(FsError::BadFileDescriptor));
+
+        let flags = OpenFlags::CREATE | OpenFlags::EXCLUSIVE | OpenFlags::WRITE;
+        assert_eq!(
+            process.open("/notes", flags, 0o644).await.err(),
+            Some(FsError::AlreadyExists)
+        );
+        process.unlink("/notes").await.unwrap();
+    });
+}
+
+#[test_case]
+fn directories_and_symlinks() {
+    task::block_on(async {
+        let process = process::current();
+        process.mkdir("/home", 0o755).await.unwrap();
+        process.mkdir("/home/user", 0o755).await.unwrap();
+        process.symlink("/home/user", "/me").await.unwrap();
+        process.symlink("loop", "/loop").await.unwrap();
+
+        process.chdir("/me/../user/.").await.unwrap();
+        assert_eq!(process.cwd(), "/home/user");
+        process.chdir("../..").await.unwrap();
+        assert_eq!(process.cwd(), "/");
+
+        assert_eq!(process.readlink("/me").await.unwrap(), "/home/user");
+        assert_eq!(
+            process.lstat("/me").await.unwrap().file_type,
+            FileType::Symlink
+        );
+        assert_eq!(
+            process.stat("/me").await.unwrap().file_type,
+            FileType::Directory
+        );
+        assert_eq!(
+            process.stat("/loop").await.err(),
+            Some(FsError::TooManyLinks)
+        );
+        assert_eq!(process.rmdir("/home").await.err(), Some(FsError::NotEmpty));
+
+        let fd = process
+            .open("/home", OpenFlags::READ | OpenFlags::DIRECTORY, 0)
+            .await
+            .unwrap();
+        let names: Vec<_> = process
+            .readdir(fd)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect();
+        assert_eq!(names, ["user"]);
+        process.close(fd).unwrap();
+    });
+}
+
+#[test_case]
+fn initrd_is_read_only() {
+    task::block_on(async {
+        let process = process::current();
+        let fd = process
+            .open("/tmp/etc/motd", OpenFlags::READ, 0)
+            .await
+            .unwrap();
+        let mut buf = vec![0; 64];
+        let len = process.read(fd, &mut buf).await.unwrap();
+        assert!(buf[..len].starts_with(b"Welcome"));
+        process.close(fd).unwrap();
+
+        // `..` leaves the initrd for the directory it is mounted on.
+        process.chdir("/tmp/etc/../..").await.unwrap();
+        assert_eq!(process.cwd(), "/");
+        assert_eq!(
+            process.mkdir("/tmp/new", 0o755).await.err(),
+            Some(FsError::ReadOnly)
+        );
+        assert_eq!(vfs::unmount("/").await.err(), Some(FsError::Busy));
+    });
+}
@@ -0,0 +1,89 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{sync::Arc, vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_os::{
+    acpi, allocator, apic,
+    block::{self, BlockDevice, BlockError, SECTOR_SIZE},
+    hlt_loop,
+    memory::{self, regions, vmm, BootInfoFrameAllocator},
+    pci, task, virtio,
+};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rust_os::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    vmm::init(mapper, frame_allocator);
+    regions::init(&boot_info.memory_map);
+    acpi::init().expect("ACPI initialization failed");
+    apic::init().expect("local APIC initialization failed");
+    pci::init();
+    virtio::init();
+
+    test_main();
+    hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+fn disk() -> Arc<dyn BlockDevice> {
+    block::find("vda").expect("no virtio disk")
+}
+
+#[test_case]
+fn reads_sectors() {
+    let disk = disk();
+    let mut buf = vec![0; 4 * SECTOR_SIZE];
+    task::block_on(disk.read(10, &mut buf)).expect("read failed");
+
+    for (i, sector) in buf.chunks(SECTOR_SIZE).enumerate() {
+        let number = 10 + i as u64;
+        assert_eq!(sector[..8], number.to_le_bytes());
+        assert_eq!(sector[8], (8 ^ number) as u8);
+    }
+}
+
+#[test_case]
+fn writes_sectors() {
+    let disk = disk();
+    let data = vec![0xa5; 2 * SECTOR_SIZE];
+    task::block_on(disk.write(100, &data)).expect("write failed");
+    task::block_on(disk.flush()).expect("flush failed");
+
+    let mut buf = vec![0; 2 * SECTOR_SIZE];
+    task::block_on(disk.read(100, &mut buf)).expect("read failed");
+    assert_eq!(buf, data);
+}
+
+#[test_case]
+fn rejects_invalid_requests() {
+    let disk = disk();
+    let mut buf = vec![0; SECTOR_SIZE];
+    let end = disk.sector_count();
+    assert_eq!(
+        task::block_on(disk.read(end, &mut buf)),
+        Err(BlockError::OutOfRange)
+    );
+    assert_eq!(
+        task::block_on(disk.read(0, &mut buf[..100])),
+        Err(BlockError::InvalidBuffer)
+    );
+}